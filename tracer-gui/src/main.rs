@@ -7,9 +7,12 @@ use eframe::egui::{Color32, ColorImage, Context, SidePanel, Slider, TextureHandl
 use once_cell::sync::OnceCell;
 use rand::{Rng, SeedableRng};
 use rand::rngs::SmallRng;
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use rayon::slice::{ParallelSlice, ParallelSliceMut};
 
 use tracer::common::scene::{Color, Scene};
-use tracer::cpu::{CpuPreparedScene, CpuRenderSettings, StopCondition, Strategy};
+use tracer::common::tonemap::{auto_exposure, AutoExposure, to_srgb8};
+use tracer::cpu::{CpuPreparedScene, CpuRenderSettings, LightSelection, StopCondition};
 use tracer::cpu::accel::NoAccel;
 use tracer::cpu::stats::ColorVarianceEstimator;
 use tracer::demos;
@@ -69,6 +72,8 @@ struct SharedImage {
 
     buffer: Vec<ColorVarianceEstimator>,
     buffer_changed: bool,
+    /// whether the scene is still being prepared, `false` once rendering has started
+    preparing: bool,
 
     prev_settings: Option<ImageSettings>,
     prev_texture: Option<TextureHandle>,
@@ -82,6 +87,7 @@ impl SharedImage {
             height,
             buffer: vec![Default::default(); (width * height) as usize],
             buffer_changed: false,
+            preparing: true,
             prev_settings: None,
             prev_texture: None,
             ctx: OnceCell::new(),
@@ -92,8 +98,11 @@ impl SharedImage {
         self.buffer[y as usize * self.width as usize + x as usize] = value;
     }
 
-    fn get_pixel(&self, x: u32, y: u32) -> &ColorVarianceEstimator {
-        &self.buffer[y as usize * self.width as usize + x as usize]
+    fn set_preparing(&mut self, preparing: bool) {
+        self.preparing = preparing;
+        if let Some(ctx) = self.ctx.get() {
+            ctx.request_repaint();
+        }
     }
 
     fn mark_changed(&mut self) {
@@ -125,18 +134,15 @@ impl SharedImage {
         let start = Instant::now();
         let mut image = ColorImage::new([self.width as usize, self.height as usize], Color32::BLACK);
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let color_orig = self.get_pixel(x, y).mean;
-                let color_mapped = settings.map(color_orig);
-
-                let color_srgb = palette::Srgb::from_linear(color_mapped);
-                let color_byte = color_srgb.into_format();
-
-                let color_32 = Color32::from_rgb(color_byte.red, color_byte.green, color_byte.blue);
-                image[(x as usize, y as usize)] = color_32;
+        // convert the scanlines in parallel
+        let width = self.width as usize;
+        image.pixels.par_chunks_mut(width).zip(self.buffer.par_chunks(width)).for_each(|(image_row, buffer_row)| {
+            for (pixel, estimator) in image_row.iter_mut().zip(buffer_row) {
+                // use the same mapping as saved images, so the preview matches them at zero exposure
+                let [red, green, blue] = to_srgb8(settings.map(estimator.mean));
+                *pixel = Color32::from_rgb(red, green, blue);
             }
-        }
+        });
 
         println!("to_image took {}s", start.elapsed().as_secs_f32());
         image
@@ -151,15 +157,14 @@ fn renderer_main(scene: &Scene, image: Arc<Mutex<SharedImage>>, stop: &AtomicBoo
 
     let settings = CpuRenderSettings {
         stop_condition: StopCondition::SampleCount(0),
-        max_bounces: 8,
-        anti_alias: true,
-        strategy: Strategy::SampleLights,
+        ..Default::default()
     };
 
     // let accel = BVH::new(&scene.objects, Default::default());
     let accel = NoAccel;
 
-    let prepared = CpuPreparedScene::new(&scene, settings, accel, width, height);
+    let prepared = CpuPreparedScene::new(scene, settings, &LightSelection::Auto, accel, width, height);
+    image.lock().unwrap().set_preparing(false);
 
     let mut buffer = vec![ColorVarianceEstimator::default(); (width * height) as usize];
 
@@ -173,9 +178,8 @@ fn renderer_main(scene: &Scene, image: Arc<Mutex<SharedImage>>, stop: &AtomicBoo
     loop {
         let x = rng.gen_range(0..width);
         let y = rng.gen_range(0..height);
-        let color = prepared.sample_pixel(&mut rng, x, y);
-
         let estimator = &mut buffer[(y * width + x) as usize];
+        let (color, _) = prepared.sample_pixel_indexed(&mut rng, x, y, estimator.count);
         estimator.update(color);
         updates.push((x, y, estimator.clone()));
 
@@ -222,7 +226,7 @@ impl eframe::App for App {
         // TODO avoid clone here?
         let _ = self.ctx.set(ctx.clone());
 
-        let (texture, width, height) = {
+        let (texture, width, height, preparing) = {
             let start = Instant::now();
             let mut image = self.image.lock().unwrap();
             let lock_time = start.elapsed();
@@ -233,11 +237,21 @@ impl eframe::App for App {
 
             println!("lock too {}s, texture {}s", lock_time.as_secs_f32(), texture_time.as_secs_f32());
 
-            (texture, image.width, image.height)
+            (texture, image.width, image.height, image.preparing)
         };
 
         SidePanel::left("side_panel").show(ctx, |ui| {
-            ui.add(Slider::new(&mut self.settings.exposure, -5.0..=5.0));
+            ui.horizontal(|ui| {
+                ui.add(Slider::new(&mut self.settings.exposure, -5.0..=5.0));
+                if ui.button("Auto").clicked() {
+                    let image = self.image.lock().unwrap();
+                    let exposure = auto_exposure(image.buffer.iter().map(|estimator| estimator.mean), AutoExposure::default());
+                    self.settings.exposure = exposure.clamp(-5.0, 5.0);
+                }
+            });
+            if preparing {
+                ui.label("Preparing scene...");
+            }
         });
 
         // TODO stop this from overriding the side panel
@@ -0,0 +1,111 @@
+//! Compare the acceleration structures on the same scene: the time to build them, the time to render a small image
+//! and the average amount of work done for each camera ray.
+//!
+//! Run with `cargo run --release --example bench_accel [tile_count] [samples]`,
+//! the scene is [scene_random_tiles_with](tracer::demos::scene_random_tiles_with) with a fixed seed.
+
+use std::time::{Duration, Instant};
+
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use tracer::common::scene::{Object, Scene};
+use tracer::cpu::{BlockOrder, CpuPreparedScene, CpuRenderSettings, LightSelection, StopCondition};
+use tracer::cpu::accel::{Accel, NoAccel};
+use tracer::cpu::accel::bvh::{BVH, BVHSplitStrategy};
+use tracer::cpu::accel::octree::Octree;
+use tracer::cpu::stats::count_rejected_hits;
+use tracer::demos;
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 90;
+
+struct BenchResult {
+    build: Duration,
+    trace: Duration,
+    nodes: f64,
+    objects: f64,
+    checksum: f64,
+    rejected_hits: u64,
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let tile_count = args.next().map_or(1000, |s| s.parse().expect("Invalid tile count"));
+    let samples = args.next().map_or(1, |s| s.parse().expect("Invalid sample count"));
+
+    let scene = demos::scene_random_tiles_with(tile_count, 0, true);
+    println!("Scene with {} objects, {}x{} pixels with {} samples", scene.objects.len(), WIDTH, HEIGHT, samples);
+    println!();
+    println!("{:<24} {:>10} {:>10} {:>8} {:>8} {:>10} {:>8}", "accel", "build", "trace", "nodes", "objects", "checksum", "rejected");
+
+    let sah = |test_planes| BVHSplitStrategy::SurfaceAreaHeuristic { test_planes };
+    let results = [
+        ("NoAccel", bench(&scene, samples, |_| NoAccel)),
+        ("Octree(16)", bench(&scene, samples, |o| Octree::new(o, 16))),
+        ("BVH(SplitLargestAxis)", bench(&scene, samples, |o| BVH::new(o, BVHSplitStrategy::SplitLargestAxis))),
+        ("BVH(SAH, centroids)", bench(&scene, samples, |o| BVH::new(o, sah(None)))),
+        ("BVH(SAH, 16 planes)", bench(&scene, samples, |o| BVH::new(o, sah(Some(16))))),
+    ];
+
+    for (name, result) in results {
+        println!(
+            "{:<24} {:>10.2?} {:>10.2?} {:>8.1} {:>8.1} {:>10.4} {:>8}",
+            name, result.build, result.trace, result.nodes, result.objects, result.checksum, result.rejected_hits,
+        );
+    }
+}
+
+/// Build the accel with `build` and render the scene with it. The checksum is the average pixel brightness,
+/// which should only differ between accels by the noise of the render.
+fn bench<A: Accel>(scene: &Scene, samples: u32, build: impl FnOnce(&[Object]) -> A) -> BenchResult {
+    let start = Instant::now();
+    let accel = build(&scene.objects);
+    let build = start.elapsed();
+
+    let settings = CpuRenderSettings {
+        stop_condition: StopCondition::SampleCount(samples),
+        block_order: BlockOrder::RowMajor,
+        track_variance: false,
+        ..Default::default()
+    };
+    let prepared = CpuPreparedScene::new(scene, settings, &LightSelection::Auto, accel, WIDTH, HEIGHT);
+
+    // every row gets its own fixed seed, so all accels trace the same rays as long as they find the same hits
+    let start = Instant::now();
+    let (checksum, rejected_hits) = (0..HEIGHT).into_par_iter()
+        .map(|y| {
+            let rng = &mut SmallRng::seed_from_u64(y as u64);
+            count_rejected_hits(|| {
+                (0..WIDTH).map(|x| {
+                    let color = prepared.calculate_pixel(rng, x, y).color;
+                    (color.red + color.green + color.blue) as f64 / 3.0
+                }).sum::<f64>()
+            })
+        })
+        .reduce(|| (0.0, 0), |(left_sum, left_rejected), (right_sum, right_rejected)| {
+            (left_sum + right_sum, left_rejected + right_rejected)
+        });
+    let trace = start.elapsed();
+
+    let (mut nodes, mut objects) = (0, 0);
+    let rng = &mut SmallRng::seed_from_u64(0);
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let cost = prepared.camera_traversal_cost(rng, x, y);
+            nodes += cost.nodes as u64;
+            objects += cost.objects as u64;
+        }
+    }
+    let pixels = (WIDTH * HEIGHT) as f64;
+
+    BenchResult {
+        build,
+        trace,
+        nodes: nodes as f64 / pixels,
+        objects: objects as f64 / pixels,
+        checksum: checksum / pixels,
+        rejected_hits,
+    }
+}
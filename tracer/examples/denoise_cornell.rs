@@ -0,0 +1,48 @@
+//! Clean up a low-sample render of the Cornell box with the built-in bilateral filter.
+//!
+//! Run with `cargo run --release --example denoise_cornell [samples]`,
+//! the noisy and the filtered image are saved to `ignored/denoise_noisy.png` and `ignored/denoise_filtered.png`.
+
+use std::fs;
+
+use imgref::ImgVec;
+
+use tracer::common::progress::{NoProgress, PixelResult};
+use tracer::cpu::{CpuPreparedScene, CpuRenderer, CpuRenderSettings, DEFAULT_MAX_PIXELS, LightSelection, StopCondition, Subsampling};
+use tracer::cpu::accel::bvh::{BVH, BVHSplitStrategy};
+use tracer::demos;
+use tracer::filter::{BilateralParams, filter_bilateral};
+use tracer::images::to_discrete_image;
+
+const SIZE: u32 = 512;
+/// The number of camera rays per pixel for the normal and depth guides, they converge much faster than the render itself.
+const GUIDE_SAMPLES: u32 = 4;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let samples = std::env::args().nth(1).map_or(8, |s| s.parse().expect("Invalid sample count"));
+
+    let scene = demos::scene_cornell_box();
+    let settings = CpuRenderSettings {
+        stop_condition: StopCondition::SampleCount(samples),
+        ..Default::default()
+    };
+    let renderer = CpuRenderer { settings, lights: LightSelection::Auto, progress_handler: NoProgress, threads: None, max_pixels: DEFAULT_MAX_PIXELS, subsampling: Subsampling::Full };
+    let image = renderer.render(&scene, SIZE, SIZE, None, |o, _| BVH::new(o, BVHSplitStrategy::default()))?;
+
+    let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, BVH::new(&scene.objects, BVHSplitStrategy::default()), SIZE, SIZE);
+    let (normal, depth) = prepared.guide_buffers(SIZE, SIZE, GUIDE_SAMPLES, 0);
+
+    let color = ImgVec::new(image.pixels().map(|p| p.color).collect(), image.width(), image.height());
+    let variance = ImgVec::new(image.pixels().map(|p| p.variance / p.samples.max(1) as f32).collect(), image.width(), image.height());
+    let filtered = filter_bilateral(color.as_ref(), variance.as_ref(), normal.as_ref(), depth.as_ref(), BilateralParams::default());
+
+    let filtered = ImgVec::new(
+        image.pixels().zip(filtered.pixels()).map(|(p, color)| PixelResult { color, ..p }).collect(),
+        image.width(), image.height(),
+    );
+
+    fs::create_dir_all("ignored")?;
+    to_discrete_image(image.as_ref(), false).0.save("ignored/denoise_noisy.png")?;
+    to_discrete_image(filtered.as_ref(), false).0.save("ignored/denoise_filtered.png")?;
+    Ok(())
+}
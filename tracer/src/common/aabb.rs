@@ -36,18 +36,21 @@ impl AxisBox {
         f(self.high);
     }
 
-    pub fn for_shape(shape: Shape) -> Self {
+    pub fn for_shape(shape: &Shape) -> Self {
         match shape {
             Shape::Sphere => AxisBox::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)),
             Shape::Plane => AxisBox::new(Point3::new(-INF, -INF, 0.0), Point3::new(INF, INF, 0.0)),
             Shape::Triangle => AxisBox::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0)),
             Shape::Square => AxisBox::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0)),
             Shape::Cylinder => AxisBox::new(Point3::new(-1.0, -INF, -1.0), Point3::new(1.0, INF, 1.0)),
+            &Shape::Torus { tube_radius: r } => AxisBox::new(Point3::new(-1.0 - r, -r, -1.0 - r), Point3::new(1.0 + r, r, 1.0 + r)),
+            Shape::Cone { .. } => AxisBox::new(Point3::new(-1.0, 0.0, -1.0), Point3::new(1.0, 1.0, 1.0)),
+            Shape::Sdf(sdf) => AxisBox::new(Point3::new(-sdf.bound, -sdf.bound, -sdf.bound), Point3::new(sdf.bound, sdf.bound, sdf.bound)),
         }
     }
 
     pub fn for_object(object: &Object) -> Self {
-        object.transform * AxisBox::for_shape(object.shape)
+        object.transform * AxisBox::for_shape(&object.shape)
     }
 
     pub fn is_finite(self) -> bool {
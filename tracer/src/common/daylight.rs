@@ -0,0 +1,146 @@
+use std::f32::consts::PI;
+
+use crate::common::math::{Norm, Point2, Unit, Vec3};
+use crate::common::scene::Color;
+use crate::common::spectrum::xyz_to_rgb;
+use crate::common::texture::{equirect_direction, ImageTexture};
+
+/// The angular radius of the sun as seen from the earth, in radians.
+pub const SUN_ANGULAR_RADIUS: f32 = 0.00465;
+
+/// Converts the luminance of the model in kcd/m² to the radiance used by the renderer, chosen so a white surface
+/// lit by a sun halfway up the sky ends up at a brightness of about one.
+const LUMINANCE_SCALE: f32 = 0.04;
+
+/// The luminance of the sun outside of the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f32 = 1.6e6;
+
+/// The wavelengths in micrometers the transmittance of the atmosphere is evaluated at for the red, green and blue channels.
+const CHANNEL_WAVELENGTHS: [f32; 3] = [0.680, 0.550, 0.440];
+
+/// Whether `direction` is within [SUN_ANGULAR_RADIUS] of the center of the sun.
+pub fn in_sun_disk(direction: Unit<Vec3>, sun_direction: Unit<Vec3>) -> bool {
+    in_disk(direction, sun_direction, SUN_ANGULAR_RADIUS.sin())
+}
+
+/// Whether `direction` is within the angle with sine `sin_radius` of `center`, for angles below 90 degrees.
+/// The cosine of such small angles is too close to one to compare it precisely, their sine still has its full precision.
+pub fn in_disk(direction: Unit<Vec3>, center: Unit<Vec3>, sin_radius: f32) -> bool {
+    direction.dot(*center) > 0.0 && direction.cross(*center).norm() <= sin_radius
+}
+
+/// The light of the sky of [Sky::Analytic](crate::common::scene::Sky::Analytic) arriving from `direction`,
+/// without the sun disk itself, see [sun_radiance].
+///
+/// This is the model from "A Practical Analytic Model for Daylight" by Preetham, Shirley and Smits: the luminance and
+/// chromaticity of the zenith are fitted to the sun elevation and the turbidity, and the
+/// Perez et al. distribution spreads them over the sky, with a bright glow around the sun and a lighter horizon.
+/// Directions below the horizon get the light of the sky just above it, suns below the horizon are treated as setting.
+pub fn sky_radiance(direction: Unit<Vec3>, sun_direction: Unit<Vec3>, turbidity: f32) -> Color {
+    let theta_sun = sun_direction.y.clamp(0.0, 1.0).acos();
+    // the distribution blows up at the horizon itself
+    let cos_theta = direction.y.max(0.01);
+    let gamma = direction.dot(*sun_direction).clamp(-1.0, 1.0).acos();
+
+    let [luminance, x, y] = zenith(theta_sun, turbidity).map(|(zenith, coefficients)| {
+        zenith * perez(coefficients, cos_theta, gamma) / perez(coefficients, 1.0, theta_sun)
+    });
+
+    let xyz = [x * luminance / y, luminance, (1.0 - x - y) * luminance / y];
+    let [red, green, blue] = xyz_to_rgb(xyz);
+    Color::new(red.max(0.0), green.max(0.0), blue.max(0.0)) * LUMINANCE_SCALE
+}
+
+/// The light of the sun disk of [Sky::Analytic](crate::common::scene::Sky::Analytic) after passing through the atmosphere,
+/// the same over the whole disk. The sun turns orange and dims as it approaches the horizon and is black below it.
+///
+/// Only Rayleigh scattering by air and the scattering by aerosols are modelled, from the appendix of the same paper.
+pub fn sun_radiance(sun_direction: Unit<Vec3>, turbidity: f32) -> Color {
+    if sun_direction.y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    // the relative amount of air the light travels through, including the curvature of the atmosphere near the horizon
+    let theta = sun_direction.y.min(1.0).acos();
+    let air_mass = 1.0 / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    let [red, green, blue] = CHANNEL_WAVELENGTHS.map(|wavelength| {
+        let rayleigh = (-0.008735 * wavelength.powf(-4.08) * air_mass).exp();
+        let aerosol = (-beta * wavelength.powf(-1.3) * air_mass).exp();
+        rayleigh * aerosol
+    });
+    Color::new(red, green, blue) * (SUN_LUMINANCE * LUMINANCE_SCALE)
+}
+
+/// An equirectangular image of [sky_radiance] without the sun, eg. to importance sample the sky or to save it.
+pub fn sky_texture(sun_direction: Unit<Vec3>, turbidity: f32, width: u32, height: u32) -> ImageTexture {
+    ImageTexture::from_fn(width, height, |x, y| {
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = 1.0 - (y as f32 + 0.5) / height as f32;
+        sky_radiance(equirect_direction(Point2::new(u, v)), sun_direction, turbidity)
+    })
+}
+
+/// The coefficients `A` to `E` of the Perez distribution.
+type Perez = [f32; 5];
+
+/// The zenith value and the Perez coefficients for the luminance (in kcd/m²) and the `x` and `y` chromaticity.
+fn zenith(theta_sun: f32, turbidity: f32) -> [(f32, Perez); 3] {
+    let t = turbidity;
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+    let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+    let thetas = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+    let fit = |rows: [[f32; 4]; 3]| {
+        let [a, b, c] = rows.map(|row| row.iter().zip(&thetas).map(|(r, s)| r * s).sum::<f32>());
+        a * t * t + b * t + c
+    };
+    let x = fit([
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+    ]);
+    let y = fit([
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+    ]);
+
+    [
+        (luminance, [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703]),
+        (x, [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452]),
+        (y, [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529]),
+    ]
+}
+
+/// The Perez distribution for a direction with cosine `cos_theta` to the zenith and at angle `gamma` from the sun.
+fn perez([a, b, c, d, e]: Perez, cos_theta: f32, gamma: f32) -> f32 {
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::daylight::{sky_radiance, sun_radiance};
+    use crate::common::math::{Norm, Vec3};
+    use crate::common::texture::luminance;
+
+    #[test]
+    fn sky_and_sun_colors() {
+        let sun = Vec3::new(0.0, 1.0, 1.0).normalized();
+        let sky = |direction: Vec3| sky_radiance(direction.normalized(), sun, 3.0);
+
+        // a blue sky that glows around the sun
+        let zenith = sky(*Vec3::y_axis());
+        assert!(zenith.blue > zenith.red, "{:?}", zenith);
+        assert!(luminance(sky(Vec3::new(0.0, 1.0, 1.2))) > luminance(sky(Vec3::new(0.0, 1.0, -1.2))));
+
+        // the sun outshines the sky and turns orange and dim near the horizon
+        let high = sun_radiance(sun, 3.0);
+        let low = sun_radiance(Vec3::new(0.0, 0.05, 1.0).normalized(), 3.0);
+        assert!(luminance(high) > 1000.0 * luminance(zenith));
+        assert!(luminance(low) < luminance(high));
+        assert!(low.red / low.blue > high.red / high.blue);
+        assert_eq!(sun_radiance(-sun, 3.0).red, 0.0);
+    }
+}
@@ -3,6 +3,21 @@ use std::fmt;
 use std::fmt::Debug;
 use std::ops::{Add, Deref, Div, Mul, Neg, Sub};
 
+/// The float type used for the intermediate results of transforms and ray intersections, `f64` with the
+/// `double-precision` feature and `f32` otherwise. Everything that's stored, including the scene itself, stays `f32`.
+/// This fixes most precision artifacts in scenes with large coordinates, where the rounding errors of these calculations
+/// scale with the distance from the origin, at the cost of about a third more render time.
+#[cfg(feature = "double-precision")]
+pub type Real = f64;
+#[cfg(not(feature = "double-precision"))]
+pub type Real = f32;
+
+/// Round a [Real] back to `f32`.
+#[allow(clippy::unnecessary_cast)]
+pub fn from_real(x: Real) -> f32 {
+    x as f32
+}
+
 pub trait Norm: Div<f32, Output=Self> + Sized + Copy + Debug {
     fn norm_squared(self) -> f32;
 
@@ -73,6 +88,11 @@ impl Vec3 {
         Unit::new_unchecked(Vec3::new(0.0, 0.0, 1.0))
     }
 
+    /// Linearly interpolate from `self` at `t = 0` to `other` at `t = 1`, see [lerp].
+    pub fn lerp(self, other: Vec3, t: f32) -> Vec3 {
+        Vec3::new(lerp(self.x, other.x, t), lerp(self.y, other.y, t), lerp(self.z, other.z, t))
+    }
+
     pub fn is_finite(self) -> bool {
         self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
     }
@@ -135,6 +155,16 @@ impl<V: Norm + Debug> Unit<V> {
     }
 }
 
+impl Unit<Vec3> {
+    /// Two unit vectors that together with this one form the right-handed orthonormal basis `(tangent, bitangent, self)`.
+    pub fn orthonormal_basis(self) -> (Unit<Vec3>, Unit<Vec3>) {
+        let helper = if self.x.abs() > 0.9 { Vec3::y_axis() } else { Vec3::x_axis() };
+        let tangent = self.cross(*helper).normalized();
+        let bitangent = Unit::new_unchecked(self.cross(*tangent));
+        (tangent, bitangent)
+    }
+}
+
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Point3 {
     pub x: f32,
@@ -179,6 +209,11 @@ impl Point3 {
         Point3::new((self.x + other.x) / 2.0, (self.y + other.y) / 2.0, (self.z + other.z) / 2.0)
     }
 
+    /// Linearly interpolate from `self` at `t = 0` to `other` at `t = 1`, see [lerp].
+    pub fn lerp(self, other: Point3, t: f32) -> Point3 {
+        Point3::from_coords(self.coords().lerp(other.coords(), t))
+    }
+
     pub fn is_finite(self) -> bool {
         self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
     }
@@ -332,8 +367,46 @@ impl Sub<Point2> for Point2 {
     }
 }
 
+//conversions
+
+impl From<[f32; 3]> for Vec3 {
+    fn from(array: [f32; 3]) -> Self {
+        Vec3::from_slice(&array)
+    }
+}
+
+impl From<(f32, f32, f32)> for Vec3 {
+    fn from((x, y, z): (f32, f32, f32)) -> Self {
+        Vec3::new(x, y, z)
+    }
+}
+
+impl From<Vec3> for [f32; 3] {
+    fn from(v: Vec3) -> Self {
+        [v.x, v.y, v.z]
+    }
+}
+
+impl From<[f32; 3]> for Point3 {
+    fn from([x, y, z]: [f32; 3]) -> Self {
+        Point3::new(x, y, z)
+    }
+}
+
+impl From<(f32, f32, f32)> for Point3 {
+    fn from((x, y, z): (f32, f32, f32)) -> Self {
+        Point3::new(x, y, z)
+    }
+}
+
+impl From<Point3> for [f32; 3] {
+    fn from(p: Point3) -> Self {
+        [p.x, p.y, p.z]
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
-struct Matrix4 {
+pub struct Matrix4 {
     rows: [[f32; 4]; 4],
 }
 
@@ -352,20 +425,20 @@ impl Debug for Matrix4 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if f.alternate() {
             let mut max_size_per_col = [0; 4];
-            for c in 0..4 {
-                for r in 0..4 {
-                    max_size_per_col[c] = max(max_size_per_col[c], format!("{:?}", self.rows[r][c]).len());
+            for (c, max_size) in max_size_per_col.iter_mut().enumerate() {
+                for row in &self.rows {
+                    *max_size = max(*max_size, format!("{:?}", row[c]).len());
                 }
             }
 
             f.write_str("[\n")?;
-            for r in 0..4 {
+            for row in &self.rows {
                 f.write_str("    ")?;
-                for c in 0..4 {
+                for (c, (value, max_size)) in row.iter().zip(max_size_per_col).enumerate() {
                     if c != 0 {
                         f.write_str(", ")?;
                     }
-                    f.write_fmt(format_args!("{:1$?}", self.rows[r][c], max_size_per_col[c]))?;
+                    f.write_fmt(format_args!("{:1$?}", value, max_size))?;
                 }
                 f.write_str("\n")?;
             }
@@ -380,8 +453,8 @@ impl Debug for Matrix4 {
 
 fn array4_from<T: Default>(mut f: impl FnMut(usize) -> T) -> [T; 4] {
     let mut result: [T; 4] = Default::default();
-    for i in 0..4 {
-        result[i] = f(i);
+    for (i, x) in result.iter_mut().enumerate() {
+        *x = f(i);
     }
     result
 }
@@ -404,15 +477,61 @@ impl Mul<[f32; 4]> for Matrix4 {
     type Output = [f32; 4];
 
     fn mul(self, rhs: [f32; 4]) -> Self::Output {
-        array4_from(|r| (0..4).map(|i| self.rows[r][i] * rhs[i]).sum())
+        self.mul_real(rhs).map(from_real)
     }
 }
 
 impl Matrix4 {
-    fn new(rows: [[f32; 4]; 4]) -> Self {
+    pub fn new(rows: [[f32; 4]; 4]) -> Self {
         Self { rows }
     }
 
+    /// Multiply with `rhs` without rounding the result back to `f32`, see [Real].
+    pub fn mul_real(self, rhs: [f32; 4]) -> [Real; 4] {
+        array4_from(|r| (0..4).map(|i| self.rows[r][i] as Real * rhs[i] as Real).sum())
+    }
+
+    /// The inverse of this matrix, or `None` if it's singular.
+    /// Uses Gauss-Jordan elimination with partial pivoting.
+    pub fn inverse(self) -> Option<Self> {
+        let mut left = self.rows;
+        let mut right = Matrix4::default().rows;
+
+        for c in 0..4 {
+            // pick the row with the largest value in this column as pivot for numerical stability
+            let pivot = (c..4).max_by(|&a, &b| left[a][c].abs().total_cmp(&left[b][c].abs())).unwrap();
+            if left[pivot][c] == 0.0 {
+                return None;
+            }
+            left.swap(c, pivot);
+            right.swap(c, pivot);
+
+            let factor = 1.0 / left[c][c];
+            for i in 0..4 {
+                left[c][i] *= factor;
+                right[c][i] *= factor;
+            }
+
+            for r in 0..4 {
+                if r == c {
+                    continue;
+                }
+                let factor = left[r][c];
+                for i in 0..4 {
+                    left[r][i] -= factor * left[c][i];
+                    right[r][i] -= factor * right[c][i];
+                }
+            }
+        }
+
+        let result = Self::new(right);
+        if result.is_finite() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
     fn transpose(self) -> Self {
         Self::new(array4x4_from(|r, c| self.rows[c][r]))
     }
@@ -453,11 +572,15 @@ impl Matrix4 {
     }
 
     fn scale(scale: f32) -> Self {
-        debug_assert!(scale != 0.0);
+        Self::scale_xyz(Vec3::new(scale, scale, scale))
+    }
+
+    fn scale_xyz(scale: Vec3) -> Self {
+        debug_assert!(scale.x != 0.0 && scale.y != 0.0 && scale.z != 0.0);
         Self::new([
-            [scale, 0.0, 0.0, 0.0],
-            [0.0, scale, 0.0, 0.0],
-            [0.0, 0.0, scale, 0.0],
+            [scale.x, 0.0, 0.0, 0.0],
+            [0.0, scale.y, 0.0, 0.0],
+            [0.0, 0.0, scale.z, 0.0],
             [0.0, 0.0, 0.0, 1.0],
         ])
     }
@@ -483,11 +606,26 @@ impl Transform {
         }
     }
 
+    /// Transform `point` without rounding the result back to `f32`, see [Real].
+    pub fn mul_point_real(self, point: Point3) -> [Real; 3] {
+        let [x, y, z, _] = self.fwd.mul_real([point.x, point.y, point.z, 1.0]);
+        [x, y, z]
+    }
+
     pub fn inv_transpose_mul(self, rhs: Vec3) -> Vec3 {
         let [x, y, z, _] = self.inv.transpose() * [rhs.x, rhs.y, rhs.z, 0.0];
         Vec3::new(x, y, z)
     }
 
+    /// Build a transform from an arbitrary affine matrix, returns `None` if the matrix is not affine or not invertible.
+    pub fn from_matrix(fwd: Matrix4) -> Option<Self> {
+        if fwd.rows[3] != [0.0, 0.0, 0.0, 1.0] {
+            return None;
+        }
+        let inv = fwd.inverse()?;
+        Some(Self { fwd, inv })
+    }
+
     pub fn translate(translation: Vec3) -> Self {
         Self {
             fwd: Matrix4::translate(translation),
@@ -509,6 +647,21 @@ impl Transform {
         }
     }
 
+    /// Scale each axis separately, all components must be nonzero.
+    pub fn scale_xyz(scale: Vec3) -> Self {
+        Transform {
+            fwd: Matrix4::scale_xyz(scale),
+            inv: Matrix4::scale_xyz(Vec3::new(1.0 / scale.x, 1.0 / scale.y, 1.0 / scale.z)),
+        }
+    }
+
+    /// Scale, then rotate around `axis` by `angle` and finally translate,
+    /// the same as `translate(translation) * rotate(axis, angle) * scale_xyz(scale)`.
+    /// This is the usual way to place an object: its shape is sized and oriented around the origin before it's moved.
+    pub fn trs(translation: Vec3, (axis, angle): (Unit<Vec3>, Angle), scale: Vec3) -> Self {
+        Self::translate(translation) * Self::rotate(axis, angle) * Self::scale_xyz(scale)
+    }
+
     /// Translates the origin to `pos` and rotates vectors pointing in the negative Z direction towards `target`
     pub fn look_at(pos: Point3, target: Point3, up: Unit<Vec3>) -> Self {
         let dir = (target - pos).normalized();
@@ -516,7 +669,20 @@ impl Transform {
     }
 
     pub fn look_in_dir(pos: Point3, dir: Unit<Vec3>, up: Unit<Vec3>) -> Self {
-        let rotate = Matrix4::face_towards(dir, up);
+        Self::look_in_dir_rolled(pos, dir, up, Angle::radians(0.0))
+    }
+
+    /// [Transform::look_at] with the camera additionally rolled by `roll` around the viewing direction.
+    /// Positive angles rotate the camera counterclockwise, so the scene appears rotated clockwise in the image.
+    pub fn look_at_rolled(pos: Point3, target: Point3, up: Unit<Vec3>, roll: Angle) -> Self {
+        let dir = (target - pos).normalized();
+        Self::look_in_dir_rolled(pos, dir, up, roll)
+    }
+
+    /// [Transform::look_in_dir] with the camera additionally rolled by `roll` around the viewing direction,
+    /// see [Transform::look_at_rolled].
+    pub fn look_in_dir_rolled(pos: Point3, dir: Unit<Vec3>, up: Unit<Vec3>, roll: Angle) -> Self {
+        let rotate = Matrix4::face_towards(dir, up) * Matrix4::rotate(Vec3::z_axis(), roll);
         let rotate = Self {
             fwd: rotate,
             inv: rotate.transpose(),
@@ -589,7 +755,7 @@ impl Mul<Point3> for Transform {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
 pub struct Angle {
     pub radians: f32,
 }
@@ -602,6 +768,28 @@ impl Angle {
     pub fn degrees(degrees: f32) -> Angle {
         Angle::radians(degrees.to_radians())
     }
+
+    pub fn to_degrees(self) -> f32 {
+        self.radians.to_degrees()
+    }
+
+    pub fn sin(self) -> f32 {
+        self.radians.sin()
+    }
+
+    pub fn cos(self) -> f32 {
+        self.radians.cos()
+    }
+
+    pub fn tan(self) -> f32 {
+        self.radians.tan()
+    }
+
+    /// Linearly interpolate from `self` at `t = 0` to `other` at `t = 1`.
+    /// This doesn't wrap around, interpolating from 350° to 10° passes through 180°.
+    pub fn lerp(self, other: Angle, t: f32) -> Angle {
+        Angle::radians(lerp(self.radians, other.radians, t))
+    }
 }
 
 impl Neg for Angle {
@@ -612,6 +800,30 @@ impl Neg for Angle {
     }
 }
 
+impl Add for Angle {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Angle { radians: self.radians + rhs.radians }
+    }
+}
+
+impl Sub for Angle {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Angle { radians: self.radians - rhs.radians }
+    }
+}
+
+impl Mul<f32> for Angle {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Angle { radians: self.radians * rhs }
+    }
+}
+
 impl Debug for Angle {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Angle({} = {}°)", self.radians, self.radians.to_degrees())
@@ -653,13 +865,17 @@ impl Axis3Owner for Vec3 {
     }
 }
 
-pub fn lerp(t: f32, x: f32, y: f32) -> f32 {
-    t * x + (1.0 - t) * y
+/// Linearly interpolate from `a` at `t = 0` to `b` at `t = 1`, `t` outside of `[0, 1]` extrapolates.
+///
+/// The argument order matches the `lerp` methods on [Vec3], [Point3] and [Angle].
+/// This used to be `lerp(t, x, y) = t * x + (1 - t) * y`, with the weight first and the endpoints swapped.
+pub fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    (1.0 - t) * a + t * b
 }
 
 #[cfg(test)]
 mod test {
-    use crate::common::math::{Point3, Transform, Vec3};
+    use crate::common::math::{Angle, lerp, Matrix4, Norm, Point3, Transform, Vec3};
 
     fn assert_close_vec3(left: Vec3, right: Vec3) {
         let delta = left - right;
@@ -670,6 +886,16 @@ mod test {
         );
     }
 
+    /// Like [assert_close_vec3], but also catches components that are too small instead of only too large.
+    fn assert_close_vec3_abs(left: Vec3, right: Vec3) {
+        let delta = left - right;
+        let max_delta = delta.x.abs().max(delta.y.abs()).max(delta.z.abs());
+        assert!(
+            left.is_finite() && right.is_finite() && max_delta < 0.0001,
+            "Expected close, finite values, got {left:?} and {right:?}"
+        );
+    }
+
     fn assert_close_point3(left: Point3, right: Point3) {
         assert_close_vec3(left - Point3::origin(), right - Point3::origin());
     }
@@ -697,4 +923,150 @@ mod test {
         let unit = trans.fwd * trans.inv;
         println!("{:?}", unit);
     }
-}
\ No newline at end of file
+
+    fn assert_close_matrix4(left: Matrix4, right: Matrix4) {
+        let max_delta = (0..4).flat_map(|r| (0..4).map(move |c| (r, c)))
+            .map(|(r, c)| (left.rows[r][c] - right.rows[r][c]).abs())
+            .fold(0.0, f32::max);
+        assert!(max_delta < 0.0001, "Expected close values, got {left:#?} and {right:#?}");
+    }
+
+    #[test]
+    fn inverse_known_transforms() {
+        let transforms = [
+            Transform::translate(Vec3::new(1.0, -2.0, 3.0)),
+            Transform::rotate(Vec3::new(1.0, 2.0, 3.0).normalized(), Angle::degrees(30.0)),
+            Transform::scale(2.5),
+            Transform::look_at(Point3::new(1.0, 2.0, 3.0), Point3::new(-1.0, 0.0, 2.0), Vec3::y_axis()),
+            Transform::translate(Vec3::new(4.0, 5.0, 6.0)) * Transform::rotate_axes_to(
+                Vec3::new(1.0, 2.0, 3.0), Vec3::new(4.0, 5.0, 6.0), Vec3::new(2.0, 4.0, 8.0),
+            ),
+        ];
+
+        for trans in transforms {
+            let inv = trans.fwd.inverse().unwrap();
+            assert_close_matrix4(trans.inv, inv);
+            assert_close_matrix4(Matrix4::default(), trans.fwd * inv);
+            assert_close_matrix4(Matrix4::default(), inv * trans.fwd);
+
+            let built = Transform::from_matrix(trans.fwd).unwrap();
+            assert_close_matrix4(trans.inv, built.inv);
+        }
+    }
+
+    #[test]
+    fn inverse_singular() {
+        let singular = Matrix4::new([
+            [1.0, 2.0, 3.0, 0.0],
+            [2.0, 4.0, 6.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_eq!(singular.inverse(), None);
+        assert_eq!(Transform::from_matrix(singular), None);
+
+        let projective = Matrix4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0, 1.0],
+        ]);
+        assert!(projective.inverse().is_some());
+        assert_eq!(Transform::from_matrix(projective), None);
+    }
+
+    #[test]
+    fn angle_degrees_radians_round_trip() {
+        for degrees in [0.0, 30.0, 90.0, -45.0, 180.0, 720.0] {
+            let angle = Angle::degrees(degrees);
+            assert!((angle.to_degrees() - degrees).abs() < 1e-4, "{:?} != {}°", angle, degrees);
+            assert!((Angle::radians(angle.radians).to_degrees() - degrees).abs() < 1e-4);
+        }
+        assert_eq!(Angle::degrees(180.0).radians, std::f32::consts::PI);
+    }
+
+    #[test]
+    fn angle_arithmetic() {
+        let a = Angle::degrees(30.0);
+        let b = Angle::degrees(60.0);
+
+        assert!(((a + b).to_degrees() - 90.0).abs() < 1e-4);
+        assert!(((a - b).to_degrees() + 30.0).abs() < 1e-4);
+        assert!(((a * 3.0).to_degrees() - 90.0).abs() < 1e-4);
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert!((a.lerp(b, 0.5).to_degrees() - 45.0).abs() < 1e-4);
+        assert!((a.sin() - 0.5).abs() < 1e-6 && (b.cos() - 0.5).abs() < 1e-6 && (Angle::degrees(45.0).tan() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn look_at_roll() {
+        let pos = Point3::new(1.0, 2.0, 3.0);
+        let target = Point3::new(-2.0, 0.5, 0.0);
+        let dir = (target - pos).normalized();
+
+        // without roll this is exactly the plain look-at rotation
+        let rotate = Matrix4::face_towards(dir, Vec3::y_axis());
+        let unrolled = Transform::translate(pos.coords()) * Transform { fwd: rotate, inv: rotate.transpose() };
+        assert_eq!(Transform::look_at_rolled(pos, target, Vec3::y_axis(), Angle::radians(0.0)), unrolled);
+        assert_eq!(Transform::look_at(pos, target, Vec3::y_axis()), unrolled);
+
+        // rolling by 90 degrees turns the camera right vector into the old up vector, the view direction stays the same
+        let rolled = Transform::look_at_rolled(pos, target, Vec3::y_axis(), Angle::degrees(90.0));
+        assert_close_vec3_abs(rolled * *Vec3::x_axis(), unrolled * *Vec3::y_axis());
+        assert_close_vec3_abs(rolled * -*Vec3::z_axis(), *dir);
+        assert_close_vec3_abs(rolled * Point3::origin() - Point3::origin(), pos - Point3::origin());
+    }
+
+    #[test]
+    fn trs_order() {
+        let translation = Vec3::new(1.0, -2.0, 3.0);
+        let axis = Vec3::new(1.0, 1.0, 0.0).normalized();
+        let angle = Angle::degrees(30.0);
+        let scale = Vec3::new(2.0, 0.5, -1.0);
+
+        let trs = Transform::trs(translation, (axis, angle), scale);
+        let manual = Transform::translate(translation) * Transform::rotate(axis, angle) * Transform::scale_xyz(scale);
+
+        for point in [Point3::origin(), Point3::new(1.0, 2.0, 3.0), Point3::new(-0.5, 0.0, 4.0)] {
+            assert_close_point3(manual * point, trs * point);
+            assert_close_point3(point, trs.inv() * (trs * point));
+        }
+
+        // the scale is applied first, along the object axes
+        let scaled_x = Transform::rotate(axis, angle) * (*Vec3::x_axis() * 2.0);
+        assert_close_point3(Point3::from_coords(translation + scaled_x), trs * Point3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn lerp_endpoints() {
+        assert_eq!(lerp(2.0, 6.0, 0.0), 2.0);
+        assert_eq!(lerp(2.0, 6.0, 1.0), 6.0);
+        assert_eq!(lerp(2.0, 6.0, 0.25), 3.0);
+
+        let (a, b) = (Vec3::new(1.0, 2.0, 3.0), Vec3::new(-1.0, 0.0, 5.0));
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert_eq!(a.lerp(b, 0.5), Vec3::new(0.0, 1.0, 4.0));
+
+        let (a, b) = (Point3::from_coords(a), Point3::from_coords(b));
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert_eq!(a.lerp(b, 0.5), a.middle(b));
+    }
+
+    #[test]
+    fn array_tuple_round_trip() {
+        let v = Vec3::new(1.0, -2.0, 3.5);
+        assert_eq!(Vec3::from([1.0, -2.0, 3.5]), v);
+        assert_eq!(Vec3::from((1.0, -2.0, 3.5)), v);
+        assert_eq!(Vec3::from(<[f32; 3]>::from(v)), v);
+
+        let p = Point3::new(-4.0, 0.0, 0.25);
+        assert_eq!(Point3::from([-4.0, 0.0, 0.25]), p);
+        assert_eq!(Point3::from((-4.0, 0.0, 0.25)), p);
+        let array: [f32; 3] = p.into();
+        assert_eq!(array, [-4.0, 0.0, 0.25]);
+        assert_eq!(Point3::from(array), p);
+    }
+}
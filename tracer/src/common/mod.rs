@@ -2,4 +2,9 @@ pub mod scene;
 pub mod util;
 pub mod math;
 pub mod aabb;
-pub mod progress;
\ No newline at end of file
+pub mod progress;
+pub mod texture;
+pub mod tonemap;
+pub mod spectrum;
+pub mod daylight;
+pub mod point_cloud;
\ No newline at end of file
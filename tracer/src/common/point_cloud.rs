@@ -0,0 +1,440 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::common::math::{Point3, Transform};
+use crate::common::scene::{Color, Material, Object, Shape, WHITE};
+
+/// A single point of a point cloud, with its color if the file has one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CloudPoint {
+    pub position: Point3,
+    pub color: Option<Color>,
+}
+
+/// How to turn the points of a point cloud into objects, see [point_cloud_to_spheres].
+#[derive(Debug, Clone)]
+pub struct PointCloudStyle {
+    /// The radius of the sphere drawn for each point.
+    pub radius: f32,
+    /// The color of points that don't have one.
+    pub default_color: Color,
+}
+
+impl Default for PointCloudStyle {
+    fn default() -> Self {
+        PointCloudStyle {
+            radius: 0.01,
+            default_color: WHITE,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PointCloudError {
+    Io(std::io::Error),
+    /// The contents don't follow the format, with a description of the problem.
+    Invalid(String),
+    /// The file extension is not one of the supported formats.
+    UnknownFormat,
+}
+
+impl Display for PointCloudError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PointCloudError::Io(e) => write!(f, "Failed to read point cloud: {}", e),
+            PointCloudError::Invalid(message) => write!(f, "Invalid point cloud: {}", message),
+            PointCloudError::UnknownFormat => write!(f, "Unknown point cloud format, expected an .xyz or .ply file"),
+        }
+    }
+}
+
+impl std::error::Error for PointCloudError {}
+
+impl From<std::io::Error> for PointCloudError {
+    fn from(e: std::io::Error) -> Self {
+        PointCloudError::Io(e)
+    }
+}
+
+fn invalid<T>(message: impl Into<String>) -> Result<T, PointCloudError> {
+    Err(PointCloudError::Invalid(message.into()))
+}
+
+/// Load the point cloud at `path`, picking the format from the extension, see [read_xyz] and [read_ply].
+pub fn load_point_cloud(path: impl AsRef<Path>) -> Result<Vec<CloudPoint>, PointCloudError> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("xyz" | "txt") => read_xyz(BufReader::new(File::open(path)?)),
+        Some("ply") => read_ply(BufReader::new(File::open(path)?)),
+        _ => Err(PointCloudError::UnknownFormat),
+    }
+}
+
+/// One sphere with the radius from `style` for each point. `material` builds the material for the color of a point,
+/// eg. [material_diffuse](crate::demos::material_diffuse) to light the points with the scene
+/// or [material_fixed](crate::demos::material_fixed) to show the colors as they were scanned.
+pub fn point_cloud_to_spheres<'a>(
+    points: &'a [CloudPoint],
+    style: &'a PointCloudStyle,
+    material: impl Fn(Color) -> Material + 'a,
+) -> impl Iterator<Item=Object> + 'a {
+    points.iter().map(move |point| Object::new(
+        Shape::Sphere,
+        material(point.color.unwrap_or(style.default_color)),
+        Transform::translate(point.position.coords()) * Transform::scale(style.radius),
+    ))
+}
+
+/// Read a point list with one point per line, `x y z` optionally followed by the color `r g b`.
+/// The values can be separated by whitespace or commas, empty lines and lines starting with `#` or `//` are skipped.
+///
+/// Colors are between 0 and 1, unless any component in the file is larger than 1, then they're all taken to be between 0 and 255.
+pub fn read_xyz(reader: impl BufRead) -> Result<Vec<CloudPoint>, PointCloudError> {
+    let mut points = vec![];
+    let mut max_component: f32 = 0.0;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+
+        let values = line.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .or_else(|e| invalid(format!("line {}: {}", i + 1, e)))?;
+
+        let position = match values[..] {
+            [x, y, z, ..] => Point3::new(x, y, z),
+            _ => return invalid(format!("line {}: expected at least 3 coordinates, got {}", i + 1, values.len())),
+        };
+        let color = match values[3..] {
+            [] => None,
+            [r, g, b, ..] => {
+                max_component = max_component.max(r).max(g).max(b);
+                Some(Color::new(r, g, b))
+            }
+            _ => return invalid(format!("line {}: expected no color or 3 color components", i + 1)),
+        };
+
+        points.push(CloudPoint { position, color });
+    }
+
+    if max_component > 1.0 {
+        for color in points.iter_mut().filter_map(|p| p.color.as_mut()) {
+            *color /= 255.0;
+        }
+    }
+
+    Ok(points)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PlyScalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PlyProperty {
+    Scalar(PlyScalar),
+    List { count: PlyScalar, item: PlyScalar },
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<(String, PlyProperty)>,
+}
+
+impl PlyScalar {
+    fn parse(name: &str) -> Result<Self, PointCloudError> {
+        match name {
+            "char" | "int8" => Ok(PlyScalar::I8),
+            "uchar" | "uint8" => Ok(PlyScalar::U8),
+            "short" | "int16" => Ok(PlyScalar::I16),
+            "ushort" | "uint16" => Ok(PlyScalar::U16),
+            "int" | "int32" => Ok(PlyScalar::I32),
+            "uint" | "uint32" => Ok(PlyScalar::U32),
+            "float" | "float32" => Ok(PlyScalar::F32),
+            "double" | "float64" => Ok(PlyScalar::F64),
+            _ => invalid(format!("unknown property type {:?}", name)),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyScalar::I8 | PlyScalar::U8 => 1,
+            PlyScalar::I16 | PlyScalar::U16 => 2,
+            PlyScalar::I32 | PlyScalar::U32 | PlyScalar::F32 => 4,
+            PlyScalar::F64 => 8,
+        }
+    }
+
+    /// The factor that maps the full range of this type to `[0, 1]`, used for colors.
+    fn color_scale(self) -> f64 {
+        match self {
+            PlyScalar::U8 => 1.0 / u8::MAX as f64,
+            PlyScalar::U16 => 1.0 / u16::MAX as f64,
+            _ => 1.0,
+        }
+    }
+
+    fn read_binary(self, reader: &mut impl Read, format: PlyFormat) -> Result<f64, PointCloudError> {
+        let mut bytes = [0; 8];
+        let bytes = &mut bytes[..self.size()];
+        reader.read_exact(bytes)?;
+        if format == PlyFormat::BinaryBigEndian {
+            bytes.reverse();
+        }
+
+        let value = match self {
+            PlyScalar::I8 => bytes[0] as i8 as f64,
+            PlyScalar::U8 => bytes[0] as f64,
+            PlyScalar::I16 => i16::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            PlyScalar::U16 => u16::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            PlyScalar::I32 => i32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            PlyScalar::U32 => u32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            PlyScalar::F32 => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            PlyScalar::F64 => f64::from_le_bytes(bytes.try_into().unwrap()),
+        };
+        Ok(value)
+    }
+}
+
+/// Read the `vertex` element of a PLY file, in any of the ascii or binary formats.
+/// The positions are the `x`, `y` and `z` properties and the colors the optional `red`, `green` and `blue` ones.
+/// Integer colors are scaled from their full range to `[0, 1]`. Other properties and elements are ignored.
+pub fn read_ply(mut reader: impl BufRead) -> Result<Vec<CloudPoint>, PointCloudError> {
+    let (format, elements) = read_ply_header(&mut reader)?;
+
+    let mut lines = vec![];
+    if format == PlyFormat::Ascii {
+        for line in (&mut reader).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                lines.push(line);
+            }
+        }
+    }
+    let mut lines = lines.iter();
+
+    for element in elements {
+        let is_vertex = element.name == "vertex";
+        let property_index = |name: &str| element.properties.iter().position(|(n, _)| n == name);
+        let position_indices = match (property_index("x"), property_index("y"), property_index("z")) {
+            (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+            _ => None,
+        };
+        let color_indices = match (property_index("red"), property_index("green"), property_index("blue")) {
+            (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+            _ => None,
+        };
+        if is_vertex && position_indices.is_none() {
+            return invalid("the vertex element needs x, y and z properties");
+        }
+
+        let mut points = vec![];
+        let mut values = vec![0.0; element.properties.len()];
+
+        for i in 0..element.count {
+            // read the values of all properties, lists are skipped
+            match format {
+                PlyFormat::Ascii => {
+                    let line = match lines.next() {
+                        Some(line) => line,
+                        None => return invalid(format!("expected {} {} lines, got {}", element.count, element.name, i)),
+                    };
+                    let mut tokens = line.split_whitespace().map(|s| s.parse::<f64>());
+                    let mut next = || match tokens.next() {
+                        Some(Ok(value)) => Ok(value),
+                        Some(Err(e)) => invalid(format!("{} {}: {}", element.name, i, e)),
+                        None => invalid(format!("{} {}: not enough values", element.name, i)),
+                    };
+
+                    for (value, (_, property)) in values.iter_mut().zip(&element.properties) {
+                        match property {
+                            PlyProperty::Scalar(_) => *value = next()?,
+                            PlyProperty::List { .. } => {
+                                for _ in 0..next()? as usize {
+                                    next()?;
+                                }
+                            }
+                        }
+                    }
+                }
+                PlyFormat::BinaryLittleEndian | PlyFormat::BinaryBigEndian => {
+                    for (value, (_, property)) in values.iter_mut().zip(&element.properties) {
+                        match *property {
+                            PlyProperty::Scalar(scalar) => *value = scalar.read_binary(&mut reader, format)?,
+                            PlyProperty::List { count, item } => {
+                                let count = count.read_binary(&mut reader, format)? as u64;
+                                std::io::copy(&mut (&mut reader).take(count * item.size() as u64), &mut std::io::sink())?;
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let (true, Some([x, y, z])) = (is_vertex, position_indices) {
+                let position = Point3::new(values[x] as f32, values[y] as f32, values[z] as f32);
+                let color = color_indices.map(|[r, g, b]| {
+                    let channel = |index: usize| match element.properties[index].1 {
+                        PlyProperty::Scalar(scalar) => (values[index] * scalar.color_scale()) as f32,
+                        PlyProperty::List { .. } => 0.0,
+                    };
+                    Color::new(channel(r), channel(g), channel(b))
+                });
+                points.push(CloudPoint { position, color });
+            }
+        }
+
+        if is_vertex {
+            return Ok(points);
+        }
+    }
+
+    invalid("no vertex element")
+}
+
+fn read_ply_header(reader: &mut impl BufRead) -> Result<(PlyFormat, Vec<PlyElement>), PointCloudError> {
+    let mut next_line = || -> Result<String, PointCloudError> {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return invalid("unexpected end of the header");
+        }
+        Ok(line.trim().to_owned())
+    };
+
+    if next_line()? != "ply" {
+        return invalid("missing ply magic number");
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+
+    loop {
+        let line = next_line()?;
+        let words = line.split_whitespace().collect::<Vec<_>>();
+
+        match words[..] {
+            ["end_header"] => break,
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            ["format", name, _version] => {
+                format = Some(match name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return invalid(format!("unknown format {:?}", name)),
+                });
+            }
+            ["element", name, count] => {
+                let count = count.parse().or_else(|_| invalid(format!("invalid element count {:?}", count)))?;
+                elements.push(PlyElement { name: name.to_owned(), count, properties: vec![] });
+            }
+            ["property", "list", count, item, name] => {
+                let property = PlyProperty::List { count: PlyScalar::parse(count)?, item: PlyScalar::parse(item)? };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push((name.to_owned(), property)),
+                    None => return invalid("property before the first element"),
+                }
+            }
+            ["property", scalar, name] => {
+                let property = PlyProperty::Scalar(PlyScalar::parse(scalar)?);
+                match elements.last_mut() {
+                    Some(element) => element.properties.push((name.to_owned(), property)),
+                    None => return invalid("property before the first element"),
+                }
+            }
+            _ => return invalid(format!("unexpected header line {:?}", line)),
+        }
+    }
+
+    match format {
+        Some(format) => Ok((format, elements)),
+        None => invalid("missing format line"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::math::Point3;
+    use crate::common::point_cloud::{CloudPoint, point_cloud_to_spheres, PointCloudStyle, read_ply, read_xyz};
+    use crate::common::scene::{Color, MaterialType, Shape};
+    use crate::demos::material_fixed;
+
+    #[test]
+    fn xyz_with_and_without_colors() {
+        let text = "# scan\n1 2 3\n\n4,5,6, 255,0,51\n";
+        let points = read_xyz(text.as_bytes()).unwrap();
+
+        assert_eq!(points, vec![
+            CloudPoint { position: Point3::new(1.0, 2.0, 3.0), color: None },
+            CloudPoint { position: Point3::new(4.0, 5.0, 6.0), color: Some(Color::new(1.0, 0.0, 0.2)) },
+        ]);
+
+        assert!(read_xyz("1 2".as_bytes()).is_err());
+        assert!(read_xyz("1 2 x".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn ply_ascii_and_binary() {
+        let header = |format: &str| format!(
+            "ply\nformat {} 1.0\ncomment test\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n",
+            format
+        );
+        let expected = vec![
+            CloudPoint { position: Point3::new(1.0, 2.0, 3.0), color: Some(Color::new(1.0, 0.0, 0.0)) },
+            CloudPoint { position: Point3::new(-1.0, 0.5, 0.0), color: Some(Color::new(0.0, 0.0, 1.0)) },
+        ];
+
+        let ascii = header("ascii") + "1 2 3 255 0 0\n-1 0.5 0 0 0 255\n3 0 1 1\n";
+        assert_eq!(read_ply(ascii.as_bytes()).unwrap(), expected);
+
+        let mut binary = header("binary_little_endian").into_bytes();
+        for (position, color) in [([1.0f32, 2.0, 3.0], [255u8, 0, 0]), ([-1.0, 0.5, 0.0], [0, 0, 255])] {
+            binary.extend(position.iter().flat_map(|x| x.to_le_bytes()));
+            binary.extend(color);
+        }
+        assert_eq!(read_ply(&binary[..]).unwrap(), expected);
+
+        assert!(read_ply("ply\nformat ascii 1.0\nend_header\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn spheres_for_points() {
+        let points = [
+            CloudPoint { position: Point3::new(1.0, 2.0, 3.0), color: None },
+            CloudPoint { position: Point3::new(0.0, 0.0, 0.0), color: Some(Color::new(1.0, 0.0, 0.0)) },
+        ];
+        let style = PointCloudStyle { radius: 0.5, default_color: Color::new(0.0, 1.0, 0.0) };
+        let objects = point_cloud_to_spheres(&points, &style, material_fixed).collect::<Vec<_>>();
+
+        assert_eq!(objects.len(), 2);
+        assert!(objects.iter().all(|o| o.shape == Shape::Sphere && o.material.material_type == MaterialType::Fixed));
+        assert_eq!(objects[0].transform * Point3::new(1.0, 0.0, 0.0), Point3::new(1.5, 2.0, 3.0));
+        assert_eq!(objects[0].material.albedo, Color::new(0.0, 1.0, 0.0));
+        assert_eq!(objects[1].material.albedo, Color::new(1.0, 0.0, 0.0));
+    }
+}
@@ -1,3 +1,4 @@
+use std::iter::FusedIterator;
 use std::ops::Range;
 use std::time::{Duration, Instant};
 use crate::common::scene::Color;
@@ -16,12 +17,13 @@ pub struct PixelResult {
     pub variance: Color,
     pub rel_variance: Color,
     pub samples: u32,
+    /// The fraction of the samples that hit an object instead of the sky, or the shadow on a
+    /// [ShadowCatcher](crate::common::scene::MaterialType::ShadowCatcher). Pixels that only see the sky have alpha zero.
+    /// `color` still includes the sky, unless it's made transparent with
+    /// [CpuRenderSettings::transparent_sky](crate::cpu::CpuRenderSettings::transparent_sky).
+    pub alpha: f32,
 }
 
-//TODO write a proper iterator for the coords in Block instead
-//  * iterate over y slower than x!
-//  * be careful about empty x/y ranges!
-//  * write a custom fold and size_hint ugh this is getting annoying?
 impl Block {
     pub fn x_range(self) -> Range<u32> {
         self.x..(self.x + self.width)
@@ -30,20 +32,115 @@ impl Block {
     pub fn y_range(self) -> Range<u32> {
         self.y..(self.y + self.height)
     }
+
+    pub fn pixel_count(self) -> u32 {
+        self.width * self.height
+    }
+
+    /// Iterate over the `(x, y)` coordinates of the pixels in this block, row by row with `x` changing fastest.
+    /// This is also the order of the pixels passed to [ProgressHandler::update].
+    pub fn iter(self) -> BlockIter {
+        BlockIter { block: self, index: 0 }
+    }
+}
+
+/// Iterator over the pixel coordinates of a [Block], see [Block::iter].
+#[derive(Debug, Clone)]
+pub struct BlockIter {
+    block: Block,
+    index: u32,
+}
+
+impl Iterator for BlockIter {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.block.pixel_count() {
+            return None;
+        }
+
+        let dx = self.index % self.block.width;
+        let dy = self.index / self.block.width;
+        self.index += 1;
+        Some((self.block.x + dx, self.block.y + dy))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.block.pixel_count().saturating_sub(self.index) as usize;
+        (len, Some(len))
+    }
+
+    fn fold<B, F: FnMut(B, Self::Item) -> B>(mut self, init: B, mut f: F) -> B {
+        // nested loops instead of a division per pixel
+        let mut acc = init;
+        while let Some(first) = self.next() {
+            // finish the current row
+            acc = f(acc, first);
+            for x in (first.0 + 1)..self.block.x_range().end {
+                acc = f(acc, (x, first.1));
+            }
+            self.index = (first.1 - self.block.y + 1) * self.block.width;
+        }
+        acc
+    }
+}
+
+impl ExactSizeIterator for BlockIter {}
+
+impl FusedIterator for BlockIter {}
+
+/// Summary of a finished pass when rendering progressively, see `StopCondition::Progressive`.
+#[derive(Debug, Copy, Clone)]
+pub struct PassInfo {
+    /// The total number of samples per pixel after this pass.
+    pub samples: u32,
+    /// The root mean square change of the pixel colors compared to the previous pass, `None` for the first pass.
+    pub rms_change: Option<f32>,
+}
+
+/// Summary of a finished render, see [ProgressHandler::finished].
+#[derive(Debug, Copy, Clone)]
+pub struct RenderStats {
+    /// The number of invalid hits that were skipped, see `stats::count_rejected_hits`.
+    /// Anything but zero means the scene probably contains malformed geometry.
+    pub rejected_hits: u64,
+}
+
+/// A step of preparing the scene before the pixels are rendered, see [ProgressHandler::prepare].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PrepareStage {
+    /// Building the acceleration structure.
+    BuildAccel,
+    /// Collecting the lights and building the sampling structures.
+    PrepareScene,
+    /// Tracing light paths before rendering, only for `Strategy::LightTracing`.
+    TraceLightPaths,
 }
 
 pub trait ProgressHandler: Send {
     type State: Send + 'static;
-    fn init(self, width: u32, height: u32) -> Self::State;
-    fn update(state: &mut Self::State, block: Block, pixels: &Vec<PixelResult>);
+
+    /// Called while preparing the scene, before [init](ProgressHandler::init), with the fraction of `stage` that's done.
+    /// Each stage is reported at least once with `0.0` when it starts and once with `1.0` when it's done.
+    fn prepare(&mut self, _: PrepareStage, _: f32) {}
+
+    /// Called before rendering an image of `width` by `height` pixels, of which only the pixels in `region` will be rendered.
+    fn init(self, width: u32, height: u32, region: Block) -> Self::State;
+    fn update(state: &mut Self::State, block: Block, pixels: &[PixelResult]);
+
+    /// Called after each pass when rendering progressively, after the [update](ProgressHandler::update)s for that pass.
+    fn pass_finished(_: &mut Self::State, _: PassInfo) {}
+
+    /// Called once after all pixels are rendered.
+    fn finished(_: &mut Self::State, _: RenderStats) {}
 }
 
 pub struct NoProgress;
 
 impl ProgressHandler for NoProgress {
     type State = ();
-    fn init(self, _: u32, _: u32) {}
-    fn update(_: &mut Self::State, _: Block, _: &Vec<PixelResult>) {}
+    fn init(self, _: u32, _: u32, _: Block) {}
+    fn update(_: &mut Self::State, _: Block, _: &[PixelResult]) {}
 }
 
 pub struct PrintProgress;
@@ -58,19 +155,19 @@ pub struct PrintProgressState {
 impl ProgressHandler for PrintProgress {
     type State = PrintProgressState;
 
-    fn init(self, width: u32, height: u32) -> Self::State {
+    fn init(self, _: u32, _: u32, region: Block) -> Self::State {
         println!("Progress {:.03}", 0.0);
 
         PrintProgressState {
-            total_pixels: (width as u64) * (height as u64),
+            total_pixels: region.pixel_count() as u64,
             finished_pixels: 0,
             prev_printed: f32::NEG_INFINITY,
             prev_time: Instant::now(),
         }
     }
 
-    fn update(state: &mut Self::State, block: Block, _: &Vec<PixelResult>) {
-        state.finished_pixels += (block.width as u64) * (block.height as u64);
+    fn update(state: &mut Self::State, block: Block, _: &[PixelResult]) {
+        state.finished_pixels += block.pixel_count() as u64;
         let progress = (state.finished_pixels as f32) / (state.total_pixels as f32);
         let delta = progress - state.prev_printed;
 
@@ -85,6 +182,24 @@ impl ProgressHandler for PrintProgress {
             state.prev_time = now;
         }
     }
+
+    fn pass_finished(state: &mut Self::State, pass: PassInfo) {
+        match pass.rms_change {
+            Some(rms_change) => println!("Finished pass with {} samples, rms change {:.06}", pass.samples, rms_change),
+            None => println!("Finished pass with {} samples", pass.samples),
+        }
+
+        // the next pass starts over
+        state.finished_pixels = 0;
+        state.prev_printed = f32::NEG_INFINITY;
+        state.prev_time = Instant::now();
+    }
+
+    fn finished(_: &mut Self::State, stats: RenderStats) {
+        if stats.rejected_hits > 0 {
+            println!("Skipped {} invalid hits, the scene probably contains malformed geometry", stats.rejected_hits);
+        }
+    }
 }
 
 pub struct CombinedProgress<L: ProgressHandler, R: ProgressHandler> {
@@ -101,12 +216,58 @@ impl<L: ProgressHandler, R: ProgressHandler> CombinedProgress<L, R> {
 impl<L: ProgressHandler, R: ProgressHandler> ProgressHandler for CombinedProgress<L, R> {
     type State = (L::State, R::State);
 
-    fn init(self, width: u32, height: u32) -> Self::State {
-        (L::init(self.left, width, height), R::init(self.right, width, height))
+    fn prepare(&mut self, stage: PrepareStage, progress: f32) {
+        self.left.prepare(stage, progress);
+        self.right.prepare(stage, progress);
+    }
+
+    fn init(self, width: u32, height: u32, region: Block) -> Self::State {
+        (L::init(self.left, width, height, region), R::init(self.right, width, height, region))
     }
 
-    fn update(state: &mut Self::State, block: Block, pixels: &Vec<PixelResult>) {
+    fn update(state: &mut Self::State, block: Block, pixels: &[PixelResult]) {
         L::update(&mut state.0, block, pixels);
         R::update(&mut state.1, block, pixels);
     }
+
+    fn pass_finished(state: &mut Self::State, pass: PassInfo) {
+        L::pass_finished(&mut state.0, pass);
+        R::pass_finished(&mut state.1, pass);
+    }
+
+    fn finished(state: &mut Self::State, stats: RenderStats) {
+        L::finished(&mut state.0, stats);
+        R::finished(&mut state.1, stats);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::progress::Block;
+
+    #[test]
+    fn block_iter_order() {
+        let block = Block { x: 3, y: 5, width: 3, height: 2 };
+        let expected = vec![(3, 5), (4, 5), (5, 5), (3, 6), (4, 6), (5, 6)];
+
+        assert_eq!(block.iter().len(), 6);
+        assert_eq!(block.iter().collect::<Vec<_>>(), expected);
+
+        // fold (used by for_each) must match next, also when starting halfway through a row
+        let mut folded = vec![];
+        block.iter().for_each(|p| folded.push(p));
+        assert_eq!(folded, expected);
+
+        let mut iter = block.iter();
+        iter.next();
+        assert_eq!(iter.len(), 5);
+        assert_eq!(iter.fold(vec![], |mut v, p| { v.push(p); v }), expected[1..]);
+    }
+
+    #[test]
+    fn block_iter_empty() {
+        assert_eq!(Block { x: 2, y: 2, width: 0, height: 4 }.iter().count(), 0);
+        assert_eq!(Block { x: 2, y: 2, width: 4, height: 0 }.iter().count(), 0);
+        assert_eq!(Block { x: 2, y: 2, width: 0, height: 0 }.iter().fold(0, |a, _| a + 1), 0);
+    }
 }
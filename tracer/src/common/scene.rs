@@ -1,48 +1,229 @@
-use crate::common::math::{Transform, Angle};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::common::daylight::{in_sun_disk, sky_radiance, sun_radiance};
+use crate::common::math::{Angle, Point2, Point3, Transform, Unit, Vec3};
+use crate::common::spectrum::WAVELENGTH_REFERENCE;
+use crate::common::texture::{equirect_uv, ImageTexture, Texture};
 
 pub type Color = palette::LinSrgb;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub const BLACK: Color = Color { red: 0.0, green: 0.0, blue: 0.0, standard: PhantomData };
+pub const WHITE: Color = Color { red: 1.0, green: 1.0, blue: 1.0, standard: PhantomData };
+
+pub const VACUUM: Medium = Medium { index_of_refraction: 1.0, volumetric_color: WHITE, dispersion: 0.0, emission: BLACK };
+
+/// Linearly interpolate each channel from `a` at `t = 0` to `b` at `t = 1`, see [lerp](crate::common::math::lerp).
+pub fn lerp_color(a: Color, b: Color, t: f32) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+/// Build a color from `[red, green, blue]`. Palette already converts colors from and into `(red, green, blue)` tuples,
+/// but the orphan rules don't allow implementing `From<[f32; 3]>` for it here.
+pub fn color_from_array([red, green, blue]: [f32; 3]) -> Color {
+    Color::new(red, green, blue)
+}
+
+/// The channels of `color` as `[red, green, blue]`, the inverse of [color_from_array].
+pub fn color_to_array(color: Color) -> [f32; 3] {
+    [color.red, color.green, color.blue]
+}
+
+#[derive(Debug, Clone)]
 pub enum Shape {
-    /// Unit sphere with center at origin
+    /// Unit sphere with center at origin,
+    /// uv is longitude and latitude with the poles on the y-axis
     Sphere,
-    /// Plane spanning the xy axes
+    /// Plane spanning the xy axes, uv is `(x, y)`
     // TODO not enough info, which point do rotations happen around?
     Plane,
-    /// Triangle with corners at `(0,0,0), (1,0,0), (0,1,0)`
+    /// Triangle with corners at `(0,0,0), (1,0,0), (0,1,0)`, uv is `(x, y)`
     Triangle,
-    /// Square with corners at `(0,0,0), (1,0,0), (1,1,0), (0,1,0)`
+    /// Square with corners at `(0,0,0), (1,0,0), (1,1,0), (0,1,0)`, uv is `(x, y)`
     Square,
-    /// Cylinder with radius 1 around the y-axis
+    /// Cylinder with radius 1 around the y-axis, uv is `(angle / 2pi, y)`
     Cylinder,
+    /// Torus with its center at the origin, a major radius of 1 around the y-axis and the given tube radius,
+    /// uv is `(angle around the y-axis / 2pi, angle around the tube / 2pi)`
+    Torus { tube_radius: f32 },
+    /// Cone with its apex at the origin opening up along the y-axis, with height 1 and a base radius of 1.
+    /// If `capped` the base is closed by a disk.
+    /// uv is `(angle / 2pi, y)` on the side and `(x, z)` mapped to `[0, 1]` on the cap.
+    Cone { capped: bool },
+    /// Implicit surface where the signed distance function is zero, found by sphere tracing.
+    /// uv is longitude and latitude of the direction from the origin, like for [Shape::Sphere].
+    Sdf(Sdf),
+}
+
+/// Shapes are compared exactly, the tube radius of a torus by its bits so even NaN equals itself.
+/// This makes the comparison reflexive, so [Shape] can be [Eq].
+impl PartialEq for Shape {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Shape::Torus { tube_radius: left }, Shape::Torus { tube_radius: right }) => left.to_bits() == right.to_bits(),
+            (Shape::Cone { capped: left }, Shape::Cone { capped: right }) => left == right,
+            (Shape::Sdf(left), Shape::Sdf(right)) => left == right,
+            // the variants with data are handled above
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for Shape {}
+
+/// A signed distance function for [Shape::Sdf], positive outside and negative inside the surface.
+///
+/// `distance` must never overestimate the distance to the surface, otherwise rays can step over thin parts of it.
+/// Underestimating is fine but slower, so distance estimators like the one for fractals can be used as well.
+#[derive(Clone)]
+pub struct Sdf {
+    pub distance: Arc<dyn Fn(Point3) -> f32 + Send + Sync>,
+    /// The radius of a sphere around the origin that contains the whole surface, rays are only traced inside of it.
+    pub bound: f32,
+}
+
+impl Sdf {
+    pub fn new(bound: f32, distance: impl Fn(Point3) -> f32 + Send + Sync + 'static) -> Sdf {
+        Sdf { distance: Arc::new(distance), bound }
+    }
+}
+
+impl Debug for Sdf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sdf(bound: {})", self.bound)
+    }
 }
 
+/// Functions can't be compared, two sdfs are only equal if they share the same function.
+/// The bounds are compared by their bits, like the tube radius of [Shape::Torus].
+impl PartialEq for Sdf {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.distance, &other.distance) && self.bound.to_bits() == other.bound.to_bits()
+    }
+}
+
+impl Eq for Sdf {}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum MaterialType {
-    Fixed { camera_only: bool },
+    /// A fixed color that ends the path, eg. for markers and debug geometry.
+    /// Combine with [Visibility::CAMERA_ONLY] to keep it from affecting the lighting.
+    Fixed,
     Diffuse,
     Mirror,
     Transparent,
+    /// An invisible surface that only shows the shadows cast onto it, for compositing a render over a photograph.
+    /// Camera rays hitting it get a black color with an alpha of how much of the light is blocked,
+    /// see [PixelResult::alpha](crate::common::progress::PixelResult::alpha). All other rays pass straight through it.
+    ShadowCatcher,
     // f is the fraction of light that's diffuse, 0 <= f <= 1
     //TODO maybe just remove Diffuse and Mirror and make a single Opque material? or even just have a single material
     DiffuseMirror(f32),
+    /// A diffuse surface with a white Blinn-Phong highlight on top, like plastic.
+    /// Higher `shininess` exponents give smaller and brighter highlights, from about 1 for a faint sheen to 10000 for almost a mirror.
+    Phong { shininess: f32 },
+    /// A rough diffuse surface following the Oren-Nayar model, for materials like clay, concrete and the moon.
+    /// Compared to [MaterialType::Diffuse] it reflects more light back towards the light source and looks flatter under grazing light.
+    /// `roughness` is the standard deviation of the slopes of the microfacets in radians, zero is the same as [MaterialType::Diffuse].
+    OrenNayar { roughness: f32 },
+    /// A polished metal that reflects like [MaterialType::Mirror], with the color `reflectance` when seen head-on.
+    /// Towards grazing angles the reflection brightens to white, following Schlick's approximation of the Fresnel equations.
+    /// The albedo still tints the reflection on top, keep it white for realistic metals like the [presets](crate::demos::METAL_GOLD).
+    Conductor { reflectance: Color },
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Material {
     pub material_type: MaterialType,
 
     pub emission: Color,
+    /// The factor `emission` is multiplied with, so the color and the intensity of a light can be set separately.
+    /// HDR values far above one are typical for small lights, negative values subtract light.
+    pub emission_strength: f32,
+    /// Optional texture the emission is multiplied with, eg. for screens or patterned lights.
+    pub emission_texture: Option<Texture>,
     pub albedo: Color,
+    /// Optional texture the albedo is multiplied with, eg. for checkered floors.
+    pub albedo_texture: Option<Texture>,
+    /// Optional cutout mask, rays pass straight through the surface where the alpha is below one half.
+    pub alpha: Option<Texture>,
+    /// Optional height field that perturbs the shading normal, see [BumpMap].
+    pub bump: Option<BumpMap>,
+    /// Optional tangent space normal map that tilts the shading normal, applied after the bump map, see [NormalMap].
+    pub normal_map: Option<NormalMap>,
+    /// Whether rays hitting the back side of the surface, the side the normal points away from, ignore it.
+    /// Useful for closed meshes built from single triangles.
+    pub cull_backface: bool,
 
     pub inside: Medium,
     pub outside: Medium,
 }
 
+/// A height field over the uv coordinates of a surface that only changes its shading normal,
+/// giving the appearance of small bumps and grooves without changing the geometry.
+///
+/// The uv coordinates must vary smoothly over the surface, the bumps are stretched along with them.
+#[derive(Clone, Debug)]
+pub struct BumpMap {
+    /// The height at each point, as the luminance of the texture.
+    pub height: Texture,
+    /// The world space displacement along the normal for a height of `1`.
+    pub scale: f32,
+}
+
+/// A texture of normals in the tangent space of a surface, like a [BumpMap] only changing the shading normal.
+///
+/// Each texel stores a unit normal `(x, y, z)` as the color `(x, y, z) / 2 + 0.5`, where `x` points towards increasing u,
+/// `y` towards increasing v and `z` along the normal of the surface. A flat normal map has the color `(0.5, 0.5, 1.0)`.
+/// Normal map images store their values directly, load them with [ColorSpace::Linear](crate::common::texture::ColorSpace::Linear).
+#[derive(Clone, Debug)]
+pub struct NormalMap {
+    pub texture: Texture,
+    /// The factor the tangential part of the normals is scaled with, `0` is flat and `1` uses the normals as stored.
+    pub strength: f32,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Medium {
     pub index_of_refraction: f32,
+    /// The fraction of light per channel that's left after travelling a distance of 1 through this medium.
+    /// Following the Beer-Lambert law light travelling a distance `t` is attenuated by `volumetric_color^t`.
     pub volumetric_color: Color,
+    /// The `B` coefficient of Cauchy's equation in µm², how much the index of refraction increases for shorter wavelengths.
+    /// Only used when rendering spectrally, see [Medium::index_of_refraction_at].
+    pub dispersion: f32,
+    /// The radiance emitted per unit of distance travelled through this medium, which is then attenuated like any other light.
+    /// Black for mediums that don't glow.
+    pub emission: Color,
+}
+
+impl Material {
+    /// The emitted radiance at the point with texture coordinates `uv`.
+    pub fn emission_at(&self, uv: Point2) -> Color {
+        match &self.emission_texture {
+            None => self.emission * self.emission_strength,
+            Some(texture) => self.emission * self.emission_strength * texture.sample(uv),
+        }
+    }
+
+    /// The albedo at the point with texture coordinates `uv`.
+    pub fn albedo_at(&self, uv: Point2) -> Color {
+        match &self.albedo_texture {
+            None => self.albedo,
+            Some(texture) => self.albedo * texture.sample(uv),
+        }
+    }
+}
+
+impl Medium {
+    /// The index of refraction at `wavelength` in nanometers, following
+    /// [Cauchy's equation](https://en.wikipedia.org/wiki/Cauchy%27s_equation) with `index_of_refraction`
+    /// the value at [WAVELENGTH_REFERENCE].
+    pub fn index_of_refraction_at(&self, wavelength: f32) -> f32 {
+        let inv_squared = |wavelength: f32| 1.0 / (wavelength / 1000.0).powi(2);
+        self.index_of_refraction + self.dispersion * (inv_squared(wavelength) - inv_squared(WAVELENGTH_REFERENCE))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +239,67 @@ pub struct Object {
        compared to transform which has 2 * 4 * 4 = 32 floats!
      */
     pub transform: Transform,
+
+    /// An optional name for debugging, for example to find the objects that make up a mesh
+    /// or to render only some objects by filtering [Scene::objects]. Objects can share a name to form a group.
+    pub name: Option<String>,
+    /// Which kinds of rays can hit this object, the others pass straight through it.
+    pub visibility: Visibility,
+}
+
+impl Object {
+    /// An unnamed object that is visible to all rays.
+    pub fn new(shape: Shape, material: Material, transform: Transform) -> Self {
+        Object { shape, material, transform, name: None, visibility: Visibility::ALL }
+    }
+
+    pub fn with_name(self, name: impl Into<String>) -> Self {
+        Object { name: Some(name.into()), ..self }
+    }
+
+    pub fn with_visibility(self, visibility: Visibility) -> Self {
+        Object { visibility, ..self }
+    }
+}
+
+/// Which kinds of rays can hit an object, see [RayKind].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Visibility {
+    pub camera: bool,
+    pub shadow: bool,
+    pub indirect: bool,
+}
+
+/// The kinds of rays traced while rendering, for deciding which objects they can hit with [Visibility].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RayKind {
+    /// Rays starting at the camera, and rays connecting a surface to the camera.
+    Camera,
+    /// Rays towards a sampled point on a light or the sky, an object hit by these casts shadows.
+    Shadow,
+    /// Rays continuing a path after reflecting or refracting, an object hit by these shows up in mirrors
+    /// and behind glass and bounces light onto other objects.
+    Indirect,
+}
+
+impl Visibility {
+    pub const ALL: Visibility = Visibility { camera: true, shadow: true, indirect: true };
+
+    /// Only visible when looking at the object directly, it doesn't affect the lighting of the rest of the scene.
+    /// A ground with this visibility is a shadow catcher: it shows the shadows cast onto it,
+    /// but doesn't bounce light onto the objects above it or block the sky below them.
+    pub const CAMERA_ONLY: Visibility = Visibility { camera: true, shadow: false, indirect: false };
+
+    /// Invisible itself but still casting shadows and bouncing light, eg. for a light blocker outside the frame.
+    pub const HIDDEN: Visibility = Visibility { camera: false, shadow: true, indirect: true };
+
+    pub fn is_visible_to(self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Indirect => self.indirect,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -66,12 +308,218 @@ pub struct Camera {
     pub fov_horizontal: Angle,
     pub transform: Transform,
 
+    /// The medium around the camera. If the camera is inside of transparent objects their media are used instead,
+    /// this is then the medium outside of all objects, eg. water for an underwater scene without a water surface.
     pub medium: Medium,
 }
 
+/// The light arriving from infinitely far away for rays that don't hit any object.
+#[derive(Debug, Clone)]
+pub enum Sky {
+    /// The same color in every direction.
+    Uniform(Color),
+    /// An equirectangular environment map, see [equirect_uv] for the mapping.
+    Environment(Arc<ImageTexture>),
+    /// A vertical gradient, interpolated linearly with the y component of the direction from `horizon` to `zenith`.
+    /// Directions below the horizon get the `horizon` color.
+    Gradient { horizon: Color, zenith: Color },
+    /// A plain `background` color seen by rays coming straight from the camera, while everything else sees `lighting`:
+    /// reflections, refractions and the light arriving at surfaces. The usual setup for product shots in front of a studio backdrop.
+    Backdrop { background: Color, lighting: Box<Sky> },
+    /// A physically based daytime sky for outdoor scenes, see [sky_radiance](crate::common::daylight::sky_radiance),
+    /// with a sun disk in `sun_direction` that's bright enough to light the scene and cast hard shadows.
+    /// `turbidity` is the haziness of the air, from about 2 for a very clear sky to 10 for a hazy one.
+    Analytic { sun_direction: Unit<Vec3>, turbidity: f32 },
+}
+
 #[derive(Debug)]
 pub struct Scene {
     pub objects: Vec<Object>,
-    pub sky_emission: Color,
+    pub sky: Sky,
     pub camera: Camera,
 }
+
+/// Which of the two scenes passed to [Scene::merge] to take a setting from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MergeSide {
+    This,
+    Other,
+}
+
+impl Scene {
+    /// Combine two scenes, the objects of `other` are appended after the objects of `self`.
+    /// The camera and sky are taken from the scene selected by `camera` and `sky`, the others are dropped.
+    ///
+    /// All emissive objects of both scenes keep emitting light, so merging two lit scenes adds up their lighting.
+    /// The [ObjectId](crate::cpu::accel::ObjectId)s of the objects of `other` are shifted by the number of objects in `self`,
+    /// which matters when selecting lights explicitly with [LightSelection](crate::cpu::LightSelection).
+    pub fn merge(self, other: Scene, camera: MergeSide, sky: MergeSide) -> Scene {
+        let mut objects = self.objects;
+        objects.extend(other.objects);
+
+        Scene {
+            objects,
+            sky: match sky {
+                MergeSide::This => self.sky,
+                MergeSide::Other => other.sky,
+            },
+            camera: match camera {
+                MergeSide::This => self.camera,
+                MergeSide::Other => other.camera,
+            },
+        }
+    }
+
+    /// Apply `transform` on top of the transforms of all objects, eg. to place an imported scene before [Scene::merge]ing it
+    /// into a larger one. The camera is moved along if `move_camera`, otherwise it keeps looking at the same spot.
+    ///
+    /// The sky is at an infinite distance and is left alone, so rotations don't turn it with the objects.
+    pub fn transformed(self, transform: Transform, move_camera: bool) -> Scene {
+        let camera = if move_camera {
+            Camera { transform: transform * self.camera.transform, ..self.camera }
+        } else {
+            self.camera
+        };
+
+        Scene {
+            objects: objects_transformed(self.objects, transform),
+            sky: self.sky,
+            camera,
+        }
+    }
+}
+
+/// Apply `transform` on top of the transform of each object, which moves them as a single group.
+pub fn objects_transformed(objects: Vec<Object>, transform: Transform) -> Vec<Object> {
+    objects.into_iter()
+        .map(|object| Object { transform: transform * object.transform, ..object })
+        .collect()
+}
+
+impl Sky {
+    pub fn emission(&self, direction: Unit<Vec3>) -> Color {
+        match self {
+            &Sky::Uniform(color) => color,
+            Sky::Environment(image) => image.sample_equirect(equirect_uv(direction)),
+            &Sky::Gradient { horizon, zenith } => {
+                let t = direction.y.max(0.0);
+                lerp_color(horizon, zenith, t)
+            }
+            Sky::Backdrop { lighting, .. } => lighting.emission(direction),
+            &Sky::Analytic { sun_direction, turbidity } => {
+                let sky = sky_radiance(direction, sun_direction, turbidity);
+                if in_sun_disk(direction, sun_direction) {
+                    sky + sun_radiance(sun_direction, turbidity)
+                } else {
+                    sky
+                }
+            }
+        }
+    }
+
+    /// The light arriving along camera rays that don't hit anything, only different from [Sky::emission] for [Sky::Backdrop].
+    pub fn camera_emission(&self, direction: Unit<Vec3>) -> Color {
+        match self {
+            &Sky::Backdrop { background, .. } => background,
+            _ => self.emission(direction),
+        }
+    }
+
+    /// The sky that lights the scene, which is the sky itself except for the `lighting` of [Sky::Backdrop].
+    pub fn lighting(&self) -> &Sky {
+        match self {
+            Sky::Backdrop { lighting, .. } => lighting.lighting(),
+            _ => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::math::{Angle, Point3, Transform, Vec3};
+    use crate::common::scene::{Camera, Color, color_from_array, color_to_array, lerp_color, MergeSide, Object, Scene, Sdf, Shape, Sky};
+    use crate::demos::{BLACK, material_diffuse, material_light, VACUUM, WHITE};
+
+    #[test]
+    fn merge_scenes() {
+        let scene = |shape, fov, sky| Scene {
+            objects: vec![
+                Object::new(shape, material_diffuse(WHITE), Transform::default()),
+                Object::new(Shape::Sphere, material_light(WHITE, 1.0), Transform::default()),
+            ],
+            sky: Sky::Uniform(sky),
+            camera: Camera { fov_horizontal: Angle::degrees(fov), transform: Transform::default(), medium: VACUUM },
+        };
+
+        let merged = scene(Shape::Plane, 60.0, BLACK).merge(scene(Shape::Cylinder, 90.0, WHITE), MergeSide::Other, MergeSide::This);
+        let shapes = merged.objects.iter().map(|o| o.shape.clone()).collect::<Vec<_>>();
+        assert_eq!(shapes, vec![Shape::Plane, Shape::Sphere, Shape::Cylinder, Shape::Sphere]);
+        assert_eq!(merged.camera.fov_horizontal, Angle::degrees(90.0));
+        assert!(matches!(merged.sky, Sky::Uniform(sky) if sky == BLACK));
+    }
+
+    #[test]
+    fn transform_scene() {
+        let scene = || Scene {
+            objects: vec![
+                Object::new(Shape::Sphere, material_diffuse(WHITE), Transform::translate(Vec3::new(1.0, 0.0, 0.0))),
+                Object::new(Shape::Square, material_light(WHITE, 1.0), Transform::scale(2.0)),
+            ],
+            sky: Sky::Uniform(BLACK),
+            camera: Camera { fov_horizontal: Angle::degrees(60.0), transform: Transform::translate(Vec3::new(0.0, 0.0, 5.0)), medium: VACUUM },
+        };
+        let positions = |scene: &Scene| scene.objects.iter().map(|o| o.transform * Point3::origin()).collect::<Vec<_>>();
+        let camera_position = |scene: &Scene| scene.camera.transform * Point3::origin();
+
+        let identity = scene().transformed(Transform::default(), true);
+        let transforms = |scene: &Scene| scene.objects.iter().map(|o| o.transform).collect::<Vec<_>>();
+        assert_eq!(transforms(&identity), transforms(&scene()));
+        assert_eq!(identity.camera.transform, scene().camera.transform);
+
+        let offset = Vec3::new(1.0, 2.0, 3.0);
+        let moved = scene().transformed(Transform::translate(offset), true);
+        assert_eq!(positions(&moved), vec![Point3::new(2.0, 2.0, 3.0), Point3::new(1.0, 2.0, 3.0)]);
+        assert_eq!(camera_position(&moved), Point3::new(1.0, 2.0, 8.0));
+        // the scale of the square is kept
+        assert_eq!(moved.objects[1].transform * Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0));
+
+        let fixed_camera = scene().transformed(Transform::translate(offset), false);
+        assert_eq!(positions(&fixed_camera), positions(&moved));
+        assert_eq!(camera_position(&fixed_camera), Point3::new(0.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn lerp_color_endpoints() {
+        let (a, b) = (Color::new(1.0, 0.5, 0.0), Color::new(0.0, 0.5, 4.0));
+        assert_eq!(lerp_color(a, b, 0.0), a);
+        assert_eq!(lerp_color(a, b, 1.0), b);
+        assert_eq!(lerp_color(a, b, 0.25), Color::new(0.75, 0.5, 1.0));
+    }
+
+    #[test]
+    fn color_array_tuple_round_trip() {
+        let color = Color::new(0.25, 1.0, 4.0);
+        assert_eq!(color_from_array([0.25, 1.0, 4.0]), color);
+        assert_eq!(color_to_array(color), [0.25, 1.0, 4.0]);
+        assert_eq!(color_from_array(color_to_array(color)), color);
+
+        assert_eq!(Color::from((0.25, 1.0, 4.0)), color);
+        let tuple: (f32, f32, f32) = color.into();
+        assert_eq!(Color::from(tuple), color);
+    }
+
+    #[test]
+    fn shape_eq() {
+        assert_eq!(Shape::Sphere, Shape::Sphere);
+        assert_ne!(Shape::Sphere, Shape::Plane);
+        assert_ne!(Shape::Cone { capped: true }, Shape::Cone { capped: false });
+        assert_ne!(Shape::Torus { tube_radius: 0.25 }, Shape::Torus { tube_radius: 0.5 });
+        // Eq requires every shape to equal itself
+        let nan = Shape::Torus { tube_radius: f32::NAN };
+        assert_eq!(nan, nan.clone());
+
+        let sdf = Sdf::new(1.0, |p| p.y);
+        assert_eq!(Shape::Sdf(sdf.clone()), Shape::Sdf(sdf.clone()));
+        assert_ne!(Shape::Sdf(sdf), Shape::Sdf(Sdf::new(1.0, |p| p.y)));
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::OnceLock;
+
+use crate::common::scene::Color;
+
+/// The range of visible wavelengths in nanometers that spectral rendering samples from.
+pub const WAVELENGTH_MIN: f32 = 380.0;
+pub const WAVELENGTH_MAX: f32 = 780.0;
+
+/// The wavelength in nanometers at which [Medium::index_of_refraction](crate::common::scene::Medium::index_of_refraction)
+/// is defined, the sodium D line.
+pub const WAVELENGTH_REFERENCE: f32 = 589.3;
+
+/// Map a wavelength uniformly sampled from the visible range to the color it contributes.
+///
+/// The weights are normalized so they average to white over the visible range,
+/// so a path that doesn't depend on the wavelength gives the same expected color as when rendering in RGB.
+pub fn wavelength_weight(wavelength: f32) -> Color {
+    let average = average_rgb();
+    let [r, g, b] = wavelength_rgb(wavelength);
+    Color::new(r / average[0], g / average[1], b / average[2])
+}
+
+pub fn sample_wavelength(r: f32) -> f32 {
+    WAVELENGTH_MIN + r * (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+/// The (unnormalized) linear sRGB color of a single wavelength, this can be negative for saturated colors.
+fn wavelength_rgb(wavelength: f32) -> [f32; 3] {
+    xyz_to_rgb(cie_xyz(wavelength))
+}
+
+/// Convert CIE XYZ to linear sRGB, colors outside of the sRGB gamut get negative channels.
+pub fn xyz_to_rgb([x, y, z]: [f32; 3]) -> [f32; 3] {
+    [
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    ]
+}
+
+fn average_rgb() -> [f32; 3] {
+    static AVERAGE: OnceLock<[f32; 3]> = OnceLock::new();
+    *AVERAGE.get_or_init(|| {
+        let steps = 4000;
+        let mut sum = [0.0; 3];
+        for i in 0..steps {
+            let rgb = wavelength_rgb(sample_wavelength((i as f32 + 0.5) / steps as f32));
+            for c in 0..3 {
+                sum[c] += rgb[c] / steps as f32;
+            }
+        }
+        sum
+    })
+}
+
+/// The CIE 1931 color matching functions, using the multi-lobe fit from
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions" by Wyman, Sloan and Shirley.
+fn cie_xyz(wavelength: f32) -> [f32; 3] {
+    fn g(x: f32, mu: f32, sigma_low: f32, sigma_high: f32) -> f32 {
+        let sigma = if x < mu { sigma_low } else { sigma_high };
+        let t = (x - mu) / sigma;
+        (-0.5 * t * t).exp()
+    }
+
+    let l = wavelength;
+    let x = 1.056 * g(l, 599.8, 37.9, 31.0) + 0.362 * g(l, 442.0, 16.0, 26.7) - 0.065 * g(l, 501.1, 20.4, 26.2);
+    let y = 0.821 * g(l, 568.8, 46.9, 40.5) + 0.286 * g(l, 530.9, 16.3, 31.1);
+    let z = 1.217 * g(l, 437.0, 11.8, 36.0) + 0.681 * g(l, 459.0, 26.0, 13.8);
+    [x, y, z]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::spectrum::{sample_wavelength, wavelength_weight};
+
+    #[test]
+    fn weights_average_to_white() {
+        let steps = 1000;
+        let mut sum = [0.0; 3];
+        for i in 0..steps {
+            let w = wavelength_weight(sample_wavelength((i as f32 + 0.5) / steps as f32));
+            sum[0] += w.red / steps as f32;
+            sum[1] += w.green / steps as f32;
+            sum[2] += w.blue / steps as f32;
+        }
+
+        for c in sum {
+            assert!((c - 1.0).abs() < 0.01, "average weight {:?}", sum);
+        }
+    }
+
+    #[test]
+    fn wavelength_colors() {
+        let red = wavelength_weight(650.0);
+        assert!(red.red > red.green && red.red > red.blue);
+        let blue = wavelength_weight(450.0);
+        assert!(blue.blue > blue.red && blue.blue > blue.green);
+    }
+}
@@ -0,0 +1,282 @@
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+
+use std::f32::consts::PI;
+
+use crate::common::math::{Point2, Unit, Vec2, Vec3};
+use crate::common::scene::{Color, lerp_color};
+
+/// A color that varies over the surface of an object, looked up by the `uv` coordinates of a hit.
+///
+/// The uv coordinates of each shape are documented on [Shape](crate::common::scene::Shape).
+/// Textures repeat outside of the `[0, 1)` uv range.
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Constant(Color),
+    /// Alternating squares with side `1 / scale` in uv space, starting with `even` at the origin.
+    Checker { even: Color, odd: Color, scale: f32 },
+    Image(Arc<ImageTexture>),
+}
+
+/// How the values stored in an image file are converted to the linear values of an [ImageTexture].
+///
+/// Image files meant to be looked at (photos, painted albedo maps) are almost always sRGB encoded,
+/// while data textures (normal maps, roughness maps, height maps) store their values directly.
+/// Decoding a data texture as sRGB darkens the mid-tones, which silently bends normals or changes roughness.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ColorSpace {
+    /// Convert from sRGB to linear, for color textures.
+    Srgb,
+    /// Only scale the values to `[0, 1]`, for data textures.
+    Linear,
+}
+
+/// An image in linear color space, sampled with nearest-neighbour lookups,
+/// or bilinear lookups for environment maps with [ImageTexture::sample_equirect].
+pub struct ImageTexture {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    /// the mean of the top and bottom row, the values at the poles for [ImageTexture::sample_equirect]
+    pole_means: [Color; 2],
+}
+
+impl Texture {
+    pub fn sample(&self, uv: Point2) -> Color {
+        match self {
+            &Texture::Constant(color) => color,
+            &Texture::Checker { even, odd, scale } => {
+                let x = (uv.coords.x * scale).floor() as i64;
+                let y = (uv.coords.y * scale).floor() as i64;
+                if (x + y).rem_euclid(2) == 0 { even } else { odd }
+            }
+            Texture::Image(image) => image.sample(uv),
+        }
+    }
+
+    /// Sample a scalar value (eg. alpha or height), defined as the luminance of the color.
+    pub fn sample_scalar(&self, uv: Point2) -> f32 {
+        luminance(self.sample(uv))
+    }
+
+    /// The gradient of [Texture::sample_scalar] with respect to `uv`, estimated with central differences.
+    /// Images use a step of one pixel, smaller steps would mostly see the flat area inside a single pixel.
+    pub fn scalar_gradient(&self, uv: Point2) -> Vec2 {
+        let (du, dv) = match self {
+            Texture::Image(image) => (1.0 / image.width as f32, 1.0 / image.height as f32),
+            Texture::Constant(_) | Texture::Checker { .. } => (1e-3, 1e-3),
+        };
+
+        let difference = |delta: Vec2| self.sample_scalar(uv + delta) - self.sample_scalar(uv - delta);
+        Vec2::new(
+            difference(Vec2::new(du, 0.0)) / (2.0 * du),
+            difference(Vec2::new(0.0, dv)) / (2.0 * dv),
+        )
+    }
+}
+
+impl ImageTexture {
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        assert_eq!((width as usize) * (height as usize), pixels.len(), "Pixel count does not match size");
+        assert!(width > 0 && height > 0, "Texture can't be empty");
+
+        let row_mean = |y: u32| {
+            let row = &pixels[(y * width) as usize..][..width as usize];
+            row.iter().fold(Color::new(0.0, 0.0, 0.0), |a, &c| a + c) / width as f32
+        };
+        let pole_means = [row_mean(0), row_mean(height - 1)];
+
+        ImageTexture { width, height, pixels, pole_means }
+    }
+
+    /// Build a texture by evaluating `f` for each pixel, with `(0, 0)` the top left corner.
+    pub fn from_fn(width: u32, height: u32, mut f: impl FnMut(u32, u32) -> Color) -> Self {
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(f(x, y));
+            }
+        }
+        ImageTexture::new(width, height, pixels)
+    }
+
+    /// Load the color channels of an image file, decoded according to `color_space`.
+    pub fn load(path: impl AsRef<Path>, color_space: ColorSpace) -> image::ImageResult<Self> {
+        Ok(ImageTexture::from_rgb8(&image::open(path)?.to_rgb8(), color_space))
+    }
+
+    /// Convert an 8-bit image to a texture, decoded according to `color_space`.
+    pub fn from_rgb8(image: &image::RgbImage, color_space: ColorSpace) -> Self {
+        ImageTexture::from_fn(image.width(), image.height(), |x, y| {
+            let [r, g, b] = image[(x, y)].0;
+            match color_space {
+                ColorSpace::Srgb => palette::Srgb::new(r, g, b).into_format().into_linear(),
+                ColorSpace::Linear => Color::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0),
+            }
+        })
+    }
+
+    /// Load the alpha channel of an image file as a grayscale texture, useful as an alpha mask.
+    pub fn load_alpha(path: impl AsRef<Path>) -> image::ImageResult<Self> {
+        let image = image::open(path)?.to_rgba8();
+        Ok(ImageTexture::from_fn(image.width(), image.height(), |x, y| {
+            let a = image[(x, y)].0[3] as f32 / 255.0;
+            Color::new(a, a, a)
+        }))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Sample the texture, `v` points upwards so `(0, 0)` is the bottom left corner of the image.
+    pub fn sample(&self, uv: Point2) -> Color {
+        let u = uv.coords.x.rem_euclid(1.0);
+        let v = uv.coords.y.rem_euclid(1.0);
+
+        let x = ((u * self.width as f32) as u32).min(self.width - 1);
+        let y = (((1.0 - v) * self.height as f32) as u32).min(self.height - 1);
+        self.get(x, y)
+    }
+
+    /// Sample the texture as an equirectangular environment map, see [equirect_uv], with bilinear filtering.
+    ///
+    /// The lookup wraps around in the `u` direction so there's no seam at `u = 0`. The top and bottom rows all
+    /// converge to a single direction at the poles, so between the center of those rows and the edge of the image
+    /// the value is blended towards the mean of the row, which makes the map continuous at the poles too.
+    pub fn sample_equirect(&self, uv: Point2) -> Color {
+        let u = uv.coords.x.rem_euclid(1.0);
+        let v = uv.coords.y.clamp(0.0, 1.0);
+
+        // continuous pixel coordinates, with pixel centers at integer values
+        let fx = u * self.width as f32 - 0.5;
+        let fy = (1.0 - v) * self.height as f32 - 0.5;
+
+        let last_row = (self.height - 1) as f32;
+        if fy < 0.0 {
+            lerp_color(self.row_bilinear(0, fx), self.pole_means[0], -2.0 * fy)
+        } else if fy > last_row {
+            lerp_color(self.row_bilinear(self.height - 1, fx), self.pole_means[1], 2.0 * (fy - last_row))
+        } else {
+            let y0 = fy.floor();
+            let top = self.row_bilinear(y0 as u32, fx);
+            let bottom = self.row_bilinear((y0 as u32 + 1).min(self.height - 1), fx);
+            lerp_color(top, bottom, fy - y0)
+        }
+    }
+
+    /// Linearly interpolate within row `y` at continuous coordinate `fx`, wrapping around horizontally.
+    fn row_bilinear(&self, y: u32, fx: f32) -> Color {
+        let x0 = fx.floor();
+        let left = (x0 as i64).rem_euclid(self.width as i64) as u32;
+        let right = (left + 1) % self.width;
+        lerp_color(self.get(left, y), self.get(right, y), fx - x0)
+    }
+}
+
+impl Debug for ImageTexture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ImageTexture({}x{})", self.width, self.height)
+    }
+}
+
+/// Map a direction to equirectangular uv coordinates.
+/// `u` is the angle around the y-axis, with `u = 0.5` towards `+z`, and `v` goes from the bottom pole `-y` to the top pole `+y`.
+pub fn equirect_uv(direction: Unit<Vec3>) -> Point2 {
+    let u = 0.5 + direction.x.atan2(direction.z) / (2.0 * PI);
+    let v = 0.5 + direction.y.clamp(-1.0, 1.0).asin() / PI;
+    Point2::new(u, v)
+}
+
+/// The inverse of [equirect_uv].
+pub fn equirect_direction(uv: Point2) -> Unit<Vec3> {
+    let phi = (uv.coords.x - 0.5) * 2.0 * PI;
+    let theta = (uv.coords.y - 0.5) * PI;
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    Unit::new_unchecked(Vec3::new(cos_theta * sin_phi, sin_theta, cos_theta * cos_phi))
+}
+
+/// The relative luminance of a linear color.
+pub fn luminance(color: Color) -> f32 {
+    0.2126 * color.red + 0.7152 * color.green + 0.0722 * color.blue
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::math::Point2;
+    use crate::common::scene::Color;
+    use crate::common::texture::{ColorSpace, ImageTexture, Texture};
+
+    #[test]
+    fn image_sample_orientation() {
+        let top_left = Color::new(1.0, 0.0, 0.0);
+        let other = Color::new(0.0, 0.0, 0.0);
+        let image = ImageTexture::from_fn(2, 2, |x, y| if (x, y) == (0, 0) { top_left } else { other });
+
+        assert_eq!(image.sample(Point2::new(0.25, 0.75)), top_left);
+        assert_eq!(image.sample(Point2::new(0.25, 0.25)), other);
+        // textures repeat
+        assert_eq!(image.sample(Point2::new(-0.75, 1.75)), top_left);
+    }
+
+    #[test]
+    fn equirect_bilinear_gradient() {
+        let (width, height) = (8, 4);
+        let gray = |value: f32| Color::new(value, value, value);
+        let close = |a: Color, b: Color| (a.red - b.red).abs() < 1e-5;
+
+        // a vertical gradient where each pixel stores the v of its center is reproduced exactly between the centers
+        let vertical = ImageTexture::from_fn(width, height, |_, y| gray(1.0 - (y as f32 + 0.5) / height as f32));
+        for i in 0..=10 {
+            let v = 0.125 + 0.75 * i as f32 / 10.0;
+            for u in [0.0, 0.3, 0.99] {
+                let actual = vertical.sample_equirect(Point2::new(u, v));
+                assert!(close(actual, gray(v)), "at ({}, {}) got {:?}", u, v, actual);
+            }
+        }
+
+        // a horizontal gradient wraps around at u = 0, halfway between the last and first column
+        let horizontal = ImageTexture::from_fn(width, height, |x, _| gray(x as f32));
+        assert!(close(horizontal.sample_equirect(Point2::new(0.0, 0.5)), gray(3.5)));
+        assert!(close(horizontal.sample_equirect(Point2::new(1.0, 0.5)), gray(3.5)));
+        assert!(close(horizontal.sample_equirect(Point2::new(2.5 / 8.0, 0.5)), gray(2.0)));
+        assert!(close(horizontal.sample_equirect(Point2::new(2.75 / 8.0, 0.5)), gray(2.25)));
+
+        // the poles get the mean of the edge row for every u, halfway there it's still half the row itself
+        for u in [0.0, 0.2, 0.7] {
+            assert!(close(horizontal.sample_equirect(Point2::new(u, 1.0)), gray(3.5)));
+            assert!(close(horizontal.sample_equirect(Point2::new(u, 0.0)), gray(3.5)));
+        }
+        let halfway = horizontal.sample_equirect(Point2::new(2.5 / 8.0, 1.0 - 0.25 / height as f32));
+        assert!(close(halfway, gray((2.0 + 3.5) / 2.0)), "got {:?}", halfway);
+    }
+
+    #[test]
+    fn checker() {
+        let texture = Texture::Checker { even: Color::new(1.0, 1.0, 1.0), odd: Color::new(0.0, 0.0, 0.0), scale: 2.0 };
+        assert_eq!(texture.sample_scalar(Point2::new(0.25, 0.25)), 1.0);
+        assert_eq!(texture.sample_scalar(Point2::new(0.75, 0.25)), 0.0);
+        assert_eq!(texture.sample_scalar(Point2::new(-0.25, 0.25)), 0.0);
+    }
+
+    #[test]
+    fn color_space_decoding() {
+        let image = image::RgbImage::from_pixel(1, 1, image::Rgb([128, 128, 128]));
+
+        let srgb = ImageTexture::from_rgb8(&image, ColorSpace::Srgb).get(0, 0);
+        assert!((srgb.red - 0.216).abs() < 1e-3, "got {}", srgb.red);
+
+        let linear = ImageTexture::from_rgb8(&image, ColorSpace::Linear).get(0, 0);
+        assert_eq!(linear, Color::new(128.0 / 255.0, 128.0 / 255.0, 128.0 / 255.0));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::common::math::lerp;
+use crate::common::scene::Color;
+use crate::common::texture::luminance;
+
+/// Map a linear color to 8-bit sRGB for display, the way saved images and all previews show it.
+///
+/// Each channel is clamped to `[0, 1]` separately, so over-bright pixels lose their hue towards white.
+/// [is_clipped] tells which channels were affected.
+pub fn to_srgb8(color: Color) -> [u8; 3] {
+    let srgb: palette::Srgb<u8> = palette::Srgb::from_linear(color).into_format();
+    [srgb.red, srgb.green, srgb.blue]
+}
+
+/// Same mapping as [to_srgb8], with 16 bits per channel.
+pub fn to_srgb16(color: Color) -> [u16; 3] {
+    let srgb: palette::Srgb<u16> = palette::Srgb::from_linear(color).into_format();
+    [srgb.red, srgb.green, srgb.blue]
+}
+
+/// Which channels of `color` are too bright to be shown and are clamped by [to_srgb8] and [to_srgb16].
+pub fn is_clipped(color: Color) -> [bool; 3] {
+    [color.red > 1.0, color.green > 1.0, color.blue > 1.0]
+}
+
+/// The settings of [auto_exposure].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AutoExposure {
+    /// The fraction of the pixels that should end up at or below `target`, the brighter rest is allowed to clip.
+    pub percentile: f32,
+    /// The luminance the `percentile` pixel is mapped to.
+    pub target: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        AutoExposure { percentile: 0.95, target: 0.8 }
+    }
+}
+
+/// The range of luminances the histogram of [auto_exposure] covers, in stops.
+const HISTOGRAM_MIN_STOPS: f32 = -20.0;
+const HISTOGRAM_MAX_STOPS: f32 = 20.0;
+/// The number of histogram bins per stop.
+const HISTOGRAM_BINS_PER_STOP: f32 = 16.0;
+
+/// Pick an exposure for `colors` from the histogram of their luminance, so the
+/// [percentile](AutoExposure::percentile) pixel ends up at the [target](AutoExposure::target) luminance.
+///
+/// The exposure is in stops, the colors should be multiplied with `2^exposure`.
+/// Black pixels are ignored, so pixels that haven't been rendered yet don't count. Returns zero if all pixels are black.
+pub fn auto_exposure(colors: impl IntoIterator<Item=Color>, settings: AutoExposure) -> f32 {
+    let bins = ((HISTOGRAM_MAX_STOPS - HISTOGRAM_MIN_STOPS) * HISTOGRAM_BINS_PER_STOP) as usize;
+    let mut histogram = vec![0u64; bins];
+    let mut count = 0;
+
+    for color in colors {
+        let luminance = luminance(color);
+        if !(luminance > 0.0 && luminance.is_finite()) {
+            continue;
+        }
+        let bin = (luminance.log2() - HISTOGRAM_MIN_STOPS) * HISTOGRAM_BINS_PER_STOP;
+        histogram[(bin.max(0.0) as usize).min(bins - 1)] += 1;
+        count += 1;
+    }
+
+    if count == 0 {
+        return 0.0;
+    }
+
+    let threshold = (settings.percentile.clamp(0.0, 1.0) * count as f32).ceil().max(1.0) as u64;
+    let mut cumulative = 0;
+    let bin = histogram.iter().position(|&n| {
+        cumulative += n;
+        cumulative >= threshold
+    }).unwrap();
+
+    // the center of the bin, in stops
+    let stops = HISTOGRAM_MIN_STOPS + (bin as f32 + 0.5) / HISTOGRAM_BINS_PER_STOP;
+    settings.target.log2() - stops
+}
+
+/// Evenly spaced points on the viridis color map, in sRGB.
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+
+/// Map `t` in `[0, 1]` to the viridis color map by interpolating between evenly spaced points on it, as 8-bit sRGB.
+/// Used to show a single value per pixel, like the sample counts or the traversal cost.
+pub fn viridis(t: f32) -> [u8; 3] {
+    let scaled = t.clamp(0.0, 1.0) * (VIRIDIS.len() - 1) as f32;
+    let index = (scaled as usize).min(VIRIDIS.len() - 2);
+    let frac = scaled - index as f32;
+
+    let (low, high) = (VIRIDIS[index], VIRIDIS[index + 1]);
+    [0, 1, 2].map(|c| lerp(low[c] as f32, high[c] as f32, frac).round() as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::scene::Color;
+    use crate::common::tonemap::{auto_exposure, AutoExposure, is_clipped, to_srgb16, to_srgb8};
+
+    #[test]
+    fn clamps_per_channel() {
+        assert_eq!(to_srgb8(Color::new(0.0, 1.0, 4.0)), [0, 255, 255]);
+        assert_eq!(to_srgb16(Color::new(-1.0, 1.0, 4.0)), [0, 65535, 65535]);
+        assert_eq!(is_clipped(Color::new(0.0, 1.0, 4.0)), [false, false, true]);
+
+        // mid gray in linear space is brighter in sRGB
+        assert_eq!(to_srgb8(Color::new(0.5, 0.5, 0.5)), [188, 188, 188]);
+    }
+
+    #[test]
+    fn auto_exposure_percentile() {
+        // 100 gray pixels from 0.01 up to 1.00 with black pixels that don't count
+        let colors = (1..=100).map(|i| i as f32 / 100.0)
+            .chain([0.0; 50])
+            .map(|v| Color::new(v, v, v));
+        let settings = AutoExposure { percentile: 0.9, target: 0.5 };
+
+        // the 90th pixel has a luminance of 0.9
+        let exposure = auto_exposure(colors, settings);
+        let mapped = 0.9 * exposure.exp2();
+        assert!((mapped - 0.5).abs() < 0.03 * 0.5, "exposure {} maps to {}", exposure, mapped);
+
+        assert_eq!(auto_exposure([Color::new(0.0, 0.0, 0.0)], settings), 0.0);
+    }
+}
@@ -1,13 +1,15 @@
 use wavefront_obj::obj;
 use wavefront_obj::obj::Primitive;
 
-use crate::common::math::{Norm, Point3, Transform, Vec3};
+use crate::common::math::{Matrix4, Norm, Point3, Transform, Vec3};
 use crate::common::scene::{Material, Object, Shape};
 
 fn vertex_to_point(vertex: &obj::Vertex) -> Point3 {
     Point3::new(vertex.x as f32, vertex.y as f32, vertex.z as f32)
 }
 
+/// The transform that maps [Shape::Triangle] onto the triangle `[a, b, c]`.
+/// The triangle must not be degenerate, see [is_degenerate_triangle], otherwise the transform is not finite.
 pub fn triangle_as_transform(a: Point3, b: Point3, c: Point3) -> Transform {
     // Conventions:
     // * The source triangle is `Shape::Triangle`, the target triangle is `[a, b, c]`.
@@ -37,28 +39,78 @@ pub fn triangle_as_transform(a: Point3, b: Point3, c: Point3) -> Transform {
     shift_target.inv() * axes_to_shifted_target * axes_to_shifted_source.inv() * shift_source
 }
 
-pub fn obj_to_triangles(obj: &obj::Object, material: Material, transform: Transform) -> impl Iterator<Item=Object> + '_ {
-    obj.geometry.iter().flat_map(move |geometry|
-        geometry.shapes.iter().filter_map(move |shape| {
+/// Whether the triangle `[a, b, c]` has no area, because its corners are collinear or coincide, or has non-finite corners.
+/// Such triangles are invisible but can't be mapped onto by [triangle_as_transform].
+pub fn is_degenerate_triangle(a: Point3, b: Point3, c: Point3) -> bool {
+    let (ab, ac, bc) = (b - a, c - a, c - b);
+    let longest_squared = ab.norm_squared().max(ac.norm_squared()).max(bc.norm_squared());
+
+    // twice the area relative to the longest edge squared doesn't depend on the scale,
+    // the comparison is negated to also catch NaN
+    !(ab.cross(ac).norm() > f32::EPSILON * longest_squared && longest_squared.is_finite())
+}
+
+/// The coordinate convention used by an imported asset.
+///
+/// The tracer itself is right-handed with Y up, and cameras look along -Z by default.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum CoordinateConvention {
+    /// Right-handed with Y up, the same as the tracer (OBJ and glTF), no conversion is needed.
+    #[default]
+    YUp,
+    /// Right-handed with Z up and -Y as the front (Blender, 3ds Max).
+    /// Converted with a rotation of -90 degrees around the X axis: `(x, y, z)` becomes `(x, z, -y)`.
+    ZUp,
+}
+
+impl CoordinateConvention {
+    /// The transform that maps points from this convention to the tracer convention.
+    /// Apply it to both the geometry and any cameras that come with the asset, before any other scene transform.
+    pub fn to_tracer(self) -> Transform {
+        match self {
+            CoordinateConvention::YUp => Transform::default(),
+            CoordinateConvention::ZUp => Transform::from_matrix(Matrix4::new([
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, -1.0, 0.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ])).unwrap(),
+        }
+    }
+}
+
+/// Convert the triangles of `obj` into objects, first converting from `convention` and then applying `transform`.
+///
+/// Degenerate triangles, see [is_degenerate_triangle], are common in exported meshes. They're skipped instead of
+/// turning into objects with a broken transform, the second value is the number of skipped triangles.
+pub fn obj_to_triangles(obj: &obj::Object, material: Material, convention: CoordinateConvention, transform: Transform) -> (Vec<Object>, usize) {
+    let transform = transform * convention.to_tracer();
+    let mut objects = vec![];
+    let mut degenerate = 0;
+
+    for geometry in &obj.geometry {
+        for shape in &geometry.shapes {
             match shape.primitive {
-                Primitive::Point(_) => None,
-                Primitive::Line(_, _) => None,
+                Primitive::Point(_) => {}
+                Primitive::Line(_, _) => {}
                 Primitive::Triangle((avi, _, _), (bvi, ..), (cvi, ..)) => {
                     let a = vertex_to_point(&obj.vertices[avi]);
                     let b = vertex_to_point(&obj.vertices[bvi]);
                     let c = vertex_to_point(&obj.vertices[cvi]);
 
+                    if is_degenerate_triangle(a, b, c) {
+                        degenerate += 1;
+                        continue;
+                    }
                     let local_transform = triangle_as_transform(a, b, c);
 
-                    Some(Object {
-                        shape: Shape::Triangle,
-                        material,
-                        transform: transform * local_transform,
-                    })
+                    objects.push(Object::new(Shape::Triangle, material.clone(), transform * local_transform).with_name(obj.name.clone()));
                 }
             }
-        })
-    )
+        }
+    }
+
+    (objects, degenerate)
 }
 
 #[cfg(windows)]
@@ -75,8 +127,11 @@ pub fn lower_process_priority() {}
 
 #[cfg(test)]
 mod test {
-    use crate::common::math::Point3;
-    use crate::common::util::triangle_as_transform;
+    use wavefront_obj::obj;
+
+    use crate::common::math::{Point3, Transform, Vec3};
+    use crate::common::util::{CoordinateConvention, is_degenerate_triangle, obj_to_triangles, triangle_as_transform};
+    use crate::demos::{material_diffuse, WHITE};
 
     #[test]
     fn triangle_as_transform_including_origin() {
@@ -89,4 +144,35 @@ mod test {
 
         assert!(trans.is_finite());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn degenerate_triangles_skipped() {
+        // a proper triangle, one with collinear corners and one with a repeated corner
+        let source = "o mesh\nv 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\nf 1 2 3\nf 1 2 4\nf 1 3 3\n";
+        let object_set = obj::parse(source).unwrap();
+        let (triangles, degenerate) = obj_to_triangles(&object_set.objects[0], material_diffuse(WHITE), CoordinateConvention::YUp, Transform::default());
+
+        assert_eq!((triangles.len(), degenerate), (1, 2));
+        assert!(triangles[0].transform.is_finite());
+
+        // thin but proper triangles are kept, no matter the scale
+        for scale in [1e-3, 1.0, 1e3] {
+            let thin = [Point3::new(0.0, 0.0, 0.0), Point3::new(scale, 0.0, 0.0), Point3::new(0.0, 1e-3 * scale, 0.0)];
+            assert!(!is_degenerate_triangle(thin[0], thin[1], thin[2]));
+            assert!(triangle_as_transform(thin[0], thin[1], thin[2]).is_finite());
+        }
+        assert!(is_degenerate_triangle(Point3::new(f32::NAN, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn z_up_convention() {
+        let transform = CoordinateConvention::ZUp.to_tracer();
+        assert_eq!(transform * *Vec3::z_axis(), *Vec3::y_axis());
+        assert_eq!(transform * -*Vec3::y_axis(), *Vec3::z_axis());
+        assert_eq!(transform * *Vec3::x_axis(), *Vec3::x_axis());
+        assert_eq!(transform * Point3::new(1.0, 2.0, 3.0), Point3::new(1.0, 3.0, -2.0));
+
+        let identity = CoordinateConvention::YUp.to_tracer();
+        assert_eq!(identity * Point3::new(1.0, 2.0, 3.0), Point3::new(1.0, 2.0, 3.0));
+    }
+}
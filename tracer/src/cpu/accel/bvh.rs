@@ -2,12 +2,12 @@ use std::fmt::{Debug, Formatter};
 use std::num::NonZeroU32;
 
 use decorum::Total;
-use itertools::{Itertools, partition};
+use itertools::partition;
 
 use crate::common::aabb::AxisBox;
 use crate::common::math::{Axis3, Axis3Owner, lerp, Point3};
 use crate::common::scene::Object;
-use crate::cpu::accel::{Accel, first_hit, ObjectId};
+use crate::cpu::accel::{Accel, first_hit_ids, GlobalObjects, object_bounds, SmallId, TraversalCost};
 use crate::cpu::geometry::{ObjectHit, Ray};
 
 /// Implementation following
@@ -15,37 +15,27 @@ use crate::cpu::geometry::{ObjectHit, Ray};
 /// * https://jacco.ompf2.com/2022/04/18/how-to-build-a-bvh-part-2-faster-rays/
 pub struct BVH {
     /// objects with infinite spans that don't fit in the tree structure
-    global_ids: Vec<SmallId>,
+    global: GlobalObjects,
     /// the tree objects
     ids: Vec<SmallId>,
     /// the tree nodes
     nodes: Vec<Node>,
 }
 
+#[derive(Default)]
 pub enum BVHSplitStrategy {
+    // TODO investigate why the other options are slower (at least on the "random tiles" scene)
+    #[default]
     SplitLargestAxis,
     SurfaceAreaHeuristic { test_planes: Option<usize> },
 }
 
-impl Default for BVHSplitStrategy {
-    fn default() -> Self {
-        // TODO investigate why the other options are slower (at least on the "random tiles" scene)
-        BVHSplitStrategy::SplitLargestAxis
-    }
-}
-
 #[derive(Debug, Clone)]
 struct Node {
     bound: AxisBox,
     kind: NodeKind,
 }
 
-/// Smaller version of ObjectId to fit more things into the cache.
-#[derive(Debug, Copy, Clone)]
-struct SmallId {
-    index: u32,
-}
-
 #[derive(Debug, Clone)]
 enum NodeKind {
     Leaf {
@@ -59,53 +49,105 @@ enum NodeKind {
 
 impl BVH {
     pub fn new(objects: &[Object], strategy: BVHSplitStrategy) -> Self {
-        assert!(objects.len() < u32::MAX as usize);
-        let total_len = objects.len() as u32;
+        Self::new_with_progress(objects, strategy, |_| {})
+    }
 
-        let mut ids = (0..total_len).map(|id| SmallId { index: id }).collect_vec();
-        // TODO also check for non-finite transforms?
-        let global_start = partition(&mut ids, |&id| AxisBox::for_shape(objects[id.index as usize].shape).is_finite());
-        let global_ids = ids.split_off(global_start);
+    /// Same as [BVH::new], calling `progress` with the fraction of the objects that have been placed in their final leaf.
+    pub fn new_with_progress(objects: &[Object], strategy: BVHSplitStrategy, mut progress: impl FnMut(f32)) -> Self {
+        let mut ids = SmallId::all(objects);
+        let global = GlobalObjects::split_off(objects, &mut ids);
 
         let len = match NonZeroU32::new(ids.len() as u32) {
-            None => return BVH { global_ids, ids: vec![], nodes: vec![] },
+            None => return BVH { global, ids: vec![], nodes: vec![] },
             Some(len) => len,
         };
 
+        let bounds = object_bounds(objects);
+        let centroids = bounds.iter().map(|b| b.low.middle(b.high)).collect();
+
         let mut builder = Builder {
             strategy,
-            objects,
+            bounds,
+            centroids,
             ids,
             nodes: vec![],
+            finished_count: 0,
+            progress: &mut progress,
+            prev_percent: 0,
         };
+        (builder.progress)(0.0);
 
         let root = builder.build_leaf(0, len);
         builder.nodes.push(root);
         builder.split(0);
 
-        builder.check(&global_ids);
-
-        BVH {
-            global_ids,
+        let bvh = BVH {
+            global,
             ids: builder.ids,
             nodes: builder.nodes,
+        };
+        if cfg!(debug_assertions) {
+            bvh.validate(objects);
         }
+        bvh
     }
 
-    fn first_hit_impl(&self, objects: &[Object], ray: &Ray, filter: &impl Fn(&Object) -> bool, node: u32, mut t_max: f32) -> Option<ObjectHit> {
-        let node = &self.nodes[node as usize];
+    /// Check that this BVH was built for `objects`: every object is contained exactly once and each node bound
+    /// tightly fits its contents. This walks the whole tree, so [BVH::new] only runs it in debug builds.
+    ///
+    /// Panics if the check fails.
+    pub fn validate(&self, objects: &[Object]) {
+        let bounds = object_bounds(objects);
+        let mut seen = vec![false; objects.len()];
 
-        if node.bound.intersects(ray).is_none() {
-            return None;
+        if !self.nodes.is_empty() {
+            self.validate_node(&bounds, 0, &mut seen);
         }
 
-        match node.kind {
+        for id in self.global.ids() {
+            let flag = &mut seen[id.index as usize];
+            assert!(!*flag, "Object {} is in the BVH more than once", id.index);
+            *flag = true;
+        }
+
+        assert!(seen.iter().all(|&b| b), "Not all objects are in the BVH");
+    }
+
+    fn validate_node(&self, bounds: &[AxisBox], node: u32, seen: &mut [bool]) -> AxisBox {
+        let node = &self.nodes[node as usize];
+        let actual_bound = match node.kind {
             NodeKind::Leaf { start, len } => {
-                let objects = (start..start + len.get()).map(|index| &objects[self.ids[index as usize].index as usize]);
-                first_hit(objects, ray, filter).map(|(index, hit)| {
-                    let id = self.ids[start as usize + index].to_large();
-                    ObjectHit { id, hit }
+                (start..start + len.get()).map(|index| {
+                    let id = self.ids[index as usize].index as usize;
+                    let flag = &mut seen[id];
+                    assert!(!*flag, "Object {} is in the BVH more than once", id);
+                    *flag = true;
+
+                    bounds[id]
                 })
+                    .reduce(AxisBox::combine).unwrap()
+            }
+            NodeKind::Branch { left_index } => {
+                let bound_left = self.validate_node(bounds, left_index, seen);
+                let bound_right = self.validate_node(bounds, left_index + 1, seen);
+                bound_left.combine(bound_right)
+            }
+        };
+
+        assert_eq!(node.bound, actual_bound, "BVH node bound doesn't fit its objects");
+        actual_bound
+    }
+
+    fn first_hit_impl(&self, objects: &[Object], ray: &Ray, filter: &impl Fn(&Object) -> bool, node: u32, mut t_max: f32, cost: &mut TraversalCost) -> Option<ObjectHit> {
+        let node = &self.nodes[node as usize];
+
+        cost.nodes += 1;
+        node.bound.intersects(ray)?;
+
+        match node.kind {
+            NodeKind::Leaf { start, len } => {
+                let ids = &self.ids[start as usize..(start + len.get()) as usize];
+                first_hit_ids(ids, objects, ray, filter, cost)
             }
             NodeKind::Branch { left_index } => {
                 let mut first_index = left_index;
@@ -114,6 +156,7 @@ impl BVH {
                 let mut second_t = self.nodes[second_index as usize].bound.intersects(ray).unwrap_or(f32::INFINITY);
 
                 // TODO why does simplifying this make everything 2x slower?
+                #[allow(clippy::neg_cmp_op_on_partial_ord)]
                 if !(first_t < second_t) {
                     std::mem::swap(&mut first_index, &mut second_index);
                     std::mem::swap(&mut first_t, &mut second_t);
@@ -122,12 +165,12 @@ impl BVH {
                 let mut best = None;
 
                 if first_t < t_max {
-                    let first = self.first_hit_impl(objects, ray, filter, first_index, t_max);
+                    let first = self.first_hit_impl(objects, ray, filter, first_index, t_max, cost);
                     t_max = f32::min(t_max, first.as_ref().map_or(f32::INFINITY, |hit| hit.hit.t));
                     best = ObjectHit::closest_option(best, first);
                 }
                 if second_t < t_max {
-                    let second = self.first_hit_impl(objects, ray, filter, second_index, t_max);
+                    let second = self.first_hit_impl(objects, ray, filter, second_index, t_max, cost);
                     best = ObjectHit::closest_option(best, second);
                 }
 
@@ -139,9 +182,19 @@ impl BVH {
 
 impl Accel for BVH {
     fn first_hit(&self, objects: &[Object], ray: &Ray, filter: impl Fn(&Object) -> bool) -> Option<ObjectHit> {
-        let global_objects = self.global_ids.iter().map(|id| &objects[id.index as usize]);
-        let global_hit = first_hit(global_objects, ray, &filter)
-            .map(|(index, hit)| ObjectHit { id: self.global_ids[index].to_large(), hit });
+        self.first_hit_counted(objects, ray, filter, &mut TraversalCost::default())
+    }
+
+    fn traversal_cost(&self, objects: &[Object], ray: &Ray) -> TraversalCost {
+        let mut cost = TraversalCost::default();
+        self.first_hit_counted(objects, ray, |_| true, &mut cost);
+        cost
+    }
+}
+
+impl BVH {
+    fn first_hit_counted(&self, objects: &[Object], ray: &Ray, filter: impl Fn(&Object) -> bool, cost: &mut TraversalCost) -> Option<ObjectHit> {
+        let global_hit = self.global.first_hit(objects, ray, &filter, cost);
 
         if self.nodes.is_empty() {
             return global_hit;
@@ -149,7 +202,7 @@ impl Accel for BVH {
 
         // TODO consider making t_max part of Ray everywhere
         let t_max = global_hit.as_ref().map_or(f32::INFINITY, |hit| hit.hit.t);
-        let tree_hit = self.first_hit_impl(objects, ray, &filter, 0, t_max);
+        let tree_hit = self.first_hit_impl(objects, ray, &filter, 0, t_max, cost);
 
         ObjectHit::closest_option(global_hit, tree_hit)
     }
@@ -173,19 +226,32 @@ impl AxisBox {
 
 struct Builder<'a> {
     strategy: BVHSplitStrategy,
-    objects: &'a [Object],
+    /// the bounding box of each object, indexed like `objects`
+    bounds: Vec<AxisBox>,
+    /// the center of the bounding box of each object, indexed like `objects`
+    // TODO figure out what centroid to use, does it need to be correct or is best-effort fine?
+    centroids: Vec<Point3>,
     ids: Vec<SmallId>,
     nodes: Vec<Node>,
+
+    /// the number of objects in leaves that won't be split any more
+    finished_count: u32,
+    progress: &'a mut dyn FnMut(f32),
+    prev_percent: u32,
 }
 
 impl Builder<'_> {
-    fn get_object(&self, index: u32) -> &Object {
-        &self.objects[self.ids[index as usize].index as usize]
+    fn get_bound(&self, index: u32) -> AxisBox {
+        self.bounds[self.ids[index as usize].index as usize]
+    }
+
+    fn get_centroid(&self, index: u32) -> Point3 {
+        self.centroids[self.ids[index as usize].index as usize]
     }
 
     fn compute_bound(&self, start: u32, len: NonZeroU32) -> AxisBox {
         (start..(start + len.get()))
-            .map(|index| AxisBox::for_object(self.get_object(index)))
+            .map(|index| self.get_bound(index))
             .reduce(AxisBox::combine)
             .unwrap()
     }
@@ -195,6 +261,16 @@ impl Builder<'_> {
         Node { bound, kind: NodeKind::Leaf { start, len } }
     }
 
+    /// Report a leaf with `len` objects that won't be split further, only calls `progress` once per percent.
+    fn finish_leaf(&mut self, len: NonZeroU32) {
+        self.finished_count += len.get();
+        let percent = (self.finished_count as u64 * 100 / self.ids.len() as u64) as u32;
+        if percent != self.prev_percent {
+            self.prev_percent = percent;
+            (self.progress)(self.finished_count as f32 / self.ids.len() as f32);
+        }
+    }
+
     fn split(&mut self, node_index: u32) {
         let node = &self.nodes[node_index as usize];
         let bound = node.bound;
@@ -206,13 +282,14 @@ impl Builder<'_> {
         // find the split axis and point
         let (split_axis, split_value) = match self.find_best_split(start, len, bound) {
             Some(split) => split,
-            None => return,
+            None => return self.finish_leaf(len),
         };
 
         // rearrange the objects
+        let centroids = &self.centroids;
         let split_index = partition(
             &mut self.ids[start as usize..][..len.get() as usize],
-            |&id| object_centroid(&self.objects[id.index as usize]).get(split_axis) < split_value,
+            |&id| centroids[id.index as usize].get(split_axis) < split_value,
         ) as u32;
 
         // stop if one of the children is empty
@@ -221,7 +298,7 @@ impl Builder<'_> {
 
         let (left_len, right_len) = match (left_len, right_len) {
             (Some(left_len), Some(right_len)) => (left_len, right_len),
-            (None, _) | (_, None) => return,
+            (None, _) | (_, None) => return self.finish_leaf(len),
         };
 
         // push the children
@@ -278,14 +355,14 @@ impl Builder<'_> {
             // try a fixed number of test planes
             for pi in 0..test_planes {
                 for axis in Axis3::ALL {
-                    let value = lerp((pi + 1) as f32 / test_planes as f32, bound.low.get(axis), bound.high.get(axis));
+                    let value = lerp(bound.high.get(axis), bound.low.get(axis), (pi + 1) as f32 / test_planes as f32);
                     try_split(axis, value)
                 }
             }
         } else {
             // try each object centroid
             for index in start..(start + len.get()) {
-                let centroid = object_centroid(self.get_object(index));
+                let centroid = self.get_centroid(index);
                 for axis in Axis3::ALL {
                     let value = centroid.get(axis);
                     try_split(axis, value);
@@ -313,7 +390,7 @@ impl Builder<'_> {
         let mut right_high = Point3::new(-INF, -INF, -INF);
 
         for index in start..(start + len.get()) {
-            let centroid = object_centroid(self.get_object(index));
+            let centroid = self.get_centroid(index);
             if centroid.get(axis) < value {
                 left_low = left_low.min(centroid);
                 left_high = left_high.max(centroid);
@@ -334,70 +411,26 @@ impl Builder<'_> {
 
         left_count as f32 * left_box.area() + right_count as f32 * right_box.area()
     }
-
-    fn check(&self, global_ids: &[SmallId]) {
-        let mut seen = vec![false; self.objects.len()];
-
-        self.check_node(0, &mut seen);
-
-        for &id in global_ids {
-            let flag = &mut seen[id.index as usize];
-            assert!(!*flag);
-            *flag = true;
-        }
-
-        assert!(seen.iter().all(|&b| b));
-    }
-
-    fn check_node(&self, node: u32, seen: &mut [bool]) -> AxisBox {
-        let node = &self.nodes[node as usize];
-        let actual_bound = match node.kind {
-            NodeKind::Leaf { start, len } => {
-                (start..start + len.get()).map(|index| {
-                    let flag = &mut seen[self.ids[index as usize].index as usize];
-                    assert!(!*flag);
-                    *flag = true;
-
-                    AxisBox::for_object(self.get_object(index))
-                })
-                    .reduce(AxisBox::combine).unwrap()
-            }
-            NodeKind::Branch { left_index } => {
-                let bound_left = self.check_node(left_index, seen);
-                let bound_right = self.check_node(left_index + 1, seen);
-                bound_left.combine(bound_right)
-            }
-        };
-
-        assert_eq!(node.bound, actual_bound);
-        actual_bound
-    }
-}
-
-// TODO figure out what centroid to use, does it need to be correct or is best-effort fine?
-//   can we just use the object BB centroid?
-fn object_centroid(object: &Object) -> Point3 {
-    let b = AxisBox::for_object(object);
-    b.low.middle(b.high)
 }
 
 impl Debug for BVH {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "BVH(global={}, ids={}, nodes={})", self.global_ids.len(), self.ids.len(), self.nodes.len())
-    }
-}
-
-impl SmallId {
-    fn to_large(self) -> ObjectId {
-        ObjectId { index: self.index as usize }
+        write!(f, "BVH(global={}, planes={}, ids={}, nodes={})", self.global.ids.len(), self.global.planes.len(), self.ids.len(), self.nodes.len())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+
     use crate::common::aabb::AxisBox;
-    use crate::common::math::{Point3, Vec3};
+    use crate::common::math::{Angle, Norm, Point3, Transform, Vec3};
+    use crate::common::scene::{Material, Object, Shape};
+    use crate::cpu::accel::{Accel, NoAccel};
+    use crate::cpu::accel::bvh::BVH;
     use crate::cpu::geometry::Ray;
+    use crate::demos::{material_diffuse, scene_random_tiles_with, WHITE};
 
     #[test]
     fn aabb_intersect() {
@@ -408,4 +441,73 @@ mod test {
         };
         assert!(aabb.intersects(&ray).is_some());
     }
+
+    #[test]
+    fn build_progress_reaches_end() {
+        let scene = scene_random_tiles_with(500, 0, false);
+
+        let mut reported = vec![];
+        BVH::new_with_progress(&scene.objects, Default::default(), |progress| reported.push(progress));
+
+        assert_eq!(reported.first(), Some(&0.0));
+        assert_eq!(reported.last(), Some(&1.0));
+        assert!(reported.windows(2).all(|w| w[0] < w[1]), "progress should increase, got {:?}", reported);
+        assert!(reported.len() <= 101);
+    }
+
+    #[test]
+    #[should_panic(expected = "BVH node bound doesn't fit its objects")]
+    fn validate_other_objects() {
+        let scene = scene_random_tiles_with(50, 0, false);
+        let bvh = BVH::new(&scene.objects, Default::default());
+        bvh.validate(&scene.objects);
+
+        let other = scene_random_tiles_with(50, 1, false);
+        bvh.validate(&other.objects);
+    }
+
+    #[test]
+    fn planes_match_no_accel() {
+        let mut scene = scene_random_tiles_with(200, 0, false);
+        let plane = |transform, cull_backface| Object::new(Shape::Plane, Material { cull_backface, ..material_diffuse(WHITE) }, transform);
+        scene.objects.push(plane(Transform::translate(Vec3::new(0.0, 0.0, -3.0)), false));
+        scene.objects.push(plane(Transform::rotate(Vec3::x_axis(), Angle::degrees(80.0)) * Transform::scale(2.0), true));
+        scene.objects.push(plane(Transform::translate(Vec3::new(5.0, 0.0, 0.0)) * Transform::rotate(Vec3::y_axis(), Angle::degrees(90.0)), false));
+
+        let bvh = BVH::new(&scene.objects, Default::default());
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        for _ in 0..1000 {
+            let start = Point3::new(rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0));
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalized();
+            let ray = Ray::new(start, direction);
+
+            let expected = NoAccel.first_hit(&scene.objects, &ray, |_| true);
+            let actual = bvh.first_hit(&scene.objects, &ray, |_| true);
+            assert_eq!(expected.map(|h| h.id), actual.map(|h| h.id), "different hit for {:?}", ray);
+        }
+    }
+
+    #[test]
+    fn traversal_cost_below_no_accel() {
+        let scene = scene_random_tiles_with(2000, 0, false);
+        let bvh = BVH::new(&scene.objects, Default::default());
+
+        // a ray far away from all tiles only visits the root
+        let miss = Ray::new(Point3::new(0.0, 0.0, 1000.0), Vec3::z_axis());
+        assert_eq!(bvh.traversal_cost(&scene.objects, &miss).nodes, 1);
+
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let n = 1000;
+        let mut total = 0;
+        for _ in 0..n {
+            let start = Point3::new(rng.gen_range(-100.0..100.0), rng.gen_range(-100.0..100.0), rng.gen_range(-20.0..20.0));
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalized();
+            total += bvh.traversal_cost(&scene.objects, &Ray::new(start, direction)).total();
+        }
+
+        let no_accel = NoAccel.traversal_cost(&scene.objects, &miss).total();
+        assert_eq!(no_accel, scene.objects.len() as u32);
+        assert!(total / n < no_accel / 10, "average cost {} is too close to {}", total / n, no_accel);
+    }
 }
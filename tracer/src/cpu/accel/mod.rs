@@ -2,8 +2,12 @@ use std::fmt::Debug;
 
 use decorum::N32;
 use derive_more::Constructor;
+use itertools::{Itertools, partition};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
-use crate::common::scene::Object;
+use crate::common::aabb::AxisBox;
+use crate::common::math::{Norm, Point3, Vec3};
+use crate::common::scene::{Object, Shape};
 use crate::cpu::geometry::{Hit, ObjectHit, Ray};
 use crate::cpu::geometry::Intersect;
 
@@ -16,8 +20,57 @@ pub struct ObjectId {
     pub index: usize,
 }
 
+/// Smaller version of ObjectId to fit more things into the cache.
+#[derive(Debug, Copy, Clone)]
+struct SmallId {
+    index: u32,
+}
+
+impl SmallId {
+    /// The ids of all `objects`, in order.
+    fn all(objects: &[Object]) -> Vec<SmallId> {
+        assert!(objects.len() < u32::MAX as usize);
+        (0..objects.len() as u32).map(|index| SmallId { index }).collect_vec()
+    }
+
+    fn to_large(self) -> ObjectId {
+        ObjectId { index: self.index as usize }
+    }
+}
+
 pub trait Accel: Debug + Sync {
     fn first_hit(&self, objects: &[Object], ray: &Ray, filter: impl Fn(&Object) -> bool) -> Option<ObjectHit>;
+
+    /// The work [Accel::first_hit] does to find the first hit along `ray` without any filter,
+    /// see [Strategy::TraversalCost](crate::cpu::Strategy::TraversalCost).
+    /// The default intersects every object, which is what [NoAccel] does.
+    fn traversal_cost(&self, objects: &[Object], _: &Ray) -> TraversalCost {
+        TraversalCost { nodes: 0, objects: objects.len() as u32 }
+    }
+}
+
+/// The number of nodes visited and objects intersected while looking for the first hit along a single ray.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct TraversalCost {
+    pub nodes: u32,
+    pub objects: u32,
+}
+
+impl TraversalCost {
+    pub fn total(self) -> u32 {
+        self.nodes + self.objects
+    }
+}
+
+/// Borrowing an accel allows sharing it between renders, eg. for the frames of an animation.
+impl<A: Accel> Accel for &A {
+    fn first_hit(&self, objects: &[Object], ray: &Ray, filter: impl Fn(&Object) -> bool) -> Option<ObjectHit> {
+        (*self).first_hit(objects, ray, filter)
+    }
+
+    fn traversal_cost(&self, objects: &[Object], ray: &Ray) -> TraversalCost {
+        (*self).traversal_cost(objects, ray)
+    }
 }
 
 #[derive(Debug)]
@@ -29,7 +82,104 @@ impl Accel for NoAccel {
     }
 }
 
-/// We don't return [ObjectHit] since the indices may not be correct.
+/// The world space bounding box of each object, computed once up front since the builders look them up many times.
+/// Objects with an infinite shape keep their object space box, transforming it could result in NaN. That box is not
+/// where the object actually is, so the builders must keep these objects out of their tree, see [GlobalObjects].
+fn object_bounds(objects: &[Object]) -> Vec<AxisBox> {
+    objects.par_iter()
+        .map(|object| {
+            let shape_bound = AxisBox::for_shape(&object.shape);
+            if shape_bound.is_finite() { object.transform * shape_bound } else { shape_bound }
+        })
+        .collect()
+}
+
+/// The first hit along `ray` with the objects `ids` point to, with the id of the object that was hit.
+/// Used for the leaves of the accels, which store the ids of their objects next to each other.
+fn first_hit_ids(ids: &[SmallId], objects: &[Object], ray: &Ray, filter: &impl Fn(&Object) -> bool, cost: &mut TraversalCost) -> Option<ObjectHit> {
+    cost.objects += ids.len() as u32;
+    let leaf_objects = ids.iter().map(|id| &objects[id.index as usize]);
+    first_hit(leaf_objects, ray, filter).map(|(index, hit)| ObjectHit { id: ids[index].to_large(), hit })
+}
+
+/// Objects with an infinite bounding box, which don't fit in the tree of an accel and are tested for every ray instead.
+struct GlobalObjects {
+    /// planes, tested with their precomputed plane equations
+    planes: Vec<GlobalPlane>,
+    /// the other infinite objects
+    ids: Vec<SmallId>,
+}
+
+/// A plane in world space as `normal . p = offset`, with `normal` the normal of the plane object.
+#[derive(Debug, Copy, Clone)]
+struct GlobalPlane {
+    id: SmallId,
+    normal: Vec3,
+    offset: f32,
+}
+
+impl GlobalObjects {
+    /// Move the objects with an infinite bounding box out of `ids`, leaving the ones that can be put in a tree.
+    fn split_off(objects: &[Object], ids: &mut Vec<SmallId>) -> Self {
+        // TODO also check for non-finite transforms?
+        let global_start = partition(&mut *ids, |&id| AxisBox::for_shape(&objects[id.index as usize].shape).is_finite());
+        let mut global_ids = ids.split_off(global_start);
+
+        let plane_start = partition(&mut global_ids, |&id| objects[id.index as usize].shape != Shape::Plane);
+        let planes = global_ids.split_off(plane_start).into_iter()
+            .map(|id| GlobalPlane::new(id, &objects[id.index as usize]))
+            .collect_vec();
+
+        GlobalObjects { planes, ids: global_ids }
+    }
+
+    fn len(&self) -> usize {
+        self.ids.len() + self.planes.len()
+    }
+
+    fn ids(&self) -> impl Iterator<Item=SmallId> + '_ {
+        self.ids.iter().copied().chain(self.planes.iter().map(|plane| plane.id))
+    }
+
+    fn first_hit(&self, objects: &[Object], ray: &Ray, filter: &impl Fn(&Object) -> bool, cost: &mut TraversalCost) -> Option<ObjectHit> {
+        let hit = first_hit_ids(&self.ids, objects, ray, filter, cost);
+        cost.objects += self.planes.len() as u32;
+        ObjectHit::closest_option(hit, self.first_plane_hit(objects, ray, filter))
+    }
+
+    /// Find the closest plane using the plane equations, only the full hit of that plane is computed.
+    fn first_plane_hit(&self, objects: &[Object], ray: &Ray, filter: &impl Fn(&Object) -> bool) -> Option<ObjectHit> {
+        let mut best: Option<(f32, SmallId)> = None;
+
+        for plane in &self.planes {
+            let object = &objects[plane.id.index as usize];
+            let denom = plane.normal.dot(*ray.direction);
+            if !filter(object) || (object.material.cull_backface && denom >= 0.0) {
+                continue;
+            }
+
+            let t = (plane.offset - plane.normal.dot(ray.start.coords())) / denom;
+            if t >= 0.0 && t.is_finite() && best.is_none_or(|(best_t, _)| t < best_t) {
+                best = Some((t, plane.id));
+            }
+        }
+
+        let (_, id) = best?;
+        let hit = objects[id.index as usize].intersect(ray)?;
+        Some(ObjectHit { id: id.to_large(), hit })
+    }
+}
+
+impl GlobalPlane {
+    fn new(id: SmallId, object: &Object) -> Self {
+        let normal = object.transform.inv_transpose_mul(*Vec3::z_axis()).normalized();
+        let point = object.transform * Point3::origin();
+        GlobalPlane { id, normal: *normal, offset: normal.dot(point.coords()) }
+    }
+}
+
+/// The first hit along `ray` with `objects`, with the index of the object in the iterator.
+/// We don't return [ObjectHit] since the index is not necessarily an [ObjectId], see [first_hit_ids].
 pub fn first_hit<'a>(objects: impl IntoIterator<Item=&'a Object>, ray: &Ray, filter: impl Fn(&Object) -> bool) -> Option<(usize, Hit)> {
     objects.into_iter().enumerate()
         .filter_map(|(index, object)| {
@@ -41,4 +191,3 @@ pub fn first_hit<'a>(objects: impl IntoIterator<Item=&'a Object>, ray: &Ray, fil
         })
         .min_by_key(|(_, hit)| N32::from_inner(hit.t))
 }
-
@@ -3,27 +3,29 @@ use std::fmt::{Debug, Display, Formatter};
 use std::ops::Range;
 
 use decorum::N32;
-use itertools::Itertools;
 
 use crate::common::aabb::AxisBox;
 use crate::common::math::{Axis3, Axis3Owner};
 use crate::common::scene::Object;
-use crate::cpu::accel::{Accel, first_hit, ObjectId};
+use crate::cpu::accel::{Accel, first_hit_ids, GlobalObjects, object_bounds, SmallId, TraversalCost};
 use crate::cpu::geometry::{ObjectHit, Ray};
 
 // TODO fix wrongly returned indices that cause light to be overactive
 pub struct Octree {
-    ids: Vec<ObjectId>,
+    /// objects with infinite spans that don't fit in the tree structure
+    global: GlobalObjects,
+    ids: Vec<SmallId>,
     nodes: Vec<Node>,
 
     node_root: usize,
 }
 
-struct Builder<'a> {
+struct Builder {
     max_flat_size: usize,
-    objects: &'a [Object],
+    /// the bounding box of each object, indexed like the objects
+    bounds: Vec<AxisBox>,
 
-    ids: Vec<ObjectId>,
+    ids: Vec<SmallId>,
     nodes: Vec<Node>,
 }
 
@@ -42,19 +44,20 @@ enum Node {
 
 impl Octree {
     pub fn new(objects: &[Object], max_flat_size: usize) -> Self {
-        let objects_len = objects.len();
+        let mut ids = SmallId::all(objects);
+        let global = GlobalObjects::split_off(objects, &mut ids);
 
         let mut builder = Builder {
             max_flat_size,
-            objects,
+            bounds: object_bounds(objects),
             ids: vec![],
             nodes: vec![],
         };
 
-        let ids = (0..objects_len).map(ObjectId::new).collect_vec();
         let node_root = builder.build_node(&ids);
 
         Octree {
+            global,
             nodes: builder.nodes,
             ids: builder.ids,
             node_root,
@@ -79,12 +82,27 @@ impl Octree {
 
 impl Accel for Octree {
     fn first_hit(&self, objects: &[Object], ray: &Ray, filter: impl Fn(&Object) -> bool) -> Option<ObjectHit> {
-        self.nodes[self.node_root].first_hit(self, objects, ray, &filter, f32::INFINITY)
+        self.first_hit_counted(objects, ray, filter, &mut TraversalCost::default())
+    }
+
+    fn traversal_cost(&self, objects: &[Object], ray: &Ray) -> TraversalCost {
+        let mut cost = TraversalCost::default();
+        self.first_hit_counted(objects, ray, |_| true, &mut cost);
+        cost
     }
 }
 
-impl Builder<'_> {
-    fn build_flat_node(&mut self, ids: &[ObjectId]) -> usize {
+impl Octree {
+    fn first_hit_counted(&self, objects: &[Object], ray: &Ray, filter: impl Fn(&Object) -> bool, cost: &mut TraversalCost) -> Option<ObjectHit> {
+        let global_hit = self.global.first_hit(objects, ray, &filter, cost);
+        let t_max = global_hit.as_ref().map_or(f32::INFINITY, |hit| hit.hit.t);
+        let tree_hit = self.nodes[self.node_root].first_hit(self, objects, ray, &filter, t_max, cost);
+        ObjectHit::closest_option(global_hit, tree_hit)
+    }
+}
+
+impl Builder {
+    fn build_flat_node(&mut self, ids: &[SmallId]) -> usize {
         let start = self.ids.len();
         self.ids.extend(ids);
         let end = self.ids.len();
@@ -93,7 +111,7 @@ impl Builder<'_> {
         self.nodes.len() - 1
     }
 
-    fn build_node(&mut self, ids: &[ObjectId]) -> usize {
+    fn build_node(&mut self, ids: &[SmallId]) -> usize {
         if ids.len() <= self.max_flat_size {
             return self.build_flat_node(ids);
         }
@@ -127,11 +145,11 @@ impl Builder<'_> {
         }
     }
 
-    fn split_objects(&self, ids: &[ObjectId], axis: Axis3, split: f32) -> (Vec<ObjectId>, Vec<ObjectId>) {
+    fn split_objects(&self, ids: &[SmallId], axis: Axis3, split: f32) -> (Vec<SmallId>, Vec<SmallId>) {
         let mut lower = vec![];
         let mut higher = vec![];
         for &id in ids {
-            let b = AxisBox::for_object(&self.objects[id.index]);
+            let b = self.bounds[id.index as usize];
             if b.low.get(axis) <= split {
                 lower.push(id);
             }
@@ -142,11 +160,11 @@ impl Builder<'_> {
         (lower, higher)
     }
 
-    fn best_axis_split(&self, ids: &[ObjectId], axis: Axis3) -> Option<(f32, usize)> {
+    fn best_axis_split(&self, ids: &[SmallId], axis: Axis3) -> Option<(f32, usize)> {
         // collect edges
         let mut edges = vec![];
         for &id in ids {
-            let b = AxisBox::for_object(&self.objects[id.index]);
+            let b = self.bounds[id.index as usize];
             edges.push(N32::from_inner(b.low.get(axis)));
             edges.push(N32::from_inner(b.high.get(axis)));
         }
@@ -170,7 +188,7 @@ impl Builder<'_> {
             let mut lower_count = 0;
             let mut higher_count = 0;
             for &id in ids {
-                let b = AxisBox::for_object(&self.objects[id.index]);
+                let b = self.bounds[id.index as usize];
                 if b.low.get(axis) <= split {
                     lower_count += 1;
                 }
@@ -196,24 +214,18 @@ impl Builder<'_> {
 }
 
 impl Node {
-    fn first_hit<'a>(&self, octree: &'a Octree, objects: &[Object], ray: &Ray, filter: &impl Fn(&Object) -> bool, mut t_max: f32) -> Option<ObjectHit> {
+    fn first_hit(&self, octree: &Octree, objects: &[Object], ray: &Ray, filter: &impl Fn(&Object) -> bool, mut t_max: f32, cost: &mut TraversalCost) -> Option<ObjectHit> {
+        cost.nodes += 1;
+
         match self {
-            Node::Flat(range) => {
-                let objects = range.clone().map(|i| &objects[octree.ids[i].index]);
-                first_hit(objects, ray, filter).map(|(index, hit)| {
-                    ObjectHit {
-                        id: octree.ids[range.start + index],
-                        hit,
-                    }
-                })
-            }
+            Node::Flat(range) => first_hit_ids(&octree.ids[range.clone()], objects, ray, filter, cost),
             &Node::Split { axis, value, node_lower, node_higher } => {
                 let start_in_lower = ray.start.get(axis) <= value;
                 let end_in_lower = ray.at(t_max).get(axis) <= value;
 
                 // compute start hit
                 let start_node = if start_in_lower { node_lower } else { node_higher };
-                let start_hit = octree.nodes[start_node].first_hit(octree, objects, ray, filter,t_max);
+                let start_hit = octree.nodes[start_node].first_hit(octree, objects, ray, filter, t_max, cost);
 
                 if let Some(hit) = start_hit.as_ref() {
                     t_max = f32::min(t_max, hit.hit.t);
@@ -222,7 +234,7 @@ impl Node {
                 // compute end hit if end is different from start
                 if end_in_lower != start_in_lower {
                     let end_node = if end_in_lower { node_lower } else { node_higher };
-                    let end_hit = octree.nodes[end_node].first_hit(octree, objects, ray, filter , t_max);
+                    let end_hit = octree.nodes[end_node].first_hit(octree, objects, ray, filter, t_max, cost);
                     ObjectHit::closest_option(start_hit, end_hit)
                 } else {
                     start_hit
@@ -262,6 +274,38 @@ impl Node {
 impl Debug for Octree {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let (len, depth) = self.len_depth();
-        writeln!(f, "Octree(ids={}, len={}, depth={}, nodes={})", self.ids.len(), len, depth, self.nodes.len())
+        writeln!(f, "Octree(global={}, ids={}, len={}, depth={}, nodes={})", self.global.len(), self.ids.len(), len, depth, self.nodes.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+
+    use crate::common::math::{Norm, Point3, Transform, Vec3};
+    use crate::common::scene::{Object, Shape};
+    use crate::cpu::accel::{Accel, NoAccel};
+    use crate::cpu::accel::octree::Octree;
+    use crate::cpu::geometry::Ray;
+    use crate::demos::{material_diffuse, scene_random_tiles_with, WHITE};
+
+    #[test]
+    fn matches_no_accel() {
+        let mut scene = scene_random_tiles_with(200, 0, false);
+        scene.objects.push(Object::new(Shape::Plane, material_diffuse(WHITE), Transform::translate(Vec3::new(0.0, 0.0, -3.0))));
+
+        let octree = Octree::new(&scene.objects, 4);
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        for _ in 0..1000 {
+            let start = Point3::new(rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0), rng.gen_range(-4.0..4.0));
+            let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalized();
+            let ray = Ray::new(start, direction);
+
+            let expected = NoAccel.first_hit(&scene.objects, &ray, |_| true);
+            let actual = octree.first_hit(&scene.objects, &ray, |_| true);
+            assert_eq!(expected.map(|h| h.id), actual.map(|h| h.id), "different hit for {:?}", ray);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,222 @@
+use imgref::ImgRef;
+
+use crate::common::math::{Angle, Point3, Transform, Unit, Vec3};
+use crate::common::progress::{PixelResult, ProgressHandler};
+use crate::common::scene::{Object, Scene};
+use crate::cpu::accel::Accel;
+use crate::cpu::driver::{CpuRenderer, ImageSizeError};
+
+/// Render `frame_count` frames of `scene` while moving the camera along `camera_path`.
+///
+/// If `camera_loops`, frame `i` uses the camera transform `camera_path(i / frame_count)`, so the last frame is
+/// just before `t = 1` and looping paths like [orbit_camera_path] repeat seamlessly.
+/// Otherwise frame `i` uses `camera_path(i / (frame_count - 1))`, so the last frame is exactly at `t = 1`,
+/// eg. at the last control point of an open [CameraPath].
+/// The geometry is static, so `accel` is built once up front and shared by all frames.
+/// The renderers are consumed by rendering, `renderer` creates the one for each frame index.
+/// All frames are rendered into the same buffer, which is passed to `frame_done` before the next frame starts.
+///
+/// The camera of `scene` is restored afterwards, also when `frame_done` returns an error
+/// or the image is too large for the renderers, see [CpuRenderer::pixel_count].
+#[allow(clippy::too_many_arguments)]
+pub fn render_animation<P: ProgressHandler, A: Accel, E: From<ImageSizeError>>(
+    scene: &mut Scene,
+    accel: &A,
+    width: u32,
+    height: u32,
+    frame_count: u32,
+    camera_path: impl Fn(f32) -> Transform,
+    camera_loops: bool,
+    mut renderer: impl FnMut(u32) -> CpuRenderer<P>,
+    mut frame_done: impl FnMut(u32, ImgRef<PixelResult>) -> Result<(), E>,
+) -> Result<(), E> {
+    let original_transform = scene.camera.transform;
+    let mut target = vec![];
+
+    let intervals = if camera_loops { frame_count } else { frame_count.saturating_sub(1).max(1) };
+
+    let mut result = Ok(());
+    for frame in 0..frame_count {
+        scene.camera.transform = camera_path(frame as f32 / intervals as f32);
+        result = render_frame(scene, accel, width, height, renderer(frame), &mut target)
+            .map_err(E::from)
+            .and_then(|()| frame_done(frame, ImgRef::new(&target, width as usize, height as usize)));
+        if result.is_err() {
+            break;
+        }
+    }
+
+    scene.camera.transform = original_transform;
+    result
+}
+
+/// Render a single frame into `target`, which is only allocated the first time.
+fn render_frame<P: ProgressHandler, A: Accel>(
+    scene: &Scene,
+    accel: &A,
+    width: u32,
+    height: u32,
+    renderer: CpuRenderer<P>,
+    target: &mut Vec<PixelResult>,
+) -> Result<(), ImageSizeError> {
+    let pixel_count = renderer.pixel_count(width, height)?;
+    target.resize(pixel_count, PixelResult::default());
+    renderer.render_into(target, scene, width, height, None, |_: &[Object], _: &mut dyn FnMut(f32)| accel)
+}
+
+/// A camera path that circles once around the vertical axis through `target`, starting at `start`
+/// and always looking at `target`. Counterclockwise when seen from above.
+pub fn orbit_camera_path(start: Point3, target: Point3) -> impl Fn(f32) -> Transform {
+    move |t| {
+        let rotation = Transform::rotate(Vec3::y_axis(), Angle::degrees(360.0 * t));
+        let position = target + rotation * (start - target);
+        Transform::look_at(position, target, Vec3::y_axis())
+    }
+}
+
+/// A smooth camera path through a list of control points, for flythroughs with [render_animation].
+///
+/// Both the camera position and the point it looks at follow a uniform Catmull-Rom spline through their control points,
+/// which passes exactly through each of them. The camera always looks from the interpolated position towards the
+/// interpolated target, so the rotation is as smooth as the two splines, without any quaternions.
+///
+/// Control point `i` is reached at `t = i / (n - 1)` for open paths and at `t = i / n` for closed paths,
+/// where the last control point connects back to the first one and the path repeats seamlessly like [orbit_camera_path].
+/// Pass `closed` as `camera_loops` to [render_animation], so open paths end at their last control point.
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    pub positions: Vec<Point3>,
+    /// The points the camera looks at, one for each position.
+    pub targets: Vec<Point3>,
+    pub up: Unit<Vec3>,
+    pub closed: bool,
+}
+
+impl CameraPath {
+    /// The camera transform at time `t`, clamped to `[0, 1]` for open paths and wrapped around for closed ones.
+    pub fn transform(&self, t: f32) -> Transform {
+        assert!(!self.positions.is_empty(), "camera path needs at least one control point");
+        assert_eq!(self.positions.len(), self.targets.len(), "camera path needs a target for each position");
+
+        let position = catmull_rom(&self.positions, self.closed, t);
+        let target = catmull_rom(&self.targets, self.closed, t);
+        Transform::look_at(position, target, self.up)
+    }
+}
+
+/// Evaluate the uniform Catmull-Rom spline through `points` at `t`, see [CameraPath].
+fn catmull_rom(points: &[Point3], closed: bool, t: f32) -> Point3 {
+    let n = points.len();
+    let (segments, t) = if closed { (n, t.rem_euclid(1.0)) } else { ((n - 1).max(1), t.clamp(0.0, 1.0)) };
+
+    let scaled = t * segments as f32;
+    let segment = (scaled as usize).min(segments - 1);
+    let s = scaled - segment as f32;
+
+    // open paths repeat their end points, closed paths wrap around
+    let point = |i: isize| if closed {
+        points[i.rem_euclid(n as isize) as usize]
+    } else {
+        points[i.clamp(0, n as isize - 1) as usize]
+    };
+    let i = segment as isize;
+    let p1 = point(i);
+    let (a, c, d) = (point(i - 1) - p1, point(i + 1) - p1, point(i + 2) - p1);
+
+    p1 + ((c - a) * s + (a * 2.0 + c * 4.0 - d) * (s * s) + (c * -3.0 - a + d) * (s * s * s)) * 0.5
+}
+
+#[cfg(test)]
+mod test {
+    use imgref::ImgRef;
+
+    use crate::common::math::{Angle, Norm, Point3, Transform, Vec3};
+    use crate::common::progress::{NoProgress, PixelResult};
+    use crate::common::scene::{Camera, Color, Object, Scene, Shape, Sky};
+    use crate::cpu::accel::NoAccel;
+    use crate::cpu::animation::{CameraPath, render_animation};
+    use crate::cpu::driver::{CpuRenderer, DEFAULT_MAX_PIXELS, ImageSizeError};
+    use crate::cpu::renderer::{CpuRenderSettings, LightSelection, StopCondition};
+    use crate::cpu::subsampling::Subsampling;
+    use crate::demos::{BLACK, material_light, VACUUM, WHITE};
+
+    fn renderer(_: u32) -> CpuRenderer<NoProgress> {
+        CpuRenderer {
+            settings: CpuRenderSettings {
+                stop_condition: StopCondition::SampleCount(1),
+                anti_alias: false,
+                ..Default::default()
+            },
+            lights: LightSelection::Auto,
+            progress_handler: NoProgress,
+            threads: None,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            subsampling: Subsampling::Full,
+        }
+    }
+
+    /// Render the frames of a 3x3 image with a single sphere light at `light`, and return the color of the center pixel
+    /// of each frame.
+    fn frame_centers(light: Vec3, frame_count: u32, camera_path: impl Fn(f32) -> Transform, camera_loops: bool) -> Vec<(u32, Color)> {
+        let mut scene = Scene {
+            objects: vec![Object::new(Shape::Sphere, material_light(WHITE, 1.0), Transform::translate(light))],
+            sky: Sky::Uniform(BLACK),
+            camera: Camera { fov_horizontal: Angle::degrees(30.0), transform: Transform::default(), medium: VACUUM },
+        };
+
+        let mut centers = vec![];
+        let result: Result<(), ImageSizeError> = render_animation(&mut scene, &NoAccel, 3, 3, frame_count, camera_path, camera_loops, renderer, |frame, image: ImgRef<PixelResult>| {
+            centers.push((frame, image[(1usize, 1usize)].color));
+            Ok(())
+        });
+
+        assert!(result.is_ok());
+        assert_eq!(scene.camera.transform * Point3::origin(), Point3::origin());
+        centers
+    }
+
+    #[test]
+    fn camera_moves_between_frames() {
+        // the camera slides to the right, the sphere only fills the center pixel in the first frame
+        let path = |t: f32| Transform::translate(Vec3::new(20.0 * t, 0.0, 0.0));
+        assert_eq!(frame_centers(Vec3::new(0.0, 0.0, -5.0), 2, path, true), vec![(0, WHITE), (1, BLACK)]);
+    }
+
+    #[test]
+    fn open_path_ends_at_last_control_point() {
+        // the sphere is only in front of the last control point
+        let path = CameraPath {
+            positions: vec![Point3::new(0.0, 0.0, 0.0), Point3::new(10.0, 0.0, 0.0), Point3::new(20.0, 0.0, 0.0)],
+            targets: vec![Point3::new(0.0, 0.0, -5.0), Point3::new(10.0, 0.0, -5.0), Point3::new(20.0, 0.0, -5.0)],
+            up: Vec3::y_axis(),
+            closed: false,
+        };
+        let centers = frame_centers(Vec3::new(20.0, 0.0, -5.0), 3, |t| path.transform(t), path.closed);
+        assert_eq!(centers, vec![(0, BLACK), (1, BLACK), (2, WHITE)]);
+    }
+
+    #[test]
+    fn camera_path_hits_control_points() {
+        let positions = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(4.0, 1.0, 0.0), Point3::new(4.0, 2.0, 4.0), Point3::new(0.0, 0.0, 6.0)];
+        let targets = vec![Point3::new(0.0, 0.0, -5.0), Point3::new(2.0, 0.0, -3.0), Point3::new(0.0, 0.0, 0.0), Point3::new(-3.0, 1.0, 2.0)];
+
+        for closed in [false, true] {
+            let path = CameraPath { positions: positions.clone(), targets: targets.clone(), up: Vec3::y_axis(), closed };
+            let n = positions.len();
+            let segments = if closed { n } else { n - 1 };
+
+            for (i, (&position, &target)) in positions.iter().zip(&targets).enumerate() {
+                let transform = path.transform(i as f32 / segments as f32);
+                let forward = (transform * Vec3::new(0.0, 0.0, -1.0)).normalized();
+                let expected = (target - position).normalized();
+
+                assert!((transform * Point3::origin()).distance_to(position) < 1e-4, "closed {closed}: control point {i}");
+                assert!((*forward - *expected).norm() < 1e-4, "closed {closed}: target {i}");
+            }
+
+            // close to a control point the path stays close to it, closed paths wrap around to the first one
+            let end = if closed { positions[0] } else { positions[n - 1] };
+            assert!((path.transform(0.999) * Point3::origin()).distance_to(end) < 0.05, "closed {closed}: end");
+        }
+    }
+}
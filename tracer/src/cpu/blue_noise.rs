@@ -0,0 +1,116 @@
+use std::sync::OnceLock;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// The number of points in the tile, after this many samples a pixel starts over with a different shift.
+const TILE_SIZE: usize = 256;
+/// The number of random candidates considered for each new point of the tile.
+const CANDIDATES: usize = 32;
+
+/// The sub-pixel offset in `[0, 1)²` of sample `sample` of the pixel at `(x, y)`, for [Jitter::BlueNoise](crate::cpu::Jitter::BlueNoise).
+///
+/// The samples of a pixel walk through a fixed tile of points generated with Mitchell's best candidate algorithm, where each
+/// new point is as far as possible from the previous ones. Every prefix of the tile is spread evenly over the pixel
+/// without the clumps of independent random offsets. The tile is shifted by a different random amount for each pixel,
+/// wrapping around at the edges, which keeps the distances between the points but hides the pattern.
+pub fn blue_noise_offset(x: u32, y: u32, sample: u32) -> (f32, f32) {
+    let tile = blue_noise_tile();
+    let (px, py) = tile[sample as usize % TILE_SIZE];
+
+    let cycle = sample / TILE_SIZE as u32;
+    let shift_x = hash(x ^ hash(y ^ hash(cycle)));
+    let shift_y = hash(shift_x);
+
+    ((px + unit_float(shift_x)).fract(), (py + unit_float(shift_y)).fract())
+}
+
+fn blue_noise_tile() -> &'static [(f32, f32)] {
+    static TILE: OnceLock<Vec<(f32, f32)>> = OnceLock::new();
+    TILE.get_or_init(|| {
+        // unlike SmallRng this generates the same tile on every platform, see pixel_rng
+        let rng = &mut StdRng::seed_from_u64(0);
+        let mut points: Vec<(f32, f32)> = Vec::with_capacity(TILE_SIZE);
+
+        while points.len() < TILE_SIZE {
+            let best = (0..CANDIDATES)
+                .map(|_| rng.gen::<(f32, f32)>())
+                .map(|candidate| {
+                    let distance = points.iter()
+                        .map(|&point| toroidal_distance_squared(candidate, point))
+                        .fold(f32::INFINITY, f32::min);
+                    (candidate, distance)
+                })
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap().0;
+            points.push(best);
+        }
+
+        points
+    })
+}
+
+/// The squared distance between two points in the unit square, where the edges wrap around.
+fn toroidal_distance_squared(a: (f32, f32), b: (f32, f32)) -> f32 {
+    let wrap = |d: f32| {
+        let d = d.abs();
+        d.min(1.0 - d)
+    };
+    let (dx, dy) = (wrap(a.0 - b.0), wrap(a.1 - b.1));
+    dx * dx + dy * dy
+}
+
+/// Integer hash with good avalanche behaviour, see <https://nullprogram.com/blog/2018/07/31/>.
+fn hash(mut v: u32) -> u32 {
+    v ^= v >> 16;
+    v = v.wrapping_mul(0x7feb352d);
+    v ^= v >> 15;
+    v = v.wrapping_mul(0x846ca68b);
+    v ^= v >> 16;
+    v
+}
+
+/// Map a hash to `[0, 1)` using its upper 24 bits, which fit exactly in an `f32`.
+fn unit_float(v: u32) -> f32 {
+    (v >> 8) as f32 / (1 << 24) as f32
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::cpu::blue_noise::blue_noise_offset;
+
+    #[test]
+    fn edge_coverage_error_lower_than_random() {
+        let samples = 8;
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        let mut error_random = 0.0;
+        let mut error_blue = 0.0;
+
+        for y in 0..32 {
+            for x in 0..32 {
+                // a straight edge through the pixel at a random angle and offset
+                let angle = rng.gen::<f32>() * std::f32::consts::TAU;
+                let (nx, ny) = (angle.cos(), angle.sin());
+                let c = rng.gen::<f32>() - 0.5;
+                let inside = |(dx, dy): (f32, f32)| (dx - 0.5) * nx + (dy - 0.5) * ny < c;
+
+                let grid = 64;
+                let exact = (0..grid * grid)
+                    .filter(|i| inside(((i % grid) as f32 / grid as f32 + 0.5 / grid as f32, (i / grid) as f32 / grid as f32 + 0.5 / grid as f32)))
+                    .count() as f32 / (grid * grid) as f32;
+
+                let random = (0..samples).filter(|_| inside(rng.gen())).count() as f32 / samples as f32;
+                let blue = (0..samples).filter(|&s| inside(blue_noise_offset(x, y, s))).count() as f32 / samples as f32;
+
+                error_random += (random - exact).powi(2);
+                error_blue += (blue - exact).powi(2);
+            }
+        }
+
+        assert!(error_blue < 0.5 * error_random, "squared error: random {}, blue noise {}", error_random, error_blue);
+    }
+}
@@ -1,93 +1,614 @@
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Instant;
 
+use crossbeam::channel::Sender;
 use imgref::ImgVec;
 use rand::prelude::SliceRandom;
 use rand::thread_rng;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator};
 
-use crate::common::progress::{Block, PixelResult, ProgressHandler};
+use crate::common::progress::{Block, PassInfo, PixelResult, PrepareStage, ProgressHandler, RenderStats};
 use crate::common::scene::{Object, Scene};
 use crate::cpu::accel::Accel;
-use crate::cpu::renderer::{CpuPreparedScene, CpuRenderSettings};
+use crate::cpu::light_tracing::LightFilm;
+use crate::cpu::masks::{BlockCoverage, MaskGroups, ObjectMasks};
+use crate::cpu::renderer::{BlockOrder, CpuPreparedScene, CpuRenderSettings, LightSelection, pixel_result, StopCondition, Strategy};
+use crate::cpu::stats::{ColorVarianceEstimator, count_rejected_hits};
+use crate::cpu::subsampling::Subsampling;
+use crate::images::DiscreteImage;
 
 pub struct CpuRenderer<P: ProgressHandler> {
     pub settings: CpuRenderSettings,
+    pub lights: LightSelection,
     pub progress_handler: P,
+    /// The number of threads to render with. `None` uses the global rayon pool,
+    /// otherwise a separate pool is built for each render so the global one is left alone.
+    pub threads: Option<usize>,
+    /// The largest number of pixels an image can have, larger images are refused with an [ImageSizeError]
+    /// instead of trying to allocate them. See [DEFAULT_MAX_PIXELS].
+    pub max_pixels: u64,
+    /// Which pixels are traced, the others are interpolated. [Subsampling::Full] for the best quality.
+    pub subsampling: Subsampling,
 }
 
-fn split_into_blocks(width: u32, height: u32) -> Vec<Block> {
-    let block_size: u32 = 16;
+/// The default for [CpuRenderer::max_pixels], enough for an 8K image with room to spare.
+pub const DEFAULT_MAX_PIXELS: u64 = 1 << 26;
 
+/// The error returned when asked to render an image with more pixels than allowed or than fit in memory.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ImageSizeError {
+    /// The pixel count doesn't even fit in a `usize`.
+    Overflow { width: u32, height: u32 },
+    /// The pixel count is larger than [CpuRenderer::max_pixels].
+    TooLarge { width: u32, height: u32, max_pixels: u64 },
+}
+
+impl Display for ImageSizeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            ImageSizeError::Overflow { width, height } =>
+                write!(f, "Image size {}x{} overflows the pixel count", width, height),
+            ImageSizeError::TooLarge { width, height, max_pixels } =>
+                write!(f, "Image size {}x{} has more than the maximum of {} pixels", width, height, max_pixels),
+        }
+    }
+}
+
+impl std::error::Error for ImageSizeError {}
+
+const BLOCK_SIZE: u32 = 16;
+
+/// Messages sent from the render threads to the collector thread.
+enum Message {
+    /// The pixels of a block and, if masks are rendered, their [BlockCoverage::coverage].
+    Block(Block, Vec<PixelResult>, Option<Vec<f32>>),
+    PassFinished(PassInfo),
+}
+
+/// Split `region` into blocks of at most [BLOCK_SIZE] pixels, in the given order.
+fn split_into_blocks(region: Block, order: BlockOrder) -> Vec<Block> {
     let mut result = Vec::new();
-    for x in (0..width).step_by(block_size as usize) {
-        for y in (0..height).step_by(block_size as usize) {
+    for y in region.y_range().step_by(BLOCK_SIZE as usize) {
+        for x in region.x_range().step_by(BLOCK_SIZE as usize) {
             result.push(Block {
                 x,
                 y,
-                width: min(block_size, width - x),
-                height: min(block_size, height - y),
+                width: min(BLOCK_SIZE, region.x_range().end - x),
+                height: min(BLOCK_SIZE, region.y_range().end - y),
             })
         }
     }
 
+    match order {
+        BlockOrder::Shuffled => result.shuffle(&mut thread_rng()),
+        BlockOrder::RowMajor => {}
+        BlockOrder::Hilbert => {
+            let grid_size = max(region.width, region.height).div_ceil(BLOCK_SIZE).next_power_of_two();
+            result.sort_by_key(|block| {
+                hilbert_index(grid_size, (block.x - region.x) / BLOCK_SIZE, (block.y - region.y) / BLOCK_SIZE)
+            });
+        }
+    }
+
     result
 }
 
+/// The index of `(x, y)` along a Hilbert curve that covers a square grid with side `n`, which must be a power of two.
+/// See <https://en.wikipedia.org/wiki/Hilbert_curve#Applications_and_mapping_algorithms>.
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    debug_assert!(n.is_power_of_two() && x < n && y < n);
+
+    let mut index = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s) > 0;
+        let ry = (y & s) > 0;
+        index += (s as u64) * (s as u64) * ((3 * rx as u64) ^ ry as u64);
+
+        // rotate the quadrant so the sub-curve is oriented correctly
+        if !ry {
+            if rx {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        s /= 2;
+    }
+    index
+}
+
+/// The number of samples every pixel gets at least, or at most for [StopCondition::Progressive].
+fn base_samples_per_pixel(stop_condition: StopCondition) -> u32 {
+    match stop_condition {
+        StopCondition::SampleCount(samples) => samples,
+        StopCondition::Variance { min_samples, .. } => min_samples,
+        StopCondition::Progressive { max_samples, .. } => max_samples,
+    }
+}
+
+/// Trace light paths for the caustics in the image, as many as the minimum number of camera samples.
+/// The invalid hits that are skipped are added to `rejected_hits`.
+fn trace_light_paths<A: Accel>(prepared_scene: &CpuPreparedScene<A>, width: u32, height: u32, rejected_hits: &AtomicU64) -> LightFilm {
+    let samples_per_pixel = base_samples_per_pixel(prepared_scene.settings.stop_condition);
+    let path_count = (width as u64) * (height as u64) * (samples_per_pixel as u64);
+
+    // each chunk gets its own film, which are summed at the end
+    let chunk_count = 4 * rayon::current_num_threads() as u64;
+    (0..chunk_count).into_par_iter().panic_fuse()
+        .map_init(thread_rng, |rng, chunk| {
+            let mut film = LightFilm::new(width, height);
+            let chunk_paths = path_count / chunk_count + (chunk < path_count % chunk_count) as u64;
+            let ((), rejected) = count_rejected_hits(|| {
+                for _ in 0..chunk_paths {
+                    prepared_scene.trace_light_path(rng, &mut film);
+                }
+            });
+            rejected_hits.fetch_add(rejected, Ordering::Relaxed);
+            film
+        })
+        .reduce(|| LightFilm::new(width, height), |mut left, right| {
+            left += &right;
+            left
+        })
+}
+
 impl<P: ProgressHandler> CpuRenderer<P> {
-    pub fn render<A: Accel>(self, scene: &Scene, width: u32, height: u32, accel: impl FnOnce(&[Object]) -> A) -> ImgVec<PixelResult> {
+    /// The number of pixels in an image of `width` by `height` pixels,
+    /// or an error if that's more than [CpuRenderer::max_pixels] or doesn't fit in a `usize`.
+    pub fn pixel_count(&self, width: u32, height: u32) -> Result<usize, ImageSizeError> {
+        let count = (width as u64).checked_mul(height as u64)
+            .and_then(|count| usize::try_from(count).ok())
+            .ok_or(ImageSizeError::Overflow { width, height })?;
+
+        if count as u64 > self.max_pixels {
+            return Err(ImageSizeError::TooLarge { width, height, max_pixels: self.max_pixels });
+        }
+        Ok(count)
+    }
+
+    /// Render the scene to an image of `width` by `height` pixels.
+    ///
+    /// If `crop` is set only the pixels in that region are rendered, the perspective is still that of the full image
+    /// and the other pixels are left at their default value.
+    /// The `accel` function builds the acceleration structure for the objects, it can report its progress to the
+    /// second argument as the fraction that's done.
+    ///
+    /// Returns an error without rendering anything if the image has too many pixels, see [CpuRenderer::pixel_count].
+    pub fn render<A: Accel>(
+        self,
+        scene: &Scene,
+        width: u32,
+        height: u32,
+        crop: Option<Block>,
+        accel: impl FnOnce(&[Object], &mut dyn FnMut(f32)) -> A + Send,
+    ) -> Result<ImgVec<PixelResult>, ImageSizeError> {
+        let mut target = vec![PixelResult::default(); self.pixel_count(width, height)?];
+        self.render_into(&mut target, scene, width, height, crop, accel)?;
+        Ok(ImgVec::new(target, width as usize, height as usize))
+    }
+
+    /// Same as [CpuRenderer::render], but also returns a coverage mask for each group of objects that share the same
+    /// [name](Object::name), unnamed objects don't get a mask. The value of a pixel is the fraction of its samples whose
+    /// camera ray first hits an object of the group, so edges are anti-aliased the same way as the render itself.
+    /// Pixels outside of `crop` stay black.
+    pub fn render_with_masks<A: Accel>(
+        self,
+        scene: &Scene,
+        width: u32,
+        height: u32,
+        crop: Option<Block>,
+        accel: impl FnOnce(&[Object], &mut dyn FnMut(f32)) -> A + Send,
+    ) -> Result<(ImgVec<PixelResult>, HashMap<String, DiscreteImage>), ImageSizeError> {
+        let mut target = vec![PixelResult::default(); self.pixel_count(width, height)?];
+        let masks = self.render_into_impl(&mut target, scene, width, height, crop, accel, true)?;
+        let masks = masks.expect("Masks were requested");
+        Ok((ImgVec::new(target, width as usize, height as usize), masks))
+    }
+
+    /// Same as [CpuRenderer::render] but writes the image into `target` in row-major order,
+    /// which allows reusing the same buffer for multiple renders. `target` is reset first.
+    pub fn render_into<A: Accel>(
+        self,
+        target: &mut [PixelResult],
+        scene: &Scene,
+        width: u32,
+        height: u32,
+        crop: Option<Block>,
+        accel: impl FnOnce(&[Object], &mut dyn FnMut(f32)) -> A + Send,
+    ) -> Result<(), ImageSizeError> {
+        self.render_into_impl(target, scene, width, height, crop, accel, false)?;
+        Ok(())
+    }
+
+    /// The shared implementation of [CpuRenderer::render_into] and [CpuRenderer::render_with_masks],
+    /// which only builds the masks if `masks` is set.
+    #[allow(clippy::too_many_arguments)]
+    fn render_into_impl<A: Accel>(
+        self,
+        target: &mut [PixelResult],
+        scene: &Scene,
+        width: u32,
+        height: u32,
+        crop: Option<Block>,
+        accel: impl FnOnce(&[Object], &mut dyn FnMut(f32)) -> A + Send,
+        masks: bool,
+    ) -> Result<Option<HashMap<String, DiscreteImage>>, ImageSizeError> {
+        let pixel_count = self.pixel_count(width, height)?;
+        assert_eq!(target.len(), pixel_count, "Target buffer size does not match image size {}x{}", width, height);
+
+        let masks = match self.threads {
+            None => self.render_into_current_pool(target, scene, width, height, crop, accel, masks),
+            Some(threads) => {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .expect("Failed to build render thread pool");
+                pool.install(|| self.render_into_current_pool(target, scene, width, height, crop, accel, masks))
+            }
+        };
+        Ok(masks)
+    }
+
+    /// The implementation of [CpuRenderer::render_into_impl], using whatever rayon pool it's called from.
+    #[allow(clippy::too_many_arguments)]
+    fn render_into_current_pool<A: Accel>(
+        self,
+        target: &mut [PixelResult],
+        scene: &Scene,
+        width: u32,
+        height: u32,
+        crop: Option<Block>,
+        accel: impl FnOnce(&[Object], &mut dyn FnMut(f32)) -> A,
+        masks: bool,
+    ) -> Option<HashMap<String, DiscreteImage>> {
+        target.fill(PixelResult::default());
+
+        let region = crop.unwrap_or(Block { x: 0, y: 0, width, height });
+        assert!(
+            region.x_range().end <= width && region.y_range().end <= height,
+            "Crop region {:?} does not fit in image of size {}x{}", region, width, height
+        );
+
+        let mut progress_handler = self.progress_handler;
+        let rejected_hits = AtomicU64::new(0);
+
         println!("Building accel");
         let start = Instant::now();
-        let accel = accel(&scene.objects);
+        progress_handler.prepare(PrepareStage::BuildAccel, 0.0);
+        let accel = accel(&scene.objects, &mut |progress| progress_handler.prepare(PrepareStage::BuildAccel, progress));
+        progress_handler.prepare(PrepareStage::BuildAccel, 1.0);
         println!("  {:?}", accel);
         println!("  took {:?}", start.elapsed());
 
-        let prepared_scene = CpuPreparedScene::new(scene, self.settings, accel, width, height);
+        progress_handler.prepare(PrepareStage::PrepareScene, 0.0);
+        let prepared_scene = CpuPreparedScene::new(scene, self.settings, &self.lights, accel, width, height);
+        progress_handler.prepare(PrepareStage::PrepareScene, 1.0);
 
-        let mut progress_handler = self.progress_handler.init(width, height);
+        let light_film = match self.settings.strategy {
+            Strategy::LightTracing => {
+                println!("Tracing light paths");
+                let start = Instant::now();
+                progress_handler.prepare(PrepareStage::TraceLightPaths, 0.0);
+                let film = trace_light_paths(&prepared_scene, width, height, &rejected_hits);
+                progress_handler.prepare(PrepareStage::TraceLightPaths, 1.0);
+                println!("  took {:?}", start.elapsed());
+                Some(film)
+            }
+            Strategy::Simple | Strategy::SampleLights | Strategy::ObjectIds | Strategy::DirectOnly | Strategy::TraversalCost { .. }
+            | Strategy::AmbientOcclusion { .. } | Strategy::EmissionOnly => None,
+        };
+
+        let mut progress_handler = progress_handler.init(width, height, region);
+        let mask_groups = masks.then(|| MaskGroups::new(&scene.objects));
+        let mut object_masks = mask_groups.as_ref().map(|groups| ObjectMasks::new(groups, width, height));
+        let mut object_masks_target = object_masks.as_mut();
 
         // channel to send results back to this thread
-        let (sender, receiver) =
-            crossbeam::channel::unbounded::<(Block, Vec<PixelResult>)>();
-
-        // start the collector thread responsible to collecting the final output and reporting progress
-        let builder = std::thread::Builder::new().name("collector".to_owned());
-        let collector_handle = builder.spawn(move || {
-            let target_buf = vec![PixelResult::default(); (width * height) as usize];
-            let mut target = ImgVec::new(target_buf, width as usize, height as usize);
-
-            for (block, pixels) in receiver.clone() {
-                for dy in 0..block.height {
-                    for dx in 0..block.width {
-                        target[(block.x + dx, block.y + dy)] = pixels[(dy * block.width + dx) as usize];
+        let (sender, receiver) = crossbeam::channel::unbounded::<Message>();
+
+        std::thread::scope(|scope| {
+            let rejected_hits = &rejected_hits;
+
+            // start the collector thread responsible to collecting the final output and reporting progress
+            let builder = std::thread::Builder::new().name("collector".to_owned());
+            let collector_handle = builder.spawn_scoped(scope, move || {
+                for message in receiver.clone() {
+                    match message {
+                        Message::Block(block, pixels, coverage) => {
+                            for ((x, y), &pixel) in block.iter().zip(&pixels) {
+                                target[y as usize * width as usize + x as usize] = pixel;
+                            }
+                            if let (Some(object_masks), Some(coverage)) = (&mut object_masks_target, coverage) {
+                                object_masks.put(block, &coverage);
+                            }
+
+                            P::update(&mut progress_handler, block, &pixels);
+                        }
+                        Message::PassFinished(pass) => P::pass_finished(&mut progress_handler, pass),
                     }
                 }
 
-                P::update(&mut progress_handler, block, &pixels);
-            }
+                // the render threads are done once all senders are dropped
+                let stats = RenderStats { rejected_hits: rejected_hits.load(Ordering::Relaxed) };
+                P::finished(&mut progress_handler, stats);
+            }).expect("Failed to spawn collector thread");
 
-            target
-        }).expect("Failed to spawn collector thread");
+            let blocks = split_into_blocks(region, self.settings.block_order);
+            let light_film = light_film.as_ref();
 
-        let mut blocks = split_into_blocks(width, height);
-        blocks.shuffle(&mut thread_rng());
+            match self.settings.stop_condition {
+                StopCondition::Progressive { max_samples, min_rms_change } => {
+                    render_progressive(&prepared_scene, &blocks, light_film, self.subsampling, max_samples, min_rms_change, mask_groups.as_ref(), rejected_hits, &sender);
+                }
+                StopCondition::SampleCount(_) | StopCondition::Variance { .. } => {
+                    // render everything on a thread pool, send data to the channel
+                    blocks.par_iter().panic_fuse().for_each_init(thread_rng, |rng, block: &Block| {
+                        let mut coverage = mask_groups.as_ref().map(|groups| BlockCoverage::new(groups, *block));
+                        let (mut data, rejected) = count_rejected_hits(|| {
+                            block.iter().enumerate()
+                                .map(|(i, (x, y))| {
+                                    if !self.subsampling.is_traced(*block, x, y) {
+                                        return PixelResult::default();
+                                    }
+                                    let mut pixel = match &mut coverage {
+                                        Some(coverage) => prepared_scene.calculate_pixel_logged(rng, x, y, &mut coverage.pixel(i)),
+                                        None => prepared_scene.calculate_pixel(rng, x, y),
+                                    };
+                                    if let Some(light_film) = light_film {
+                                        pixel.color += light_film.get(x, y);
+                                    }
+                                    pixel
+                                })
+                                .collect::<Vec<PixelResult>>()
+                        });
+                        rejected_hits.fetch_add(rejected, Ordering::Relaxed);
+                        self.subsampling.reconstruct(*block, &mut data);
+                        let coverage = coverage.map(|coverage| coverage.coverage(self.subsampling));
 
-        // render everything on a thread pool, send data to the channel
-        blocks.par_iter().panic_fuse().for_each_init(thread_rng, |rng, block: &Block| {
-            let mut data = Vec::new();
-            for y in block.y_range() {
-                for x in block.x_range() {
-                    data.push(prepared_scene.calculate_pixel(rng, x, y))
+                        sender.send(Message::Block(*block, data, coverage)).expect("Failed to send block result over channel");
+                    });
                 }
             }
 
-            sender.send((*block, data)).expect("Failed to send block result over channel");
+            drop(sender);
+
+            collector_handle.join()
+                .expect("Joining collector thread deadlocked?")
         });
 
-        drop(sender);
+        object_masks.map(ObjectMasks::into_map)
+    }
+}
+
+/// Render `blocks` in passes that double the number of samples per pixel, see [StopCondition::Progressive].
+#[allow(clippy::too_many_arguments)]
+fn render_progressive<A: Accel>(
+    prepared_scene: &CpuPreparedScene<A>,
+    blocks: &[Block],
+    light_film: Option<&LightFilm>,
+    subsampling: Subsampling,
+    max_samples: u32,
+    min_rms_change: f32,
+    mask_groups: Option<&MaskGroups>,
+    rejected_hits: &AtomicU64,
+    sender: &Sender<Message>,
+) {
+    let mut estimators: Vec<(Block, Vec<ColorVarianceEstimator>, Option<BlockCoverage>)> = blocks.iter()
+        .map(|&block| {
+            let coverage = mask_groups.map(|groups| BlockCoverage::new(groups, block));
+            (block, vec![ColorVarianceEstimator::default(); block.pixel_count() as usize], coverage)
+        })
+        .collect();
+    let traced_count = blocks.iter()
+        .map(|&block| block.iter().filter(|&(x, y)| subsampling.is_traced(block, x, y)).count() as u64)
+        .sum::<u64>();
 
-        let result = collector_handle.join()
-            .expect("Joining collector thread deadlocked?");
-        result
+    let mut samples = 0;
+    while samples < max_samples {
+        let next_samples = min(max(2 * samples, 1), max_samples);
+
+        let squared_change: f64 = estimators.par_iter_mut().panic_fuse()
+            .map_init(thread_rng, |rng, (block, block_estimators, coverage)| {
+                let mut squared_change = 0.0;
+
+                let (mut data, rejected) = count_rejected_hits(|| {
+                    block.iter().zip(block_estimators.iter_mut()).enumerate()
+                        .map(|(i, ((x, y), estimator))| {
+                            if !subsampling.is_traced(*block, x, y) {
+                                return PixelResult::default();
+                            }
+                            let prev_mean = estimator.mean;
+                            for _ in samples..next_samples {
+                                let (color, alpha) = match coverage {
+                                    Some(coverage) => prepared_scene.sample_pixel_logged(rng, x, y, estimator.count, &mut coverage.pixel(i)),
+                                    None => prepared_scene.sample_pixel_indexed(rng, x, y, estimator.count),
+                                };
+                                estimator.update_with_alpha(color, alpha);
+                            }
+
+                            let delta = estimator.mean - prev_mean;
+                            squared_change += ((delta.red * delta.red + delta.green * delta.green + delta.blue * delta.blue) / 3.0) as f64;
+
+                            let mut pixel = pixel_result(estimator);
+                            if let Some(light_film) = light_film {
+                                pixel.color += light_film.get(x, y);
+                            }
+                            pixel
+                        })
+                        .collect::<Vec<PixelResult>>()
+                });
+                rejected_hits.fetch_add(rejected, Ordering::Relaxed);
+                subsampling.reconstruct(*block, &mut data);
+                let coverage = coverage.as_ref().map(|coverage| coverage.coverage(subsampling));
+
+                sender.send(Message::Block(*block, data, coverage)).expect("Failed to send block result over channel");
+                squared_change
+            })
+            .sum();
+
+        // the first pass starts from black, so its change is meaningless
+        let rms_change = if samples == 0 {
+            None
+        } else {
+            Some((squared_change / traced_count as f64).sqrt() as f32)
+        };
+        samples = next_samples;
+
+        sender.send(Message::PassFinished(PassInfo { samples, rms_change })).expect("Failed to send pass result over channel");
+
+        if rms_change.is_some_and(|rms_change| rms_change < min_rms_change) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::common::math::{Angle, Transform, Vec3};
+    use crate::common::progress::{Block, NoProgress, PixelResult, PrepareStage, ProgressHandler};
+    use crate::common::scene::{Camera, Object, Scene, Shape, Sky};
+    use crate::cpu::accel::NoAccel;
+    use crate::cpu::driver::{BLOCK_SIZE, CpuRenderer, DEFAULT_MAX_PIXELS, hilbert_index, ImageSizeError, split_into_blocks};
+    use crate::cpu::renderer::{BlockOrder, CpuRenderSettings, LightSelection, StopCondition};
+    use crate::cpu::subsampling::Subsampling;
+    use crate::demos::{material_diffuse, VACUUM, WHITE};
+
+    /// A scene with the given objects under a white sky, seen by a camera at the origin looking along -z.
+    fn test_scene(objects: Vec<Object>) -> Scene {
+        let camera = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM };
+        Scene { objects, sky: Sky::Uniform(WHITE), camera }
+    }
+
+    /// A renderer without anti-aliasing that renders the blocks in row-major order.
+    fn test_renderer<P: ProgressHandler>(stop_condition: StopCondition, progress_handler: P, threads: Option<usize>, max_pixels: u64) -> CpuRenderer<P> {
+        CpuRenderer {
+            settings: CpuRenderSettings {
+                stop_condition,
+                anti_alias: false,
+                block_order: BlockOrder::RowMajor,
+                ..Default::default()
+            },
+            lights: LightSelection::Auto,
+            progress_handler,
+            threads,
+            max_pixels,
+            subsampling: Subsampling::Full,
+        }
+    }
+
+    #[test]
+    fn hilbert_neighbours() {
+        let n = 8;
+        let mut cells: Vec<(u32, u32)> = (0..n).flat_map(|y| (0..n).map(move |x| (x, y))).collect();
+        cells.sort_by_key(|&(x, y)| hilbert_index(n, x, y));
+
+        for pair in cells.windows(2) {
+            let (ax, ay) = pair[0];
+            let (bx, by) = pair[1];
+            assert_eq!(ax.abs_diff(bx) + ay.abs_diff(by), 1, "{:?} is not a neighbour", pair);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn block_orders_cover_image() {
+        let (width, height): (u32, u32) = (100, 37);
+        let expected_count = width.div_ceil(BLOCK_SIZE) * height.div_ceil(BLOCK_SIZE);
+
+        for order in [BlockOrder::Shuffled, BlockOrder::RowMajor, BlockOrder::Hilbert] {
+            let blocks = split_into_blocks(Block { x: 0, y: 0, width, height }, order);
+            assert_eq!(blocks.len() as u32, expected_count);
+
+            let area: u32 = blocks.iter().map(|b| b.width * b.height).sum();
+            assert_eq!(area, width * height);
+        }
+    }
+
+    #[test]
+    fn blocks_stay_in_crop() {
+        let crop = Block { x: 10, y: 20, width: 40, height: 17 };
+
+        for order in [BlockOrder::Shuffled, BlockOrder::RowMajor, BlockOrder::Hilbert] {
+            let blocks = split_into_blocks(crop, order);
+
+            let area: u32 = blocks.iter().map(|b| b.pixel_count()).sum();
+            assert_eq!(area, crop.pixel_count());
+            for block in blocks {
+                assert!(crop.x_range().contains(&block.x) && block.x_range().end <= crop.x_range().end);
+                assert!(crop.y_range().contains(&block.y) && block.y_range().end <= crop.y_range().end);
+            }
+        }
+    }
+
+    /// Records the number of threads in the pool the scene is prepared on.
+    struct ThreadCountProgress(Arc<AtomicUsize>);
+
+    impl ProgressHandler for ThreadCountProgress {
+        type State = ();
+
+        fn prepare(&mut self, _: PrepareStage, _: f32) {
+            self.0.store(rayon::current_num_threads(), Ordering::Relaxed);
+        }
+
+        fn init(self, _: u32, _: u32, _: Block) {}
+        fn update(_: &mut Self::State, _: Block, _: &[PixelResult]) {}
+    }
+
+    #[test]
+    fn render_in_separate_pool() {
+        let scene = test_scene(vec![]);
+        let threads = rayon::current_num_threads() + 3;
+        let observed = Arc::new(AtomicUsize::new(0));
+
+        let renderer = test_renderer(StopCondition::SampleCount(1), ThreadCountProgress(observed.clone()), Some(threads), DEFAULT_MAX_PIXELS);
+        let image = renderer.render(&scene, 4, 4, None, |_: &[Object], _: &mut dyn FnMut(f32)| NoAccel).unwrap();
+
+        assert_eq!(observed.load(Ordering::Relaxed), threads);
+        assert!(image.pixels().all(|pixel| pixel.color == WHITE));
+    }
+
+    #[test]
+    fn refuse_huge_images() {
+        let scene = test_scene(vec![]);
+        let renderer = |max_pixels| test_renderer(StopCondition::SampleCount(1), NoProgress, None, max_pixels);
+        let accel = |_: &[Object], _: &mut dyn FnMut(f32)| -> NoAccel { panic!("Should not start rendering") };
+
+        let (width, height) = (u32::MAX, 1 << 20);
+        let max_pixels = DEFAULT_MAX_PIXELS;
+        assert_eq!(renderer(max_pixels).render(&scene, width, height, None, accel).unwrap_err(), ImageSizeError::TooLarge { width, height, max_pixels });
+        assert_eq!(renderer(16).render(&scene, 5, 4, None, accel).unwrap_err(), ImageSizeError::TooLarge { width: 5, height: 4, max_pixels: 16 });
+        assert_eq!(renderer(16).pixel_count(4, 4), Ok(16));
+
+        if usize::BITS < 64 {
+            assert_eq!(renderer(u64::MAX).pixel_count(u32::MAX, u32::MAX), Err(ImageSizeError::Overflow { width: u32::MAX, height: u32::MAX }));
+        }
+    }
+
+    #[test]
+    fn masks_per_group() {
+        // two squares named "left" cover the left half of the image, an unnamed one the top right quadrant
+        let square = |name: Option<&str>, x: f32, y: f32| Object {
+            name: name.map(str::to_owned),
+            ..Object::new(Shape::Square, material_diffuse(WHITE), Transform::translate(Vec3::new(x, y, -1.0)))
+        };
+        let scene = test_scene(vec![square(Some("left"), -1.0, -1.0), square(Some("left"), -1.0, 0.0), square(None, 0.0, 0.0)]);
+
+        for stop_condition in [StopCondition::SampleCount(1), StopCondition::Progressive { max_samples: 2, min_rms_change: 0.0 }] {
+            let renderer = test_renderer(stop_condition, NoProgress, None, DEFAULT_MAX_PIXELS);
+            let (_, masks) = renderer.render_with_masks(&scene, 4, 4, None, |_: &[Object], _: &mut dyn FnMut(f32)| NoAccel).unwrap();
+
+            assert_eq!(masks.keys().collect::<Vec<_>>(), vec!["left"]);
+            for (x, y, pixel) in masks["left"].enumerate_pixels() {
+                let expected = if x < 2 { 255 } else { 0 };
+                assert_eq!(pixel.0, [expected; 3], "{:?} pixel ({}, {})", stop_condition, x, y);
+            }
+        }
+    }
+}
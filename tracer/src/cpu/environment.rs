@@ -0,0 +1,303 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use crate::common::daylight::in_disk;
+use crate::common::math::{Norm, Point2, Unit, Vec3};
+use crate::common::texture::{equirect_direction, equirect_uv, ImageTexture, luminance};
+
+/// Importance sampler for an equirectangular environment map.
+///
+/// Pixels are picked proportional to their luminance times the solid angle they cover,
+/// using a marginal distribution over the rows and a conditional distribution over the pixels within each row.
+/// The luminance of a pixel is the maximum of its neighbourhood, see [filtered_luminance].
+///
+/// A sun that is much smaller than a pixel can be sampled separately, see [EnvironmentSampler::with_sun].
+pub struct EnvironmentSampler {
+    width: u32,
+    height: u32,
+    /// cumulative distribution over the rows, the last value is 1
+    row_cdf: Vec<f32>,
+    /// cumulative distribution over the pixels of each row, the last value of each row is 1
+    pixel_cdf: Vec<f32>,
+    sun: Option<SunDisk>,
+}
+
+/// A small disk of directions that is sampled uniformly, see [EnvironmentSampler::with_sun].
+struct SunDisk {
+    direction: Unit<Vec3>,
+    sin_radius: f32,
+    /// `1 - cos(radius)`, computed separately since it loses most of its precision as a difference
+    one_minus_cos: f32,
+}
+
+impl EnvironmentSampler {
+    /// Build the sampler, returns `None` if the environment is completely black.
+    pub fn new(image: &ImageTexture) -> Option<Self> {
+        let width = image.width();
+        let height = image.height();
+
+        let mut row_cdf = Vec::with_capacity(height as usize);
+        let mut pixel_cdf = Vec::with_capacity((width * height) as usize);
+        let mut total = 0.0;
+
+        for y in 0..height {
+            // rows near the poles cover a smaller solid angle
+            let solid_angle = row_latitude(y, height).cos();
+
+            let row_start = pixel_cdf.len();
+            let mut row_total = 0.0;
+            for x in 0..width {
+                row_total += filtered_luminance(image, x, y) * solid_angle;
+                pixel_cdf.push(row_total);
+            }
+            normalize_cdf(&mut pixel_cdf[row_start..], row_total);
+
+            total += row_total;
+            row_cdf.push(total);
+        }
+
+        if !(total > 0.0 && total.is_finite()) {
+            return None;
+        }
+        normalize_cdf(&mut row_cdf, total);
+
+        Some(EnvironmentSampler { width, height, row_cdf, pixel_cdf, sun: None })
+    }
+
+    /// Also sample the disk of directions within `angular_radius` of `direction`, like the sun of
+    /// [Sky::Analytic](crate::common::scene::Sky::Analytic) that's too small and bright to be part of the map.
+    pub fn with_sun(self, direction: Unit<Vec3>, angular_radius: f32) -> Self {
+        let one_minus_cos = 2.0 * (angular_radius / 2.0).sin().powi(2);
+        let sun = SunDisk { direction, sin_radius: angular_radius.sin(), one_minus_cos };
+        EnvironmentSampler { sun: Some(sun), ..self }
+    }
+
+    /// One sample of the map and one of the sun if there is one, each with the sum of the probability densities of
+    /// both in solid angle measure. Dividing by that weighs them with the balance heuristic of multiple importance
+    /// sampling, adding up the weighted samples gives a single estimate that is good both for the sun and for the map.
+    pub fn samples(&self, rng: &mut impl Rng) -> impl Iterator<Item=(Unit<Vec3>, f32)> + '_ {
+        let map = self.sample(rng);
+        let sun = self.sun.as_ref().map(|sun| {
+            let direction = sun.sample(rng);
+            (direction, self.pdf(direction))
+        });
+
+        // the map density of the map sample is already known, only the sun one still has to be looked up
+        std::iter::once(map).chain(sun).map(|(direction, map_pdf)| {
+            let sun_pdf = self.sun.as_ref().map_or(0.0, |sun| sun.pdf(direction));
+            (direction, map_pdf + sun_pdf)
+        })
+    }
+
+    /// Sample a direction from the map, returns the direction and its probability density in solid angle measure.
+    pub fn sample(&self, rng: &mut impl Rng) -> (Unit<Vec3>, f32) {
+        let y = sample_cdf(&self.row_cdf, rng.gen());
+        let x = sample_cdf(self.row_pixel_cdf(y), rng.gen());
+
+        let (dx, dy): (f32, f32) = rng.gen();
+        let u = (x as f32 + dx) / self.width as f32;
+        let v = 1.0 - (y as f32 + dy) / self.height as f32;
+
+        let direction = equirect_direction(Point2::new(u, v));
+        (direction, self.pdf_pixel(x, y, v))
+    }
+
+    /// The probability density of sampling the given direction from the map, in solid angle measure.
+    pub fn pdf(&self, direction: Unit<Vec3>) -> f32 {
+        let uv = equirect_uv(direction);
+        let x = ((uv.coords.x * self.width as f32) as u32).min(self.width - 1);
+        let y = (((1.0 - uv.coords.y) * self.height as f32) as u32).min(self.height - 1);
+        self.pdf_pixel(x, y, uv.coords.y)
+    }
+
+    fn pdf_pixel(&self, x: u32, y: u32, v: f32) -> f32 {
+        let p_row = cdf_step(&self.row_cdf, y);
+        let p_pixel = cdf_step(self.row_pixel_cdf(y), x);
+
+        // convert from pixel probability to uv density to solid angle density
+        let pdf_uv = p_row * p_pixel * (self.width * self.height) as f32;
+        let cos_latitude = ((v - 0.5) * PI).cos();
+        pdf_uv / (2.0 * PI * PI * cos_latitude)
+    }
+
+    fn row_pixel_cdf(&self, y: u32) -> &[f32] {
+        &self.pixel_cdf[(y * self.width) as usize..][..self.width as usize]
+    }
+}
+
+impl SunDisk {
+    fn sample(&self, rng: &mut impl Rng) -> Unit<Vec3> {
+        let one_minus_cos = rng.gen::<f32>() * self.one_minus_cos;
+        let sin = (one_minus_cos * (2.0 - one_minus_cos)).sqrt();
+        let (sin_phi, cos_phi) = (2.0 * PI * rng.gen::<f32>()).sin_cos();
+
+        let (tangent, bitangent) = self.direction.orthonormal_basis();
+        (*tangent * (sin * cos_phi) + *bitangent * (sin * sin_phi) + *self.direction * (1.0 - one_minus_cos)).normalized()
+    }
+
+    fn pdf(&self, direction: Unit<Vec3>) -> f32 {
+        if in_disk(direction, self.direction, self.sin_radius) {
+            1.0 / (2.0 * PI * self.one_minus_cos)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The maximum luminance of the pixels the bilinear lookup of [ImageTexture::sample_equirect] can blend in
+/// anywhere within pixel `(x, y)`: the 3x3 neighbourhood, and the pole for the top and bottom row.
+/// Using the pixel itself would give zero probability to the edge of a black pixel next to a bright one,
+/// which the sky is still visible through.
+fn filtered_luminance(image: &ImageTexture, x: u32, y: u32) -> f32 {
+    let (width, height) = (image.width(), image.height());
+
+    let mut result: f32 = 0.0;
+    for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+        for dx in [width - 1, 0, 1] {
+            result = result.max(luminance(image.get((x + dx) % width, ny)));
+        }
+    }
+
+    let pole = |direction: Unit<Vec3>| luminance(image.sample_equirect(equirect_uv(direction)));
+    if y == 0 {
+        result = result.max(pole(Vec3::y_axis()));
+    }
+    if y == height - 1 {
+        result = result.max(pole(-Vec3::y_axis()));
+    }
+
+    result.max(0.0)
+}
+
+fn row_latitude(y: u32, height: u32) -> f32 {
+    (0.5 - (y as f32 + 0.5) / height as f32) * PI
+}
+
+fn normalize_cdf(cdf: &mut [f32], total: f32) {
+    if total > 0.0 {
+        cdf.iter_mut().for_each(|c| *c /= total);
+    } else {
+        // fall back to a uniform distribution, this row will never be picked anyway
+        let len = cdf.len() as f32;
+        cdf.iter_mut().enumerate().for_each(|(i, c)| *c = (i + 1) as f32 / len);
+    }
+}
+
+fn sample_cdf(cdf: &[f32], r: f32) -> u32 {
+    (cdf.partition_point(|&c| c <= r) as u32).min(cdf.len() as u32 - 1)
+}
+
+fn cdf_step(cdf: &[f32], index: u32) -> f32 {
+    let index = index as usize;
+    cdf[index] - if index == 0 { 0.0 } else { cdf[index - 1] }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use rand_distr::{Distribution, UnitSphere};
+
+    use crate::common::daylight::{in_sun_disk, SUN_ANGULAR_RADIUS};
+    use crate::common::math::{Norm, Point2, Unit, Vec3};
+    use crate::common::scene::Color;
+    use crate::common::texture::{equirect_direction, equirect_uv, ImageTexture};
+    use crate::cpu::environment::EnvironmentSampler;
+
+    fn sun_sky() -> ImageTexture {
+        ImageTexture::from_fn(64, 32, |x, y| {
+            if (40..42).contains(&x) && (6..8).contains(&y) {
+                Color::new(1000.0, 1000.0, 900.0)
+            } else {
+                Color::new(0.2, 0.3, 0.5)
+            }
+        })
+    }
+
+    fn mean_variance(values: &[f32]) -> (f32, f32) {
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32;
+        (mean, variance)
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let sampler = EnvironmentSampler::new(&sun_sky()).unwrap();
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        let n = 100_000;
+        let total: f32 = (0..n).map(|_| {
+            let direction = Unit::new_unchecked(Vec3::from_slice(&UnitSphere.sample(rng)));
+            sampler.pdf(direction) * 4.0 * PI
+        }).sum();
+
+        let integral = total / n as f32;
+        assert!((integral - 1.0).abs() < 0.1, "pdf integrates to {}", integral);
+    }
+
+    #[test]
+    fn lower_variance_than_uniform() {
+        let image = sun_sky();
+        let sampler = EnvironmentSampler::new(&image).unwrap();
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let up = Vec3::y_axis();
+
+        // estimate the irradiance on an upwards facing surface
+        let radiance = |d: Unit<Vec3>| image.sample_equirect(equirect_uv(d)).green * d.dot(*up).max(0.0);
+        let samples = 64;
+
+        let importance: Vec<f32> = (0..200).map(|_| {
+            (0..samples).map(|_| {
+                let (d, pdf) = sampler.sample(rng);
+                radiance(d) / pdf
+            }).sum::<f32>() / samples as f32
+        }).collect();
+        let uniform: Vec<f32> = (0..200).map(|_| {
+            (0..samples).map(|_| {
+                let d = Unit::new_unchecked(Vec3::from_slice(&UnitSphere.sample(rng)));
+                radiance(d) * 4.0 * PI
+            }).sum::<f32>() / samples as f32
+        }).collect();
+
+        // numerically integrate over a fine uv grid for the reference value
+        let (grid_w, grid_h) = (1024, 512);
+        let mut expected = 0.0;
+        for gy in 0..grid_h {
+            for gx in 0..grid_w {
+                let uv = Point2::new((gx as f32 + 0.5) / grid_w as f32, (gy as f32 + 0.5) / grid_h as f32);
+                let cos_latitude = ((uv.coords.y - 0.5) * PI).cos();
+                expected += radiance(equirect_direction(uv)) * cos_latitude * 2.0 * PI * PI / (grid_w * grid_h) as f32;
+            }
+        }
+
+        let (mean_importance, var_importance) = mean_variance(&importance);
+        let (_, var_uniform) = mean_variance(&uniform);
+        assert!((mean_importance - expected).abs() < 0.05 * expected, "expected {}, got {}", expected, mean_importance);
+        assert!(var_importance * 10.0 < var_uniform, "importance {}, uniform {}", var_importance, var_uniform);
+    }
+
+    #[test]
+    fn sun_sampled_with_map() {
+        let sky = ImageTexture::from_fn(64, 32, |_, _| Color::new(0.2, 0.2, 0.2));
+        let sun = Vec3::new(0.3, 0.8, 0.2).normalized();
+        let sampler = EnvironmentSampler::new(&sky).unwrap().with_sun(sun, SUN_ANGULAR_RADIUS);
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        // the irradiance on an upwards facing surface from the uniform sky and the much brighter sun disk
+        let sun_radiance = 1e5;
+        let radiance = |d: Unit<Vec3>| 0.2 + if in_sun_disk(d, sun) { sun_radiance } else { 0.0 };
+        let expected = 0.2 * PI + sun_radiance * PI * SUN_ANGULAR_RADIUS.sin().powi(2) * sun.y;
+
+        let estimates: Vec<f32> = (0..1000).map(|_| {
+            sampler.samples(rng).map(|(d, pdf)| radiance(d) * d.y.max(0.0) / pdf).sum()
+        }).collect();
+        let (mean, _) = mean_variance(&estimates);
+
+        assert!((mean - expected).abs() < 0.02 * expected, "expected {}, got {}", expected, mean);
+        // every estimate finds the sun, without fireflies from the map samples that happen to hit it
+        assert!(estimates.iter().all(|&e| (e - expected).abs() < 0.5 * expected), "{:?}", estimates.iter().find(|&&e| (e - expected).abs() >= 0.5 * expected));
+    }
+}
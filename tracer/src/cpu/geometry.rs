@@ -1,15 +1,19 @@
 #![allow(dead_code)]
 
+use std::f32::consts::PI;
 use std::fmt::Debug;
 use std::ops::Mul;
 
 use rand::distributions::Distribution;
 use rand::Rng;
-use rand_distr::UnitSphere;
+use rand_distr::{UnitDisc, UnitSphere};
 
-use crate::common::math::{Norm, Point2, Point3, Transform, Unit, Vec2, Vec3};
-use crate::common::scene::{Object, Shape};
+use crate::common::math::{from_real, Norm, Point2, Point3, Real, Transform, Unit, Vec2, Vec3};
+use crate::common::scene::{BumpMap, NormalMap, Object, Sdf, Shape};
+use crate::common::texture::equirect_uv;
 use crate::cpu::accel::ObjectId;
+use crate::cpu::renderer::disk_to_hemisphere;
+use crate::cpu::stats::record_rejected_hit;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Ray {
@@ -38,25 +42,64 @@ impl Mul<&Ray> for Transform {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// A ray transformed to object space, where the intersections are calculated.
+///
+/// The start is also kept in [Real] precision. For large objects or objects far from the origin it's very close to the
+/// surface relative to the size of the object, and rounding it to `f32` would lose most of the distance to the surface.
+struct ObjectRay {
+    start: Point3,
+    real_start: [Real; 3],
+    direction: Unit<Vec3>,
+}
+
+impl ObjectRay {
+    fn new(transform: Transform, ray: &Ray) -> ObjectRay {
+        let [x, y, z] = transform.inv().mul_point_real(ray.start);
+        ObjectRay {
+            start: Point3::new(from_real(x), from_real(y), from_real(z)),
+            real_start: [x, y, z],
+            direction: (transform.inv() * *ray.direction).normalized(),
+        }
+    }
+
+    fn at(&self, t: f32) -> Point3 {
+        self.start + *self.direction * t
+    }
+
+    /// Like [ObjectRay::at], but starting from the [Real] precision start.
+    fn at_real(&self, t: Real) -> Point3 {
+        let at = |i: usize, direction: f32| from_real(self.real_start[i] + direction as Real * t);
+        Point3::new(at(0, self.direction.x), at(1, self.direction.y), at(2, self.direction.z))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Hit {
     pub t: f32,
     pub point: Point3,
     pub normal: Unit<Vec3>,
+    /// Texture coordinates, see [Shape] for the convention used by each shape.
+    pub uv: Point2,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ObjectHit {
     pub id: ObjectId,
     pub hit: Hit,
 }
 
 impl Hit {
+    /// Transform a hit found in object space back to world space, where `direction` is the world space ray direction.
+    ///
+    /// The object space ray direction is normalized, so `t` is divided by the length the transform gives to world space distances
+    /// along the ray. The normal is transformed with the inverse transpose, which keeps it pointing to the same side of the surface
+    /// even for mirrored transforms, so it must not be flipped again for negative determinants.
     fn transform(&self, transform: Transform, direction: Unit<Vec3>) -> Hit {
         Hit {
             t: self.t / (transform.inv() * (*direction)).norm(),
             point: transform * self.point,
             normal: transform.inv_transpose_mul(*self.normal).normalized(),
+            uv: self.uv,
         }
     }
 }
@@ -79,9 +122,10 @@ impl ObjectHit {
     }
 }
 
-fn sphere_intersect(ray: &Ray) -> Option<Hit> {
-    let b: f32 = ray.start.coords().dot(*ray.direction);
-    let c: f32 = ray.start.coords().norm_squared() - 1.0;
+fn sphere_intersect(ray: &ObjectRay) -> Option<Hit> {
+    let [sx, sy, sz] = ray.real_start;
+    let b = sx * ray.direction.x as Real + sy * ray.direction.y as Real + sz * ray.direction.z as Real;
+    let c = sx * sx + sy * sy + sz * sz - 1.0;
 
     let d = b * b - c;
     if d < 0.0 || (c > 0.0 && b > 0.0) {
@@ -98,30 +142,33 @@ fn sphere_intersect(ray: &Ray) -> Option<Hit> {
     };
 
     //renormalize for better accuracy and bail if zero
-    let result = ray.at(t).coords().try_normalized()?;
+    let result = ray.at_real(t).coords().try_normalized()?;
 
     Some(Hit {
-        t,
+        t: from_real(t),
         point: Point3::from_coords(*result),
         normal: result,
+        uv: equirect_uv(result),
     })
 }
 
-fn plane_intersect(ray: &Ray) -> Option<Hit> {
-    let t = -ray.start.z / ray.direction.z;
+fn plane_intersect(ray: &ObjectRay) -> Option<Hit> {
+    let t = from_real(-ray.real_start[2] / ray.direction.z as Real);
 
     if !t.is_finite() || t < 0.0 {
         None
     } else {
+        let point = ray.at(t);
         Some(Hit {
             t,
-            point: ray.at(t),
+            point,
             normal: Vec3::z_axis(),
+            uv: Point2::new(point.x, point.y),
         })
     }
 }
 
-fn triangle_intersect(ray: &Ray) -> Option<Hit> {
+fn triangle_intersect(ray: &ObjectRay) -> Option<Hit> {
     plane_intersect(ray).filter(|hit| {
         let x = hit.point.x;
         let y = hit.point.y;
@@ -129,7 +176,7 @@ fn triangle_intersect(ray: &Ray) -> Option<Hit> {
     })
 }
 
-fn square_intersect(ray: &Ray) -> Option<Hit> {
+fn square_intersect(ray: &ObjectRay) -> Option<Hit> {
     plane_intersect(ray).filter(|hit| {
         let x = hit.point.x;
         let y = hit.point.y;
@@ -137,13 +184,17 @@ fn square_intersect(ray: &Ray) -> Option<Hit> {
     })
 }
 
-fn cylinder_intersect(ray: &Ray) -> Option<Hit> {
+fn cylinder_intersect(ray: &ObjectRay) -> Option<Hit> {
     //work in xz plane
-    let start = Point2::new(ray.start.x, ray.start.z);
-    let (direction, dir_2d_norm) = Vec2::new(ray.direction.x, ray.direction.z).normalized_and_get();
+    let [sx, _, sz] = ray.real_start;
+    let (dx, dz) = (ray.direction.x as Real, ray.direction.z as Real);
+    let dir_2d_norm = (dx * dx + dz * dz).sqrt();
+    if dir_2d_norm == 0.0 {
+        return None;
+    }
 
-    let b: f32 = start.coords.dot(*direction);
-    let c: f32 = start.coords.norm_squared() - 1.0;
+    let b = (sx * dx + sz * dz) / dir_2d_norm;
+    let c = sx * sx + sz * sz - 1.0;
 
     let d = b * b - c;
     if d < 0.0 || (c > 0.0 && b > 0.0) {
@@ -162,16 +213,210 @@ fn cylinder_intersect(ray: &Ray) -> Option<Hit> {
     //scale back to 3D
     let t = t / dir_2d_norm;
 
-    let mut point = ray.at(t);
+    let mut point = ray.at_real(t);
+    let t = from_real(t);
     let normal = Vec3::new(point.x, 0.0, point.z).normalized();
     point.x = normal.x; //renormalize point for better accuracy
     point.z = normal.z;
 
-    if point != point {
+    if !point.is_finite() {
         return None;
     };
 
-    Some(Hit { t, point, normal })
+    let uv = Point2::new(angle_to_u(point.x, point.z), point.y);
+    Some(Hit { t, point, normal, uv })
+}
+
+fn torus_intersect(ray: &ObjectRay, tube_radius: f32) -> Option<Hit> {
+    let r = tube_radius as f64;
+
+    // move the start close to the torus first, the coefficients of the quartic lose a lot of precision for far away rays
+    let start = ray.start.coords();
+    let direction = *ray.direction;
+    let t_shift = (-start.dot(direction) - (1.0 + tube_radius)).max(0.0);
+    let o = start + direction * t_shift;
+    let (ox, oy, oz) = (o.x as f64, o.y as f64, o.z as f64);
+    let (dx, dy, dz) = (direction.x as f64, direction.y as f64, direction.z as f64);
+
+    // substitute the ray into `(|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2)` with `R = 1`
+    let m = ox * dx + oy * dy + oz * dz;
+    let k = ox * ox + oy * oy + oz * oz + 1.0 - r * r;
+    let (roots, count) = solve_quartic(
+        4.0 * m,
+        4.0 * m * m + 2.0 * k - 4.0 * (dx * dx + dz * dz),
+        4.0 * m * k - 8.0 * (ox * dx + oz * dz),
+        k * k - 4.0 * (ox * ox + oz * oz),
+    );
+
+    let t = roots[..count].iter()
+        .map(|&t| t as f32 + t_shift)
+        .filter(|&t| t >= 0.0)
+        .min_by(|a, b| a.total_cmp(b))?;
+
+    let point = ray.at(t);
+    // the normal points away from the closest point on the center circle of the tube
+    let ring = Vec3::new(point.x, 0.0, point.z).try_normalized()?;
+    let from_ring = point - Point3::from_coords(*ring);
+    let normal = from_ring.try_normalized()?;
+
+    let v = 0.5 + from_ring.y.atan2(from_ring.dot(*ring)) / (2.0 * PI);
+    let uv = Point2::new(angle_to_u(point.x, point.z), v);
+    Some(Hit { t, point, normal, uv })
+}
+
+fn cone_intersect(ray: &ObjectRay, capped: bool) -> Option<Hit> {
+    let o = ray.start;
+    let d = ray.direction;
+    let (ox, oy, oz) = (o.x as f64, o.y as f64, o.z as f64);
+    let (dx, dy, dz) = (d.x as f64, d.y as f64, d.z as f64);
+
+    // substitute the ray into `x^2 + z^2 = y^2`, in f64 since the roots are solved in f64 anyway
+    let a = dx * dx + dz * dz - dy * dy;
+    let b = 2.0 * (ox * dx + oz * dz - oy * dy);
+    let c = ox * ox + oz * oz - oy * oy;
+
+    let roots = if a.abs() > 1e-9 {
+        solve_quadratic(b / a, c / a).map(|t| t.map(|t| t as f32))
+    } else {
+        // the ray is parallel to the side of the cone, so it only crosses it once
+        [Some((-c / b) as f32), None]
+    };
+
+    // only keep the upper half of the double cone
+    let side = roots.into_iter().flatten()
+        .filter(|&t| t >= 0.0 && t.is_finite() && (0.0..=1.0).contains(&ray.at(t).y))
+        .min_by(|a, b| a.total_cmp(b))
+        .map(|t| {
+            let point = ray.at(t);
+            // the normal is undefined at the apex itself
+            let normal = Vec3::new(point.x, -point.y, point.z).try_normalized().unwrap_or(-Vec3::y_axis());
+            let uv = Point2::new(angle_to_u(point.x, point.z), point.y);
+            Hit { t, point, normal, uv }
+        });
+
+    let cap = if capped {
+        let t = (1.0 - o.y) / d.y;
+        let point = ray.at(t);
+        if t >= 0.0 && t.is_finite() && point.x * point.x + point.z * point.z <= 1.0 {
+            let uv = Point2::new(0.5 + point.x / 2.0, 0.5 + point.z / 2.0);
+            Some(Hit { t, point, normal: Vec3::y_axis(), uv })
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    match (side, cap) {
+        (Some(side), Some(cap)) => Some(if side.t <= cap.t { side } else { cap }),
+        (side, cap) => side.or(cap),
+    }
+}
+
+/// The real roots of `x^4 + a x^3 + b x^2 + c x + d`, using Ferrari's method followed by Newton polishing.
+/// Returns the roots and how many of them are valid.
+fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> ([f64; 4], usize) {
+    // depress the quartic with `x = y - a/4` into `y^4 + p y^2 + q y + r`
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut roots = [0.0; 4];
+    let mut count = 0;
+    let mut push_quadratic = |b: f64, c: f64| {
+        for y in solve_quadratic(b, c).into_iter().flatten() {
+            roots[count] = y;
+            count += 1;
+        }
+    };
+
+    if q.abs() < 1e-12 {
+        // biquadratic, solve for `y^2`
+        for y2 in solve_quadratic(p, r).into_iter().flatten() {
+            if y2 >= 0.0 {
+                push_quadratic(0.0, -y2);
+            }
+        }
+    } else {
+        // factor into `(y^2 + s y + alpha)(y^2 - s y + beta)`, where `s^2` is the positive root of the resolvent cubic
+        let z = largest_cubic_root(2.0 * p, p * p - 4.0 * r, -q * q);
+        if z <= 0.0 {
+            return (roots, 0);
+        }
+        let s = z.sqrt();
+        push_quadratic(s, (p + z - q / s) / 2.0);
+        push_quadratic(-s, (p + z + q / s) / 2.0);
+    }
+
+    let eval = |x: f64| (((x + a) * x + b) * x + c) * x + d;
+    let derivative = |x: f64| ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+    for root in &mut roots[..count] {
+        let mut x = *root - a / 4.0;
+        for _ in 0..2 {
+            // only keep steps that improve the root, newton can overshoot near double roots
+            let next = x - eval(x) / derivative(x);
+            if next.is_finite() && eval(next).abs() < eval(x).abs() {
+                x = next;
+            }
+        }
+        *root = x;
+    }
+
+    (roots, count)
+}
+
+/// The real roots of `x^2 + b x + c`, computed in a way that avoids cancellation.
+fn solve_quadratic(b: f64, c: f64) -> [Option<f64>; 2] {
+    let d = b * b - 4.0 * c;
+    if d < 0.0 {
+        return [None, None];
+    }
+
+    let q = -0.5 * (b + b.signum() * d.sqrt());
+    if q == 0.0 {
+        // both `b` and `c` are zero
+        return [Some(0.0), None];
+    }
+    [Some(q), Some(c / q)]
+}
+
+/// The largest real root of `x^3 + a x^2 + b x + c`.
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // depress the cubic with `x = y - a/3` into `y^3 + p y + q`
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let d = q * q / 4.0 + p * p * p / 27.0;
+
+    let y = if d > 0.0 {
+        // a single real root, Cardano's formula
+        let sqrt_d = d.sqrt();
+        (-q / 2.0 + sqrt_d).cbrt() + (-q / 2.0 - sqrt_d).cbrt()
+    } else {
+        // three real roots, the trigonometric solution gives the largest one for k = 0
+        let m = 2.0 * (-p / 3.0).sqrt();
+        if m == 0.0 {
+            0.0
+        } else {
+            let angle = (3.0 * q / (p * m)).clamp(-1.0, 1.0).acos() / 3.0;
+            m * angle.cos()
+        }
+    };
+
+    // polish the root, Cardano's formula can be inaccurate
+    let mut x = y - a / 3.0;
+    for _ in 0..2 {
+        let slope = (3.0 * x + 2.0 * a) * x + b;
+        if slope != 0.0 {
+            x -= (((x + a) * x + b) * x + c) / slope;
+        }
+    }
+    x
+}
+
+/// Map the angle of `(x, z)` around the y-axis to `[0, 1]`.
+fn angle_to_u(x: f32, z: f32) -> f32 {
+    0.5 + x.atan2(z) / (2.0 * PI)
 }
 
 pub trait Intersect {
@@ -181,11 +426,20 @@ pub trait Intersect {
 
     fn area(&self) -> f32;
 
+    /// Sample a point on the surface together with its weight, without taking into account where it's seen from.
+    /// Lights are sampled with [Intersect::sample_from] instead.
     fn sample<R: Rng>(&self, rng: &mut R) -> (f32, Point3);
+
+    /// Sample a point on the surface to light the point `from` with, together with its weight.
+    fn sample_from<R: Rng>(&self, from: Point3, rng: &mut R) -> (f32, Point3);
+
+    /// The probability density per solid angle that [Intersect::sample_from] picks the point seen from `from` in `direction`,
+    /// zero if the ray doesn't hit the surface.
+    fn pdf_from(&self, from: Point3, direction: Unit<Vec3>) -> f32;
 }
 
-fn intersect_transformed_shape(shape: Shape, transform: Transform, ray: &Ray) -> Option<Hit> {
-    let obj_ray = transform.inv() * ray;
+fn intersect_transformed_shape(shape: &Shape, transform: Transform, ray: &Ray) -> Option<Hit> {
+    let obj_ray = ObjectRay::new(transform, ray);
 
     let obj_hit = match shape {
         Shape::Sphere => sphere_intersect(&obj_ray),
@@ -193,18 +447,23 @@ fn intersect_transformed_shape(shape: Shape, transform: Transform, ray: &Ray) ->
         Shape::Triangle => triangle_intersect(&obj_ray),
         Shape::Square => square_intersect(&obj_ray),
         Shape::Cylinder => cylinder_intersect(&obj_ray),
+        Shape::Torus { tube_radius } => torus_intersect(&obj_ray, *tube_radius),
+        Shape::Cone { capped } => cone_intersect(&obj_ray, *capped),
+        Shape::Sdf(sdf) => sdf_intersect(&obj_ray, sdf),
     };
-    check_hit(&obj_hit);
+    let obj_hit = check_hit(obj_hit);
 
-    let world_hit = obj_hit.map(|hit| hit.transform(transform, ray.direction));
-    check_hit(&world_hit);
-
-    world_hit
+    check_hit(obj_hit.map(|hit| hit.transform(transform, ray.direction)))
 }
 
 impl Intersect for Object {
     fn intersect(&self, ray: &Ray) -> Option<Hit> {
-        intersect_transformed_shape(self.shape, self.transform, ray)
+        let hit = intersect_transformed_shape(&self.shape, self.transform, ray);
+        if self.material.cull_backface {
+            hit.filter(|hit| hit.normal.dot(*ray.direction) < 0.0)
+        } else {
+            hit
+        }
     }
 
     fn area_seen_from(&self, from: Point3) -> f32 {
@@ -217,25 +476,297 @@ impl Intersect for Object {
     }
 
     fn area(&self) -> f32 {
-        assert_eq!(self.shape, Shape::Sphere);
-
-        4.0 * std::f32::consts::PI
+        match self.shape {
+            Shape::Sphere => 4.0 * std::f32::consts::PI,
+            Shape::Square => (self.transform * *Vec3::x_axis()).cross(self.transform * *Vec3::y_axis()).norm(),
+            Shape::Triangle => (self.transform * *Vec3::x_axis()).cross(self.transform * *Vec3::y_axis()).norm() / 2.0,
+            _ => panic!("Area not implemented for {:?}", self.shape),
+        }
     }
 
     fn sample<R: Rng>(&self, rng: &mut R) -> (f32, Point3) {
-        assert_eq!(self.shape, Shape::Sphere);
+        match self.shape {
+            Shape::Sphere => {
+                let vec = Vec3::from_slice(&UnitSphere.sample(rng));
+                //TODO 2.0 is not exactly the correct weight because not exactly half of the sphere is visible
+                (2.0, self.transform * (Point3::origin() + vec))
+            }
+            // the area sample doesn't depend on where it's seen from
+            Shape::Square | Shape::Triangle => self.sample_from(Point3::origin(), rng),
+            _ => panic!("Sampling not implemented for {:?}", self.shape),
+        }
+    }
+
+    /// For squares and triangles the point is uniform over the area, so the weight is the inverse of the area density.
+    /// For spheres the direction from `from` is uniform over the visible cap, the weight is the inverse of the solid angle density.
+    /// Spheres must be uniformly scaled.
+    fn sample_from<R: Rng>(&self, from: Point3, rng: &mut R) -> (f32, Point3) {
+        match self.shape {
+            Shape::Sphere => {
+                let center = self.transform * Point3::origin();
+                let radius = (self.transform * *Vec3::x_axis()).norm();
+                let (axis, distance) = (center - from).normalized_and_get();
+
+                if distance <= radius {
+                    // inside the sphere all directions hit it
+                    let direction = Vec3::from_slice(&UnitSphere.sample(rng));
+                    let t = sphere_exit_distance(from - center, direction, radius);
+                    return (4.0 * PI, from + direction * t);
+                }
+
+                // sample uniformly within the cone of directions that hits the sphere
+                let cos_max = (1.0 - (radius / distance).powi(2)).max(0.0).sqrt();
+                let cos = 1.0 - rng.gen::<f32>() * (1.0 - cos_max);
+                let sin = (1.0 - cos * cos).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.gen::<f32>();
+                let (tangent, bitangent) = axis.orthonormal_basis();
+                let direction = *tangent * (sin * phi.cos()) + *bitangent * (sin * phi.sin()) + *axis * cos;
+
+                // the first intersection with the sphere along that direction
+                let t = distance * cos - (radius * radius - distance * distance * sin * sin).max(0.0).sqrt();
+                (2.0 * PI * (1.0 - cos_max), from + direction * t)
+            }
+            Shape::Square => {
+                let point = Point3::new(rng.gen(), rng.gen(), 0.0);
+                (self.area(), self.transform * point)
+            }
+            Shape::Triangle => {
+                // fold the points of the square outside the triangle back in
+                let (x, y) = (rng.gen::<f32>(), rng.gen::<f32>());
+                let point = if x + y > 1.0 { Point3::new(1.0 - x, 1.0 - y, 0.0) } else { Point3::new(x, y, 0.0) };
+                (self.area(), self.transform * point)
+            }
+            _ => panic!("Sampling not implemented for {:?}", self.shape),
+        }
+    }
+
+    fn pdf_from(&self, from: Point3, direction: Unit<Vec3>) -> f32 {
+        match self.shape {
+            Shape::Sphere => {
+                let center = self.transform * Point3::origin();
+                let radius = (self.transform * *Vec3::x_axis()).norm();
+                let (axis, distance) = (center - from).normalized_and_get();
+
+                if distance <= radius {
+                    return 1.0 / (4.0 * PI);
+                }
+
+                let cos_max = (1.0 - (radius / distance).powi(2)).max(0.0).sqrt();
+                if direction.dot(*axis) >= cos_max {
+                    1.0 / (2.0 * PI * (1.0 - cos_max))
+                } else {
+                    0.0
+                }
+            }
+            Shape::Square | Shape::Triangle => {
+                // convert the uniform area density to solid angle
+                match intersect_transformed_shape(&self.shape, self.transform, &Ray { start: from, direction }) {
+                    Some(hit) => hit.t * hit.t / (self.area() * direction.dot(*hit.normal).abs()),
+                    None => 0.0,
+                }
+            }
+            _ => panic!("Sampling not implemented for {:?}", self.shape),
+        }
+    }
+}
+
+impl Object {
+    /// Whether this is a sphere with `point` inside of it, also when it's scaled non-uniformly.
+    pub fn encloses(&self, point: Point3) -> bool {
+        // the sphere is the unit sphere in object space
+        self.shape == Shape::Sphere && (self.transform.inv() * point - Point3::origin()).norm_squared() <= 1.0
+    }
+
+    /// Like [Intersect::sample_from], but only for spheres that enclose `from`, eg. an emissive dome around the whole scene.
+    /// Every direction hits those, but only the ones above the surface at `from` with `normal` can light it,
+    /// so the direction is cosine weighted around `normal` instead of uniform over the whole sphere.
+    /// Sampling them like distant environment light keeps even a dome with a uniform emission free of noise.
+    ///
+    /// `None` for all other objects and points, which should use [Intersect::sample_from].
+    pub fn sample_enclosing<R: Rng>(&self, from: Point3, normal: Unit<Vec3>, rng: &mut R) -> Option<(f32, Point3)> {
+        if !self.encloses(from) {
+            return None;
+        }
+        let direction = disk_to_hemisphere(Vec2::from_slice(&UnitDisc.sample(rng)), normal);
+        let cos = direction.dot(*normal).max(f32::MIN_POSITIVE);
+
+        // leave the unit sphere in object space, the direction isn't normalized there so `t` is still the world distance
+        let inv = self.transform.inv();
+        let (start, object_direction) = (inv * from - Point3::origin(), inv * *direction);
+        let (a, b, c) = (object_direction.norm_squared(), start.dot(object_direction), start.norm_squared() - 1.0);
+        let t = (-b + (b * b - a * c).max(0.0).sqrt()) / a;
+        Some((PI / cos, from + *direction * t))
+    }
+}
+
+/// The distance along the unit `direction` from `start`, relative to the center of a sphere with `radius` it's inside of,
+/// to where it leaves that sphere.
+fn sphere_exit_distance(start: Vec3, direction: Vec3, radius: f32) -> f32 {
+    let b = start.dot(direction);
+    let c = start.norm_squared() - radius * radius;
+    -b + (b * b - c).max(0.0).sqrt()
+}
+
+/// The shading normal at `hit` on `object`: the surface normal perturbed by the [BumpMap] of its material, if any.
+/// Falls back to the surface normal where the uv coordinates are degenerate, eg. at the poles of a sphere.
+pub fn shading_normal(object: &Object, hit: &Hit) -> Unit<Vec3> {
+    let material = &object.material;
+    if material.bump.is_none() && material.normal_map.is_none() {
+        return hit.normal;
+    }
+
+    let (du, dv) = uv_tangents(&object.shape, object.transform.inv() * hit.point);
+    let (du, dv) = (object.transform * du, object.transform * dv);
+
+    let mut normal = hit.normal;
+    if let Some(bump) = &material.bump {
+        normal = bumped_normal(bump, hit.uv, normal, du, dv);
+    }
+    if let Some(normal_map) = &material.normal_map {
+        normal = mapped_normal(normal_map, hit.uv, normal, du, dv);
+    }
+    normal
+}
+
+/// The normal of the surface displaced by `bump`, where `du` and `dv` are the world space derivatives of the point.
+fn bumped_normal(bump: &BumpMap, uv: Point2, normal: Unit<Vec3>, du: Vec3, dv: Vec3) -> Unit<Vec3> {
+    let gradient = bump.height.scalar_gradient(uv) * bump.scale;
+
+    // differentiate the displaced surface `point + height * normal`, ignoring the change of the normal itself
+    let bumped = (du + *normal * gradient.x).cross(dv + *normal * gradient.y);
+
+    // the uv coordinates can run either way around the normal, keep the result on the same side
+    let orientation = du.cross(dv).dot(*normal).signum();
+    (bumped * orientation).try_normalized()
+        .filter(|bumped| bumped.is_finite())
+        .unwrap_or(normal)
+}
+
+/// The normal from `normal_map` transformed out of the tangent space at the surface, where `du` and `dv` are the world
+/// space derivatives of the point. For triangles these are the edges, so the tangents follow the uv deltas along them.
+fn mapped_normal(normal_map: &NormalMap, uv: Point2, normal: Unit<Vec3>, du: Vec3, dv: Vec3) -> Unit<Vec3> {
+    // the derivatives are not orthogonal to the normal in general, eg. after a bump map or for skewed transforms
+    let tangent = match (du - *normal * du.dot(*normal)).try_normalized() {
+        Some(tangent) => tangent,
+        None => return normal,
+    };
+    // the uv coordinates can run either way around the normal, mirror the bitangent to follow v
+    let bitangent = normal.cross(*tangent);
+    let bitangent = bitangent * bitangent.dot(dv).signum();
+
+    let texel = normal_map.texture.sample(uv);
+    let x = (2.0 * texel.red - 1.0) * normal_map.strength;
+    let y = (2.0 * texel.green - 1.0) * normal_map.strength;
+    let z = 2.0 * texel.blue - 1.0;
+
+    (*tangent * x + bitangent * y + *normal * z).try_normalized()
+        .filter(|mapped| mapped.is_finite() && mapped.dot(*normal) > 0.0)
+        .unwrap_or(normal)
+}
+
+/// The derivatives of the object space point at `point` with respect to the uv coordinates,
+/// following the conventions documented on [Shape].
+fn uv_tangents(shape: &Shape, point: Point3) -> (Vec3, Vec3) {
+    // the shapes of revolution use the same u, the angle around the y-axis
+    let around_y = Vec3::new(point.z, 0.0, -point.x) * (2.0 * PI);
 
-        let vec = Vec3::from_slice(&UnitSphere.sample(rng));
-        //TODO 2.0 is not exactly the correct weight because not exactly half of the sphere is visible
-        (2.0, self.transform * (Point3::origin() + vec))
+    match shape {
+        Shape::Plane | Shape::Triangle | Shape::Square => (*Vec3::x_axis(), *Vec3::y_axis()),
+        Shape::Sphere | Shape::Sdf(_) => {
+            let r = (point.x * point.x + point.z * point.z).sqrt();
+            (around_y, Vec3::new(-point.x * point.y / r, r, -point.z * point.y / r) * PI)
+        }
+        Shape::Cylinder => (around_y, *Vec3::y_axis()),
+        Shape::Torus { .. } => {
+            // only degenerate for tubes that reach the axis
+            let ring = Vec3::new(point.x, 0.0, point.z).try_normalized().unwrap_or(Vec3::x_axis());
+            let from_ring = point - Point3::from_coords(*ring);
+            let along_tube = *ring * -from_ring.y + *Vec3::y_axis() * from_ring.dot(*ring);
+            (around_y, along_tube * (2.0 * PI))
+        }
+        Shape::Cone { capped } => {
+            if *capped && point.y >= 1.0 - 1e-4 {
+                (Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0))
+            } else {
+                (around_y, Vec3::new(point.x / point.y, 1.0, point.z / point.y))
+            }
+        }
     }
 }
 
-fn check_hit(hit: &Option<Hit>) {
-    if let Some(hit) = hit {
-        debug_assert!(hit.t >= 0.0);
-        debug_assert!(hit.normal == hit.normal);
-        debug_assert!(hit.point == hit.point);
+/// The maximum number of steps [sdf_intersect] takes along a ray before giving up.
+const SDF_MAX_STEPS: u32 = 512;
+/// How close to the surface of an [Sdf] a point has to be to count as a hit, in object space.
+const SDF_EPSILON: f32 = 1e-4;
+
+fn sdf_intersect(ray: &ObjectRay, sdf: &Sdf) -> Option<Hit> {
+    let distance = |point: Point3| (sdf.distance)(point);
+
+    // only march within the bounding sphere
+    let start = ray.start.coords();
+    let b = start.dot(*ray.direction);
+    let c = start.norm_squared() - sdf.bound * sdf.bound;
+    let d = b * b - c;
+    if d < 0.0 || (c > 0.0 && b > 0.0) {
+        return None;
+    }
+    let t_near = -b - d.sqrt();
+    let t_far = -b + d.sqrt();
+
+    // march on the side of the surface the ray starts on, so rays leaving the inside find the exit.
+    // Rays that start on the surface, like the ones bouncing off of it, first have to get away from it.
+    let (mut t, side, mut left_surface) = if t_near > 0.0 {
+        (t_near, 1.0, true)
+    } else {
+        let start_distance = distance(ray.start);
+        (0.0, start_distance.signum(), start_distance.abs() >= SDF_EPSILON)
+    };
+
+    for _ in 0..SDF_MAX_STEPS {
+        let point = ray.at(t);
+        let step = side * distance(point);
+
+        if step < SDF_EPSILON {
+            if left_surface {
+                let normal = sdf_gradient(distance, point).try_normalized()?;
+                let uv = point.coords().try_normalized().map_or(Point2::new(0.0, 0.0), equirect_uv);
+                return Some(Hit { t, point, normal, uv });
+            }
+        } else {
+            left_surface = true;
+        }
+
+        t += step.max(SDF_EPSILON);
+        if t > t_far {
+            return None;
+        }
+    }
+
+    None
+}
+
+/// The gradient of `distance` at `point` with central differences, which is the normal of the surface close to it.
+fn sdf_gradient(distance: impl Fn(Point3) -> f32, point: Point3) -> Vec3 {
+    let h = SDF_EPSILON;
+    let axis = |axis: Vec3| distance(point + axis * h) - distance(point - axis * h);
+    Vec3::new(axis(*Vec3::x_axis()), axis(*Vec3::y_axis()), axis(*Vec3::z_axis()))
+}
+
+/// Skip invalid hits caused by malformed geometry instead of letting them corrupt the image, see [count_rejected_hits](crate::cpu::stats::count_rejected_hits).
+/// With the `strict-hits` feature debug builds panic on them instead.
+fn check_hit(hit: Option<Hit>) -> Option<Hit> {
+    let hit = hit?;
+    let valid = hit.t >= 0.0 && hit.normal.is_finite() && hit.point.is_finite();
+
+    if cfg!(feature = "strict-hits") {
+        debug_assert!(valid, "Invalid hit {:?}", hit);
+    }
+
+    if valid {
+        Some(hit)
+    } else {
+        record_rejected_hit();
+        None
     }
 }
 
@@ -246,10 +777,20 @@ fn clamp(x: f32, min: f32, max: f32) -> f32 {
 
 #[cfg(test)]
 mod test {
-    use crate::common::math::{Norm, Point3, Vec3};
-    use crate::common::scene::Shape;
+    use std::f32::consts::PI;
+    use std::sync::Arc;
+
+    use rand::distributions::Distribution;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use rand_distr::UnitSphere;
+
+    use crate::common::math::{Angle, Matrix4, Norm, Point2, Point3, Transform, Unit, Vec2, Vec3};
+    use crate::common::scene::{BumpMap, Color, Material, NormalMap, Object, Sdf, Shape};
+    use crate::common::texture::{equirect_direction, ImageTexture, Texture};
     use crate::common::util::triangle_as_transform;
-    use crate::cpu::geometry::{intersect_transformed_shape, Ray};
+    use crate::cpu::geometry::{Intersect, intersect_transformed_shape, Ray, shading_normal, uv_tangents};
+    use crate::demos::{color_gray, material_diffuse, WHITE};
 
     #[test]
     fn triangle_transform_dist() {
@@ -272,7 +813,7 @@ mod test {
         println!("{:?}", transform);
         println!("{:?}", ray);
 
-        let hit = intersect_transformed_shape(Shape::Triangle, transform, &ray).unwrap();
+        let hit = intersect_transformed_shape(&Shape::Triangle, transform, &ray).unwrap();
 
         println!("center: {:?}", triangle_center);
 
@@ -281,4 +822,318 @@ mod test {
 
         assert!((expected_dist - hit.t).abs() < 0.001);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn scaled_and_mirrored_sphere_dist() {
+        let ellipsoid = |mirror: f32| Transform::from_matrix(Matrix4::new([
+            [2.0 * mirror, 0.0, 0.0, 0.0],
+            [0.0, 0.5, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])).unwrap();
+
+        // the point on the ellipse x²/4 + y²/0.25 = 1 along the diagonal
+        let s = 1.0 / 4.25f32.sqrt();
+        let cases = [
+            (Point3::new(5.0, 0.0, 0.0), Point3::new(2.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)),
+            (Point3::new(0.0, 5.0, 0.0), Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+            (Point3::new(3.0, 3.0, 0.0), Point3::new(s, s, 0.0), Vec3::new(s / 4.0, 4.0 * s, 0.0)),
+        ];
+
+        for mirror in [1.0, -1.0] {
+            let transform = ellipsoid(mirror);
+            for (start, expected_point, expected_normal) in cases {
+                let ray = Ray::new(start, (Point3::origin() - start).normalized());
+                let hit = intersect_transformed_shape(&Shape::Sphere, transform, &ray).unwrap();
+
+                assert!((hit.t - start.distance_to(expected_point)).abs() < 1e-4, "mirror {}: {:?}", mirror, hit);
+                assert!(hit.point.distance_to(expected_point) < 1e-4, "mirror {}: {:?}", mirror, hit);
+                assert!((hit.normal.dot(*expected_normal.normalized()) - 1.0).abs() < 1e-4, "mirror {}: {:?}", mirror, hit);
+            }
+        }
+    }
+
+    #[test]
+    fn enclosing_flat_ellipsoid() {
+        let dome = Object::new(Shape::Sphere, material_diffuse(WHITE), Transform::scale_xyz(Vec3::new(10.0, 1.0, 10.0)));
+        assert!(dome.encloses(Point3::new(9.0, 0.0, 0.0)));
+        assert!(!dome.encloses(Point3::new(0.0, 2.0, 0.0)));
+
+        // the sampled points are on the surface of the ellipsoid
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let from = Point3::new(5.0, 0.5, 0.0);
+        for _ in 0..100 {
+            let (_, point) = dome.sample_enclosing(from, Vec3::y_axis(), rng).unwrap();
+            let object_point = dome.transform.inv() * point - Point3::origin();
+            assert!((object_point.norm() - 1.0).abs() < 1e-4, "{:?}", point);
+        }
+        assert!(dome.sample_enclosing(Point3::new(0.0, 2.0, 0.0), Vec3::y_axis(), rng).is_none());
+    }
+
+    #[test]
+    fn planet_scale_sphere_dist() {
+        // a camera a few meters above an earth sized sphere, far from the origin, looking down at the ground
+        let (radius, offset) = (6.4e6f32, Vec3::new(3.0e7, 0.0, 0.0));
+        let transform = Transform::translate(offset + Vec3::new(0.0, -radius, 0.0)) * Transform::scale(radius);
+        let start = Point3::from_coords(offset + Vec3::new(0.0, 2.0, 0.0));
+
+        let mut max_rel_error = 0.0f64;
+        for i in 0..64 {
+            let dx = 1.0 + i as f32 / 8.0;
+            let direction = Vec3::new(dx, -1.0, 0.3).normalized();
+            let hit = intersect_transformed_shape(&Shape::Sphere, transform, &Ray::new(start, direction)).unwrap();
+
+            // the exact distance to the sphere, solved in f64 relative to the start
+            let (b, c) = (direction.y as f64 * (radius as f64 + 2.0), (radius as f64 + 2.0).powi(2) - (radius as f64).powi(2));
+            let expected_t = -b - (b * b - c).sqrt();
+            max_rel_error = max_rel_error.max((hit.t as f64 - expected_t).abs() / expected_t);
+        }
+
+        // the start is 3e7 away from the origin, where neighbouring f32 values are 2 meters apart,
+        // so with f32 the distance of a few meters is only roughly right
+        let tolerance = if cfg!(feature = "double-precision") { 1e-5 } else { 0.25 };
+        assert!(max_rel_error < tolerance, "relative t error {}", max_rel_error);
+    }
+
+    #[test]
+    #[cfg(not(feature = "strict-hits"))]
+    fn invalid_hits_rejected() {
+        use crate::common::math::Point2;
+        use crate::cpu::geometry::{check_hit, Hit};
+        use crate::cpu::stats::count_rejected_hits;
+
+        let valid = || Hit { t: 1.0, point: Point3::origin(), normal: Vec3::z_axis(), uv: Point2::new(0.0, 0.0) };
+        let invalid = [
+            Hit { t: f32::NAN, ..valid() },
+            Hit { t: -1.0, ..valid() },
+            Hit { point: Point3::new(f32::INFINITY, 0.0, 0.0), ..valid() },
+        ];
+
+        let count = invalid.len() as u64;
+        let ((), rejected) = count_rejected_hits(|| {
+            assert!(check_hit(Some(valid())).is_some());
+            for hit in invalid {
+                assert!(check_hit(Some(hit)).is_none());
+            }
+        });
+        assert_eq!(rejected, count);
+    }
+
+    #[test]
+    fn cull_backface_one_sided_quad() {
+        let mut quad = Object::new(
+            Shape::Square,
+            Material {
+                cull_backface: true,
+                ..material_diffuse(WHITE)
+            },
+            Transform::translate(Vec3::new(-0.5, -0.5, 0.0)),
+        );
+
+        // the normal of the square points towards positive z
+        let front = Ray::new(Point3::new(0.0, 0.0, 1.0), -Vec3::z_axis());
+        let back = Ray::new(Point3::new(0.0, 0.0, -1.0), Vec3::z_axis());
+
+        assert!(quad.intersect(&front).is_some());
+        assert!(quad.intersect(&back).is_none());
+
+        quad.material.cull_backface = false;
+        assert!(quad.intersect(&back).is_some());
+    }
+
+    #[test]
+    fn cone_hits_and_misses() {
+        let capped = Shape::Cone { capped: true };
+        let open = Shape::Cone { capped: false };
+        let intersect = |shape: &Shape, start, direction| intersect_transformed_shape(shape, Transform::default(), &Ray::new(start, direction));
+
+        // through the side halfway up
+        let hit = intersect(&open, Point3::new(-5.0, 0.5, 0.0), Vec3::x_axis()).unwrap();
+        assert!((hit.point.distance_to(Point3::new(-0.5, 0.5, 0.0))) < 1e-5);
+        assert!((hit.normal.dot(*Vec3::new(-1.0, -1.0, 0.0).normalized()) - 1.0).abs() < 1e-5);
+
+        // from above along the axis, only the capped cone stops the ray at the base
+        let hit = intersect(&capped, Point3::new(0.0, 5.0, 0.0), -Vec3::y_axis()).unwrap();
+        assert_eq!((hit.t, hit.normal), (4.0, Vec3::y_axis()));
+        let hit = intersect(&open, Point3::new(0.1, 5.0, 0.0), -Vec3::y_axis()).unwrap();
+        assert!((hit.point.y - 0.1).abs() < 1e-5 && hit.normal.dot(*Vec3::y_axis()) < 0.0);
+
+        // above the base and through the mirrored lower half of the double cone
+        assert!(intersect(&capped, Point3::new(-5.0, 1.5, 0.0), Vec3::x_axis()).is_none());
+        assert!(intersect(&capped, Point3::new(-5.0, -0.5, 0.0), Vec3::x_axis()).is_none());
+    }
+
+    #[test]
+    fn torus_axis_misses() {
+        let shape = Shape::Torus { tube_radius: 0.25 };
+        let ray = Ray::new(Point3::new(0.0, 5.0, 0.0), -Vec3::y_axis());
+        assert!(intersect_transformed_shape(&shape, Transform::default(), &ray).is_none());
+    }
+
+    #[test]
+    fn sdf_sphere_matches_sphere() {
+        let sdf = Shape::Sdf(Sdf::new(1.5, |point| point.coords().norm() - 1.0));
+        let transform = Transform::translate(Vec3::new(1.0, -2.0, 0.5)) * Transform::scale(2.0);
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        for i in 0..100 {
+            // alternate between rays from outside and rays from inside of the sphere, through a point near its center
+            let offset = Vec3::from(UnitSphere.sample(rng)) * if i % 2 == 0 { 3.0 } else { 0.9 };
+            let start = transform * (Point3::origin() + offset);
+            let target = transform * (Point3::origin() + Vec3::from(UnitSphere.sample(rng)) * 0.5);
+            let ray = Ray::new(start, (target - start).normalized());
+
+            let expected = intersect_transformed_shape(&Shape::Sphere, transform, &ray).unwrap();
+            let actual = intersect_transformed_shape(&sdf, transform, &ray).unwrap();
+            assert!((actual.t - expected.t).abs() < 1e-3, "{:?}: expected {:?}, got {:?}", ray, expected, actual);
+            assert!(actual.point.distance_to(expected.point) < 1e-3, "{:?}: expected {:?}, got {:?}", ray, expected, actual);
+            assert!(actual.normal.dot(*expected.normal) > 0.999, "{:?}: expected {:?}, got {:?}", ray, expected, actual);
+            // compare the directions instead of the uv itself, which jumps at the seam
+            assert!((*equirect_direction(actual.uv) - *equirect_direction(expected.uv)).norm() < 1e-3, "{:?}: expected {:?}, got {:?}", ray, expected, actual);
+        }
+
+        // rays leaving the surface like bounces do, away from it or through the inside
+        let outward = Ray::new(transform * Point3::new(0.0, 0.0, 1.0 + 1e-4), Vec3::z_axis());
+        assert!(intersect_transformed_shape(&sdf, transform, &outward).is_none());
+        let inward = Ray::new(transform * Point3::new(0.0, 0.0, 1.0 - 1e-4), -Vec3::z_axis());
+        let hit = intersect_transformed_shape(&sdf, transform, &inward).unwrap();
+        assert!(hit.point.distance_to(transform * Point3::new(0.0, 0.0, -1.0)) < 1e-3, "{:?}", hit);
+    }
+
+    #[test]
+    fn torus_grazing_hits() {
+        let tube_radius = 0.25;
+        let shape = Shape::Torus { tube_radius };
+
+        // just inside of the top of the tube and just inside of the outer edge
+        let rays = [
+            Ray::new(Point3::new(-5.0, 0.249, 0.0), Vec3::x_axis()),
+            Ray::new(Point3::new(-5.0, 0.0, 1.249), Vec3::x_axis()),
+            Ray::new(Point3::new(3.0, 4.0, 5.0), (Point3::new(1.0, 0.0, 0.0) - Point3::new(3.0, 4.0, 5.0)).normalized()),
+        ];
+
+        for ray in rays {
+            let hit = intersect_transformed_shape(&shape, Transform::default(), &ray)
+                .unwrap_or_else(|| panic!("{:?} should hit the torus", ray));
+
+            let p = hit.point;
+            let ring_distance = (Vec3::new(p.x, 0.0, p.z).norm() - 1.0).hypot(p.y);
+            assert!((ring_distance - tube_radius).abs() < 1e-4, "hit point {:?} is not on the torus", p);
+            assert!(hit.normal.dot(*ray.direction) < 0.0);
+            assert!((ray.at(hit.t).distance_to(p)) < 1e-4);
+        }
+
+        // just outside of the tube
+        let ray = Ray::new(Point3::new(-5.0, 0.251, 0.0), Vec3::x_axis());
+        assert!(intersect_transformed_shape(&shape, Transform::default(), &ray).is_none());
+    }
+
+    #[test]
+    fn sphere_uv_tangents() {
+        let eps = 1e-3;
+        for uv in [Point2::new(0.1, 0.3), Point2::new(0.6, 0.5), Point2::new(0.9, 0.8)] {
+            let point = Point3::from_coords(*equirect_direction(uv));
+            let (du, dv) = uv_tangents(&Shape::Sphere, point);
+
+            let du_expected = (*equirect_direction(uv + Vec2::new(eps, 0.0)) - *equirect_direction(uv)) / eps;
+            let dv_expected = (*equirect_direction(uv + Vec2::new(0.0, eps)) - *equirect_direction(uv)) / eps;
+            assert!((du - du_expected).norm() < 0.02 * du.norm(), "expected {:?}, got {:?}", du_expected, du);
+            assert!((dv - dv_expected).norm() < 0.02 * dv.norm(), "expected {:?}, got {:?}", dv_expected, dv);
+        }
+    }
+
+    #[test]
+    fn bump_ramp_tilts_normal() {
+        // the height increases linearly along u, with one unit of height over the width of the square
+        let size = 64;
+        let ramp = ImageTexture::from_fn(size, 1, |x, _| color_gray((x as f32 + 0.5) / size as f32));
+        let square = Object::new(
+            Shape::Square,
+            Material {
+                bump: Some(BumpMap { height: Texture::Image(Arc::new(ramp)), scale: 0.5 }),
+                ..material_diffuse(WHITE)
+            },
+            Transform::scale(2.0),
+        );
+
+        let ray = Ray::new(Point3::new(1.0, 1.0, 1.0), -Vec3::z_axis());
+        let hit = square.intersect(&ray).unwrap();
+        let normal = shading_normal(&square, &hit);
+
+        // the surface rises by 0.5 over a distance of 2
+        let expected = Vec3::new(-0.25, 0.0, 1.0).normalized();
+        assert!((*normal - *expected).norm() < 1e-3, "expected {:?}, got {:?}", expected, normal);
+    }
+
+    #[test]
+    fn normal_map_follows_triangle_uv() {
+        let (a, b, c) = (Point3::new(0.0, 0.0, 0.0), Point3::new(2.0, 0.0, 1.0), Point3::new(0.5, 1.0, 0.0));
+        let encode = |x: f32, y: f32, z: f32| Texture::Constant(Color::new(x / 2.0 + 0.5, y / 2.0 + 0.5, z / 2.0 + 0.5));
+
+        // both windings of the same triangle, which swaps the u and v edges and flips the normal
+        for (b, c) in [(b, c), (c, b)] {
+            let triangle = |texture| Object::new(
+                Shape::Triangle,
+                Material { normal_map: Some(NormalMap { texture, strength: 1.0 }), ..material_diffuse(WHITE) },
+                triangle_as_transform(a, b, c),
+            );
+
+            let flat = triangle(encode(0.0, 0.0, 1.0));
+            let ray = Ray::new(Point3::new(0.5, 0.3, 2.0), -Vec3::z_axis());
+            let hit = flat.intersect(&ray).unwrap();
+            assert!((*shading_normal(&flat, &hit) - *hit.normal).norm() < 1e-5);
+
+            // the tangent runs along the u edge, the bitangent is in the plane towards the v edge
+            let tangent = (b - a).normalized();
+            let bitangent = hit.normal.cross(*tangent);
+            let bitangent = bitangent * bitangent.dot(c - a).signum();
+
+            for (x, y, expected) in [(0.6, 0.0, *tangent), (0.0, 0.6, bitangent), (-0.6, 0.0, -*tangent)] {
+                let object = triangle(encode(x, y, 0.8));
+                let expected = expected * 0.6 + *hit.normal * 0.8;
+                let normal = shading_normal(&object, &hit);
+                assert!((*normal - expected).norm() < 1e-3, "expected {:?}, got {:?}", expected, normal);
+            }
+        }
+    }
+
+    #[test]
+    fn sample_pdf_consistent() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let object = |shape, transform| Object::new(shape, material_diffuse(WHITE), transform);
+        let from = Point3::new(0.3, -0.2, 1.2);
+
+        let cases = [
+            ("sphere", object(Shape::Sphere, Transform::translate(Vec3::new(0.5, 0.0, 0.0)) * Transform::scale(0.8))),
+            ("inside sphere", object(Shape::Sphere, Transform::scale(3.0))),
+            ("square", object(Shape::Square, Transform::rotate(Vec3::x_axis(), Angle::degrees(30.0)) * Transform::scale(1.5))),
+            ("triangle", object(Shape::Triangle, triangle_as_transform(Point3::new(-1.0, 0.0, 0.0), Point3::new(1.0, -0.5, 0.5), Point3::new(0.0, 1.0, -0.5)))),
+        ];
+
+        let n = 200_000;
+        for (name, object) in cases {
+            // the pdf integrates to one over all directions
+            let mut integral = 0.0;
+            let mut hits = 0;
+            for _ in 0..n {
+                let direction = Unit::new_unchecked(Vec3::from_slice(&UnitSphere.sample(rng)));
+                integral += object.pdf_from(from, direction) * 4.0 * PI;
+                hits += object.intersect(&Ray::new(from, direction)).is_some() as u32;
+            }
+            let integral = integral / n as f32;
+            assert!((integral - 1.0).abs() < 0.02, "{name}: pdf integrates to {integral}");
+
+            // averaging one over the pdf of the sampled directions gives the solid angle covered by the object
+            let solid_angle = (0..n)
+                .map(|_| {
+                    let (_, target) = object.sample_from(from, rng);
+                    let pdf = object.pdf_from(from, (target - from).normalized());
+                    // rays towards points right on the edge can numerically miss
+                    if pdf > 0.0 { 1.0 / pdf } else { 0.0 }
+                })
+                .sum::<f32>() / n as f32;
+            let expected = hits as f32 / n as f32 * 4.0 * PI;
+            assert!((solid_angle - expected).abs() < 0.02 * expected, "{name}: expected solid angle {expected}, got {solid_angle}");
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use imgref::ImgVec;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::common::math::{Norm, Vec3};
+use crate::cpu::accel::Accel;
+use crate::cpu::renderer::{CpuPreparedScene, pixel_rng};
+
+impl<'a, A: Accel> CpuPreparedScene<'a, A> {
+    /// The geometry the camera sees through each pixel, averaged over `samples` camera rays: the normal of the first
+    /// visible hit, flipped to face the camera, and the distance to that hit. Pixels that only see the sky get a zero
+    /// normal and an infinite depth, and so do pixels whose normals cancel out.
+    ///
+    /// Each pixel uses its own rng from [pixel_rng] with `seed`, so the guides don't depend on the threads.
+    ///
+    /// These are the guides that keep [filter_bilateral](crate::filter::filter_bilateral) from blurring across edges.
+    pub fn guide_buffers(&self, width: u32, height: u32, samples: u32, seed: u64) -> (ImgVec<Vec3>, ImgVec<f32>) {
+        let rows: Vec<Vec<(Vec3, f32)>> = (0..height).into_par_iter().panic_fuse()
+            .map(|y| {
+                (0..width).map(|x| {
+                    let rng = &mut pixel_rng(x, y, seed);
+                    let mut normal = Vec3::new(0.0, 0.0, 0.0);
+                    let mut depth = 0.0;
+                    let mut hits = 0;
+
+                    for sample in 0..samples {
+                        let ray = self.camera.ray(rng, x, y, sample);
+                        if let Some((object_hit, _)) = self.pick(&ray) {
+                            let hit = object_hit.hit;
+                            normal = normal + if hit.normal.dot(*ray.direction) > 0.0 { -*hit.normal } else { *hit.normal };
+                            depth += hit.t;
+                            hits += 1;
+                        }
+                    }
+
+                    if hits == 0 {
+                        (Vec3::new(0.0, 0.0, 0.0), f32::INFINITY)
+                    } else {
+                        // opposite normals can cancel out, normalizing what's left would give NaN or noise
+                        let length = normal.norm();
+                        let normal = if length > 1e-6 * hits as f32 { normal / length } else { Vec3::new(0.0, 0.0, 0.0) };
+                        (normal, depth / hits as f32)
+                    }
+                }).collect()
+            })
+            .collect();
+
+        let (normals, depths) = rows.into_iter().flatten().unzip();
+        (ImgVec::new(normals, width as usize, height as usize), ImgVec::new(depths, width as usize, height as usize))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::accel::NoAccel;
+    use crate::cpu::renderer::{CpuPreparedScene, CpuRenderSettings, LightSelection};
+    use crate::demos::scene_cornell_box;
+
+    #[test]
+    fn guides_are_seeded() {
+        let scene = scene_cornell_box();
+        let prepared = CpuPreparedScene::new(&scene, CpuRenderSettings::default(), &LightSelection::Auto, NoAccel, 8, 8);
+
+        let (normals, depths) = prepared.guide_buffers(8, 8, 4, 3);
+        let (normals_again, depths_again) = prepared.guide_buffers(8, 8, 4, 3);
+        assert_eq!(normals.buf(), normals_again.buf());
+        assert_eq!(depths.buf(), depths_again.buf());
+
+        for (normal, depth) in normals.pixels().zip(depths.pixels()) {
+            assert!(normal.x.is_finite() && normal.y.is_finite() && normal.z.is_finite());
+            assert!(depth > 0.0);
+        }
+    }
+}
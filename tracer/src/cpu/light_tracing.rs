@@ -0,0 +1,291 @@
+use std::f32::consts::PI;
+use std::ops::AddAssign;
+
+use rand::distributions::Distribution;
+use rand::Rng;
+use rand_distr::{UnitDisc, UnitSphere};
+
+use crate::common::math::{Norm, Point2, Point3, Unit, Vec2, Vec3};
+use crate::common::scene::{Color, MaterialType, Object, RayKind, Shape};
+use crate::common::texture::equirect_uv;
+use crate::cpu::accel::Accel;
+use crate::cpu::geometry::{Hit, Intersect, Ray};
+use crate::cpu::renderer::{color_exp, CpuPreparedScene, disk_to_hemisphere, filter_visible, MediumStack, refract_ratio, sample_direction, SurfaceBrdf};
+
+/// The contributions of light paths splatted onto the image, see [CpuPreparedScene::trace_light_path].
+#[derive(Debug, Clone)]
+pub struct LightFilm {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    paths: u64,
+}
+
+impl LightFilm {
+    pub fn new(width: u32, height: u32) -> Self {
+        LightFilm {
+            width,
+            height,
+            pixels: vec![Color::new(0.0, 0.0, 0.0); (width * height) as usize],
+            paths: 0,
+        }
+    }
+
+    /// The estimated color of the pixel at `(x, y)`, averaged over all paths traced so far.
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        if self.paths == 0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.pixels[(y * self.width + x) as usize] / self.paths as f32
+    }
+
+    fn splat(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] += color;
+    }
+}
+
+impl AddAssign<&LightFilm> for LightFilm {
+    fn add_assign(&mut self, rhs: &LightFilm) {
+        assert_eq!((self.width, self.height), (rhs.width, rhs.height), "Film sizes don't match");
+        for (left, &right) in self.pixels.iter_mut().zip(&rhs.pixels) {
+            *left += right;
+        }
+        self.paths += rhs.paths;
+    }
+}
+
+impl<'a, A: Accel> CpuPreparedScene<'a, A> {
+    /// Trace a single path starting from a random light and splat its caustic contributions onto `film`.
+    ///
+    /// The path is followed through specular surfaces, and when it then hits a diffuse surface that's visible from the camera
+    /// the light reflected towards the camera is added to the corresponding pixel. This complements the caustic paths
+    /// that [Strategy::LightTracing](crate::cpu::Strategy::LightTracing) leaves out of the camera paths.
+    /// Spherical lights must be uniformly scaled.
+    pub fn trace_light_path(&self, rng: &mut impl Rng, film: &mut LightFilm) {
+        film.paths += 1;
+
+        if self.lights.is_empty() {
+            return;
+        }
+        let light = &self.scene.objects[self.lights[rng.gen_range(0..self.lights.len())].index];
+        let (start, normal, uv, area) = sample_emitter(light, rng);
+
+        // pick a cosine weighted direction, the cosine cancels out with the emitted radiance leaving only the area and pi
+        let (wavelength, wavelength_weight) = self.sample_wavelength(rng);
+        let emission = light.material.emission_at(uv);
+        let mut throughput = emission * wavelength_weight * (area * PI * self.lights.len() as f32);
+        let mut ray = Ray {
+            start,
+            direction: disk_to_hemisphere(Vec2::from_slice(&UnitDisc.sample(rng)), normal),
+        };
+        let mut media = MediumStack::new(light.material.outside);
+        let mut after_specular = false;
+
+        for _ in 0..self.settings.max_bounces {
+            let object_hit = match self.first_opaque_hit(&ray, filter_visible(RayKind::Indirect)) {
+                Some(object_hit) => object_hit,
+                None => break,
+            };
+            let mut hit = object_hit.hit;
+            let object = &self.scene.objects[object_hit.id.index];
+            let material = self.shading_material(object);
+            if let MaterialType::Fixed = material.material_type {
+                break;
+            }
+
+            let medium = media.current();
+            throughput *= color_exp(medium.volumetric_color, hit.t);
+
+            let into = hit.normal.dot(*ray.direction) < 0.0;
+            if !into {
+                hit.normal = -hit.normal;
+            }
+            let next_media = media.crossing(object_hit.id, object, into);
+
+            let refract_ratio = refract_ratio(medium, next_media.current(), wavelength);
+            let sample = sample_direction(&ray, &hit, material.material_type, refract_ratio, rng);
+
+            // direct light on diffuse surfaces is already handled by the camera paths
+            if after_specular && sample.diffuse_fraction != 0.0 {
+                let brdf = SurfaceBrdf::new(material, hit.uv, -ray.direction);
+                self.splat_to_camera(film, &hit, throughput, &brdf, medium.volumetric_color);
+            }
+
+            if !sample.specular {
+                break;
+            }
+
+            throughput *= sample.tint(material.albedo_at(hit.uv)) * sample.weight();
+            if sample.crosses_surface {
+                media = next_media;
+            }
+            ray = Ray {
+                start: self.settings.tolerances.offset_ray_start(&hit, sample.direction),
+                direction: sample.direction,
+            };
+            after_specular = true;
+        }
+    }
+
+    /// Add the light `arriving` at the hit reflected towards the camera according to `brdf` to the pixel that sees it, if it's visible.
+    fn splat_to_camera(&self, film: &mut LightFilm, hit: &Hit, arriving: Color, brdf: &SurfaceBrdf, volumetric_color: Color) {
+        let (x, y, cos_camera) = match self.camera.project(hit.point) {
+            Some(pixel) => pixel,
+            None => return,
+        };
+
+        let (direction, distance) = (self.camera.position() - hit.point).normalized_and_get();
+        let cos_surface = direction.dot(*hit.normal);
+        if cos_surface <= 0.0 {
+            return;
+        }
+
+        let ray = Ray { start: self.settings.tolerances.offset_ray_start(hit, direction), direction };
+        if let Some(blocker) = self.first_opaque_hit(&ray, filter_visible(RayKind::Camera)) {
+            if blocker.hit.t < distance {
+                return;
+            }
+        }
+
+        // convert from the area around the hit to the area of the pixel on the image plane
+        let importance = cos_surface / (distance * distance * self.camera.pixel_area() * cos_camera.powi(3));
+        let reflected = arriving * brdf.eval(hit.normal, direction);
+        film.splat(x, y, reflected * color_exp(volumetric_color, distance) * importance);
+    }
+}
+
+/// The total power emitted by `light`, the emitted radiance integrated over its surface and all directions.
+/// Every point of a light emits diffusely, so this is `π` times the emitting area times the average emission.
+/// Emission textures are averaged over a grid of points. Spherical lights must be uniformly scaled.
+pub(super) fn light_power(light: &Object) -> Color {
+    let area = match light.shape {
+        Shape::Sphere => {
+            let radius = (light.transform * *Vec3::x_axis()).norm();
+            4.0 * PI * radius * radius
+        }
+        // unless culled the square emits from both sides
+        Shape::Square if !light.material.cull_backface => 2.0 * light.area(),
+        _ => light.area(),
+    };
+
+    let material = &light.material;
+    let emission = if material.emission_texture.is_none() {
+        material.emission_at(Point2::new(0.5, 0.5))
+    } else {
+        const GRID: usize = 16;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        let mut total_weight = 0.0;
+        for i in 0..GRID {
+            for j in 0..GRID {
+                let uv = Point2::new((i as f32 + 0.5) / GRID as f32, (j as f32 + 0.5) / GRID as f32);
+                // the equirectangular uv coordinates of spheres cover less area towards the poles
+                let weight = match light.shape {
+                    Shape::Sphere => ((uv.coords.y - 0.5) * PI).cos(),
+                    _ => 1.0,
+                };
+                total += material.emission_at(uv) * weight;
+                total_weight += weight;
+            }
+        }
+        total / total_weight
+    };
+
+    emission * (PI * area)
+}
+
+/// Sample a uniform point on the surface of a light, returning the point, the normal it emits along,
+/// the uv coordinates and the total emitting area. The point is already offset so rays leaving it don't hit the light again.
+fn sample_emitter(light: &Object, rng: &mut impl Rng) -> (Point3, Unit<Vec3>, Point2, f32) {
+    match light.shape {
+        Shape::Sphere => {
+            let local = Vec3::from_slice(&UnitSphere.sample(rng));
+            let point = light.transform * Point3::from_coords(local);
+            let normal = light.transform.inv_transpose_mul(local).normalized();
+            let radius = point.distance_to(light.transform * Point3::origin());
+            let area = 4.0 * PI * radius * radius;
+
+            let start = point + *normal * (radius * 1e-4);
+            (start, normal, equirect_uv(Unit::new_unchecked(local)), area)
+        }
+        Shape::Square => {
+            let (u, v) = (rng.gen(), rng.gen());
+            let point = light.transform * Point3::new(u, v, 0.0);
+            let mut normal = light.transform.inv_transpose_mul(*Vec3::z_axis()).normalized();
+            let mut area = light.area();
+
+            // unless culled the square emits from both sides
+            if !light.material.cull_backface {
+                area *= 2.0;
+                if rng.gen::<bool>() {
+                    normal = -normal;
+                }
+            }
+
+            let offset = 1e-4 * area.sqrt();
+            (point + *normal * offset, normal, Point2::new(u, v), area)
+        }
+        _ => panic!("Light tracing only supports spherical and square lights"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use crate::common::math::{Angle, Point3, Transform, Vec3};
+    use crate::common::scene::{Camera, Material, MaterialType, Object, Scene, Shape, Sky};
+    use crate::cpu::accel::NoAccel;
+    use crate::cpu::light_tracing::LightFilm;
+    use crate::cpu::renderer::{CpuPreparedScene, CpuRenderSettings, LightSelection, StopCondition, Strategy};
+    use crate::demos::{BLACK, color_gray, material_diffuse, material_light, VACUUM, WHITE};
+
+    #[test]
+    fn mirror_caustic_matches_simple() {
+        // a light above a floor and a wall, its direct light onto them is blocked so they're only lit through a mirror
+        let horizontal = |y: f32, size: f32| {
+            Transform::translate(Vec3::new(-size / 2.0, y, -size / 2.0))
+                * Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0))
+                * Transform::scale(size)
+        };
+        let mirror = Material { material_type: MaterialType::Mirror, ..material_diffuse(WHITE) };
+        let objects = vec![
+            Object::new(Shape::Plane, material_diffuse(color_gray(0.8)), Transform::rotate(Vec3::x_axis(), Angle::degrees(-90.0))),
+            Object::new(Shape::Plane, material_diffuse(color_gray(0.8)), Transform::translate(Vec3::new(-2.5, 0.0, 0.0)) * Transform::rotate(Vec3::y_axis(), Angle::degrees(90.0))),
+            Object::new(Shape::Square, mirror, horizontal(2.0, 4.0)),
+            Object::new(Shape::Square, material_diffuse(BLACK), horizontal(0.7, 1.2)),
+            Object::new(Shape::Sphere, material_light(WHITE, 10.0), Transform::translate(Vec3::new(0.0, 1.0, 0.0)) * Transform::scale(0.25)),
+        ];
+        let camera = Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(Point3::new(0.0, 1.2, 5.0), Point3::new(-0.5, 0.3, 0.0), Vec3::y_axis()),
+            medium: VACUUM,
+        };
+        let scene = Scene { objects, sky: Sky::Uniform(BLACK), camera };
+
+        let (width, height) = (6, 6);
+        let render = |strategy, samples: u32, light_paths: u32| {
+            let settings = CpuRenderSettings { stop_condition: StopCondition::SampleCount(samples), strategy, ..Default::default() };
+            let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, width, height);
+            let rng = &mut SmallRng::seed_from_u64(0);
+
+            let mut film = LightFilm::new(width, height);
+            for _ in 0..light_paths {
+                prepared.trace_light_path(rng, &mut film);
+            }
+
+            let mut total = 0.0;
+            for y in 0..height {
+                for x in 0..width {
+                    let camera = (0..samples).map(|_| prepared.sample_pixel(rng, x, y).red).sum::<f32>() / samples as f32;
+                    total += camera + film.get(x, y).red;
+                }
+            }
+            total
+        };
+
+        let simple = render(Strategy::Simple, 20_000, 0);
+        let light_tracing = render(Strategy::LightTracing, 2_000, 2_000_000);
+        assert!((light_tracing - simple).abs() < 0.05 * simple, "light tracing {}, simple {}", light_tracing, simple);
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use crate::common::progress::{Block, PixelResult};
+use crate::common::scene::{Color, Object};
+use crate::cpu::accel::ObjectId;
+use crate::cpu::path_debug::{BounceDebug, PathLog};
+use crate::cpu::subsampling::Subsampling;
+use crate::images::DiscreteImage;
+
+/// The objects that share the same [name](Object::name) form a group, each group gets its own coverage mask,
+/// see [CpuRenderer::render_with_masks](crate::cpu::CpuRenderer::render_with_masks). Unnamed objects are in no group.
+pub(super) struct MaskGroups {
+    names: Vec<String>,
+    object_groups: Vec<Option<usize>>,
+}
+
+impl MaskGroups {
+    pub fn new(objects: &[Object]) -> Self {
+        let mut names: Vec<String> = vec![];
+        let object_groups = objects.iter()
+            .map(|object| {
+                let name = object.name.as_deref()?;
+                let group = names.iter().position(|n| n == name).unwrap_or_else(|| {
+                    names.push(name.to_owned());
+                    names.len() - 1
+                });
+                Some(group)
+            })
+            .collect();
+
+        MaskGroups { names, object_groups }
+    }
+}
+
+/// For each pixel of a block, how many samples there are and how many of their camera rays hit each group first.
+/// A progressive render keeps adding to it in every pass.
+pub(super) struct BlockCoverage<'g> {
+    groups: &'g MaskGroups,
+    block: Block,
+    /// the hits of all groups for the first pixel, then for the second one, ...
+    hits: Vec<u32>,
+    samples: Vec<u32>,
+}
+
+/// The [PathLog] that counts the camera hits of the samples of a single pixel into a [BlockCoverage].
+pub(super) struct PixelCoverage<'a> {
+    object_groups: &'a [Option<usize>],
+    hits: &'a mut [u32],
+    samples: &'a mut u32,
+}
+
+impl<'g> BlockCoverage<'g> {
+    pub fn new(groups: &'g MaskGroups, block: Block) -> Self {
+        let pixels = block.pixel_count() as usize;
+        BlockCoverage { groups, block, hits: vec![0; pixels * groups.names.len()], samples: vec![0; pixels] }
+    }
+
+    /// The log for the `i`-th pixel of the block, in the order of [Block::iter].
+    pub fn pixel(&mut self, i: usize) -> PixelCoverage<'_> {
+        let group_count = self.groups.names.len();
+        PixelCoverage {
+            object_groups: &self.groups.object_groups,
+            hits: &mut self.hits[i * group_count..(i + 1) * group_count],
+            samples: &mut self.samples[i],
+        }
+    }
+
+    /// The fraction of the samples that hit each group, all pixels of the first group first.
+    /// Pixels that are not traced are interpolated the same way as the image.
+    pub fn coverage(&self, subsampling: Subsampling) -> Vec<f32> {
+        let group_count = self.groups.names.len();
+        let mut result = Vec::with_capacity(self.hits.len());
+
+        for group in 0..group_count {
+            let mut pixels: Vec<PixelResult> = self.samples.iter().enumerate()
+                .map(|(i, &samples)| {
+                    let fraction = if samples == 0 { 0.0 } else { self.hits[i * group_count + group] as f32 / samples as f32 };
+                    PixelResult { color: Color::new(fraction, fraction, fraction), ..PixelResult::default() }
+                })
+                .collect();
+            subsampling.reconstruct(self.block, &mut pixels);
+            result.extend(pixels.iter().map(|pixel| pixel.color.red));
+        }
+
+        result
+    }
+}
+
+impl PathLog for PixelCoverage<'_> {
+    fn record(&mut self, _: impl FnOnce() -> BounceDebug) {}
+
+    fn camera_hit(&mut self, id: Option<ObjectId>) {
+        *self.samples += 1;
+        if let Some(group) = id.and_then(|id| self.object_groups[id.index]) {
+            self.hits[group] += 1;
+        }
+    }
+}
+
+/// The masks of all groups, filled in one block at a time.
+pub(super) struct ObjectMasks {
+    names: Vec<String>,
+    images: Vec<DiscreteImage>,
+}
+
+impl ObjectMasks {
+    /// Black masks, pixels that are never filled in stay that way.
+    pub fn new(groups: &MaskGroups, width: u32, height: u32) -> Self {
+        ObjectMasks { names: groups.names.clone(), images: vec![DiscreteImage::new(width, height); groups.names.len()] }
+    }
+
+    /// Fill in `block` with the `coverage` from [BlockCoverage::coverage].
+    pub fn put(&mut self, block: Block, coverage: &[f32]) {
+        let pixels = block.pixel_count() as usize;
+        for (group, image) in self.images.iter_mut().enumerate() {
+            for (i, (x, y)) in block.iter().enumerate() {
+                let value = (255.0 * coverage[group * pixels + i]).round() as u8;
+                image.put_pixel(x, y, image::Rgb([value; 3]));
+            }
+        }
+    }
+
+    pub fn into_map(self) -> HashMap<String, DiscreteImage> {
+        self.names.into_iter().zip(self.images).collect()
+    }
+}
@@ -1,8 +1,21 @@
-pub use driver::CpuRenderer;
-pub use renderer::{CpuPreparedScene, CpuRenderSettings, StopCondition, Strategy};
+pub use animation::{CameraPath, orbit_camera_path, render_animation};
+pub use driver::{CpuRenderer, DEFAULT_MAX_PIXELS, ImageSizeError};
+pub use geometry::{Hit, ObjectHit, Ray};
+pub use light_tracing::LightFilm;
+pub use path_debug::{BounceDebug, PathDebug, PathStop};
+pub use subsampling::Subsampling;
+pub use renderer::{BlockOrder, object_id_color, BounceLimits, CpuPreparedScene, CpuRenderSettings, ImageOrigin, Jitter, LightSampling, LightSelection, pixel_rng, RayCamera, StopCondition, Strategy, Tolerances, VarianceMetric};
 
 mod driver;
+mod animation;
 mod renderer;
 mod geometry;
+mod environment;
+mod light_tracing;
+mod blue_noise;
+mod masks;
+mod guides;
+mod path_debug;
+mod subsampling;
 pub mod stats;
 pub mod accel;
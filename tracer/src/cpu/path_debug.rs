@@ -0,0 +1,151 @@
+use rand::Rng;
+
+use crate::common::math::{Unit, Vec3};
+use crate::common::scene::{Color, Medium};
+use crate::cpu::accel::{Accel, ObjectId};
+use crate::cpu::geometry::{ObjectHit, Ray};
+use crate::cpu::renderer::CpuPreparedScene;
+
+/// A single camera sample traced by [CpuPreparedScene::trace_path_debug], with every bounce along the path.
+#[derive(Debug, Clone)]
+pub struct PathDebug {
+    /// The bounces in the order they happen, starting with the camera ray.
+    pub bounces: Vec<BounceDebug>,
+    /// The color of the sample, the same as [CpuPreparedScene::sample_pixel_indexed] would return.
+    pub color: Color,
+    pub alpha: f32,
+}
+
+/// A single ray of a [PathDebug] and what happens at its end.
+#[derive(Debug, Clone)]
+pub struct BounceDebug {
+    pub ray: Ray,
+    /// The medium `ray` travels through.
+    pub medium: Medium,
+    /// The surface `ray` hits, with the shading normal on the side `ray` arrives from.
+    /// `None` if the ray escapes to the sky or isn't traced at all because the path ran out of bounces.
+    pub hit: Option<ObjectHit>,
+    /// The sampled direction of the next ray and the factor the light arriving from it is multiplied with,
+    /// including the albedo. Set for every surface that scatters light, even if the next ray isn't traced, see `stop`.
+    pub next: Option<(Unit<Vec3>, Color)>,
+    /// The light leaving the surface towards `ray` that doesn't come from the next ray:
+    /// emission and sampled lights for surfaces, the sky for rays that escape.
+    pub direct: Color,
+    /// All light leaving the surface towards `ray`, `direct` plus the weighted light arriving from the next ray.
+    /// The absorption and emission of `medium` along `ray` itself are not included yet.
+    pub color: Color,
+    /// Why the path ends here, `None` if the next ray is traced and is the next bounce.
+    pub stop: Option<PathStop>,
+}
+
+/// Why a path ends, see [BounceDebug::stop].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PathStop {
+    /// The ray didn't hit anything.
+    Sky,
+    /// There are no bounces left, either in total or of the kind that was sampled, see [BounceLimits](crate::cpu::BounceLimits).
+    BounceLimit,
+    /// The next direction points into the surface because of a bump map, which is treated as absorbed.
+    WrongSide,
+    /// The next ray is only checked for sky visibility by [Strategy::DirectOnly](crate::cpu::Strategy::DirectOnly).
+    DirectOnly,
+    /// The surface has a [MaterialType::Fixed](crate::common::scene::MaterialType::Fixed) color.
+    Fixed,
+    /// The surface is a [MaterialType::ShadowCatcher](crate::common::scene::MaterialType::ShadowCatcher).
+    ShadowCatcher,
+    /// [Strategy::EmissionOnly](crate::cpu::Strategy::EmissionOnly) stops at the first surface.
+    EmissionOnly,
+}
+
+/// Collects the bounces of a path while it's traced. The unit type ignores them, so normal rendering doesn't pay
+/// for building them.
+pub(super) trait PathLog {
+    fn record(&mut self, bounce: impl FnOnce() -> BounceDebug);
+
+    /// Called once for each sample with the object its camera ray hits first, `None` if it hits nothing.
+    #[inline(always)]
+    fn camera_hit(&mut self, _: Option<ObjectId>) {}
+}
+
+impl PathLog for () {
+    #[inline(always)]
+    fn record(&mut self, _: impl FnOnce() -> BounceDebug) {}
+}
+
+impl PathLog for Vec<BounceDebug> {
+    fn record(&mut self, bounce: impl FnOnce() -> BounceDebug) {
+        self.push(bounce());
+    }
+}
+
+impl BounceDebug {
+    /// A bounce where the path stops without sampling a next ray.
+    pub(super) fn end(ray: &Ray, medium: Medium, hit: Option<ObjectHit>, color: Color, stop: PathStop) -> Self {
+        BounceDebug { ray: *ray, medium, hit, next: None, direct: color, color, stop: Some(stop) }
+    }
+}
+
+impl<'a, A: Accel> CpuPreparedScene<'a, A> {
+    /// Trace one camera sample through the pixel at `(x, y)` like [CpuPreparedScene::sample_pixel_indexed],
+    /// but also record every bounce along the path. Useful to find out why a pixel is black or a firefly.
+    ///
+    /// The debug views [Strategy::ObjectIds](crate::cpu::Strategy::ObjectIds),
+    /// [Strategy::TraversalCost](crate::cpu::Strategy::TraversalCost) and
+    /// [Strategy::AmbientOcclusion](crate::cpu::Strategy::AmbientOcclusion) don't trace any paths, their bounces are
+    /// empty. [Strategy::EmissionOnly](crate::cpu::Strategy::EmissionOnly) stops at the first surface, its path is a
+    /// single bounce that ends in [PathStop::EmissionOnly] or [PathStop::Sky].
+    pub fn trace_path_debug(&self, rng: &mut impl Rng, x: u32, y: u32, sample: u32) -> PathDebug {
+        let mut bounces = vec![];
+        let (color, alpha) = self.sample_pixel_logged(rng, x, y, sample, &mut bounces);
+
+        // each bounce is recorded once the rest of its path is known, so the deepest one comes first
+        bounces.reverse();
+        PathDebug { bounces, color, alpha }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use crate::common::math::{Angle, Transform, Vec3};
+    use crate::common::scene::{Camera, Object, Scene, Shape, Sky};
+    use crate::cpu::accel::{NoAccel, ObjectId};
+    use crate::cpu::path_debug::PathStop;
+    use crate::cpu::renderer::{CpuPreparedScene, CpuRenderSettings, LightSelection, StopCondition, Strategy};
+    use crate::demos::{color_gray, material_diffuse, VACUUM};
+
+    #[test]
+    fn path_matches_sample() {
+        // a gray floor under a gray sky, the camera looks straight down
+        let floor = Object::new(Shape::Plane, material_diffuse(color_gray(0.5)), Transform::translate(Vec3::new(0.0, 0.0, -2.0)));
+        let camera = Camera { fov_horizontal: Angle::degrees(30.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects: vec![floor], sky: Sky::Uniform(color_gray(0.8)), camera };
+
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(1),
+            strategy: Strategy::Simple,
+            ..Default::default()
+        };
+        let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 3, 3);
+
+        for sample in 0..16 {
+            let path = prepared.trace_path_debug(&mut SmallRng::seed_from_u64(sample as u64), 1, 1, sample);
+            let (color, alpha) = prepared.sample_pixel_indexed(&mut SmallRng::seed_from_u64(sample as u64), 1, 1, sample);
+            assert_eq!((path.color, path.alpha), (color, alpha));
+
+            // the camera ray hits the floor, which bounces the path straight up into the sky
+            let [first, second] = &path.bounces[..] else { panic!("expected two bounces, got {:?}", path.bounces) };
+            assert_eq!(first.hit.as_ref().map(|hit| hit.id), Some(ObjectId::new(0)));
+            assert!(first.next.unwrap().0.z > 0.0);
+            assert_eq!(first.stop, None);
+            assert_eq!(first.color, path.color);
+
+            assert!(second.hit.is_none());
+            assert_eq!(second.stop, Some(PathStop::Sky));
+            assert_eq!(second.ray.direction, first.next.unwrap().0);
+            assert_eq!(first.color, first.direct + first.next.unwrap().1 * second.color);
+        }
+    }
+}
@@ -1,22 +1,94 @@
 use std::cmp::max;
+use std::f32::consts::PI;
+use std::ops::Range;
 
 use rand::distributions::Distribution;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use rand_distr::UnitDisc;
 
-use crate::common::math::{Norm, Point3, Transform, Unit, Vec2, Vec3};
+use crate::common::daylight::{sky_texture, sun_radiance, SUN_ANGULAR_RADIUS};
+use crate::common::math::{Norm, Point2, Point3, Transform, Unit, Vec2, Vec3};
 use crate::common::progress::PixelResult;
-use crate::common::scene::{Camera, Color, MaterialType, Medium, Object, Scene};
-use crate::cpu::accel::{Accel, ObjectId};
-use crate::cpu::geometry::{Hit, Intersect, ObjectHit, Ray};
-use crate::cpu::stats::ColorVarianceEstimator;
+use crate::common::scene::{BLACK, Camera, Color, Material, MaterialType, Medium, Object, RayKind, Scene, Shape, Sky, VACUUM, WHITE};
+use crate::common::spectrum;
+use crate::common::texture::luminance;
+use crate::common::tonemap::viridis;
+use crate::cpu::accel::{Accel, ObjectId, TraversalCost};
+use crate::cpu::blue_noise::blue_noise_offset;
+use crate::cpu::environment::EnvironmentSampler;
+use crate::cpu::geometry::{Hit, Intersect, ObjectHit, Ray, shading_normal};
+use crate::cpu::light_tracing::light_power;
+use crate::cpu::path_debug::{BounceDebug, PathLog, PathStop};
+use crate::cpu::stats::{ColorMeanEstimator, ColorVarianceEstimator};
 
 #[derive(Debug, Copy, Clone)]
 pub struct CpuRenderSettings {
     pub stop_condition: StopCondition,
+    /// The maximum number of bounces of any kind along a path.
     pub max_bounces: u32,
+    /// Additional limits for each kind of bounce, on top of `max_bounces`.
+    pub bounce_limits: BounceLimits,
     pub anti_alias: bool,
+    /// How the sub-pixel positions of the samples are picked, only used with `anti_alias`.
+    pub jitter: Jitter,
     pub strategy: Strategy,
+    pub light_sampling: LightSampling,
+    pub block_order: BlockOrder,
+    /// Which corner of the image is pixel `(0, 0)`, see [ImageOrigin].
+    pub image_origin: ImageOrigin,
+    /// Trace each path at a single random wavelength, which is slower to converge but shows dispersion.
+    /// See [Medium::dispersion].
+    pub spectral: bool,
+    /// Clamp the luminance of the light arriving at the second and later bounces of a path, removing most fireflies.
+    /// Direct light and the light reflected once towards the camera are never clamped, so highlights keep their brightness.
+    /// This is biased: the clamped energy is lost and the image gets darker, especially for scenes lit mostly indirectly.
+    pub max_indirect_luminance: Option<f32>,
+    /// Leave the sky seen directly by the camera out of the color, for compositing the render over a background image.
+    /// The color is then premultiplied by [PixelResult::alpha]. The sky still lights the scene and shows up in reflections.
+    pub transparent_sky: bool,
+    /// Shade every object that isn't a light with the same matte light gray material, a "clay render" to judge the
+    /// geometry and lighting of a scene without its materials. Cutouts and bump maps are kept since they shape the geometry,
+    /// [MaterialType::Fixed] and [MaterialType::ShadowCatcher] objects don't interact with light and are also left alone.
+    pub clay: bool,
+    /// Keep track of the variance of each pixel, see [PixelResult::variance]. Without it the variances are left at zero
+    /// and the samples are only summed, which is slightly faster. Always tracked for [StopCondition::Variance], which needs it.
+    pub track_variance: bool,
+    pub tolerances: Tolerances,
+}
+
+impl Default for CpuRenderSettings {
+    /// Path tracing with light sampling and 16 anti-aliased samples per pixel, everything else off or at its default.
+    fn default() -> Self {
+        CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(16),
+            max_bounces: 8,
+            bounce_limits: BounceLimits::default(),
+            anti_alias: true,
+            jitter: Jitter::BlueNoise,
+            strategy: Strategy::SampleLights,
+            light_sampling: LightSampling::default(),
+            block_order: BlockOrder::default(),
+            image_origin: ImageOrigin::default(),
+            spectral: false,
+            max_indirect_luminance: None,
+            transparent_sky: false,
+            clay: false,
+            track_variance: true,
+            tolerances: Tolerances::default(),
+        }
+    }
+}
+
+impl CpuRenderSettings {
+    /// The jitter for [RayCamera::new], `None` if anti-aliasing is disabled.
+    fn camera_jitter(&self) -> Option<Jitter> {
+        self.anti_alias.then_some(self.jitter)
+    }
+
+    fn tracks_variance(&self) -> bool {
+        self.track_variance || matches!(self.stop_condition, StopCondition::Variance { .. })
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -25,310 +97,1561 @@ pub enum StopCondition {
     // TODO consider variance in neighborhood instead of only single pixel
     // TODO rel_var should really be `var / mag / "difference in color between neighboring pixels"`,
     //   currently we just end up focusing on edges instead of surfaces
-    Variance { min_samples: u32, max_relative_variance: f32 },
+    Variance { min_samples: u32, max_relative_variance: f32, metric: VarianceMetric },
+    /// Render the whole image in passes that double the number of samples per pixel (1, 2, 4, ...) up to `max_samples`,
+    /// reporting the RMS change of the image between passes to [ProgressHandler::pass_finished](crate::common::progress::ProgressHandler::pass_finished).
+    /// Stops early once that change drops below `min_rms_change`, use `0.0` to always render all samples.
+    /// Only [CpuRenderer](crate::cpu::CpuRenderer) renders in passes, for single pixels this is the same as `SampleCount(max_samples)`.
+    Progressive { max_samples: u32, min_rms_change: f32 },
+}
+
+/// What the variance of [StopCondition::Variance] is measured on.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VarianceMetric {
+    /// Each of the red, green and blue channels separately, a pixel is only done once all of them have converged.
+    Channels,
+    /// The luminance of the samples, which better matches the perceived noise.
+    /// Pixels with converged brightness but some remaining color noise stop sampling.
+    Luminance,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum Strategy {
     Simple,
     SampleLights,
+    /// [Strategy::SampleLights] combined with a light tracing pass for caustics,
+    /// paths from a light through one or more specular surfaces onto a diffuse surface the camera sees directly.
+    /// Only [CpuRenderer](crate::cpu::CpuRenderer) runs that pass, [CpuPreparedScene::sample_pixel] leaves out those caustics.
+    LightTracing,
+    /// Debug view that doesn't trace any light, instead every pixel gets a false color depending on the object
+    /// the camera ray hits first, see [object_id_color]. Pixels that don't hit anything are black.
+    ObjectIds,
+    /// Cheap preview that only includes direct light, useful while moving the camera around.
+    /// Lights are sampled like [Strategy::SampleLights] and the sky is added through a single visibility ray,
+    /// but paths stop at the first non-specular bounce so indirect light is left out.
+    DirectOnly,
+    /// Debug view that shows how much work the acceleration structure does for the camera ray as a heatmap,
+    /// the number of nodes visited plus the number of objects intersected, see [Accel::traversal_cost].
+    /// Costs of `max_cost` and above get the brightest color.
+    TraversalCost { max_cost: u32 },
+    /// Grayscale ambient occlusion, a cheap shading cue that doesn't depend on the lighting or the materials.
+    /// For the first surface the camera sees, `rays` cosine weighted rays are shot over the hemisphere around the normal,
+    /// the pixel is the fraction of them that travel at least `radius` without hitting anything, so `rays` must be positive.
+    /// Pixels that don't hit anything are white with an alpha of zero.
+    AmbientOcclusion { rays: u32, radius: f32 },
+    /// Preview for placing lights that only shows the emission of the first surface the camera sees and the sky,
+    /// without any lighting or bounces. Even cheaper than [Strategy::DirectOnly].
+    EmissionOnly,
+}
+
+impl Strategy {
+    pub fn samples_lights(self) -> bool {
+        match self {
+            Strategy::Simple | Strategy::ObjectIds | Strategy::TraversalCost { .. } | Strategy::AmbientOcclusion { .. }
+            | Strategy::EmissionOnly => false,
+            Strategy::SampleLights | Strategy::LightTracing | Strategy::DirectOnly => true,
+        }
+    }
+}
+
+/// How the sub-pixel positions of the camera samples are picked, see [CpuRenderSettings::jitter].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Jitter {
+    /// Independent uniform random positions, which clump together and leave gaps at low sample counts.
+    Random,
+    /// Positions that are spread evenly over the pixel for any number of samples, see [blue_noise_offset].
+    /// Anti-aliased edges converge faster than with [Jitter::Random].
+    BlueNoise,
+}
+
+/// The order in which the blocks of the image are rendered, mostly visible in progress previews.
+/// The render threads split the list between them, so each thread follows the order within its own part of it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum BlockOrder {
+    /// A random order, which spreads expensive parts of the image over the threads.
+    #[default]
+    Shuffled,
+    /// Row by row from the top, this may be slower on scenes where the cost of pixels varies a lot.
+    RowMajor,
+    /// Along a Hilbert curve, which fills the image in a compact and cache-friendly pattern.
+    Hilbert,
+}
+
+/// Where the rows of the image start, which decides whether the first row of the rendered image and of the saved files
+/// is the top or the bottom of the picture. Tools that expect the other convention show the image upside down.
+///
+/// This is applied by [RayCamera], so the rendered image, the progress handlers and [draw_axes](crate::images::draw_axes)
+/// all use the same convention. The image writers store the rows in the order they are rendered in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ImageOrigin {
+    /// The first row is the top of the picture, like most image formats and viewers expect.
+    #[default]
+    TopLeft,
+    /// The first row is the bottom of the picture, like OpenGL textures and some EXR consumers expect.
+    BottomLeft,
+}
+
+/// Which objects are sampled explicitly as lights by [Strategy::SampleLights] and the light tracing pass.
+/// Only spheres and squares can be sampled.
+///
+/// Emissive objects that are not selected are still visible and light the scene,
+/// but only through rays that happen to hit them.
+#[derive(Debug, Clone, Default)]
+pub enum LightSelection {
+    /// All emissive spheres and squares that are visible to shadow rays.
+    /// Spheres around the shaded point, like a dome around the whole scene, are sampled like the sky, see [Object::sample_enclosing].
+    #[default]
+    Auto,
+    /// Only the given objects, which must all be emissive spheres or squares visible to shadow rays.
+    Only(Vec<ObjectId>),
+    /// All lights [LightSelection::Auto] would pick except the given ones.
+    Exclude(Vec<ObjectId>),
+    /// All lights [LightSelection::Auto] would pick, where each of the given groups is sampled as if it's a single light,
+    /// eg. for a grid of identical bulbs. Each time a light is sampled, a single random light of the group is picked,
+    /// so [LightSampling::All] traces one shadow ray per group instead of one per light.
+    ///
+    /// The lights in a group should have a similar size and brightness, otherwise the picked light is often a bad one,
+    /// which makes the render noisy. Each light can only be in a single group.
+    Grouped(Vec<Vec<ObjectId>>),
+}
+
+/// How the lights selected by [LightSelection] are sampled at each diffuse surface.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum LightSampling {
+    /// Trace a shadow ray towards every light, which gets slow for scenes with many lights.
+    #[default]
+    All,
+    /// Look at `candidates` random light samples and only trace a shadow ray towards one of them,
+    /// picked proportionally to how much light it would contribute if it's not blocked.
+    /// Much cheaper than [LightSampling::All] for scenes with many lights, and less noisy than picking a single random light.
+    Reservoir { candidates: u32 },
+}
+
+/// The maximum number of bounces of each kind along a path, tracked independently.
+///
+/// This allows eg. following many refractions through glass without also spending that budget on diffuse bounces.
+#[derive(Debug, Copy, Clone)]
+pub struct BounceLimits {
+    pub diffuse: u32,
+    /// reflections on mirrors and total internal reflection
+    pub specular: u32,
+    /// refractions through transparent surfaces
+    pub transmission: u32,
+}
+
+impl BounceLimits {
+    pub const UNLIMITED: BounceLimits = BounceLimits { diffuse: u32::MAX, specular: u32::MAX, transmission: u32::MAX };
+}
+
+impl Default for BounceLimits {
+    fn default() -> Self {
+        BounceLimits::UNLIMITED
+    }
+}
+
+/// Numerical tolerances used when tracing rays, the defaults work for scenes with sizes around `1.0`.
+///
+/// Very large or very small scenes may need different values to avoid self-intersections or light leaking through surfaces.
+/// There is no tolerance for the length of unit vectors, their error does not depend on the scale of the scene.
+#[derive(Debug, Copy, Clone)]
+pub struct Tolerances {
+    /// The minimum distance new rays start away from the surface they leave.
+    pub min_ray_bias: f32,
+    /// The distance new rays start away from the surface relative to the magnitude of the hit point and the distance travelled.
+    pub relative_ray_bias: f32,
+    /// Hits closer than this distance to the start of a ray are ignored.
+    pub min_ray_t: f32,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Tolerances {
+            min_ray_bias: 1e-6,
+            relative_ray_bias: 1e-5,
+            min_ray_t: 0.0,
+        }
+    }
+}
+
+/// The number of bounces left along a path, both in total and for each kind.
+#[derive(Debug, Copy, Clone)]
+struct BouncesLeft {
+    total: u32,
+    limits: BounceLimits,
+}
+
+/// The media a ray is currently inside of, to correctly handle nested and overlapping transparent objects.
+///
+/// The entered objects are kept as a stack, the current medium is the inside of the most recently entered object
+/// that has not been exited yet. Rays can exit objects they never entered, eg. when the camera starts inside of one,
+/// then the outside of that object becomes the medium underneath the stack.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct MediumStack {
+    base: Medium,
+    entered: [(ObjectId, Medium); MAX_NESTED_MEDIA],
+    len: usize,
+}
+
+/// How a ray was generated, which determines which light contributions it should still count.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum RayOrigin {
+    Camera,
+    /// a diffuse bounce on the first surface the camera sees
+    FirstDiffuse,
+    Diffuse,
+    /// a specular bounce with only specular bounces before it
+    Specular,
+    /// specular bounces after a diffuse bounce on the first surface the camera sees,
+    /// the caustics the light tracing pass splats onto the image
+    SplattedCaustic,
+    /// a specular bounce after a diffuse one, these are the paths that form caustics
+    Caustic,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum BounceKind {
+    Diffuse,
+    Specular,
+    Transmission,
 }
 
+/// A scene prepared for rendering with the CPU.
+///
+/// Only [CpuPreparedScene::camera] depends on the camera and the image size, everything else is derived from the
+/// objects and the settings. The camera can be swapped with [CpuPreparedScene::set_camera] without rebuilding the
+/// acceleration structure, which keeps interactive navigation responsive.
 pub struct CpuPreparedScene<'a, A> {
     pub scene: &'a Scene,
     pub camera: RayCamera,
     pub accel: A,
+    /// The objects that are sampled explicitly as lights, see [LightSelection]. The lights of each group are next to each other.
     pub lights: Vec<ObjectId>,
     pub settings: CpuRenderSettings,
+    /// the ranges of `lights` that are sampled as a single light, see [LightSelection::Grouped]
+    light_groups: Vec<Range<usize>>,
+    /// the total power emitted by `lights`, see [CpuPreparedScene::light_power]
+    light_power: Color,
+    /// importance sampler for the sky, if it is an environment map or an analytic sky
+    environment: Option<EnvironmentSampler>,
+    /// for each object whether it's in `lights`
+    sampled_light: Vec<bool>,
+    /// the material replacing those of all other objects for [CpuRenderSettings::clay]
+    clay_material: Material,
+    /// the media camera rays start in, see [CpuPreparedScene::find_camera_media]
+    camera_media: MediumStack,
 }
 
 impl<'a, A: Accel> CpuPreparedScene<'a, A> {
-    pub fn new(scene: &'a Scene, settings: CpuRenderSettings, accel: A, width: u32, height: u32) -> Self {
-        let camera = RayCamera::new(&scene.camera, settings.anti_alias, width, height);
+    pub fn new(scene: &'a Scene, settings: CpuRenderSettings, lights: &LightSelection, accel: A, width: u32, height: u32) -> Self {
+        if let Strategy::AmbientOcclusion { rays, .. } = settings.strategy {
+            assert!(rays > 0, "Ambient occlusion needs at least one ray");
+        }
+        let camera = RayCamera::new(&scene.camera, settings.camera_jitter(), settings.image_origin, width, height);
+
+        let mut light_groups = vec![];
+        let mut selected = vec![];
+        for group in lights.select(scene) {
+            let start = selected.len();
+            selected.extend(group);
+            light_groups.push(start..selected.len());
+        }
+        let lights = selected;
+
+        let mut sampled_light = vec![false; scene.objects.len()];
+        for light in &lights {
+            sampled_light[light.index] = true;
+        }
+        let light_power = lights.iter()
+            .map(|light| light_power(&scene.objects[light.index]))
+            .fold(Color::new(0.0, 0.0, 0.0), |a, b| a + b);
 
-        let lights = scene.objects.iter().enumerate().filter_map(|(id, object)| {
-            if is_light(object) { Some(ObjectId::new(id)) } else { None }
-        }).collect();
+        let environment = match scene.sky.lighting() {
+            Sky::Uniform(_) | Sky::Gradient { .. } | Sky::Backdrop { .. } => None,
+            Sky::Environment(image) => EnvironmentSampler::new(image),
+            &Sky::Analytic { sun_direction, turbidity } => {
+                let sampler = EnvironmentSampler::new(&sky_texture(sun_direction, turbidity, 256, 128));
+                // a sun below the horizon is black
+                let sun_visible = !is_black(sun_radiance(sun_direction, turbidity));
+                sampler.map(|sampler| if sun_visible { sampler.with_sun(sun_direction, SUN_ANGULAR_RADIUS) } else { sampler })
+            }
+        };
 
-        CpuPreparedScene {
+        let mut prepared = CpuPreparedScene {
             scene,
             camera,
             accel,
             lights,
             settings,
+            light_groups,
+            light_power,
+            environment,
+            sampled_light,
+            clay_material: clay_material(),
+            camera_media: MediumStack::new(scene.camera.medium),
+        };
+        prepared.camera_media = prepared.find_camera_media();
+        prepared
+    }
+
+    /// The total power emitted by the [lights](CpuPreparedScene::lights), their emission integrated over their surface
+    /// and all directions. Computed once while preparing the scene, useful eg. as a starting point for the exposure.
+    /// Emissive objects that are not sampled as lights and the sky are not included.
+    pub fn light_power(&self) -> Color {
+        self.light_power
+    }
+
+    /// Replace the camera and image size, [Scene::camera] is ignored from now on.
+    pub fn set_camera(&mut self, camera: &Camera, width: u32, height: u32) {
+        self.camera = RayCamera::new(camera, self.settings.camera_jitter(), self.settings.image_origin, width, height);
+        self.camera_media = self.find_camera_media();
+    }
+
+    /// The media around the camera position, taking into account the transparent objects the camera is inside of.
+    ///
+    /// These objects are found by following a probe ray from the camera through all surfaces that separate two media,
+    /// any surface it leaves without having entered it first belongs to an object around the camera. Those are then
+    /// entered from the outermost one inwards. If there are none the camera is in [Camera::medium].
+    fn find_camera_media(&self) -> MediumStack {
+        // an arbitrary direction, unlikely to run exactly along the edges between the faces of axis-aligned meshes
+        let direction = Vec3::new(0.27, 0.53, 0.8).normalized();
+        let mut probe = Ray { start: self.camera.position(), direction };
+
+        let mut entered: Vec<(ObjectId, Medium)> = vec![];
+        let mut around: Vec<ObjectId> = vec![];
+
+        for _ in 0..MAX_CAMERA_PROBE_CROSSINGS {
+            let Some(object_hit) = self.accel.first_hit(&self.scene.objects, &probe, |o| o.material.inside != o.material.outside) else {
+                break;
+            };
+            let (id, object) = (object_hit.id, &self.scene.objects[object_hit.id.index]);
+
+            if object_hit.hit.normal.dot(*direction) < 0.0 {
+                entered.push((id, object.material.inside));
+            } else {
+                // match the exits the same way as MediumStack::crossing
+                let left_index = entered.iter().rposition(|&(entered_id, _)| entered_id == id)
+                    .or_else(|| entered.iter().rposition(|&(_, medium)| medium == object.material.inside));
+                match left_index {
+                    Some(index) => { entered.remove(index); }
+                    None => around.push(id),
+                }
+            }
+
+            probe.start = self.settings.tolerances.offset_ray_start(&object_hit.hit, direction);
         }
+
+        let base = match around.last() {
+            Some(outermost) => self.scene.objects[outermost.index].material.outside,
+            None => self.camera.medium,
+        };
+        around.iter().rev().fold(MediumStack::new(base), |media, &id| media.crossing(id, &self.scene.objects[id.index], true))
+    }
+
+    /// The material `object` is shaded with, which is only different from its own material for [CpuRenderSettings::clay].
+    pub(super) fn shading_material<'s>(&'s self, object: &'s Object) -> &'s Material {
+        let keep = !self.settings.clay
+            || is_light(object)
+            || matches!(object.material.material_type, MaterialType::Fixed | MaterialType::ShadowCatcher);
+        if keep { &object.material } else { &self.clay_material }
+    }
+
+    /// [CpuPreparedScene::calculate_pixel] with the rng from [pixel_rng] instead of a shared one. The result only depends
+    /// on the scene, the settings, the pixel and `seed`, not on which thread renders it or what was rendered before,
+    /// which makes it useful for regression tests of the shading.
+    pub fn calculate_pixel_seeded(&self, x: u32, y: u32, seed: u64) -> PixelResult {
+        self.calculate_pixel(&mut pixel_rng(x, y, seed), x, y)
     }
 
+    /// Sample the pixel at `(x, y)` until the [StopCondition] in the settings is reached.
     pub fn calculate_pixel(&self, rng: &mut impl Rng, x: u32, y: u32) -> PixelResult {
+        self.calculate_pixel_logged(rng, x, y, &mut ())
+    }
+
+    /// [CpuPreparedScene::calculate_pixel] that also records the paths of all samples in `log`.
+    pub(super) fn calculate_pixel_logged(&self, rng: &mut impl Rng, x: u32, y: u32, log: &mut impl PathLog) -> PixelResult {
+        if !self.settings.tracks_variance() {
+            let mut estimator = ColorMeanEstimator::default();
+            while !self.settings.stop_condition.is_done_count(estimator.count) {
+                let (color, alpha) = self.sample_pixel_logged(rng, x, y, estimator.count, log);
+                estimator.update_with_alpha(color, alpha);
+            }
+            return estimator.pixel_result();
+        }
+
         let mut estimator = ColorVarianceEstimator::default();
 
         while !&self.settings.stop_condition.is_done(&estimator) {
-            let color = self.sample_pixel(rng, x, y);
-            estimator.update(color);
+            let (color, alpha) = self.sample_pixel_logged(rng, x, y, estimator.count, log);
+            estimator.update_with_alpha(color, alpha);
         }
 
-        let variance = estimator.variance().unwrap_or(Color::new(0.0, 0.0, 0.0));
-        PixelResult {
-            color: estimator.mean,
-            variance,
-            rel_variance: variance / (estimator.mean + Color::new(1.0, 1.0, 1.0)),
-            samples: estimator.count,
-        }
+        pixel_result(&estimator)
     }
-    
+
+    /// Trace exactly one camera sample through the pixel at `(x, y)`, ignoring the [StopCondition].
+    ///
+    /// This is the building block for custom accumulation loops, the samples are unbiased estimates of the pixel color
+    /// and can be averaged in any order, for example with a [ColorVarianceEstimator].
+    /// The sub-pixel position is random, use [CpuPreparedScene::sample_pixel_indexed] to get the even spread of [Jitter::BlueNoise].
     pub fn sample_pixel(&self, rng: &mut impl Rng, x: u32, y: u32) -> Color {
-        trace_ray(
-            self.scene,
-            &self.accel,
-            &self.lights,
-            self.settings.strategy,
-            &self.camera.ray(rng, x, y),
-            true,
-            rng,
-            self.settings.max_bounces,
-            true,
-            self.scene.camera.medium,
-        )
+        self.sample_pixel_with_alpha(rng, x, y).0
     }
-}
 
-impl StopCondition {
-    fn is_done(self, estimator: &ColorVarianceEstimator) -> bool {
-        fn variance_lte(estimator: &ColorVarianceEstimator, right: f32) -> bool {
-            //TODO figure out a better way to allow blackness and add a mechanism to ignore variance in huge means
-            let variance = estimator.variance().expect("Not enough samples to even compute the variance!");
-            let rel_variance = variance / (estimator.mean + Color::new(1.0, 1.0, 1.0));
+    /// Like [CpuPreparedScene::sample_pixel], but also returns the alpha of the sample, see [PixelResult::alpha].
+    pub fn sample_pixel_with_alpha(&self, rng: &mut impl Rng, x: u32, y: u32) -> (Color, f32) {
+        let sample = rng.gen();
+        self.sample_pixel_indexed(rng, x, y, sample)
+    }
 
-            //we care about the variance of the mean, not the variance of the values themselves
-            let left = rel_variance / (estimator.count as f32).sqrt();
-            left.red <= right && left.green <= right && left.blue <= right
+    /// Like [CpuPreparedScene::sample_pixel_with_alpha], but `sample` is the number of samples already taken in this pixel,
+    /// it picks the sub-pixel position for [Jitter::BlueNoise].
+    pub fn sample_pixel_indexed(&self, rng: &mut impl Rng, x: u32, y: u32, sample: u32) -> (Color, f32) {
+        self.sample_pixel_logged(rng, x, y, sample, &mut ())
+    }
+
+    /// [CpuPreparedScene::sample_pixel_indexed] that also records the bounces of the path in `log`.
+    pub(super) fn sample_pixel_logged(&self, rng: &mut impl Rng, x: u32, y: u32, sample: u32, log: &mut impl PathLog) -> (Color, f32) {
+        if let Strategy::TraversalCost { max_cost } = self.settings.strategy {
+            let ray = self.camera.ray(rng, x, y, sample);
+            let cost = self.accel.traversal_cost(&self.scene.objects, &ray);
+            let [r, g, b] = viridis(cost.total() as f32 / max_cost as f32);
+            return (palette::Srgb::new(r, g, b).into_format().into_linear(), 1.0);
         }
 
-        match self {
-            StopCondition::SampleCount(samples) =>
-                estimator.count >= samples,
-            StopCondition::Variance { min_samples, max_relative_variance } =>
-                estimator.count >= max(min_samples, 2) &&
-                    variance_lte(estimator, max_relative_variance),
+        let ray = self.camera.ray(rng, x, y, sample);
+
+        if let Strategy::ObjectIds = self.settings.strategy {
+            let object_hit = self.first_opaque_hit(&ray, filter_visible(RayKind::Camera));
+            log.camera_hit(object_hit.as_ref().map(|object_hit| object_hit.id));
+            return match object_hit {
+                Some(object_hit) => (object_id_color(object_hit.id), 1.0),
+                None => (Color::new(0.0, 0.0, 0.0), 0.0),
+            };
+        }
+
+        if let Strategy::AmbientOcclusion { rays, radius } = self.settings.strategy {
+            let object_hit = self.first_opaque_hit(&ray, filter_visible(RayKind::Camera));
+            log.camera_hit(object_hit.as_ref().map(|object_hit| object_hit.id));
+            return match object_hit {
+                Some(object_hit) => {
+                    let visible = self.ambient_occlusion(rng, ray.direction, &object_hit.hit, rays, radius);
+                    (Color::new(visible, visible, visible), 1.0)
+                }
+                None => (Color::new(1.0, 1.0, 1.0), 0.0),
+            };
         }
+
+        let bounces_left = BouncesLeft { total: self.settings.max_bounces, limits: self.settings.bounce_limits };
+
+        let (wavelength, weight) = self.sample_wavelength(rng);
+        let (color, alpha) = self.trace_ray(&ray, RayOrigin::Camera, 1.0, rng, bounces_left, wavelength, self.camera_media, log);
+        (color * weight, alpha)
     }
-}
 
-pub struct RayCamera {
-    x_span: f32,
-    y_span: f32,
-    width: f32,
-    height: f32,
-    transform: Transform,
-    anti_alias: bool,
-}
+    /// The work the acceleration structure does for a random camera ray through the pixel at `(x, y)`,
+    /// see [Accel::traversal_cost].
+    pub fn camera_traversal_cost(&self, rng: &mut impl Rng, x: u32, y: u32) -> TraversalCost {
+        let sample = rng.gen();
+        let ray = self.camera.ray(rng, x, y, sample);
+        self.accel.traversal_cost(&self.scene.objects, &ray)
+    }
 
-impl RayCamera {
-    pub fn new(camera: &Camera, anti_alias: bool, width: u32, height: u32) -> RayCamera {
-        let x_span = 2.0 * (camera.fov_horizontal.radians / 2.0).tan();
-        RayCamera {
-            x_span,
-            y_span: x_span * (height as f32) / (width as f32),
-            width: width as f32,
-            height: height as f32,
-            transform: camera.transform,
-            anti_alias,
-        }
+    /// The fraction of `rays` cosine weighted rays from `hit` that travel at least `radius` without hitting anything,
+    /// see [Strategy::AmbientOcclusion]. The rays leave on the side of the surface `direction` arrives from.
+    fn ambient_occlusion(&self, rng: &mut impl Rng, direction: Unit<Vec3>, hit: &Hit, rays: u32, radius: f32) -> f32 {
+        let normal = if hit.normal.dot(*direction) < 0.0 { hit.normal } else { -hit.normal };
+        let start = self.settings.tolerances.offset_ray_start(hit, normal);
+
+        let unblocked = (0..rays)
+            .filter(|_| {
+                let direction = disk_to_hemisphere(Vec2::from_slice(&UnitDisc.sample(rng)), normal);
+                let blocker = self.first_opaque_hit(&Ray { start, direction }, filter_visible(RayKind::Shadow));
+                !blocker.is_some_and(|blocker| blocker.hit.t < radius)
+            })
+            .count();
+        unblocked as f32 / rays as f32
     }
 
-    fn ray<R: Rng>(&self, rng: &mut R, x: u32, y: u32) -> Ray {
-        let (dx, dy) = if self.anti_alias {
-            rng.gen()
+    /// Sample the wavelength for a new path if rendering spectrally, also returns the color weight for that wavelength.
+    pub(super) fn sample_wavelength(&self, rng: &mut impl Rng) -> (Option<f32>, Color) {
+        if self.settings.spectral {
+            let wavelength = spectrum::sample_wavelength(rng.gen());
+            (Some(wavelength), spectrum::wavelength_weight(wavelength))
         } else {
-            (0.5, 0.5)
+            (None, Color::new(1.0, 1.0, 1.0))
+        }
+    }
+
+    /// Find the object the camera sees along `ray`, together with its material, without any shading.
+    /// Objects hidden from the camera and parts cut out by an alpha texture are skipped, like for camera rays.
+    pub fn pick(&self, ray: &Ray) -> Option<(ObjectHit, &Material)> {
+        let object_hit = self.first_opaque_hit(ray, filter_visible(RayKind::Camera))?;
+        let material = &self.scene.objects[object_hit.id.index].material;
+        Some((object_hit, material))
+    }
+
+    /// Find the first hit along the ray, skipping hits that are masked out by the material alpha texture.
+    /// The returned `t` is still relative to the start of `ray`.
+    pub(super) fn first_opaque_hit(&self, ray: &Ray, filter: impl Fn(&Object) -> bool) -> Option<ObjectHit> {
+        // skip hits closer than the minimum distance by starting the ray that far along
+        let t_min = self.settings.tolerances.min_ray_t;
+        let mut curr_ray = Ray { start: ray.at(t_min), direction: ray.direction };
+        let mut t_offset = t_min;
+
+        loop {
+            let mut object_hit = self.accel.first_hit(&self.scene.objects, &curr_ray, &filter)?;
+            object_hit.hit.t += t_offset;
+
+            let material = &self.scene.objects[object_hit.id.index].material;
+            let masked = material.alpha.as_ref()
+                .is_some_and(|alpha| alpha.sample_scalar(object_hit.hit.uv) < ALPHA_CUTOFF);
+            if !masked {
+                return Some(object_hit);
+            }
+
+            // continue the ray from just beyond the masked surface
+            curr_ray = Ray {
+                start: self.settings.tolerances.offset_ray_start(&object_hit.hit, ray.direction),
+                direction: ray.direction,
+            };
+            t_offset = object_hit.hit.t;
+        }
+    }
+
+    /// The light arriving at `hit` from the sampled lights and the sky reflected according to `brdf` towards the viewer.
+    fn sample_lights<R: Rng>(&self, next_start: Point3, medium: Medium, rng: &mut R, hit: &Hit, brdf: &SurfaceBrdf) -> Color {
+        let mut result = match self.settings.light_sampling {
+            LightSampling::All => {
+                let mut result = Color::new(0.0, 0.0, 0.0);
+                for group in &self.light_groups {
+                    let (light_id, weight, target) = self.sample_light_group(group, next_start, hit, rng);
+                    result += self.light_sample_contribution(light_id, weight, target, next_start, medium, hit, brdf);
+                }
+                result
+            }
+            LightSampling::Reservoir { candidates } =>
+                self.sample_lights_reservoir(candidates, next_start, medium, rng, hit, brdf),
         };
 
-        let x = ((x as f32 + dx) / self.width - 0.5) * self.x_span;
-        let y = ((self.height - (y as f32 + dy)) / self.height - 0.5) * self.y_span;
+        for (direction, pdf) in self.environment.iter().flat_map(|environment| environment.samples(rng)) {
+            let cos = direction.dot(*hit.normal);
 
-        self.transform * &Ray {
-            start: Point3::origin(),
-            direction: Vec3::new(x, y, -1.0).normalized(),
+            if cos > 0.0 && pdf > 0.0 && pdf.is_finite() {
+                let sky_ray = Ray { start: next_start, direction };
+
+                // the sky is only visible if we don't hit anything
+                if self.first_opaque_hit(&sky_ray, filter_visible(RayKind::Shadow)).is_none() {
+                    let volumetric_mask = color_exp(medium.volumetric_color, f32::INFINITY);
+                    result += self.scene.sky.emission(direction) * brdf.eval(hit.normal, direction) * volumetric_mask * (cos / pdf);
+                }
+            }
         }
+
+        result
     }
-}
 
-const SHADOW_BIAS: f32 = 0.0001;
+    /// One sample of a sky without an [EnvironmentSampler] from a cosine weighted direction above the surface at `hit`.
+    /// The next bounce also finds the sky in those directions, so both are weighted with the balance heuristic
+    /// of multiple importance sampling, see [bounce_sky_weight].
+    ///
+    /// Unlike the other lights this is only sampled when the bounce picked a non-specular direction,
+    /// so a [MaterialType::DiffuseMirror] doesn't mix up sky samples with mirror reflections.
+    /// The result is divided by the probability of that, [SampleInfo::diffuse_fraction].
+    fn sample_sky_hemisphere<R: Rng>(&self, next_start: Point3, medium: Medium, rng: &mut R, hit: &Hit, brdf: &SurfaceBrdf) -> Color {
+        let direction = disk_to_hemisphere(Vec2::from_slice(&UnitDisc.sample(rng)), hit.normal);
+        let pdf = sky_hemisphere_pdf(hit.normal, direction);
+        if pdf <= 0.0 || self.first_opaque_hit(&Ray { start: next_start, direction }, filter_visible(RayKind::Shadow)).is_some() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
 
-pub fn is_light(object: &Object) -> bool {
-    !is_black(object.material.emission)
-}
+        let weight = 1.0 - bounce_sky_weight(brdf.pdf(hit.normal, direction), pdf);
+        let volumetric_mask = color_exp(medium.volumetric_color, f32::INFINITY);
+        self.scene.sky.emission(direction) * brdf.eval(hit.normal, direction) * volumetric_mask * (direction.dot(*hit.normal) / pdf * weight)
+    }
+
+    /// Sample a random light of `group`, with the weight of a sample of the whole group.
+    fn sample_light_group<R: Rng>(&self, group: &Range<usize>, next_start: Point3, hit: &Hit, rng: &mut R) -> (ObjectId, f32, Point3) {
+        let light_id = self.lights[rng.gen_range(group.clone())];
+        let (weight, target) = sample_light(&self.scene.objects[light_id.index], next_start, hit, rng);
+        (light_id, weight * group.len() as f32, target)
+    }
+
+    /// Pick a single light sample out of `candidates` random ones with weighted reservoir sampling,
+    /// proportional to the light they would contribute if they're not blocked. Only the picked sample is traced,
+    /// so this needs a single shadow ray no matter how many lights there are.
+    ///
+    /// This is resampled importance sampling as used by ReSTIR, without the reuse between pixels.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_lights_reservoir<R: Rng>(&self, candidates: u32, next_start: Point3, medium: Medium, rng: &mut R, hit: &Hit, brdf: &SurfaceBrdf) -> Color {
+        if self.lights.is_empty() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        // the picked light sample and its target weight
+        let mut picked = None;
+        let mut total_weight = 0.0;
+
+        for _ in 0..candidates {
+            let light_id = self.lights[rng.gen_range(0..self.lights.len())];
+            let light = &self.scene.objects[light_id.index];
+            let (weight, target) = sample_light(light, next_start, hit, rng);
 
-fn sample_lights<R: Rng>(scene: &Scene, accel: &impl Accel, lights: &[ObjectId], next_start: Point3, medium: Medium, rng: &mut R, hit: &Hit) -> Color {
-    let mut result = Color::new(0.0, 0.0, 0.0);
+            // the estimate for all lights from this candidate, assuming it's visible
+            let contribution = unblocked_light_contribution(light, weight, target, next_start, medium, hit, brdf);
+            // lights with a negative strength are picked by the magnitude of their contribution
+            let target_weight = luminance(contribution * self.lights.len() as f32).abs();
 
-    for &light_id in lights {
-        let light = &scene.objects[light_id.index];
+            total_weight += target_weight;
+            if target_weight > 0.0 && rng.gen::<f32>() * total_weight < target_weight {
+                picked = Some((light_id, weight, target, target_weight));
+            }
+        }
+
+        match picked {
+            None => Color::new(0.0, 0.0, 0.0),
+            Some((light_id, weight, target, target_weight)) => {
+                let contribution = self.light_sample_contribution(light_id, weight, target, next_start, medium, hit, brdf);
+                contribution * (self.lights.len() as f32 * total_weight / (candidates as f32 * target_weight))
+            }
+        }
+    }
+
+    /// The light arriving from the sample `target` on the light `light_id`, taken with `weight`, or black if it's blocked.
+    #[allow(clippy::too_many_arguments)]
+    fn light_sample_contribution(&self, light_id: ObjectId, weight: f32, target: Point3, next_start: Point3, medium: Medium, hit: &Hit, brdf: &SurfaceBrdf) -> Color {
+        let light = &self.scene.objects[light_id.index];
         assert!(is_light(light));
 
-        let (weight, target) = light.sample(rng);
         let light_ray = Ray { start: next_start, direction: (target - next_start).normalized() };
 
         // TODO is this actually correct for transparent objects ?
-        match accel.first_hit(&scene.objects, &light_ray, filter_fixed_camera_only(false)) {
+        match self.first_opaque_hit(&light_ray, filter_visible(RayKind::Shadow)) {
             // the light is unobstructed, it's the first thing we hit again
             Some(ObjectHit { id: object, hit: light_hit }) if object == light_id => {
-                let abs_cos = light_ray.direction.dot(*hit.normal).abs();
-                let volumetric_mask = color_exp(medium.volumetric_color, light_hit.t);
-
-                result += light.material.emission * weight * abs_cos * volumetric_mask * light.area_seen_from(next_start);
+                let light_cos = light_ray.direction.dot(*light_hit.normal).abs();
+                let emission = light.material.emission_at(light_hit.uv);
+                light_contribution(light, emission, weight, light_ray.direction, light_hit.t, light_cos, medium, hit, brdf)
             }
             // another object is blocking the light
-            Some(_) => {}
+            Some(_) => Color::new(0.0, 0.0, 0.0),
             // hit nothing, should means we missed the edge of the light because of numerical issues
-            None => {}
+            None => Color::new(0.0, 0.0, 0.0),
         }
     }
 
-    result
-}
+    /// The fraction of the direct light arriving at `hit` that is blocked by other objects,
+    /// estimated from a single sample of each light and of the sky. Used to render a [MaterialType::ShadowCatcher].
+    fn shadow_fraction<R: Rng>(&self, next_start: Point3, medium: Medium, rng: &mut R, hit: &Hit) -> f32 {
+        let mut unblocked = Color::new(0.0, 0.0, 0.0);
+        let mut arriving = Color::new(0.0, 0.0, 0.0);
 
-fn filter_fixed_camera_only(is_camera_ray: bool) -> impl Fn(&Object) -> bool {
-    move |o: &Object| {
-        match o.material.material_type {
-            MaterialType::Fixed { camera_only } => is_camera_ray || !camera_only,
-            _ => true,
+        for group in &self.light_groups {
+            let (light_id, weight, target) = self.sample_light_group(group, next_start, hit, rng);
+            let light = &self.scene.objects[light_id.index];
+            let contribution = unblocked_light_contribution(light, weight, target, next_start, medium, hit, &SurfaceBrdf::diffuse(WHITE));
+            unblocked += contribution;
+
+            // unlike for shading, missing the edge of the light doesn't count as a shadow here
+            let (direction, distance) = (target - next_start).normalized_and_get();
+            let blocker = self.first_opaque_hit(&Ray { start: next_start, direction }, filter_visible(RayKind::Shadow));
+            if !blocker.is_some_and(|blocker| blocker.id != light_id && blocker.hit.t < distance) {
+                arriving += contribution;
+            }
         }
-    }
-}
 
-fn trace_ray<'a, R: Rng>(
-    scene: &Scene,
-    accel: &'a impl Accel,
-    lights: &[ObjectId],
-    strategy: Strategy,
-    ray: &Ray,
-    is_camera_ray: bool,
-    rng: &mut R,
-    bounces_left: u32,
-    specular: bool,
-    medium: Medium,
-) -> Color {
-    if bounces_left == 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
+        let sample = sample_direction(&Ray { start: next_start, direction: hit.normal }, hit, MaterialType::Diffuse, 1.0, rng);
+        let sky = self.scene.sky.emission(sample.direction) * color_exp(medium.volumetric_color, f32::INFINITY) * sample.weight();
+        unblocked += sky;
+        if self.first_opaque_hit(&Ray { start: next_start, direction: sample.direction }, filter_visible(RayKind::Shadow)).is_none() {
+            arriving += sky;
+        }
 
-    let filter = filter_fixed_camera_only(is_camera_ray);
-    let (t, result) = if let Some(object_hit) = accel.first_hit(&scene.objects, ray, filter) {
-        let ObjectHit { id: object, mut hit } = object_hit;
-        let object = &scene.objects[object.index];
+        let unblocked = luminance(unblocked);
+        if unblocked > 0.0 {
+            (1.0 - luminance(arriving) / unblocked).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
 
-        if let MaterialType::Fixed { camera_only } = object.material.material_type {
-            debug_assert!(is_camera_ray || !camera_only);
-            return object.material.albedo;
+    /// Trace the path continuing along `ray`, returning the light arriving along it and, for camera rays, the alpha.
+    /// The alpha of all other rays is always one.
+    /// The sky is weighted by `sky_weight` if the ray misses everything, see [bounce_sky_weight].
+    /// Every bounce is recorded in `log` after the rest of the path, so the deepest bounce comes first.
+    #[allow(clippy::too_many_arguments)]
+    fn trace_ray<R: Rng>(
+        &self,
+        ray: &Ray,
+        origin: RayOrigin,
+        sky_weight: f32,
+        rng: &mut R,
+        bounces_left: BouncesLeft,
+        wavelength: Option<f32>,
+        media: MediumStack,
+        log: &mut impl PathLog,
+    ) -> (Color, f32) {
+        let medium = media.current();
+        if bounces_left.total == 0 {
+            log.record(|| BounceDebug::end(ray, medium, None, Color::new(0.0, 0.0, 0.0), PathStop::BounceLimit));
+            return (Color::new(0.0, 0.0, 0.0), 1.0);
         }
 
-        // figure out the next medium
-        let into = hit.normal.dot(*ray.direction) < 0.0;
-        let next_medium = if into {
-            debug_assert_eq!(medium, object.material.outside);
-            object.material.inside
-        } else {
-            hit.normal = -hit.normal;
-            debug_assert_eq!(medium, object.material.inside);
-            object.material.outside
-        };
+        let kind = if origin == RayOrigin::Camera { RayKind::Camera } else { RayKind::Indirect };
+        let mut alpha = 1.0;
+        let object_hit = self.first_opaque_hit(ray, filter_visible(kind));
+        if origin == RayOrigin::Camera {
+            log.camera_hit(object_hit.as_ref().map(|object_hit| object_hit.id));
+        }
+        let (t, result) = if let Some(object_hit) = object_hit {
+            let ObjectHit { id: object_id, mut hit } = object_hit;
+            let object = &self.scene.objects[object_id.index];
+            let material = self.shading_material(object);
 
-        // sample the next ray
-        let refract_ratio = medium.index_of_refraction / next_medium.index_of_refraction;
-        let sample = sample_direction(&ray, &hit, object.material.material_type, refract_ratio, rng);
+            match material.material_type {
+                MaterialType::Fixed => {
+                    let color = material.albedo_at(hit.uv);
+                    log.record(|| BounceDebug::end(ray, medium, Some(ObjectHit { id: object_id, hit }), color, PathStop::Fixed));
+                    return (color, 1.0);
+                }
+                MaterialType::ShadowCatcher => {
+                    // the catcher itself is transparent, only the shadow on it is black
+                    if hit.normal.dot(*ray.direction) > 0.0 {
+                        hit.normal = -hit.normal;
+                    }
+                    let start = self.settings.tolerances.offset_ray_start(&hit, hit.normal);
+                    let shadow = self.shadow_fraction(start, medium, rng, &hit);
+                    let color = medium_emission(medium, hit.t);
+                    log.record(|| BounceDebug::end(ray, medium, Some(ObjectHit { id: object_id, hit }), color, PathStop::ShadowCatcher));
+                    return (color, shadow);
+                }
+                _ => {}
+            }
 
-        let mut result = Color::new(0.0, 0.0, 0.0);
+            if let Strategy::EmissionOnly = self.settings.strategy {
+                let color = material.emission_at(hit.uv);
+                log.record(|| BounceDebug::end(ray, medium, Some(ObjectHit { id: object_id, hit: hit.clone() }), color, PathStop::EmissionOnly));
+                return (color_exp(medium.volumetric_color, hit.t) * color + medium_emission(medium, hit.t), 1.0);
+            }
 
-        // add the light contributions
-        match strategy {
-            Strategy::Simple => {
-                result += object.material.emission;
+            // figure out the next medium
+            let into = hit.normal.dot(*ray.direction) < 0.0;
+            // bump maps only change the normal used for shading, new rays must still leave on the correct side of the surface
+            let surface_normal = if into { hit.normal } else { -hit.normal };
+            hit.normal = shading_normal(object, &hit);
+            if !into {
+                hit.normal = -hit.normal;
             }
-            Strategy::SampleLights => {
-                if specular {
-                    result += object.material.emission;
+            let next_media = media.crossing(object_id, object, into);
+
+            // sample the next ray
+            let refract_ratio = refract_ratio(medium, next_media.current(), wavelength);
+            let sample = sample_direction(ray, &hit, material.material_type, refract_ratio, rng);
+            let brdf = SurfaceBrdf::new(material, hit.uv, -ray.direction);
+
+            // the sky found by the next ray, unless it's already sampled as a light
+            let next_sky_weight = match (sample.specular || !self.samples_sky(), &self.environment) {
+                (true, _) => 1.0,
+                (false, Some(_)) => 0.0,
+                (false, None) => bounce_sky_weight(brdf.pdf(hit.normal, sample.direction), sky_hemisphere_pdf(hit.normal, sample.direction)),
+            };
+
+            let mut result = Color::new(0.0, 0.0, 0.0);
+
+            // add the light contributions
+            match self.settings.strategy {
+                Strategy::Simple => {
+                    result += material.emission_at(hit.uv);
                 }
+                Strategy::ObjectIds | Strategy::TraversalCost { .. } | Strategy::AmbientOcclusion { .. } | Strategy::EmissionOnly =>
+                    unreachable!("Debug views don't trace any paths"),
+                Strategy::SampleLights | Strategy::LightTracing | Strategy::DirectOnly => {
+                    // lights that are not sampled are only found by hitting them, so they always count
+                    // caustics on the first surface the camera sees are already included by the light tracing pass
+                    let light_traced = origin == RayOrigin::SplattedCaustic && matches!(self.settings.strategy, Strategy::LightTracing);
+                    if !self.sampled_light[object_id.index] || (origin.is_specular() && !light_traced) {
+                        result += material.emission_at(hit.uv);
+                    }
 
-                if sample.diffuse_fraction != 0.0 {
-                    let light_start = hit.point + (*hit.normal * SHADOW_BIAS);
-                    let light_contribution = sample_lights(scene, accel, lights, light_start, medium, rng, &hit);
-                    result += object.material.albedo * light_contribution * sample.diffuse_fraction;
+                    let light_start = self.settings.tolerances.offset_ray_start(&hit, hit.normal);
+                    if sample.diffuse_fraction != 0.0 {
+                        result += self.sample_lights(light_start, medium, rng, &hit, &brdf);
+                    }
+                    if !sample.specular && self.environment.is_none() && self.samples_sky() {
+                        result += self.sample_sky_hemisphere(light_start, medium, rng, &hit, &brdf) / sample.diffuse_fraction;
+                    }
                 }
             }
-        }
 
-        // add the contribution of the next ray
-        let next_ray = Ray {
-            start: hit.point + (*sample.direction * SHADOW_BIAS),
-            direction: sample.direction,
+            // add the contribution of the next ray
+            let next_ray = Ray {
+                start: self.settings.tolerances.offset_ray_start(&hit, sample.direction),
+                direction: sample.direction,
+            };
+            let next_media = if sample.crosses_surface { next_media } else { media };
+            let direct_only = matches!(self.settings.strategy, Strategy::DirectOnly) && !sample.specular;
+            let wrong_side = object.material.bump.is_some()
+                && (sample.direction.dot(*surface_normal) < 0.0) != sample.crosses_surface;
+            let next_contribution = match bounces_left.after(sample.kind()) {
+                _ if wrong_side => Color::new(0.0, 0.0, 0.0),
+                Some(_) if direct_only => self.sky_visible(&next_ray, next_media.current(), next_sky_weight),
+                Some(next_bounces_left) =>
+                    self.trace_ray(&next_ray, origin.next(&sample), next_sky_weight, rng, next_bounces_left, wavelength, next_media, log).0,
+                None => Color::new(0.0, 0.0, 0.0),
+            };
+
+            // the first bounce is the path vertex visible to the camera, everything arriving at later ones is indirect
+            let is_first_bounce = bounces_left.total == self.settings.max_bounces;
+            let next_contribution = match self.settings.max_indirect_luminance {
+                Some(max_luminance) if !is_first_bounce => clamp_luminance(next_contribution, max_luminance),
+                _ => next_contribution,
+            };
+
+            let tint = sample.tint(material.albedo_at(hit.uv));
+            let direct = result;
+            result += tint * next_contribution * sample.weight();
+
+            log.record(|| {
+                let stop = match bounces_left.after(sample.kind()) {
+                    _ if wrong_side => Some(PathStop::WrongSide),
+                    Some(_) if direct_only => Some(PathStop::DirectOnly),
+                    Some(_) => None,
+                    None => Some(PathStop::BounceLimit),
+                };
+                BounceDebug {
+                    ray: *ray,
+                    medium,
+                    hit: Some(ObjectHit { id: object_id, hit: hit.clone() }),
+                    next: Some((sample.direction, tint * sample.weight())),
+                    direct,
+                    color: result,
+                    stop,
+                }
+            });
+            (hit.t, result)
+        } else {
+            if origin == RayOrigin::Camera {
+                alpha = 0.0;
+            }
+            let sky = if origin == RayOrigin::Camera && self.settings.transparent_sky {
+                Color::new(0.0, 0.0, 0.0)
+            } else if origin == RayOrigin::Camera {
+                self.scene.sky.camera_emission(ray.direction)
+            } else {
+                self.scene.sky.emission(ray.direction) * sky_weight
+            };
+            log.record(|| BounceDebug::end(ray, medium, None, sky, PathStop::Sky));
+            (f32::INFINITY, sky)
         };
-        let next_medium = if sample.crosses_surface { next_medium } else { medium };
-        let next_contribution = trace_ray(scene, accel, lights, strategy, &next_ray, false, rng, bounces_left - 1, sample.specular, next_medium);
 
-        result += object.material.albedo * next_contribution * sample.weight;
+        (color_exp(medium.volumetric_color, t) * result + medium_emission(medium, t), alpha)
+    }
 
-        (hit.t, result)
-    } else {
-        (f32::INFINITY, scene.sky_emission)
-    };
+    /// Whether the sky is sampled explicitly as a light at every diffuse bounce, like the [lights](CpuPreparedScene::lights),
+    /// instead of only contributing through rays that happen to miss all objects.
+    ///
+    /// Environment maps and analytic skies are sampled by brightness, which finds small bright spots like the sun.
+    /// Other skies are sampled with cosine weighted directions, combined with the next bounce by multiple importance sampling.
+    /// A black sky doesn't light anything, so it's not sampled.
+    pub fn samples_sky(&self) -> bool {
+        let black = matches!(self.scene.sky.lighting(), Sky::Uniform(color) if *color == BLACK);
+        self.settings.strategy.samples_lights() && !black
+    }
 
-    color_exp(medium.volumetric_color, t) * result
+    /// The sky light arriving along `ray` if it doesn't hit anything, weighted by `sky_weight`,
+    /// used instead of recursing by [Strategy::DirectOnly].
+    fn sky_visible(&self, ray: &Ray, medium: Medium, sky_weight: f32) -> Color {
+        if sky_weight == 0.0 || self.first_opaque_hit(ray, filter_visible(RayKind::Indirect)).is_some() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        color_exp(medium.volumetric_color, f32::INFINITY) * self.scene.sky.emission(ray.direction) * sky_weight + medium_emission(medium, f32::INFINITY)
+    }
 }
 
-#[derive(Debug)]
-struct SampleInfo {
-    /// the direction of the next ray
-    direction: Unit<Vec3>,
-    /// the weight associated with the direction sampling, needs to be divided out of the contribution of the next ray
-    weight: f32,
+impl LightSelection {
+    /// The selected lights, split into the groups that are sampled as a single light.
+    fn select(&self, scene: &Scene) -> Vec<Vec<ObjectId>> {
+        // shadow rays towards lights that are invisible to them would never arrive
+        let can_sample = |object: &Object| is_light(object) && object.visibility.shadow && matches!(object.shape, Shape::Sphere | Shape::Square);
+        let all = || scene.objects.iter().enumerate().filter_map(|(id, object)| {
+            if can_sample(object) { Some(ObjectId::new(id)) } else { None }
+        });
+        let assert_can_sample = |id: ObjectId| {
+            assert!(can_sample(&scene.objects[id.index]), "Selected light {:?} is not an emissive sphere or square visible to shadow rays", id);
+        };
 
-    /// whether this sample crosses the surface, used to determine the next medium
-    crosses_surface: bool,
-    /// whether this sample was the result of a specular event, used for light sampling
-    specular: bool,
+        match self {
+            LightSelection::Auto => all().map(|id| vec![id]).collect(),
+            LightSelection::Only(ids) => {
+                ids.iter().for_each(|&id| assert_can_sample(id));
+                ids.iter().map(|&id| vec![id]).collect()
+            }
+            LightSelection::Exclude(ids) => all().filter(|id| !ids.contains(id)).map(|id| vec![id]).collect(),
+            LightSelection::Grouped(groups) => {
+                let mut grouped = vec![false; scene.objects.len()];
+                for &id in groups.iter().flatten() {
+                    assert_can_sample(id);
+                    assert!(!grouped[id.index], "Light {:?} is in multiple groups", id);
+                    grouped[id.index] = true;
+                }
 
-    /// the fraction of this surface that behaves diffuse, used for light sampling
-    diffuse_fraction: f32,
+                let mut result: Vec<Vec<ObjectId>> = groups.iter().filter(|group| !group.is_empty()).cloned().collect();
+                result.extend(all().filter(|id| !grouped[id.index]).map(|id| vec![id]));
+                result
+            }
+        }
+    }
 }
 
-fn sample_direction<R: Rng>(ray: &Ray, hit: &Hit, material_type: MaterialType, refract_ratio: f32, rng: &mut R) -> SampleInfo {
-    match material_type {
-        MaterialType::Fixed { .. } => panic!("Can't sample direction for {material_type:?}"),
-        MaterialType::Diffuse => {
-            // cosine weighed sampling from the hemisphere pointing towards hit.normal
-            let disk = Vec2::from_slice(&UnitDisc.sample(rng));
-            let direction = disk_to_hemisphere(disk, hit.normal);
-            SampleInfo { weight: 0.5, diffuse_fraction: 1.0, specular: false, crosses_surface: false, direction }
-        }
-        MaterialType::Mirror => {
-            let direction = reflect_direction(ray.direction, hit.normal);
-            SampleInfo { weight: 1.0, diffuse_fraction: 0.0, specular: true, crosses_surface: false, direction }
+/// The maximum number of overlapping objects tracked by [MediumStack], deeper nesting forgets the outermost objects.
+const MAX_NESTED_MEDIA: usize = 4;
+
+/// The maximum number of surfaces the probe ray of [CpuPreparedScene::find_camera_media] crosses,
+/// which keeps preparing scenes with many transparent objects fast.
+const MAX_CAMERA_PROBE_CROSSINGS: usize = 256;
+
+impl MediumStack {
+    pub(super) fn new(base: Medium) -> Self {
+        MediumStack {
+            base,
+            entered: [(ObjectId::new(usize::MAX), base); MAX_NESTED_MEDIA],
+            len: 0,
         }
-        MaterialType::Transparent => {
-            let (crosses_surface, direction) = snells_law(ray.direction, hit.normal, refract_ratio);
-            SampleInfo { weight: 1.0, diffuse_fraction: 0.0, specular: true, crosses_surface, direction }
+    }
+
+    pub(super) fn current(&self) -> Medium {
+        match self.len {
+            0 => self.base,
+            len => self.entered[len - 1].1,
         }
-        MaterialType::DiffuseMirror(f) => {
-            let mut sample = if rng.gen::<f32>() < f {
-                sample_direction(ray, hit, MaterialType::Diffuse, refract_ratio, rng)
-            } else {
-                sample_direction(ray, hit, MaterialType::Mirror, refract_ratio, rng)
-            };
+    }
 
-            sample.diffuse_fraction = f;
-            sample
+    /// The media after crossing the surface of `object`, either going `into` it or leaving it.
+    ///
+    /// When leaving an object that was not entered itself, the most recently entered object with the same inside medium
+    /// is assumed to be left instead. This handles closed meshes, where rays often enter and leave through different faces.
+    pub(super) fn crossing(mut self, id: ObjectId, object: &Object, into: bool) -> Self {
+        let entered = &self.entered[..self.len];
+        let left_index = entered.iter().rposition(|&(entered_id, _)| entered_id == id)
+            .or_else(|| entered.iter().rposition(|&(_, medium)| medium == object.material.inside));
+
+        if into {
+            if self.len == MAX_NESTED_MEDIA {
+                // forget the outermost object
+                self.base = self.entered[0].1;
+                self.entered.copy_within(1.., 0);
+                self.len -= 1;
+            }
+            self.entered[self.len] = (id, object.material.inside);
+            self.len += 1;
+        } else if let Some(index) = left_index {
+            self.entered.copy_within(index + 1..self.len, index);
+            self.len -= 1;
+        } else {
+            // we're leaving an object we never entered, everything we entered since must be inside of it
+            self.base = object.material.outside;
+            self.len = 0;
         }
+
+        self
     }
 }
 
-fn disk_to_hemisphere(disk: Vec2, normal: Unit<Vec3>) -> Unit<Vec3> {
-    let z = (1.0 - disk.norm_squared()).sqrt();
-    let result = Unit::new_unchecked(Vec3::new(disk.x, disk.y, z));
-    if result.dot(*normal) >= 0.0 {
-        result
-    } else {
-        -result
+impl BouncesLeft {
+    /// The bounces left after a bounce of the given kind, `None` if that kind of bounce is not allowed any more.
+    fn after(self, kind: BounceKind) -> Option<BouncesLeft> {
+        let mut next = self;
+        next.total = next.total.checked_sub(1)?;
+
+        let left = match kind {
+            BounceKind::Diffuse => &mut next.limits.diffuse,
+            BounceKind::Specular => &mut next.limits.specular,
+            BounceKind::Transmission => &mut next.limits.transmission,
+        };
+        *left = left.checked_sub(1)?;
+
+        Some(next)
+    }
+}
+
+impl RayOrigin {
+    /// Whether lights hit by this ray still need to be counted, because light sampling at the previous bounce didn't include them.
+    fn is_specular(self) -> bool {
+        match self {
+            RayOrigin::Camera | RayOrigin::Specular | RayOrigin::SplattedCaustic | RayOrigin::Caustic => true,
+            RayOrigin::FirstDiffuse | RayOrigin::Diffuse => false,
+        }
+    }
+
+    fn next(self, sample: &SampleInfo) -> RayOrigin {
+        match (sample.specular, self) {
+            (false, RayOrigin::Camera) => RayOrigin::FirstDiffuse,
+            (false, _) => RayOrigin::Diffuse,
+            (true, RayOrigin::Camera | RayOrigin::Specular) => RayOrigin::Specular,
+            (true, RayOrigin::FirstDiffuse | RayOrigin::SplattedCaustic) => RayOrigin::SplattedCaustic,
+            (true, RayOrigin::Diffuse | RayOrigin::Caustic) => RayOrigin::Caustic,
+        }
+    }
+}
+
+impl StopCondition {
+    fn is_done(self, estimator: &ColorVarianceEstimator) -> bool {
+        fn variance_lte(estimator: &ColorVarianceEstimator, right: f32, metric: VarianceMetric) -> bool {
+            //TODO figure out a better way to allow blackness and add a mechanism to ignore variance in huge means
+            //we care about the variance of the mean, not the variance of the values themselves
+            let scale = 1.0 / (estimator.count as f32).sqrt();
+
+            match metric {
+                VarianceMetric::Channels => {
+                    let variance = estimator.variance().expect("Not enough samples to even compute the variance!");
+                    let left = variance / (estimator.mean + Color::new(1.0, 1.0, 1.0)) * scale;
+                    left.red <= right && left.green <= right && left.blue <= right
+                }
+                VarianceMetric::Luminance => {
+                    let variance = estimator.luminance_variance().expect("Not enough samples to even compute the variance!");
+                    variance / (estimator.luminance_mean() + 1.0) * scale <= right
+                }
+            }
+        }
+
+        match self {
+            StopCondition::SampleCount(samples) =>
+                estimator.count >= samples,
+            StopCondition::Variance { min_samples, max_relative_variance, metric } =>
+                estimator.count >= max(min_samples, 2) &&
+                    variance_lte(estimator, max_relative_variance, metric),
+            StopCondition::Progressive { max_samples, .. } =>
+                estimator.count >= max_samples,
+        }
+    }
+
+    /// Like [StopCondition::is_done] for the conditions that only look at the number of samples.
+    fn is_done_count(self, count: u32) -> bool {
+        match self {
+            StopCondition::SampleCount(samples) => count >= samples,
+            StopCondition::Progressive { max_samples, .. } => count >= max_samples,
+            StopCondition::Variance { .. } => unreachable!("variance is always tracked for StopCondition::Variance"),
+        }
+    }
+}
+
+pub(super) fn pixel_result(estimator: &ColorVarianceEstimator) -> PixelResult {
+    let variance = estimator.variance().unwrap_or(Color::new(0.0, 0.0, 0.0));
+    PixelResult {
+        color: estimator.mean,
+        variance,
+        rel_variance: variance / (estimator.mean + Color::new(1.0, 1.0, 1.0)),
+        samples: estimator.count,
+        alpha: estimator.alpha_mean,
+    }
+}
+
+/// The camera of a scene for an image with a specific size, mapping pixels to rays and points back to pixels.
+pub struct RayCamera {
+    x_span: f32,
+    y_span: f32,
+    width: f32,
+    height: f32,
+    transform: Transform,
+    anti_alias: Option<Jitter>,
+    origin: ImageOrigin,
+    medium: Medium,
+}
+
+impl RayCamera {
+    /// Build the camera for an image of `width` by `height` pixels with its first row at `origin`,
+    /// `anti_alias` is `None` to always shoot rays through the pixel centers.
+    pub fn new(camera: &Camera, anti_alias: Option<Jitter>, origin: ImageOrigin, width: u32, height: u32) -> RayCamera {
+        let x_span = 2.0 * (camera.fov_horizontal.radians / 2.0).tan();
+        RayCamera {
+            x_span,
+            y_span: x_span * (height as f32) / (width as f32),
+            width: width as f32,
+            height: height as f32,
+            transform: camera.transform,
+            anti_alias,
+            origin,
+            medium: camera.medium,
+        }
+    }
+
+    pub fn position(&self) -> Point3 {
+        self.transform * Point3::origin()
+    }
+
+    /// The area of a single pixel on the image plane at distance 1 in front of the camera.
+    pub fn pixel_area(&self) -> f32 {
+        (self.x_span / self.width) * (self.y_span / self.height)
+    }
+
+    /// Find the pixel that sees `point`, the inverse of [RayCamera::ray] without anti-aliasing.
+    /// Also returns the cosine between the camera direction and the direction towards `point`.
+    pub fn project(&self, point: Point3) -> Option<(u32, u32, f32)> {
+        let local = (self.transform.inv() * point).coords();
+        if local.z >= 0.0 {
+            return None;
+        }
+
+        let (x, y) = self.local_to_image(local);
+        if !(0.0..self.width).contains(&x) || !(0.0..self.height).contains(&y) {
+            return None;
+        }
+
+        let cos = -local.z / local.norm();
+        Some((x as u32, y as u32, cos))
+    }
+
+    /// Project the line segment between `a` and `b` onto the image, returning the continuous pixel coordinates of the endpoints.
+    /// The part of the segment behind the camera is clipped off, the result can still extend beyond the edges of the image.
+    pub fn project_segment(&self, a: Point3, b: Point3) -> Option<((f32, f32), (f32, f32))> {
+        let inv = self.transform.inv();
+        let mut a = (inv * a).coords();
+        let mut b = (inv * b).coords();
+
+        // clip against a plane just in front of the camera
+        let near = -1e-4;
+        if a.z >= near && b.z >= near {
+            return None;
+        }
+        if a.z > near {
+            a = b + (a - b) * ((near - b.z) / (a.z - b.z));
+        }
+        if b.z > near {
+            b = a + (b - a) * ((near - a.z) / (b.z - a.z));
+        }
+
+        Some((self.local_to_image(a), self.local_to_image(b)))
+    }
+
+    fn local_to_image(&self, local: Vec3) -> (f32, f32) {
+        let x_plane = local.x / -local.z;
+        let y_plane = local.y / -local.z;
+        let x = (x_plane / self.x_span + 0.5) * self.width;
+        let y = self.flip_y((y_plane / self.y_span + 0.5) * self.height);
+        (x, y)
+    }
+
+    pub(super) fn ray<R: Rng>(&self, rng: &mut R, x: u32, y: u32, sample: u32) -> Ray {
+        let (dx, dy) = match self.anti_alias {
+            None => (0.5, 0.5),
+            Some(Jitter::Random) => rng.gen(),
+            Some(Jitter::BlueNoise) => blue_noise_offset(x, y, sample),
+        };
+
+        self.image_ray(x as f32 + dx, y as f32 + dy)
+    }
+
+    /// Convert between the image `y` and the distance above the bottom of the picture, this is its own inverse.
+    fn flip_y(&self, y: f32) -> f32 {
+        match self.origin {
+            ImageOrigin::TopLeft => self.height - y,
+            ImageOrigin::BottomLeft => y,
+        }
+    }
+
+    /// The camera ray through the continuous image coordinates `(x, y)`, where pixel `(i, j)` covers `[i, i + 1) x [j, j + 1)`.
+    /// Useful to turn a mouse position into a ray for [CpuPreparedScene::pick].
+    pub fn image_ray(&self, x: f32, y: f32) -> Ray {
+        let x = (x / self.width - 0.5) * self.x_span;
+        let y = (self.flip_y(y) / self.height - 0.5) * self.y_span;
+
+        self.transform * &Ray {
+            start: Point3::origin(),
+            direction: Vec3::new(x, y, -1.0).normalized(),
+        }
+    }
+}
+
+const ALPHA_CUTOFF: f32 = 0.5;
+/// The Lambertian BRDF for a white surface, it reflects all incoming light.
+pub(super) const DIFFUSE_BRDF: f32 = 1.0 / PI;
+/// The fraction of the light reflected by the white highlight of [MaterialType::Phong], the rest is reflected diffusely.
+const PHONG_HIGHLIGHT: f32 = 0.1;
+/// The probability of sampling the next direction from the highlight of [MaterialType::Phong] instead of the diffuse part.
+const PHONG_HIGHLIGHT_PROBABILITY: f32 = 0.5;
+
+/// The non-specular part of the BRDF at a hit, used to weigh the light arriving from sampled lights and the sky.
+/// Specular reflections and transmissions are delta distributions that sampled lights can't contribute to.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct SurfaceBrdf {
+    /// the diffuse reflectance, including the albedo
+    diffuse: Color,
+    /// the shininess of the Blinn-Phong highlight and the direction towards the viewer
+    highlight: Option<(f32, Unit<Vec3>)>,
+    /// the Oren-Nayar roughness of the diffuse part and the direction towards the viewer, Lambertian if `None`
+    rough: Option<(OrenNayar, Unit<Vec3>)>,
+    /// the probability that [sample_direction] samples the diffuse part, if it picks a non-specular direction
+    diffuse_probability: f32,
+}
+
+impl SurfaceBrdf {
+    /// A surface that only reflects diffusely, with the given reflectance.
+    pub(super) fn diffuse(reflectance: Color) -> Self {
+        SurfaceBrdf { diffuse: reflectance, highlight: None, rough: None, diffuse_probability: 1.0 }
+    }
+
+    /// The BRDF of `material` at `uv`, seen from direction `towards_viewer`.
+    pub(super) fn new(material: &Material, uv: Point2, towards_viewer: Unit<Vec3>) -> Self {
+        let albedo = material.albedo_at(uv);
+        match material.material_type {
+            MaterialType::Diffuse => SurfaceBrdf::diffuse(albedo),
+            MaterialType::DiffuseMirror(f) => SurfaceBrdf::diffuse(albedo * f),
+            MaterialType::Phong { shininess } => SurfaceBrdf {
+                diffuse: albedo * (1.0 - PHONG_HIGHLIGHT),
+                highlight: Some((shininess, towards_viewer)),
+                rough: None,
+                diffuse_probability: 1.0 - PHONG_HIGHLIGHT_PROBABILITY,
+            },
+            MaterialType::OrenNayar { roughness } => SurfaceBrdf {
+                diffuse: albedo,
+                highlight: None,
+                rough: Some((OrenNayar::new(roughness), towards_viewer)),
+                diffuse_probability: 1.0,
+            },
+            MaterialType::Fixed | MaterialType::ShadowCatcher | MaterialType::Mirror | MaterialType::Transparent | MaterialType::Conductor { .. } =>
+                SurfaceBrdf { diffuse_probability: 0.0, ..SurfaceBrdf::diffuse(Color::new(0.0, 0.0, 0.0)) },
+        }
+    }
+
+    /// Evaluate the BRDF for light arriving from `direction` at a surface with the given normal.
+    pub(super) fn eval(&self, normal: Unit<Vec3>, direction: Unit<Vec3>) -> Color {
+        let mut result = self.diffuse * DIFFUSE_BRDF;
+        if let Some((rough, towards_viewer)) = self.rough {
+            result *= rough.factor(normal, direction, towards_viewer);
+        }
+        if let Some((shininess, towards_viewer)) = self.highlight {
+            let half = (*direction + *towards_viewer).normalized();
+            let value = PHONG_HIGHLIGHT * blinn_phong(shininess, half.dot(*normal));
+            result += Color::new(value, value, value);
+        }
+        result
+    }
+
+    /// The probability density of [sample_direction] picking `direction` at a surface with the given normal, in solid angle measure,
+    /// given that it picks a non-specular direction. Those are the directions this BRDF describes.
+    pub(super) fn pdf(&self, normal: Unit<Vec3>, direction: Unit<Vec3>) -> f32 {
+        let cos = direction.dot(*normal);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+
+        let mut pdf = self.diffuse_probability * cos / PI;
+        if let Some((shininess, towards_viewer)) = self.highlight {
+            // the density of the half vector picked by sample_highlight, converted to the reflected direction
+            let half = (*direction + *towards_viewer).normalized();
+            let (cos_half, cos_out) = (half.dot(*normal), towards_viewer.dot(*half));
+            if cos_half > 0.0 && cos_out > 0.0 {
+                pdf += PHONG_HIGHLIGHT_PROBABILITY * (shininess + 1.0) / (2.0 * PI) * cos_half.powf(shininess) / (4.0 * cos_out);
+            }
+        }
+        pdf
+    }
+}
+
+/// The probability density of [CpuPreparedScene::sample_sky_hemisphere] picking `direction` above a surface with `normal`.
+fn sky_hemisphere_pdf(normal: Unit<Vec3>, direction: Unit<Vec3>) -> f32 {
+    direction.dot(*normal).max(0.0) / PI
+}
+
+/// The weight of the sky found by a bounce that picked its direction with `brdf_pdf`, when the same direction
+/// could also have been sampled with `sky_pdf` by [CpuPreparedScene::sample_sky_hemisphere], which gets the rest of the weight.
+/// This is the balance heuristic of multiple importance sampling.
+fn bounce_sky_weight(brdf_pdf: f32, sky_pdf: f32) -> f32 {
+    if brdf_pdf + sky_pdf > 0.0 { brdf_pdf / (brdf_pdf + sky_pdf) } else { 1.0 }
+}
+
+/// The normalized Blinn-Phong highlight for the cosine between the half vector and the normal,
+/// see <https://www.farbrausch.de/~fg/stuff/phong.pdf>. It reflects close to all incoming light.
+fn blinn_phong(shininess: f32, cos_half: f32) -> f32 {
+    (shininess + 8.0) / (8.0 * PI) * cos_half.max(0.0).powf(shininess)
+}
+
+/// The qualitative Oren-Nayar model for rough diffuse surfaces as a factor on the Lambertian BRDF,
+/// see <https://en.wikipedia.org/wiki/Oren%E2%80%93Nayar_reflectance_model>.
+#[derive(Debug, Copy, Clone)]
+struct OrenNayar {
+    a: f32,
+    b: f32,
+}
+
+impl OrenNayar {
+    fn new(roughness: f32) -> Self {
+        let sigma2 = roughness * roughness;
+        OrenNayar { a: 1.0 - 0.5 * sigma2 / (sigma2 + 0.33), b: 0.45 * sigma2 / (sigma2 + 0.09) }
+    }
+
+    /// The factor for light arriving from `direction` that leaves towards `towards_viewer`, symmetric in both directions.
+    fn factor(self, normal: Unit<Vec3>, direction: Unit<Vec3>, towards_viewer: Unit<Vec3>) -> f32 {
+        let cos_in = direction.dot(*normal);
+        let cos_out = towards_viewer.dot(*normal);
+
+        // the dot product of the tangential parts is `sin_in * sin_out * cos(phi_in - phi_out)`,
+        // and `sin(alpha) * tan(beta)` with alpha the largest and beta the smallest angle to the normal is `sin_in * sin_out / max_cos`
+        let tangent_dot = (*direction - *normal * cos_in).dot(*towards_viewer - *normal * cos_out);
+        let max_cos = cos_in.abs().max(cos_out.abs());
+        let angular = if max_cos > 0.0 { tangent_dot.max(0.0) / max_cos } else { 0.0 };
+
+        self.a + self.b * angular
+    }
+}
+
+impl Tolerances {
+    /// The start point for a ray leaving `hit` in `direction`, offset along the normal to the side `direction` points to.
+    ///
+    /// Offsetting along the normal instead of the direction keeps grazing rays near edges from skipping past the neighbouring face.
+    /// The floating point error on the hit point grows with the coordinates and the distance the ray travelled,
+    /// so the offset scales with both to avoid self-intersections in large scenes without causing leaks in small ones.
+    pub(super) fn offset_ray_start(self, hit: &Hit, direction: Unit<Vec3>) -> Point3 {
+        let point = hit.point;
+        let magnitude = point.x.abs().max(point.y.abs()).max(point.z.abs()) + hit.t;
+        let bias = (magnitude * self.relative_ray_bias).max(self.min_ray_bias);
+
+        let side = if direction.dot(*hit.normal) >= 0.0 { 1.0 } else { -1.0 };
+        point + *hit.normal * (bias * side)
+    }
+}
+
+/// The false color used for `id` by [Strategy::ObjectIds], neighbouring ids get very different colors.
+pub fn object_id_color(id: ObjectId) -> Color {
+    // scramble the bits of the index, so nearby ids don't get similar colors
+    let hash = (id.index as u32 ^ 0x5bd1e995).wrapping_mul(0x9e3779b9);
+    let hash = hash ^ (hash >> 15);
+    let channel = |shift: u32| 0.1 + 0.9 * ((hash >> shift) & 0xff) as f32 / 255.0;
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+/// The light arriving from the sample `target` on `light` if nothing blocks it, without tracing a shadow ray.
+#[allow(clippy::too_many_arguments)]
+fn unblocked_light_contribution(light: &Object, weight: f32, target: Point3, next_start: Point3, medium: Medium, hit: &Hit, brdf: &SurfaceBrdf) -> Color {
+    let (direction, distance) = (target - next_start).normalized_and_get();
+    let light_cos = match light.shape {
+        Shape::Sphere => 1.0,
+        _ => direction.dot(*light.transform.inv_transpose_mul(*Vec3::z_axis()).normalized()).abs(),
+    };
+    let emission = light.material.emission * light.material.emission_strength;
+    light_contribution(light, emission, weight, direction, distance, light_cos, medium, hit, brdf)
+}
+
+/// Sample a point on `light` to light `hit` from `next_start` with, together with its weight, see [Intersect::sample_from].
+/// Spheres around `next_start`, like an emissive dome around the whole scene, only sample the directions above the surface,
+/// see [Object::sample_enclosing].
+fn sample_light<R: Rng>(light: &Object, next_start: Point3, hit: &Hit, rng: &mut R) -> (f32, Point3) {
+    light.sample_enclosing(next_start, hit.normal, rng).unwrap_or_else(|| light.sample_from(next_start, rng))
+}
+
+/// The light from a sample on `light` at distance `t` along `direction` reflected by the surface at `hit` according to `brdf`,
+/// assuming it's visible. `light_cos` is the cosine between the direction and the light normal, unused for spheres.
+#[allow(clippy::too_many_arguments)]
+fn light_contribution(light: &Object, emission: Color, weight: f32, direction: Unit<Vec3>, t: f32, light_cos: f32, medium: Medium, hit: &Hit, brdf: &SurfaceBrdf) -> Color {
+    let abs_cos = direction.dot(*hit.normal).abs();
+    let volumetric_mask = color_exp(medium.volumetric_color, t);
+
+    let solid_angle_weight = match light.shape {
+        // spheres are already sampled by solid angle
+        Shape::Sphere => weight,
+        // convert the area sample to solid angle, the light emits from both sides
+        _ => weight * light_cos / (t * t),
+    };
+
+    emission * brdf.eval(hit.normal, direction) * volumetric_mask * (abs_cos * solid_angle_weight)
+}
+
+pub fn is_light(object: &Object) -> bool {
+    !is_black(object.material.emission) && object.material.emission_strength != 0.0
+}
+
+/// The matte light gray material of [CpuRenderSettings::clay].
+fn clay_material() -> Material {
+    Material {
+        material_type: MaterialType::Diffuse,
+        emission: BLACK,
+        emission_strength: 1.0,
+        emission_texture: None,
+        albedo: Color::new(0.8, 0.8, 0.8),
+        albedo_texture: None,
+        alpha: None,
+        bump: None,
+        normal_map: None,
+        cull_backface: false,
+        inside: VACUUM,
+        outside: VACUUM,
+    }
+}
+
+/// A deterministic rng for the pixel at `(x, y)`, see [CpuPreparedScene::calculate_pixel_seeded].
+///
+/// The coordinates are packed into a single `u64` as `x << 32 | y`, multiplied by `0x9E37_79B9_7F4A_7C15` (wrapping)
+/// and xor-ed with `seed`. That value seeds a [StdRng] through [SeedableRng::seed_from_u64].
+/// Unlike [SmallRng](rand::rngs::SmallRng), the numbers [StdRng] generates don't depend on the platform, but
+/// they can change with the version of `rand`. Functions like `sin` and `exp` can also round slightly differently
+/// between platforms, so tests should still compare results with a small tolerance.
+pub fn pixel_rng(x: u32, y: u32, seed: u64) -> StdRng {
+    let coords = (x as u64) << 32 | y as u64;
+    StdRng::seed_from_u64(seed ^ coords.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+/// The filter for [Accel::first_hit] that skips objects invisible to rays of the given kind, see [Visibility](crate::common::scene::Visibility).
+/// Shadow catchers are only visible to camera rays, independent of their visibility.
+pub(super) fn filter_visible(kind: RayKind) -> impl Fn(&Object) -> bool {
+    move |o: &Object| {
+        let catcher = o.material.material_type == MaterialType::ShadowCatcher;
+        o.visibility.is_visible_to(kind) && (kind == RayKind::Camera || !catcher)
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct SampleInfo {
+    /// the direction of the next ray
+    pub(super) direction: Unit<Vec3>,
+    /// the BRDF value for this direction, for specular samples the factor in front of the delta distribution
+    pub(super) brdf: f32,
+    /// the probability density this direction was sampled with, for specular samples the probability of picking it
+    pub(super) pdf: f32,
+    /// the cosine between the direction and the normal, including the sign
+    pub(super) cos: f32,
+
+    /// whether this sample crosses the surface, used to determine the next medium
+    pub(super) crosses_surface: bool,
+    /// whether this sample was the result of a specular event, used for light sampling
+    pub(super) specular: bool,
+
+    /// the fraction of this surface that behaves diffuse, used for light sampling
+    pub(super) diffuse_fraction: f32,
+    /// whether the light arriving from this direction is tinted by the albedo, false for the white highlight of [MaterialType::Phong]
+    pub(super) tinted: bool,
+    /// the color of the reflection on top of the albedo, only not white for [MaterialType::Conductor]
+    pub(super) reflectance: Color,
+}
+
+impl SampleInfo {
+    /// The factor to multiply the light arriving from [SampleInfo::direction] with, `brdf * cos / pdf`.
+    /// For specular samples the cosine is already cancelled out by the delta distribution.
+    pub(super) fn weight(&self) -> f32 {
+        if self.pdf == 0.0 {
+            return 0.0;
+        }
+
+        if self.specular {
+            self.brdf / self.pdf
+        } else {
+            self.brdf * self.cos.abs() / self.pdf
+        }
+    }
+
+    /// The color to multiply the light arriving from [SampleInfo::direction] with on top of the weight,
+    /// for a surface with the given albedo.
+    pub(super) fn tint(&self, albedo: Color) -> Color {
+        let tint = if self.tinted { albedo } else { WHITE };
+        tint * self.reflectance
+    }
+
+    fn kind(&self) -> BounceKind {
+        if !self.specular {
+            BounceKind::Diffuse
+        } else if self.crosses_surface {
+            BounceKind::Transmission
+        } else {
+            BounceKind::Specular
+        }
+    }
+}
+
+pub(super) fn sample_direction<R: Rng>(ray: &Ray, hit: &Hit, material_type: MaterialType, refract_ratio: f32, rng: &mut R) -> SampleInfo {
+    match material_type {
+        MaterialType::Fixed | MaterialType::ShadowCatcher => panic!("Can't sample direction for {material_type:?}"),
+        MaterialType::Diffuse => {
+            // cosine weighed sampling from the hemisphere pointing towards hit.normal
+            let disk = Vec2::from_slice(&UnitDisc.sample(rng));
+            let direction = disk_to_hemisphere(disk, hit.normal);
+            let cos = direction.dot(*hit.normal);
+            SampleInfo { brdf: DIFFUSE_BRDF, pdf: cos / PI, cos, diffuse_fraction: 1.0, specular: false, crosses_surface: false, tinted: true, reflectance: WHITE, direction }
+        }
+        MaterialType::Mirror => {
+            let direction = reflect_direction(ray.direction, hit.normal);
+            let cos = direction.dot(*hit.normal);
+            SampleInfo { brdf: 1.0, pdf: 1.0, cos, diffuse_fraction: 0.0, specular: true, crosses_surface: false, tinted: true, reflectance: WHITE, direction }
+        }
+        MaterialType::Transparent => {
+            let (crosses_surface, direction) = snells_law(ray.direction, hit.normal, refract_ratio);
+            let cos = direction.dot(*hit.normal);
+            SampleInfo { brdf: 1.0, pdf: 1.0, cos, diffuse_fraction: 0.0, specular: true, crosses_surface, tinted: true, reflectance: WHITE, direction }
+        }
+        MaterialType::DiffuseMirror(f) => {
+            let mut sample = if rng.gen::<f32>() < f {
+                sample_direction(ray, hit, MaterialType::Diffuse, refract_ratio, rng)
+            } else {
+                sample_direction(ray, hit, MaterialType::Mirror, refract_ratio, rng)
+            };
+
+            sample.diffuse_fraction = f;
+            sample
+        }
+        MaterialType::OrenNayar { roughness } => {
+            let mut sample = sample_direction(ray, hit, MaterialType::Diffuse, refract_ratio, rng);
+            sample.brdf *= OrenNayar::new(roughness).factor(hit.normal, sample.direction, -ray.direction);
+            sample
+        }
+        MaterialType::Conductor { reflectance } => {
+            let mut sample = sample_direction(ray, hit, MaterialType::Mirror, refract_ratio, rng);
+            sample.reflectance = schlick_fresnel(reflectance, sample.cos);
+            sample
+        }
+        MaterialType::Phong { shininess } => {
+            if rng.gen::<f32>() < PHONG_HIGHLIGHT_PROBABILITY {
+                sample_highlight(ray, hit, shininess, rng)
+            } else {
+                let mut sample = sample_direction(ray, hit, MaterialType::Diffuse, refract_ratio, rng);
+                sample.brdf *= 1.0 - PHONG_HIGHLIGHT;
+                sample.pdf *= 1.0 - PHONG_HIGHLIGHT_PROBABILITY;
+                sample
+            }
+        }
+    }
+}
+
+/// Sample a direction from the Blinn-Phong highlight of [MaterialType::Phong] by picking a half vector around the normal
+/// and reflecting the incoming ray around it. Directions that end up below the surface get zero weight.
+fn sample_highlight<R: Rng>(ray: &Ray, hit: &Hit, shininess: f32, rng: &mut R) -> SampleInfo {
+    let (tangent, bitangent) = hit.normal.orthonormal_basis();
+    let cos_half = rng.gen::<f32>().powf(1.0 / (shininess + 1.0));
+    let sin_half = (1.0 - cos_half * cos_half).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.gen::<f32>();
+    let half = (*tangent * (sin_half * phi.cos()) + *bitangent * (sin_half * phi.sin()) + *hit.normal * cos_half).normalized();
+
+    let direction = reflect_direction(ray.direction, half);
+    let cos = direction.dot(*hit.normal);
+    let cos_out = -ray.direction.dot(*half);
+
+    let (brdf, pdf) = if cos > 0.0 && cos_out > 0.0 {
+        let pdf_half = (shininess + 1.0) / (2.0 * PI) * cos_half.powf(shininess);
+        (PHONG_HIGHLIGHT * blinn_phong(shininess, cos_half), PHONG_HIGHLIGHT_PROBABILITY * pdf_half / (4.0 * cos_out))
+    } else {
+        (0.0, 0.0)
+    };
+
+    SampleInfo { brdf, pdf, cos, diffuse_fraction: 1.0, specular: false, crosses_surface: false, tinted: false, reflectance: WHITE, direction }
+}
+
+/// Schlick's approximation of the Fresnel reflectance of a conductor with reflectance `f0` at normal incidence,
+/// for light arriving at an angle with cosine `cos` to the normal.
+fn schlick_fresnel(f0: Color, cos: f32) -> Color {
+    let f = (1.0 - cos.abs()).clamp(0.0, 1.0).powi(5);
+    f0 + (WHITE - f0) * f
+}
+
+/// Project a point on the unit disk up onto the hemisphere around `normal`,
+/// uniform points on the disk give cosine weighted directions.
+pub(super) fn disk_to_hemisphere(disk: Vec2, normal: Unit<Vec3>) -> Unit<Vec3> {
+    let z = (1.0 - disk.norm_squared()).max(0.0).sqrt();
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    (*tangent * disk.x + *bitangent * disk.y + *normal * z).normalized()
+}
+
+/// The ratio of the indices of refraction when going from `medium` to `next_medium`,
+/// at the given wavelength or at the reference wavelength if not rendering spectrally.
+pub(super) fn refract_ratio(medium: Medium, next_medium: Medium, wavelength: Option<f32>) -> f32 {
+    match wavelength {
+        None => medium.index_of_refraction / next_medium.index_of_refraction,
+        Some(wavelength) => medium.index_of_refraction_at(wavelength) / next_medium.index_of_refraction_at(wavelength),
     }
 }
 
@@ -358,15 +1681,48 @@ fn is_black(color: Color) -> bool {
     color == Color::new(0.0, 0.0, 0.0)
 }
 
-fn color_exp(base: Color, exp: f32) -> Color {
+pub(super) fn color_exp(base: Color, exp: f32) -> Color {
     Color::new(fast_powf(base.red, exp), fast_powf(base.green, exp), fast_powf(base.blue, exp))
 }
 
+/// Scale `color` down so its luminance is at most `max_luminance`, keeping its hue.
+fn clamp_luminance(color: Color, max_luminance: f32) -> Color {
+    let luminance = luminance(color);
+    if luminance > max_luminance {
+        color * (max_luminance / luminance)
+    } else {
+        color
+    }
+}
+
+/// The light emitted by `medium` along a ray of length `t`, including the attenuation of that light by the medium itself.
+fn medium_emission(medium: Medium, t: f32) -> Color {
+    if is_black(medium.emission) {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    // integrate emission * base^s for s from 0 to t
+    let channel = |emission: f32, base: f32| {
+        if emission == 0.0 || base == 0.0 {
+            0.0
+        } else if base == 1.0 {
+            emission * t
+        } else {
+            emission * (fast_powf(base, t) - 1.0) / base.ln()
+        }
+    };
+
+    let Medium { emission, volumetric_color: base, .. } = medium;
+    Color::new(channel(emission.red, base.red), channel(emission.green, base.green), channel(emission.blue, base.blue))
+}
+
 fn fast_powf(base: f32, exp: f32) -> f32 {
     debug_assert!(base >= 0.0);
-    debug_assert!(!(base == 0.0 && exp == 0.0));
 
-    if base == 0.0 || base == 1.0 || exp == 1.0 {
+    if exp == 0.0 {
+        // no attenuation at zero distance, also for fully absorbing mediums
+        1.0
+    } else if base == 0.0 || base == 1.0 || exp == 1.0 {
         base
     } else if exp.is_infinite() {
         if (base > 1.0) ^ (exp < 0.0) {
@@ -381,14 +1737,921 @@ fn fast_powf(base: f32, exp: f32) -> f32 {
 
 #[cfg(test)]
 mod test {
-    use crate::common::math::{Vec2, Vec3};
-    use crate::cpu::renderer::disk_to_hemisphere;
+    use std::f32::consts::PI;
+    use std::sync::Arc;
+
+    use imgref::ImgVec;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+    use rand_distr::{Distribution, UnitSphere};
+
+    use crate::common::math::{Angle, Norm, Point2, Point3, Transform, Vec2, Vec3};
+    use crate::common::scene::{Camera, Color, Material, MaterialType, Medium, Object, Scene, Shape, Sky, Visibility};
+    use crate::common::util::triangle_as_transform;
+    use crate::cpu::accel::{NoAccel, ObjectId};
+    use crate::cpu::geometry::{Hit, Intersect, Ray};
+    use crate::common::texture::{ImageTexture, luminance};
+    use crate::cpu::renderer::{BounceKind, BounceLimits, BouncesLeft, clamp_luminance, color_exp, CpuPreparedScene, CpuRenderSettings, DIFFUSE_BRDF, disk_to_hemisphere, ImageOrigin, LightSampling, LightSelection, medium_emission, MediumStack, object_id_color, pixel_rng, RayCamera, sample_direction, StopCondition, Strategy, SurfaceBrdf, Tolerances};
+    use crate::demos::{BLACK, color_gray, GLASS_IOR, material_conductor, material_diffuse, material_glass, material_glass_tinted, material_light, material_mixed, material_oren_nayar, material_phong, material_shadow_catcher, METAL_GOLD, scene_cornell_box, VACUUM, WHITE};
+    use crate::images::to_discrete_image;
+
+    #[test]
+    fn bounce_limits_independent() {
+        let limits = BounceLimits { diffuse: 1, specular: 0, transmission: 2 };
+        let left = BouncesLeft { total: 3, limits };
+
+        assert!(left.after(BounceKind::Specular).is_none());
+
+        let left = left.after(BounceKind::Transmission).unwrap();
+        let left = left.after(BounceKind::Diffuse).unwrap();
+        assert!(left.after(BounceKind::Diffuse).is_none());
+
+        // the total limit still applies
+        let left = left.after(BounceKind::Transmission).unwrap();
+        assert_eq!(left.total, 0);
+        assert!(left.after(BounceKind::Transmission).is_none());
+    }
+
+    #[test]
+    fn light_power_of_sphere() {
+        let light = Object::new(
+            Shape::Sphere,
+            material_light(Color::new(1.0, 0.5, 0.0), 3.0),
+            Transform::translate(Vec3::new(1.0, 2.0, 3.0)) * Transform::scale(2.0),
+        );
+        let camera = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects: vec![light], sky: Sky::Uniform(BLACK), camera };
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(1),
+            anti_alias: false,
+            ..Default::default()
+        };
+        let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 1, 1);
+
+        // every point of the surface emits 3 * pi in total, over an area of 4 * pi * 2^2
+        let expected = 3.0 * PI * 4.0 * PI * 4.0;
+        let power = prepared.light_power();
+        assert!((power.red - expected).abs() < 1e-3 * expected, "expected {}, got {:?}", expected, power);
+        assert!((power.green - 0.5 * expected).abs() < 1e-3 * expected, "expected {}, got {:?}", 0.5 * expected, power);
+        assert_eq!(power.blue, 0.0);
+    }
+
+    #[test]
+    fn light_selection() {
+        let object = |material| Object::new(Shape::Sphere, material, Transform::default());
+        let scene = Scene {
+            objects: vec![
+                object(material_light(WHITE, 1.0)),
+                object(material_diffuse(WHITE)),
+                object(material_light(WHITE, 1.0)),
+                Object { shape: Shape::Triangle, ..object(material_light(WHITE, 1.0)) },
+                Object { shape: Shape::Square, ..object(material_light(WHITE, 1.0)) },
+            ],
+            sky: Sky::Uniform(BLACK),
+            camera: Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM },
+        };
+        let ids = |indices: &[usize]| indices.iter().map(|&i| ObjectId::new(i)).collect::<Vec<_>>();
+
+        assert_eq!(LightSelection::Auto.select(&scene).concat(), ids(&[0, 2, 4]));
+        assert_eq!(LightSelection::Only(ids(&[2])).select(&scene).concat(), ids(&[2]));
+        assert_eq!(LightSelection::Exclude(ids(&[0])).select(&scene).concat(), ids(&[2, 4]));
+        // lights that are not in a group are sampled separately
+        assert_eq!(LightSelection::Grouped(vec![ids(&[4, 0])]).select(&scene), vec![ids(&[4, 0]), ids(&[2])]);
+    }
+
+    #[test]
+    fn object_ids_view() {
+        let object = |x: f32| Object::new(Shape::Sphere, material_light(WHITE, 1.0), Transform::translate(Vec3::new(x, 0.0, -5.0)));
+        let scene = Scene {
+            objects: vec![object(-2.0), object(2.0)],
+            sky: Sky::Uniform(WHITE),
+            camera: Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM },
+        };
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(1),
+            anti_alias: false,
+            strategy: Strategy::ObjectIds,
+            ..Default::default()
+        };
+        let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 5, 5);
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        assert_eq!(prepared.sample_pixel(rng, 1, 2), object_id_color(ObjectId::new(0)));
+        assert_eq!(prepared.sample_pixel(rng, 3, 2), object_id_color(ObjectId::new(1)));
+        assert_eq!(prepared.sample_pixel(rng, 2, 0), BLACK);
+        assert_ne!(object_id_color(ObjectId::new(0)), object_id_color(ObjectId::new(1)));
+    }
+
+    #[test]
+    fn clay_ignores_materials() {
+        let scene = scene_cornell_box();
+        let settings = |clay| CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(1),
+            max_bounces: 4,
+            anti_alias: false,
+            clay,
+            ..Default::default()
+        };
+
+        // the leftmost pixel sees the red wall
+        let wall = |clay| {
+            let prepared = CpuPreparedScene::new(&scene, settings(clay), &LightSelection::Auto, NoAccel, 9, 9);
+            let rng = &mut SmallRng::seed_from_u64(0);
+            let n = 2000;
+            (0..n).map(|_| prepared.sample_pixel(rng, 0, 4)).fold(BLACK, |a, c| a + c) / n as f32
+        };
+
+        let colored = wall(false);
+        assert!(colored.green < 0.1 * colored.red, "expected a red wall, got {:?}", colored);
+        let clay = wall(true);
+        assert!(clay.red > colored.red && (clay.green - clay.red).abs() < 0.1 * clay.red, "expected a gray wall, got {:?}", clay);
+    }
+
+    /// Sample the only pixel of a 1x1 image `n` times with `strategy`, seen from the origin looking along -z.
+    /// Returns whether the sky is sampled as a light, and the mean and variance of the red channel.
+    fn pixel_statistics(objects: &[Object], sky: Sky, strategy: Strategy, n: u32) -> (bool, f32, f32) {
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(1),
+            anti_alias: false,
+            strategy,
+            ..Default::default()
+        };
+        let camera = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects: objects.to_vec(), sky, camera };
+        let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 1, 1);
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        let values = (0..n).map(|_| prepared.sample_pixel(rng, 0, 0).red).collect::<Vec<_>>();
+        let mean = values.iter().sum::<f32>() / n as f32;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / n as f32;
+        (prepared.samples_sky(), mean, variance)
+    }
+
+    /// A wall standing on the floor at `z = -2` next to the view direction of [pixel_statistics], hiding part of the sky.
+    fn wall(material: Material) -> Object {
+        let transform = triangle_as_transform(Point3::new(0.5, -50.0, -2.0), Point3::new(0.5, 50.0, -2.0), Point3::new(0.5, -50.0, 8.0));
+        Object::new(Shape::Square, material, transform)
+    }
+
+    #[test]
+    fn direct_only_skips_indirect() {
+        let square = |x: f32, z: f32, size: f32| Object::new(
+            Shape::Square,
+            material_diffuse(WHITE),
+            Transform::translate(Vec3::new(x, -size / 2.0, z)) * Transform::scale(size),
+        );
+        let average = |objects: &[Object], strategy| pixel_statistics(objects, Sky::Uniform(WHITE), strategy, 20_000).1;
+
+        // a floor under the open sky only receives direct light
+        let floor = vec![square(-50.0, -2.0, 100.0)];
+        let direct = average(&floor, Strategy::DirectOnly);
+        let full = average(&floor, Strategy::SampleLights);
+        assert!((direct - 1.0).abs() < 0.01, "got {}", direct);
+        assert!((direct - full).abs() < 0.01, "expected {}, got {}", full, direct);
+
+        // the light reflected by a wall next to the floor is left out
+        let covered = vec![square(-50.0, -2.0, 100.0), wall(material_diffuse(WHITE))];
+        let direct = average(&covered, Strategy::DirectOnly);
+        let full = average(&covered, Strategy::SampleLights);
+        assert!(direct < full - 0.05, "expected less than {}, got {}", full, direct);
+    }
+
+    #[test]
+    fn ambient_occlusion_between_planes() {
+        // the camera looks down at a floor through a ceiling it can't see, one unit above the floor
+        let plane = |z: f32, visibility: Visibility| Object::new(
+            Shape::Plane,
+            material_diffuse(WHITE),
+            Transform::translate(Vec3::new(0.0, 0.0, z)),
+        ).with_visibility(visibility);
+        let floor = plane(-2.0, Visibility::ALL);
+        let ceiling = plane(-1.0, Visibility { camera: false, ..Visibility::ALL });
+
+        let average = |objects: Vec<Object>, radius: f32| {
+            let camera = Camera { fov_horizontal: Angle::degrees(30.0), transform: Transform::default(), medium: VACUUM };
+            let scene = Scene { objects, sky: Sky::Uniform(WHITE), camera };
+            let settings = CpuRenderSettings {
+                stop_condition: StopCondition::SampleCount(1),
+                anti_alias: false,
+                strategy: Strategy::AmbientOcclusion { rays: 16, radius },
+                ..Default::default()
+            };
+            let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 1, 1);
+            let rng = &mut SmallRng::seed_from_u64(0);
+
+            let n = 2_000;
+            (0..n).map(|_| prepared.sample_pixel(rng, 0, 0).red).sum::<f32>() / n as f32
+        };
+
+        assert_eq!(average(vec![floor.clone()], 2.0), 1.0);
+        assert_eq!(average(vec![floor.clone(), ceiling.clone()], 0.5), 1.0);
+
+        // cosine weighted rays at an angle with cosine `c` from the normal reach the ceiling after `1 / c`,
+        // so a fraction `(1 / radius)²` of them gets further than `radius`
+        let visible = average(vec![floor, ceiling], 2.0);
+        assert!((visible - 0.25).abs() < 0.01, "got {}", visible);
+    }
+
+    #[test]
+    #[should_panic(expected = "Ambient occlusion needs at least one ray")]
+    fn ambient_occlusion_without_rays() {
+        let camera = Camera { fov_horizontal: Angle::degrees(30.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects: vec![], sky: Sky::Uniform(WHITE), camera };
+        let settings = CpuRenderSettings { strategy: Strategy::AmbientOcclusion { rays: 0, radius: 1.0 }, ..Default::default() };
+        CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 1, 1);
+    }
+
+    #[test]
+    fn emission_only_shows_lights_and_sky() {
+        // the camera looks down at a floor with a light on the left half and a plain diffuse surface on the right
+        let square = |x: f32, material: Material| Object::new(
+            Shape::Square,
+            material,
+            Transform::translate(Vec3::new(x, -25.0, -2.0)) * Transform::scale(50.0),
+        );
+        let light = Color::new(1.0, 2.0, 3.0);
+        let objects = vec![square(-50.0, material_light(light, 2.0)), square(0.0, material_diffuse(WHITE))];
+        let sky = Color::new(0.5, 0.6, 0.7);
+        let render = |objects: Vec<Object>| {
+            let camera = Camera { fov_horizontal: Angle::degrees(30.0), transform: Transform::default(), medium: VACUUM };
+            let scene = Scene { objects, sky: Sky::Uniform(sky), camera };
+            let settings = CpuRenderSettings {
+                stop_condition: StopCondition::SampleCount(1),
+                anti_alias: false,
+                strategy: Strategy::EmissionOnly,
+                ..Default::default()
+            };
+            let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 2, 1);
+            let rng = &mut SmallRng::seed_from_u64(0);
+            [prepared.sample_pixel_with_alpha(rng, 0, 0), prepared.sample_pixel_with_alpha(rng, 1, 0)]
+        };
+
+        assert_eq!(render(objects), [(light * 2.0, 1.0), (BLACK, 1.0)]);
+        assert_eq!(render(vec![]), [(sky, 0.0), (sky, 0.0)]);
+    }
+
+    #[test]
+    fn seeded_pixels_are_deterministic() {
+        // the documented seeding scheme, this value must not change between platforms
+        assert_eq!(pixel_rng(3, 5, 7).gen::<u64>(), 11860336538087708379);
+
+        let scene = scene_cornell_box();
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(4),
+            ..Default::default()
+        };
+        let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 8, 8);
+
+        let first = prepared.calculate_pixel_seeded(4, 5, 1);
+        // rendering other pixels in between doesn't change the result
+        let _ = prepared.calculate_pixel_seeded(5, 4, 1);
+        let again = prepared.calculate_pixel_seeded(4, 5, 1);
+        assert_eq!((again.color, again.variance, again.alpha), (first.color, first.variance, first.alpha));
+        assert_ne!(prepared.calculate_pixel_seeded(4, 5, 2).color, first.color);
+    }
+
+    #[test]
+    fn backdrop_only_seen_by_camera() {
+        let mirror = Object::new(
+            Shape::Plane,
+            Material { material_type: MaterialType::Mirror, ..material_diffuse(WHITE) },
+            Transform::translate(Vec3::new(0.0, 0.0, -2.0)),
+        );
+        let sky = || Sky::Backdrop { background: color_gray(0.5), lighting: Box::new(Sky::Uniform(WHITE)) };
+        let camera = || Camera { fov_horizontal: Angle::degrees(30.0), transform: Transform::default(), medium: VACUUM };
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(1),
+            anti_alias: false,
+            ..Default::default()
+        };
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        // the camera sees the backdrop directly, but the lighting in the mirror
+        let empty = Scene { objects: vec![], sky: sky(), camera: camera() };
+        let prepared = CpuPreparedScene::new(&empty, settings, &LightSelection::Auto, NoAccel, 1, 1);
+        assert_eq!(prepared.sample_pixel(rng, 0, 0), color_gray(0.5));
+
+        let mirrored = Scene { objects: vec![mirror], sky: sky(), camera: camera() };
+        let prepared = CpuPreparedScene::new(&mirrored, settings, &LightSelection::Auto, NoAccel, 1, 1);
+        assert_eq!(prepared.sample_pixel(rng, 0, 0), WHITE);
+    }
+
+    #[test]
+    fn sky_sampling_matches_misses() {
+        // a floor lit by a sky with a bright patch straight above it, and the same floor with a wall hiding part of the sky
+        let floor = Object::new(Shape::Plane, material_diffuse(WHITE), Transform::translate(Vec3::new(0.0, 0.0, -2.0)));
+        let environment = Arc::new(ImageTexture::from_fn(64, 32, |x, y| {
+            let patch = (30..34).contains(&x) && (12..20).contains(&y);
+            if patch { color_gray(20.0) } else { color_gray(0.2) }
+        }));
+        let estimate = |objects: &[Object], strategy| pixel_statistics(objects, Sky::Environment(environment.clone()), strategy, 50_000);
+
+        for objects in [vec![floor.clone()], vec![floor.clone(), wall(material_diffuse(WHITE))]] {
+            let (misses_sampled, misses, misses_variance) = estimate(&objects, Strategy::Simple);
+            let (sky_sampled, sampled, sampled_variance) = estimate(&objects, Strategy::SampleLights);
+
+            assert!(!misses_sampled && sky_sampled);
+            assert!((sampled - misses).abs() < 0.05 * misses, "expected {}, got {}", misses, sampled);
+            assert!(sampled_variance < 0.5 * misses_variance, "expected less than {}, got {}", misses_variance, sampled_variance);
+        }
+    }
+
+    #[test]
+    fn uniform_sky_sampling_matches_misses() {
+        // floors of different materials lit by a uniform sky, with a black wall hiding part of it
+        let estimate = |floor: &Material, strategy| {
+            let floor = Object::new(Shape::Plane, floor.clone(), Transform::translate(Vec3::new(0.0, 0.0, -2.0)));
+            pixel_statistics(&[floor, wall(material_diffuse(BLACK))], Sky::Uniform(color_gray(0.8)), strategy, 50_000)
+        };
+
+        for floor in [material_diffuse(WHITE), material_phong(WHITE, 20.0), material_mixed(WHITE, 0.5)] {
+            let (misses_sampled, misses, misses_variance) = estimate(&floor, Strategy::Simple);
+            let (sky_sampled, sampled, sampled_variance) = estimate(&floor, Strategy::SampleLights);
+
+            assert!(!misses_sampled && sky_sampled);
+            assert!((sampled - misses).abs() < 0.02 * misses, "{:?}: expected {}, got {}", floor.material_type, misses, sampled);
+            assert!(sampled_variance < misses_variance, "{:?}: expected less than {}, got {}", floor.material_type, misses_variance, sampled_variance);
+        }
+    }
+
+    #[test]
+    fn dome_light_sampled_like_sky() {
+        // a gray floor inside of an emissive dome, and the same floor with a wall hiding part of the dome
+        let dome = Object::new(Shape::Sphere, material_light(WHITE, 1.0), Transform::scale(20.0));
+        let floor = Object::new(Shape::Plane, material_diffuse(color_gray(0.5)), Transform::translate(Vec3::new(0.0, 0.0, -2.0)));
+        let estimate = |objects: &[Object], strategy| pixel_statistics(objects, Sky::Uniform(BLACK), strategy, 20_000);
+
+        // the open floor sees the whole upper half of the dome, every sample gets exactly the reflected dome
+        let (_, open, open_variance) = estimate(&[dome.clone(), floor.clone()], Strategy::SampleLights);
+        assert!((open - 0.5).abs() < 1e-3 && open_variance < 1e-6, "expected all 0.5, got mean {} and variance {}", open, open_variance);
+
+        // with part of the dome hidden it still converges to the same result as only finding the dome by hitting it
+        let covered = [dome, floor, wall(material_diffuse(WHITE))];
+        let (_, hits, _) = estimate(&covered, Strategy::Simple);
+        let (_, sampled, _) = estimate(&covered, Strategy::SampleLights);
+        assert!((sampled - hits).abs() < 0.02 * hits, "expected {}, got {}", hits, sampled);
+    }
+
+    #[test]
+    fn pick_object() {
+        let object = |shape, material, transform, visibility| Object::new(shape, material, transform).with_visibility(visibility);
+        let red = Color::new(1.0, 0.0, 0.0);
+        let objects = vec![
+            // a square in front of the sphere that's hidden from the camera
+            object(Shape::Square, material_diffuse(WHITE), Transform::translate(Vec3::new(-1.0, -1.0, -2.0)) * Transform::scale(2.0), Visibility { camera: false, ..Visibility::ALL }),
+            object(Shape::Sphere, material_diffuse(red), Transform::translate(Vec3::new(0.0, 0.0, -5.0)), Visibility::ALL),
+        ];
+        let camera = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects, sky: Sky::Uniform(BLACK), camera };
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(1),
+            anti_alias: false,
+            ..Default::default()
+        };
+        let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 100, 100);
+
+        let (object_hit, material) = prepared.pick(&prepared.camera.image_ray(50.0, 50.0)).unwrap();
+        assert_eq!(object_hit.id, ObjectId::new(1));
+        assert!((object_hit.hit.point - Point3::new(0.0, 0.0, -4.0)).norm() < 1e-4, "{:?}", object_hit.hit);
+        assert!((*object_hit.hit.normal - *Vec3::z_axis()).norm() < 1e-4, "{:?}", object_hit.hit);
+        assert_eq!(material.albedo, red);
+
+        assert!(prepared.pick(&prepared.camera.image_ray(0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn set_camera_keeps_scene() {
+        let object = Object::new(Shape::Sphere, material_diffuse(WHITE), Transform::translate(Vec3::new(0.0, 0.0, -5.0)));
+        let camera = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects: vec![object], sky: Sky::Uniform(BLACK), camera };
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(1),
+            anti_alias: false,
+            ..Default::default()
+        };
+        let mut prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 100, 100);
+        assert!(prepared.pick(&prepared.camera.image_ray(50.0, 50.0)).is_some());
+
+        // move the camera to the side and change the image size, the center now misses the sphere
+        let medium = Medium { index_of_refraction: 1.3, ..VACUUM };
+        let moved = Camera { fov_horizontal: Angle::degrees(30.0), transform: Transform::translate(Vec3::new(3.0, 0.0, 0.0)), medium };
+        prepared.set_camera(&moved, 10, 20);
+        assert!(prepared.pick(&prepared.camera.image_ray(5.0, 10.0)).is_none());
+        assert_eq!(prepared.camera.position(), Point3::new(3.0, 0.0, 0.0));
+        assert_eq!(prepared.camera.medium, medium);
+    }
+
+    #[test]
+    fn object_visibility() {
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(1),
+            anti_alias: false,
+            ..Default::default()
+        };
+
+        // the camera looks at a wall lit by a small light above it
+        let base = vec![
+            Object::new(Shape::Square, material_diffuse(WHITE), Transform::translate(Vec3::new(-50.0, -50.0, -2.0)) * Transform::scale(100.0)),
+            Object::new(Shape::Sphere, material_light(WHITE, 100.0), Transform::translate(Vec3::new(0.0, 2.0, -1.0)) * Transform::scale(0.1)),
+        ];
+        let average = |extra: Option<(Transform, Visibility)>| {
+            let mut objects = base.clone();
+            if let Some((transform, visibility)) = extra {
+                objects.push(Object::new(Shape::Square, material_diffuse(BLACK), transform).with_visibility(visibility));
+            }
+            let camera = Camera { fov_horizontal: Angle::degrees(10.0), transform: Transform::default(), medium: VACUUM };
+            let scene = Scene { objects, sky: Sky::Uniform(BLACK), camera };
+            let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 1, 1);
+            let rng = &mut SmallRng::seed_from_u64(0);
+
+            let n = 1000;
+            (0..n).map(|_| prepared.sample_pixel(rng, 0, 0).red).sum::<f32>() / n as f32
+        };
+
+        // between the light and the wall, but outside of the view of the camera
+        let shadow = Transform::trs(Vec3::new(-0.5, 1.0, -1.0), (Vec3::x_axis(), Angle::degrees(-90.0)), Vec3::new(1.0, 1.0, 1.0));
+        // right in front of the camera, but not blocking the light
+        let front = Transform::translate(Vec3::new(-0.5, -0.5, -1.0));
+
+        let lit = average(None);
+        assert!(lit > 0.01, "got {}", lit);
+        assert!(average(Some((shadow, Visibility::ALL))) < lit * 0.01);
+        assert!(average(Some((shadow, Visibility::HIDDEN))) < lit * 0.01);
+        assert!((average(Some((shadow, Visibility::CAMERA_ONLY))) - lit).abs() < lit * 0.01);
+        assert_eq!(average(Some((front, Visibility::ALL))), 0.0);
+        assert!((average(Some((front, Visibility::HIDDEN))) - lit).abs() < lit * 0.01);
+    }
+
+    #[test]
+    fn alpha_is_coverage() {
+        // a wall covering the left half of the only pixel
+        let wall = Object::new(
+            Shape::Square,
+            material_diffuse(BLACK),
+            Transform::translate(Vec3::new(-100.0, -50.0, -1.0)) * Transform::scale(100.0),
+        );
+        let camera = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects: vec![wall], sky: Sky::Uniform(WHITE), camera };
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(2000),
+            ..Default::default()
+        };
+        let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 1, 1);
+        let result = prepared.calculate_pixel(&mut SmallRng::seed_from_u64(0), 0, 0);
+
+        // the black wall and the white sky are still distinguishable by the alpha
+        assert!((result.alpha - 0.5).abs() < 0.05, "got {}", result.alpha);
+        assert!((result.color.red - (1.0 - result.alpha)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn shadow_catcher_alpha() {
+        let settings = |transparent_sky| CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(100),
+            anti_alias: false,
+            transparent_sky,
+            ..Default::default()
+        };
+
+        // the camera looks at a catcher lit by a small light above it, optionally with a square blocking the light
+        let pixel = |blocked: bool, transparent_sky: bool| {
+            let mut objects = vec![
+                Object::new(Shape::Square, material_shadow_catcher(), Transform::translate(Vec3::new(-50.0, -50.0, -2.0)) * Transform::scale(100.0)),
+                Object::new(Shape::Sphere, material_light(WHITE, 100.0), Transform::translate(Vec3::new(0.0, 2.0, -1.0)) * Transform::scale(0.1)),
+            ];
+            if blocked {
+                let transform = Transform::trs(Vec3::new(-0.5, 1.0, -1.0), (Vec3::x_axis(), Angle::degrees(-90.0)), Vec3::new(1.0, 1.0, 1.0));
+                objects.push(Object::new(Shape::Square, material_diffuse(WHITE), transform));
+            }
+            let camera = Camera { fov_horizontal: Angle::degrees(10.0), transform: Transform::default(), medium: VACUUM };
+            let scene = Scene { objects, sky: Sky::Uniform(BLACK), camera };
+            let prepared = CpuPreparedScene::new(&scene, settings(transparent_sky), &LightSelection::Auto, NoAccel, 1, 1);
+            prepared.calculate_pixel(&mut SmallRng::seed_from_u64(0), 0, 0)
+        };
+
+        let lit = pixel(false, false);
+        assert_eq!((lit.color, lit.alpha), (BLACK, 0.0));
+        let shadowed = pixel(true, false);
+        assert_eq!((shadowed.color, shadowed.alpha), (BLACK, 1.0));
+
+        // looking at the sky instead
+        let camera = Camera { fov_horizontal: Angle::degrees(10.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects: vec![], sky: Sky::Uniform(WHITE), camera };
+        let sky = |transparent_sky| {
+            let prepared = CpuPreparedScene::new(&scene, settings(transparent_sky), &LightSelection::Auto, NoAccel, 1, 1);
+            let result = prepared.calculate_pixel(&mut SmallRng::seed_from_u64(0), 0, 0);
+            let (image, _) = to_discrete_image(ImgVec::new(vec![result], 1, 1).as_ref(), transparent_sky);
+            (result.color, result.alpha, image.get_pixel(0, 0).0[3])
+        };
+        // the saved image is only transparent when the sky is
+        assert_eq!(sky(false), (WHITE, 0.0, 255));
+        assert_eq!(sky(true), (BLACK, 0.0, 0));
+    }
+
+    #[test]
+    fn camera_inside_glass() {
+        let sky = Color::new(0.2, 0.5, 1.0);
+        let tint = Color::new(0.5, 0.8, 0.9);
+        let object = |material, scale| Object::new(Shape::Sphere, material, Transform::scale(scale));
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(64),
+            anti_alias: false,
+            strategy: Strategy::Simple,
+            ..Default::default()
+        };
+
+        // a clear sphere around a tinted one around the camera, the straight ray out only gets tinted by the inner one
+        let scene = Scene {
+            objects: vec![object(material_glass(WHITE), 3.0), object(material_glass_tinted(tint, GLASS_IOR), 1.0)],
+            sky: Sky::Uniform(sky),
+            camera: Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM },
+        };
+        let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 1, 1);
+        assert_eq!(prepared.camera_media.current(), scene.objects[1].material.inside);
+
+        // the center pixel leaves both spheres head on, only the outer surface reflects a bit of the light
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let color = prepared.calculate_pixel(rng, 0, 0).color;
+        let expected = sky * tint;
+        for (actual, expected) in [(color.red, expected.red), (color.green, expected.green), (color.blue, expected.blue)] {
+            assert!((actual - expected).abs() < 0.1 * expected, "{:?} vs {:?}", color, expected);
+        }
+
+        // once the camera moves out of the spheres it's back in its own medium
+        let mut prepared = prepared;
+        let outside = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::translate(Vec3::new(0.0, 0.0, 10.0)), medium: VACUUM };
+        prepared.set_camera(&outside, 1, 1);
+        assert_eq!(prepared.camera_media.current(), VACUUM);
+    }
+
+    #[test]
+    fn medium_stack_overlapping() {
+        let object = |material| Object::new(Shape::Sphere, material, Transform::default());
+        let a = object(material_glass(WHITE));
+        let b = object(material_glass_tinted(WHITE, 1.3));
+        let (id_a, id_b) = (ObjectId::new(0), ObjectId::new(1));
+
+        let media = MediumStack::new(VACUUM);
+        let media = media.crossing(id_a, &a, true);
+        assert_eq!(media.current(), a.material.inside);
+        let media = media.crossing(id_b, &b, true);
+        assert_eq!(media.current(), b.material.inside);
+
+        // leaving the first sphere while still inside of the second one
+        let media = media.crossing(id_a, &a, false);
+        assert_eq!(media.current(), b.material.inside);
+        let media = media.crossing(id_b, &b, false);
+        assert_eq!(media.current(), VACUUM);
+    }
+
+    #[test]
+    fn medium_stack_unknown_exit() {
+        let glass = Object::new(Shape::Square, material_glass(WHITE), Transform::default());
+        let outer = Object::new(Shape::Sphere, material_glass_tinted(WHITE, 1.3), Transform::default());
+
+        // a camera starting inside of the glass
+        let media = MediumStack::new(glass.material.inside);
+        assert_eq!(media.crossing(ObjectId::new(0), &glass, false).current(), VACUUM);
+
+        // entering and leaving a mesh through different faces
+        let media = MediumStack::new(VACUUM)
+            .crossing(ObjectId::new(5), &outer, true)
+            .crossing(ObjectId::new(0), &glass, true)
+            .crossing(ObjectId::new(1), &glass, false);
+        assert_eq!(media.current(), outer.material.inside);
+    }
+
+    #[test]
+    fn camera_project_inverse_of_ray() {
+        let camera = Camera {
+            fov_horizontal: Angle::degrees(70.0),
+            transform: Transform::look_at(Point3::new(1.0, 2.0, 3.0), Point3::origin(), Vec3::y_axis()),
+            medium: VACUUM,
+        };
+        let ray_camera = RayCamera::new(&camera, None, ImageOrigin::TopLeft, 64, 48);
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        for (x, y) in [(0, 0), (63, 0), (20, 30), (63, 47)] {
+            let ray = ray_camera.ray(rng, x, y, 0);
+            let (px, py, cos) = ray_camera.project(ray.at(5.0)).unwrap();
+            assert_eq!((px, py), (x, y));
+            assert!(0.0 < cos && cos <= 1.0);
+        }
+        assert!(ray_camera.project(Point3::new(2.0, 4.0, 6.0)).is_none());
+    }
+
+    #[test]
+    fn camera_project_segment_clips_behind() {
+        let camera = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM };
+        let ray_camera = RayCamera::new(&camera, None, ImageOrigin::TopLeft, 64, 64);
+
+        // fully behind the camera
+        assert!(ray_camera.project_segment(Point3::new(0.0, 0.0, 1.0), Point3::new(1.0, 0.0, 2.0)).is_none());
+
+        // the visible endpoint is unchanged, the other one ends up far to the side
+        let (a, b) = ray_camera.project_segment(Point3::new(0.5, 0.0, -1.0), Point3::new(0.5, 0.0, 1.0)).unwrap();
+        assert_eq!(a, (48.0, 32.0));
+        assert!(b.0 > 1000.0 && b.1 == 32.0);
+    }
+
+    #[test]
+    fn image_origin_flips_rows() {
+        // a light that only the top row of the picture sees
+        let light = Object::new(
+            Shape::Square,
+            material_light(WHITE, 1.0),
+            triangle_as_transform(Point3::new(-10.0, 2.0, -4.0), Point3::new(10.0, 2.0, -4.0), Point3::new(-10.0, 10.0, -4.0)),
+        );
+        let camera = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects: vec![light], sky: Sky::Uniform(BLACK), camera };
+
+        for (origin, marked_row) in [(ImageOrigin::TopLeft, 0), (ImageOrigin::BottomLeft, 3)] {
+            let settings = CpuRenderSettings {
+                stop_condition: StopCondition::SampleCount(1),
+                anti_alias: false,
+                strategy: Strategy::EmissionOnly,
+                image_origin: origin,
+                ..Default::default()
+            };
+            let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, 4, 4);
+            let rng = &mut SmallRng::seed_from_u64(0);
+            let pixels = (0..4).flat_map(|y| (0..4).map(move |x| (x, y)))
+                .map(|(x, y)| prepared.calculate_pixel(rng, x, y))
+                .collect::<Vec<_>>();
+            let (image, _) = to_discrete_image(ImgVec::new(pixels, 4, 4).as_ref(), false);
+
+            for (x, y, pixel) in image.enumerate_pixels() {
+                let expected = if y == marked_row { 255 } else { 0 };
+                assert_eq!(pixel.0[0], expected, "{:?} at ({}, {})", origin, x, y);
+            }
+            assert_eq!(prepared.camera.project(Point3::new(0.0, 3.0, -4.0)).map(|(_, y, _)| y), Some(marked_row));
+        }
+    }
+
+    #[test]
+    fn no_self_intersection_far_away() {
+        let plane = Object::new(
+            Shape::Plane,
+            material_diffuse(WHITE),
+            Transform::translate(Vec3::new(0.0, 5000.0, 0.0)) * Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0)),
+        );
+        let camera = Point3::new(0.0, 5050.0, 30_000.0);
+        let light = Point3::new(0.0, 100_000.0, 0.0);
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        let mut self_hits = 0;
+        for _ in 0..1000 {
+            let target = Point3::new(rng.gen_range(-1000.0..1000.0), 5000.0, rng.gen_range(0.0..10_000.0));
+            let hit = plane.intersect(&Ray::new(camera, (target - camera).normalized())).unwrap();
+
+            let start = Tolerances::default().offset_ray_start(&hit, (light - hit.point).normalized());
+            let shadow_ray = Ray::new(start, (light - start).normalized());
+            if plane.intersect(&shadow_ray).is_some() {
+                self_hits += 1;
+            }
+        }
+
+        assert_eq!(self_hits, 0);
+    }
+
+    /// Sample the lights `n` times from a diffuse hit at the origin facing +z, with the same random samples each call.
+    /// Returns the mean light received at the hit and the variance of its red channel.
+    fn light_estimate(objects: &[Object], settings: CpuRenderSettings, lights: &LightSelection, n: u32) -> (Color, f32) {
+        let camera = Camera { fov_horizontal: Angle::degrees(90.0), transform: Transform::default(), medium: VACUUM };
+        let scene = Scene { objects: objects.to_vec(), sky: Sky::Uniform(BLACK), camera };
+        let prepared = CpuPreparedScene::new(&scene, settings, lights, NoAccel, 1, 1);
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let hit = Hit { t: 1.0, point: Point3::origin(), normal: Vec3::z_axis(), uv: Point2::new(0.0, 0.0) };
+
+        let values = (0..n).map(|_| prepared.sample_lights(Point3::origin(), VACUUM, rng, &hit, &SurfaceBrdf::diffuse(WHITE))).collect::<Vec<_>>();
+        let mean = values.iter().fold(BLACK, |acc, &v| acc + v) / n as f32;
+        let variance = values.iter().map(|v| (v.red - mean.red) * (v.red - mean.red)).sum::<f32>() / n as f32;
+        (mean, variance)
+    }
+
+    #[test]
+    fn square_light_irradiance() {
+        let light = Object::new(Shape::Square, material_light(WHITE, 1.0), Transform::translate(Vec3::new(-0.5, -0.5, 2.0)));
+        // blocks exactly the half of the light with x < 0 as seen from the origin
+        let blocker = Object::new(
+            Shape::Square,
+            material_diffuse(WHITE),
+            triangle_as_transform(Point3::new(-1.0, -1.0, 1.5), Point3::new(0.0, -1.0, 1.5), Point3::new(-1.0, 1.0, 1.5)),
+        );
+
+        // the form factor from a point to a parallel rectangle above one of its corners
+        let corner_form_factor = |x: f32, y: f32| {
+            let (sx, sy) = ((1.0 + x * x).sqrt(), (1.0 + y * y).sqrt());
+            (x / sx * (y / sx).atan() + y / sy * (x / sy).atan()) / (2.0 * PI)
+        };
+        let expected = 4.0 * corner_form_factor(0.25, 0.25) * PI * DIFFUSE_BRDF;
+
+        for (objects, fraction) in [(vec![light.clone()], 1.0), (vec![light, blocker], 0.5)] {
+            let (irradiance, _) = light_estimate(&objects, CpuRenderSettings::default(), &LightSelection::Auto, 20_000);
+
+            let expected = fraction * expected;
+            assert!((irradiance.red - expected).abs() < 0.02 * expected, "expected {}, got {}", expected, irradiance.red);
+        }
+    }
+
+    #[test]
+    fn reservoir_light_sampling() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let objects = (0..30).map(|_| {
+            let brightness = 10f32.powf(rng.gen_range(0.0..2.0));
+            Object::new(
+                Shape::Sphere,
+                material_light(WHITE, brightness),
+                Transform::translate(Vec3::new(rng.gen_range(-2.0..2.0), rng.gen_range(-2.0..2.0), rng.gen_range(0.5..2.0)))
+                    * Transform::scale(0.05),
+            )
+        }).collect::<Vec<_>>();
+
+        let estimate = |light_sampling| {
+            let settings = CpuRenderSettings { light_sampling, ..Default::default() };
+            let (mean, variance) = light_estimate(&objects, settings, &LightSelection::Auto, 20_000);
+            (mean.red, variance)
+        };
+
+        let (expected, _) = estimate(LightSampling::All);
+        let (single, single_variance) = estimate(LightSampling::Reservoir { candidates: 1 });
+        let (many, many_variance) = estimate(LightSampling::Reservoir { candidates: 16 });
+
+        assert!((single - expected).abs() < 0.05 * expected, "expected {}, got {}", expected, single);
+        assert!((many - expected).abs() < 0.02 * expected, "expected {}, got {}", expected, many);
+        assert!(many_variance < single_variance / 4.0, "expected less than {}, got {}", single_variance / 4.0, many_variance);
+    }
+
+    #[test]
+    fn grouped_lights_match_individual() {
+        // a grid of identical square lights above the hit, facing down
+        let objects = (0..25).map(|i| Object::new(
+            Shape::Square,
+            material_light(WHITE, 10.0),
+            Transform::translate(Vec3::new((i % 5) as f32 - 2.0, (i / 5) as f32 - 2.0, 2.0)) * Transform::scale(0.2),
+        )).collect::<Vec<_>>();
+
+        let estimate = |lights: &LightSelection| light_estimate(&objects, CpuRenderSettings::default(), lights, 20_000).0.red;
+
+        let expected = estimate(&LightSelection::Auto);
+        // group all but the first light, which stays a separate light
+        let grouped = estimate(&LightSelection::Grouped(vec![(1..25).map(ObjectId::new).collect()]));
+        assert!((grouped - expected).abs() < 0.03 * expected, "expected {}, got {}", expected, grouped);
+    }
+
+    #[test]
+    fn emission_strength_scales_light() {
+        // the light received at the hit from a single sphere light, with the same random sample each time
+        let received = |material: Material, light_sampling| {
+            let light = Object::new(Shape::Sphere, material, Transform::translate(Vec3::new(0.0, 0.0, 2.0)) * Transform::scale(0.5));
+            let settings = CpuRenderSettings { light_sampling, ..Default::default() };
+            light_estimate(&[light], settings, &LightSelection::Auto, 1).0
+        };
+
+        let color = Color::new(0.2, 0.5, 1.0);
+        for light_sampling in [LightSampling::All, LightSampling::Reservoir { candidates: 4 }] {
+            let baked = received(material_light(color * 8.0, 1.0), light_sampling);
+            assert!(baked.red > 0.0);
+            assert_eq!(received(material_light(color, 8.0), light_sampling), baked);
+            assert_eq!(received(material_light(color, -8.0), light_sampling), baked * -1.0);
+            assert_eq!(received(material_light(color, 0.0), light_sampling), BLACK);
+        }
+    }
+
+    #[test]
+    fn color_exp_black_zero_distance() {
+        let medium = Color::new(0.0, 0.5, 1.0);
+        assert_eq!(color_exp(medium, 0.0), WHITE);
+        assert_eq!(color_exp(medium, 2.0), Color::new(0.0, 0.25, 1.0));
+        assert_eq!(color_exp(medium, f32::INFINITY), Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn clamp_luminance_keeps_hue() {
+        let color = Color::new(4.0, 2.0, 0.0);
+        assert_eq!(clamp_luminance(color, 100.0), color);
+
+        let clamped = clamp_luminance(color, 1.0);
+        assert!((luminance(clamped) - 1.0).abs() < 1e-6);
+        assert!((clamped.red / clamped.green - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn medium_emission_integral() {
+        let medium = Medium { volumetric_color: Color::new(1.0, 0.5, 0.0), emission: WHITE, ..VACUUM };
+        assert_eq!(medium_emission(VACUUM, f32::INFINITY), BLACK);
+
+        let short = medium_emission(medium, 2.0);
+        assert_eq!(short.red, 2.0);
+        assert!((short.green - 0.75 / 2f32.ln()).abs() < 1e-6);
+        assert_eq!(short.blue, 0.0);
+
+        // the absorption limits the glow of thick mediums
+        let infinite = medium_emission(medium, f32::INFINITY);
+        assert!((infinite.green - 1.0 / 2f32.ln()).abs() < 1e-6);
+    }
 
     #[test]
     fn disk_to_hemisphere_z() {
         let disk = Vec2::new(0.1, 0.1);
         let normal = Vec3::z_axis();
         let result = disk_to_hemisphere(disk, normal);
-        println!("{:?}", result);
+        assert!((result.z - (1.0f32 - 0.02).sqrt()).abs() < 1e-6, "{:?}", result);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn disk_to_hemisphere_around_normal() {
+        let disk = Vec2::new(0.3, -0.4);
+        let z = (1.0f32 - 0.25).sqrt();
+
+        for normal in [Vec3::z_axis(), Vec3::x_axis(), -Vec3::y_axis(), Vec3::new(1.0, 2.0, -3.0).normalized()] {
+            let result = disk_to_hemisphere(disk, normal);
+            assert!((result.dot(*normal) - z).abs() < 1e-6, "{result:?} not at the right height above {normal:?}");
+            // the center of the disk maps to the normal itself
+            assert!((disk_to_hemisphere(Vec2::new(0.0, 0.0), normal).dot(*normal) - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn oren_nayar_sampling_matches_brdf() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let hit = Hit { t: 1.0, point: Point3::origin(), normal: Vec3::z_axis(), uv: Point2::new(0.0, 0.0) };
+        let incoming = Vec3::new(2.0, 0.0, -1.0).normalized();
+        let ray = Ray { start: Point3::new(-2.0, 0.0, 1.0), direction: incoming };
+
+        // without roughness it's the Lambertian BRDF
+        let lambert = SurfaceBrdf::new(&material_oren_nayar(WHITE, 0.0), hit.uv, -incoming);
+        let direction = Vec3::new(-1.0, 0.5, 1.0).normalized();
+        assert_eq!(lambert.eval(hit.normal, direction).red, DIFFUSE_BRDF);
+
+        let material = material_oren_nayar(WHITE, 1.0);
+        let brdf = SurfaceBrdf::new(&material, hit.uv, -incoming);
+        // rough surfaces reflect more light back to where it came from
+        assert!(brdf.eval(hit.normal, -incoming).red > brdf.eval(hit.normal, Vec3::new(2.0, 0.0, 1.0).normalized()).red);
+
+        let n = 200_000;
+        let sampled = (0..n)
+            .map(|_| sample_direction(&ray, &hit, material.material_type, 1.0, rng).weight())
+            .sum::<f32>() / n as f32;
+        let uniform = (0..n)
+            .map(|_| {
+                let direction = Vec3::from_slice(&UnitSphere.sample(rng));
+                let direction = if direction.z < 0.0 { -direction } else { direction }.normalized();
+                brdf.eval(hit.normal, direction).red * direction.z * 2.0 * PI
+            })
+            .sum::<f32>() / n as f32;
+
+        assert!(sampled > 0.5 && sampled < 1.0, "got {}", sampled);
+        assert!((sampled - uniform).abs() < 0.02 * uniform, "sampled {}, uniform {}", sampled, uniform);
+    }
+
+    #[test]
+    fn conductor_brightens_at_grazing_angles() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let hit = Hit { t: 1.0, point: Point3::origin(), normal: Vec3::z_axis(), uv: Point2::new(0.0, 0.0) };
+        let material = material_conductor(METAL_GOLD);
+
+        let mut reflected = |incoming: Vec3| {
+            let ray = Ray { start: Point3::origin() - *incoming.normalized(), direction: incoming.normalized() };
+            let sample = sample_direction(&ray, &hit, material.material_type, 1.0, rng);
+            assert!(sample.specular && !sample.crosses_surface);
+            sample.tint(material.albedo) * sample.weight()
+        };
+
+        // head-on the reflection has the color of the metal, at grazing angles it becomes almost white
+        let head_on = reflected(Vec3::new(0.0, 0.0, -1.0));
+        let grazing = reflected(Vec3::new(1.0, 0.0, -0.01));
+        assert!((head_on - METAL_GOLD).red.abs() < 1e-6 && (head_on - METAL_GOLD).blue.abs() < 1e-6, "got {:?}", head_on);
+        assert!(grazing.blue > 0.9 && grazing.blue > head_on.blue, "got {:?}", grazing);
+    }
+
+    #[test]
+    fn phong_sampling_matches_brdf() {
+        let rng = &mut SmallRng::seed_from_u64(0);
+        let hit = Hit { t: 1.0, point: Point3::origin(), normal: Vec3::z_axis(), uv: Point2::new(0.0, 0.0) };
+        let incoming = Vec3::new(0.5, 0.0, -1.0).normalized();
+        let ray = Ray { start: Point3::new(-0.5, 0.0, 1.0), direction: incoming };
+        let material = material_phong(WHITE, 20.0);
+        let brdf = SurfaceBrdf::new(&material, hit.uv, -incoming);
+
+        // the reflected fraction of uniform incoming light, estimated with importance sampling and with uniform sampling
+        let n = 200_000;
+        let sampled = (0..n)
+            .map(|_| sample_direction(&ray, &hit, material.material_type, 1.0, rng))
+            .map(|sample| {
+                assert!(!sample.specular && !sample.crosses_surface);
+                sample.weight()
+            })
+            .sum::<f32>() / n as f32;
+        let uniform = (0..n)
+            .map(|_| {
+                let direction = Vec3::from_slice(&UnitSphere.sample(rng));
+                let direction = if direction.z < 0.0 { -direction } else { direction }.normalized();
+                brdf.eval(hit.normal, direction).red * direction.z * 2.0 * PI
+            })
+            .sum::<f32>() / n as f32;
+
+        assert!(sampled > 0.9 && sampled <= 1.01, "got {}", sampled);
+        assert!((sampled - uniform).abs() < 0.02 * uniform, "sampled {}, uniform {}", sampled, uniform);
+    }
+}
@@ -1,4 +1,65 @@
+use std::cell::Cell;
+
+use crate::common::progress::PixelResult;
 use crate::common::scene::Color;
+use crate::common::texture::luminance;
+
+thread_local! {
+    static REJECTED_HITS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Run `f` on the current thread and also return the number of invalid hits it skipped,
+/// hits with a negative or NaN distance or with a non-finite point or normal.
+/// These come from malformed geometry, eg. degenerate triangles in imported meshes or singular transforms.
+///
+/// Only the hits of `f` itself are counted, not those of other threads, so `f` shouldn't hand work to a thread pool.
+pub fn count_rejected_hits<R>(f: impl FnOnce() -> R) -> (R, u64) {
+    let before = REJECTED_HITS.get();
+    let result = f();
+    (result, REJECTED_HITS.get() - before)
+}
+
+pub(crate) fn record_rejected_hit() {
+    REJECTED_HITS.set(REJECTED_HITS.get() + 1);
+}
+
+/// Calculates only the mean of a value by summing the samples, a cheaper alternative to [ColorVarianceEstimator]
+/// when the variance isn't needed.
+#[derive(Debug, Default, Clone)]
+pub struct ColorMeanEstimator {
+    pub count: u32,
+    sum: Color,
+    alpha_sum: f32,
+}
+
+impl ColorMeanEstimator {
+    /// Updates the internal state given a new sample with the given alpha, `value` should be premultiplied by it.
+    pub fn update_with_alpha(&mut self, value: Color, alpha: f32) {
+        self.count += 1;
+        self.sum += value;
+        self.alpha_sum += alpha;
+    }
+
+    /// The current mean, black if there are no samples yet.
+    pub fn mean(&self) -> Color {
+        self.sum / self.count.max(1) as f32
+    }
+
+    /// The current mean of the alpha values.
+    pub fn alpha_mean(&self) -> f32 {
+        self.alpha_sum / self.count.max(1) as f32
+    }
+
+    /// The [PixelResult] for the samples so far, with the variances left at zero.
+    pub fn pixel_result(&self) -> PixelResult {
+        PixelResult {
+            color: self.mean(),
+            samples: self.count,
+            alpha: self.alpha_mean(),
+            ..PixelResult::default()
+        }
+    }
+}
 
 /// Calculates the variance of a value online with only a fixed amount of memory using
 /// [Welford's algorithm](https://en.wikipedia.org/wiki/Algorithms_for_calculating_variance#Welford's_online_algorithm).
@@ -9,16 +70,33 @@ pub struct ColorVarianceEstimator {
     pub count: u32,
     pub mean: Color,
     m2: Color,
+    // the luminance is tracked separately, its variance depends on the covariance between the channels
+    luminance_mean: f32,
+    luminance_m2: f32,
+    /// The mean of the alpha values passed to [ColorVarianceEstimator::update_with_alpha].
+    pub alpha_mean: f32,
 }
 
 impl ColorVarianceEstimator {
-    /// Updates the internal state given a new sample.
+    /// Updates the internal state given a new fully opaque sample.
     pub fn update(&mut self, value: Color) {
+        self.update_with_alpha(value, 1.0);
+    }
+
+    /// Updates the internal state given a new sample with the given alpha, `value` should be premultiplied by it.
+    pub fn update_with_alpha(&mut self, value: Color, alpha: f32) {
         self.count += 1;
         let delta = value - self.mean;
         self.mean += delta / (self.count as f32);
         let delta_2 = value - self.mean;
         self.m2 += delta * delta_2;
+
+        let luminance = luminance(value);
+        let delta = luminance - self.luminance_mean;
+        self.luminance_mean += delta / (self.count as f32);
+        self.luminance_m2 += delta * (luminance - self.luminance_mean);
+
+        self.alpha_mean += (alpha - self.alpha_mean) / (self.count as f32);
     }
 
     /// Returns the current variance.
@@ -26,7 +104,21 @@ impl ColorVarianceEstimator {
         if self.count >= 2 {
             Some(self.m2 / (self.count as f32))
         } else {
-            return None
+            None
+        }
+    }
+
+    /// Returns the current mean of the luminance, see [luminance].
+    pub fn luminance_mean(&self) -> f32 {
+        self.luminance_mean
+    }
+
+    /// Returns the current variance of the luminance, see [luminance].
+    pub fn luminance_variance(&self) -> Option<f32> {
+        if self.count >= 2 {
+            Some(self.luminance_m2 / (self.count as f32))
+        } else {
+            None
         }
     }
 }
@@ -59,4 +151,42 @@ mod test {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn luminance_variance_includes_covariance() {
+        // perfectly anti-correlated red and green, each channel is noisy but the luminance barely changes
+        let colors = [Color::new(1.0, 0.0, 0.5), Color::new(0.0, 1.0, 0.5), Color::new(0.8, 0.2, 0.5), Color::new(0.3, 0.7, 0.5)];
+        let mut estimator = ColorVarianceEstimator::default();
+        for &color in &colors {
+            estimator.update(color);
+        }
+
+        let luminances = colors.map(luminance);
+        let expected_mean = luminances.iter().sum::<f32>() / 4.0;
+        let expected_variance = luminances.iter().map(|&l| (l - expected_mean).powi(2)).sum::<f32>() / 4.0;
+
+        assert!((estimator.luminance_mean() - expected_mean).abs() < 1e-6);
+        assert!((estimator.luminance_variance().unwrap() - expected_variance).abs() < 1e-6);
+        assert!(estimator.luminance_variance().unwrap() < estimator.variance().unwrap().red);
+    }
+
+    #[test]
+    fn mean_estimator_matches_variance_estimator() {
+        let mut mean_estimator = ColorMeanEstimator::default();
+        let mut variance_estimator = ColorVarianceEstimator::default();
+
+        for i in 0..1000 {
+            let x = i as f32;
+            let (color, alpha) = (Color::new((x * 0.37).sin().abs(), (x * 1.3).fract() * 10.0, 0.1), (i % 3) as f32 / 2.0);
+            mean_estimator.update_with_alpha(color, alpha);
+            variance_estimator.update_with_alpha(color, alpha);
+        }
+
+        let (mean, expected) = (mean_estimator.mean(), variance_estimator.mean);
+        assert_eq!(mean_estimator.count, variance_estimator.count);
+        for (actual, expected) in [(mean.red, expected.red), (mean.green, expected.green), (mean.blue, expected.blue)] {
+            assert!((actual - expected).abs() < 1e-4 * expected.abs().max(1.0), "{actual} != {expected}");
+        }
+        assert!((mean_estimator.alpha_mean() - variance_estimator.alpha_mean).abs() < 1e-5);
+    }
+}
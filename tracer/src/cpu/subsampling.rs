@@ -0,0 +1,141 @@
+use crate::common::progress::{Block, PixelResult};
+
+/// Which pixels are traced, the others are interpolated from the traced pixels around them. See [CpuRenderer::subsampling](crate::cpu::CpuRenderer::subsampling).
+///
+/// This trades quality for speed, eg. for interactive previews of expensive scenes. Interpolation can't recover
+/// anything between the traced pixels: edges become jagged, thin features and texture detail blur or disappear
+/// and the noise of the traced pixels is smeared out into blotches. Interpolated pixels report zero samples.
+///
+/// The pattern is applied per render block, so the interpolation never needs pixels of other blocks and the progress
+/// handlers only ever see finished pixels.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Subsampling {
+    /// Trace every pixel.
+    Full,
+    /// Trace every other pixel in a checkerboard pattern, the others are the average of their traced direct neighbours.
+    /// Takes about half the time.
+    Checkerboard,
+    /// Trace one pixel in each `step` by `step` square, plus the last row and column of each block,
+    /// and interpolate the others bilinearly. Takes about `1 / step²` of the time.
+    Grid { step: u32 },
+}
+
+impl Subsampling {
+    /// Whether the pixel at `(x, y)` in `block` is traced instead of interpolated.
+    pub(super) fn is_traced(self, block: Block, x: u32, y: u32) -> bool {
+        match self {
+            Subsampling::Full => true,
+            // a single pixel has no neighbours to interpolate from
+            Subsampling::Checkerboard => (x + y).is_multiple_of(2) || block.pixel_count() == 1,
+            Subsampling::Grid { step } => {
+                assert!(step > 0, "Subsampling step must be positive");
+                let (dx, dy) = (x - block.x, y - block.y);
+                (dx % step == 0 || dx == block.width - 1) && (dy % step == 0 || dy == block.height - 1)
+            }
+        }
+    }
+
+    /// Fill in the pixels of `block` that are not traced, `pixels` are all pixels of the block in row-major order.
+    pub(super) fn reconstruct(self, block: Block, pixels: &mut [PixelResult]) {
+        assert_eq!(pixels.len(), block.pixel_count() as usize);
+        let (width, height) = (block.width as usize, block.height as usize);
+
+        for (i, (x, y)) in block.iter().enumerate() {
+            if self.is_traced(block, x, y) {
+                continue;
+            }
+            let (dx, dy) = ((x - block.x) as usize, (y - block.y) as usize);
+
+            pixels[i] = match self {
+                Subsampling::Full => unreachable!(),
+                Subsampling::Checkerboard => {
+                    let neighbours = [(-1, 0), (1, 0), (0, -1), (0, 1)].into_iter()
+                        .map(|(ox, oy)| (dx as isize + ox, dy as isize + oy))
+                        .filter(|&(nx, ny)| (0..width as isize).contains(&nx) && (0..height as isize).contains(&ny))
+                        .map(|(nx, ny)| (pixels[ny as usize * width + nx as usize], 1.0));
+                    interpolate(neighbours)
+                }
+                Subsampling::Grid { step } => {
+                    let step = step as usize;
+                    // the traced pixels around this one, and how far along between them it is
+                    let around = |d: usize, size: usize| {
+                        let low = d - d % step;
+                        let high = (low + step).min(size - 1);
+                        let t = if high == low { 0.0 } else { (d - low) as f32 / (high - low) as f32 };
+                        (low, high, t)
+                    };
+                    let (x0, x1, tx) = around(dx, width);
+                    let (y0, y1, ty) = around(dy, height);
+
+                    interpolate([
+                        (pixels[y0 * width + x0], (1.0 - tx) * (1.0 - ty)),
+                        (pixels[y0 * width + x1], tx * (1.0 - ty)),
+                        (pixels[y1 * width + x0], (1.0 - tx) * ty),
+                        (pixels[y1 * width + x1], tx * ty),
+                    ])
+                }
+            };
+        }
+    }
+}
+
+/// The weighted average of the given pixels, with zero samples since it's not traced itself.
+fn interpolate(pixels: impl IntoIterator<Item=(PixelResult, f32)>) -> PixelResult {
+    let mut result = PixelResult::default();
+    let mut total_weight = 0.0;
+    for (pixel, weight) in pixels {
+        result.color += pixel.color * weight;
+        result.variance += pixel.variance * weight;
+        result.rel_variance += pixel.rel_variance * weight;
+        result.alpha += pixel.alpha * weight;
+        total_weight += weight;
+    }
+
+    result.color /= total_weight;
+    result.variance /= total_weight;
+    result.rel_variance /= total_weight;
+    result.alpha /= total_weight;
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::progress::{Block, PixelResult};
+    use crate::common::scene::Color;
+    use crate::cpu::subsampling::Subsampling;
+
+    #[test]
+    fn reconstructs_gradient() {
+        let block = Block { x: 16, y: 32, width: 16, height: 11 };
+        let value = |x: u32, y: u32| (x + 2 * y) as f32;
+
+        for subsampling in [Subsampling::Checkerboard, Subsampling::Grid { step: 4 }] {
+            let mut pixels: Vec<PixelResult> = block.iter()
+                .map(|(x, y)| if subsampling.is_traced(block, x, y) {
+                    PixelResult { color: Color::new(value(x, y), 0.0, 0.0), samples: 1, alpha: 1.0, ..PixelResult::default() }
+                } else {
+                    PixelResult::default()
+                })
+                .collect();
+            let traced = pixels.iter().filter(|pixel| pixel.samples > 0).count();
+            subsampling.reconstruct(block, &mut pixels);
+
+            for ((x, y), pixel) in block.iter().zip(&pixels) {
+                // the checkerboard only has all four neighbours away from the edges of the block
+                let interior = x > block.x && x < block.x + block.width - 1 && y > block.y && y < block.y + block.height - 1;
+                if subsampling == Subsampling::Checkerboard && !interior {
+                    continue;
+                }
+                assert!((pixel.color.red - value(x, y)).abs() < 1e-3, "{:?} at ({}, {}): {:?}", subsampling, x, y, pixel.color);
+                assert_eq!(pixel.alpha, 1.0);
+            }
+
+            let expected = match subsampling {
+                Subsampling::Checkerboard => 16 * 11 / 2,
+                // columns 0, 4, 8, 12 and 15, rows 0, 4, 8 and 10
+                _ => 5 * 4,
+            };
+            assert_eq!(traced, expected, "{:?}", subsampling);
+        }
+    }
+}
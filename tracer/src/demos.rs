@@ -1,24 +1,38 @@
+use std::f32::consts::PI;
 use std::fs::read_to_string;
 use std::marker::PhantomData;
 use std::path::Path;
+use std::sync::Arc;
 
 use rand::{Rng, SeedableRng};
 use rand::rngs::SmallRng;
 use rand_distr::Distribution;
 use rand_distr::UnitSphere;
+use rayon::iter::{IntoParallelIterator, ParallelExtend, ParallelIterator};
 use wavefront_obj::obj;
 
-use crate::common::math::{Angle, Point3, Transform, Unit, Vec3};
-use crate::common::scene::{Camera, Color, Material, MaterialType, Medium, Object, Scene, Shape};
-use crate::common::util::{obj_to_triangles, triangle_as_transform};
+use crate::common::aabb::AxisBox;
+use crate::common::math::{Angle, Norm, Point2, Point3, Transform, Unit, Vec3};
+use crate::common::point_cloud::{CloudPoint, point_cloud_to_spheres, PointCloudStyle};
+use crate::common::scene::{BumpMap, Camera, Color, lerp_color, Material, MaterialType, Medium, NormalMap, Object, Scene, Sdf, Shape, Sky, Visibility};
+use crate::common::texture::{equirect_direction, ImageTexture, Texture};
+use crate::common::util::{CoordinateConvention, obj_to_triangles, triangle_as_transform};
+
+pub use crate::common::scene::{BLACK, VACUUM, WHITE};
 
 pub const VACUUM_IOR: f32 = 1.0;
 pub const GLASS_IOR: f32 = 1.52;
+/// The Cauchy `B` coefficient of BK7 glass in µm², see [Medium::dispersion].
+pub const GLASS_DISPERSION: f32 = 0.00420;
+
 
-pub const BLACK: Color = Color { red: 0.0, green: 0.0, blue: 0.0, standard: PhantomData };
-pub const WHITE: Color = Color { red: 1.0, green: 1.0, blue: 1.0, standard: PhantomData };
+/// The reflectance at normal incidence of common metals in linear RGB, for [material_conductor].
+pub const METAL_GOLD: Color = Color { red: 1.0, green: 0.71, blue: 0.29, standard: PhantomData };
+pub const METAL_COPPER: Color = Color { red: 0.95, green: 0.64, blue: 0.54, standard: PhantomData };
+pub const METAL_SILVER: Color = Color { red: 0.95, green: 0.93, blue: 0.88, standard: PhantomData };
+pub const METAL_ALUMINUM: Color = Color { red: 0.91, green: 0.92, blue: 0.92, standard: PhantomData };
+pub const METAL_IRON: Color = Color { red: 0.56, green: 0.57, blue: 0.58, standard: PhantomData };
 
-pub const VACUUM: Medium = Medium { index_of_refraction: 1.0, volumetric_color: WHITE };
 
 pub fn color_by_name(name: &str) -> Color {
     palette::Srgb::from_format(palette::named::from_str(name).expect("Invalid color name"))
@@ -33,15 +47,32 @@ pub fn medium_glass(volumetric_color: Color) -> Medium {
     Medium {
         index_of_refraction: GLASS_IOR,
         volumetric_color,
+        dispersion: GLASS_DISPERSION,
+        emission: BLACK,
     }
 }
 
+/// The volumetric color for which white light turns into `color` after travelling `depth` through the medium.
+/// See [Medium::volumetric_color] for the underlying model, for `depth == 1` this is just `color`.
+pub fn volumetric_color_at_depth(color: Color, depth: f32) -> Color {
+    assert!(depth > 0.0, "depth must be positive, got {}", depth);
+    let exp = 1.0 / depth;
+    Color::new(color.red.powf(exp), color.green.powf(exp), color.blue.powf(exp))
+}
+
 pub fn material_diffuse(albedo: Color) -> Material {
     Material {
         material_type: MaterialType::Diffuse,
 
         albedo,
         emission: BLACK,
+        emission_strength: 1.0,
+        emission_texture: None,
+        albedo_texture: None,
+        alpha: None,
+        bump: None,
+        normal_map: None,
+        cull_backface: false,
 
         inside: VACUUM,
         outside: VACUUM,
@@ -54,41 +85,231 @@ pub fn material_mixed(albedo: Color, diffuse_fraction: f32) -> Material {
         material_type: MaterialType::DiffuseMirror(diffuse_fraction),
         albedo,
         emission: BLACK,
+        emission_strength: 1.0,
+        emission_texture: None,
+        albedo_texture: None,
+        alpha: None,
+        bump: None,
+        normal_map: None,
+        cull_backface: false,
         inside: VACUUM,
         outside: VACUUM,
     }
 }
 
+/// A rough diffuse material, see [MaterialType::OrenNayar].
+pub fn material_oren_nayar(albedo: Color, roughness: f32) -> Material {
+    assert!(roughness >= 0.0);
+    Material {
+        material_type: MaterialType::OrenNayar { roughness },
+        ..material_diffuse(albedo)
+    }
+}
+
+/// A polished metal, see [MaterialType::Conductor] and the presets like [METAL_GOLD].
+pub fn material_conductor(reflectance: Color) -> Material {
+    Material {
+        material_type: MaterialType::Conductor { reflectance },
+        ..material_diffuse(WHITE)
+    }
+}
+
+/// A plastic-like material with a white highlight, see [MaterialType::Phong].
+pub fn material_phong(albedo: Color, shininess: f32) -> Material {
+    assert!(shininess >= 0.0);
+    Material {
+        material_type: MaterialType::Phong { shininess },
+        ..material_diffuse(albedo)
+    }
+}
+
 pub fn material_glass(volumetric_color: Color) -> Material {
     Material {
         material_type: MaterialType::Transparent,
         albedo: WHITE,
         emission: BLACK,
+        emission_strength: 1.0,
+        emission_texture: None,
+        albedo_texture: None,
+        alpha: None,
+        bump: None,
+        normal_map: None,
+        cull_backface: false,
         inside: medium_glass(volumetric_color),
         outside: VACUUM,
     }
 }
 
-pub fn material_light(emission: Color) -> Material {
+/// Tinted glass where white light has the color `color_at_unit_depth` after travelling a distance 1 through it.
+/// Thicker parts of the object will have a more saturated color, use [volumetric_color_at_depth] to start from a different depth.
+pub fn material_glass_tinted(color_at_unit_depth: Color, index_of_refraction: f32) -> Material {
+    Material {
+        inside: Medium {
+            index_of_refraction,
+            ..medium_glass(color_at_unit_depth)
+        },
+        ..material_glass(WHITE)
+    }
+}
+
+/// A black material emitting `color` with the given strength, see [Material::emission_strength].
+pub fn material_light(color: Color, strength: f32) -> Material {
     Material {
         material_type: MaterialType::Diffuse,
         albedo: BLACK,
-        emission,
+        emission: color,
+        emission_strength: strength,
+        emission_texture: None,
+        albedo_texture: None,
+        alpha: None,
+        bump: None,
+        normal_map: None,
+        cull_backface: false,
         inside: VACUUM,
         outside: VACUUM,
     }
 }
 
-pub fn material_fixed(color: Color, camera_only: bool) -> Material {
+pub fn material_fixed(color: Color) -> Material {
     Material {
-        material_type: MaterialType::Fixed { camera_only },
+        material_type: MaterialType::Fixed,
         albedo: color,
         emission: BLACK,
+        emission_strength: 1.0,
+        emission_texture: None,
+        albedo_texture: None,
+        alpha: None,
+        bump: None,
+        normal_map: None,
+        cull_backface: false,
         inside: VACUUM,
         outside: VACUUM,
     }
 }
 
+/// An invisible material that only shows the shadows cast onto it, see [MaterialType::ShadowCatcher].
+pub fn material_shadow_catcher() -> Material {
+    Material {
+        material_type: MaterialType::ShadowCatcher,
+        ..material_diffuse(WHITE)
+    }
+}
+
+/// A diffuse material with a checkered albedo, alternating squares with side `1 / scale`.
+/// Combine with [ground_plane] for the classic checkered floor.
+pub fn material_checker(even: Color, odd: Color, scale: f32) -> Material {
+    Material {
+        albedo_texture: Some(Texture::Checker { even, odd, scale }),
+        ..material_diffuse(WHITE)
+    }
+}
+
+/// The standard ground plane, the xz plane through the origin.
+pub fn ground_plane(material: Material) -> Object {
+    Object::new(Shape::Plane, material, Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0)))
+}
+
+/// A grayscale alpha mask with a random cluster of elliptical leaves, for use on foliage cards.
+pub fn texture_leaves(seed: u64, leaf_count: usize) -> Texture {
+    let rng = &mut SmallRng::seed_from_u64(seed);
+    let leaves: Vec<(f32, f32, f32, f32)> = (0..leaf_count).map(|_| {
+        let x = rng.gen_range(0.2..0.8);
+        let y = rng.gen_range(0.2..0.8);
+        let angle: f32 = rng.gen_range(0.0..std::f32::consts::PI);
+        let size = rng.gen_range(0.08..0.2);
+        (x, y, angle, size)
+    }).collect();
+
+    let size = 256;
+    let image = ImageTexture::from_fn(size, size, |x, y| {
+        let px = (x as f32 + 0.5) / size as f32;
+        let py = (y as f32 + 0.5) / size as f32;
+
+        let inside = leaves.iter().any(|&(lx, ly, angle, size)| {
+            let (s, c) = angle.sin_cos();
+            let dx = px - lx;
+            let dy = py - ly;
+            let along = (c * dx + s * dy) / size;
+            let across = (-s * dx + c * dy) / (size / 2.5);
+            along * along + across * across < 1.0
+        });
+
+        color_gray(if inside { 1.0 } else { 0.0 })
+    });
+
+    Texture::Image(Arc::new(image))
+}
+
+/// Vertical color bars like a television test card.
+pub fn texture_color_bars() -> Texture {
+    let bars = [
+        Color::new(1.0, 1.0, 1.0),
+        Color::new(1.0, 1.0, 0.0),
+        Color::new(0.0, 1.0, 1.0),
+        Color::new(0.0, 1.0, 0.0),
+        Color::new(1.0, 0.0, 1.0),
+        Color::new(1.0, 0.0, 0.0),
+        Color::new(0.0, 0.0, 1.0),
+    ];
+
+    let image = ImageTexture::from_fn(bars.len() as u32, 1, |x, _| bars[x as usize]);
+    Texture::Image(Arc::new(image))
+}
+
+/// A procedural equirectangular sky with a blue gradient, a dark ground and a small, very bright sun.
+pub fn texture_sun_sky(sun_direction: Unit<Vec3>, sun_angle: Angle) -> ImageTexture {
+    let (width, height) = (512, 256);
+    let sun_cos = sun_angle.radians.cos();
+
+    ImageTexture::from_fn(width, height, |x, y| {
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = 1.0 - (y as f32 + 0.5) / height as f32;
+        let direction = equirect_direction(Point2::new(u, v));
+
+        if direction.dot(*sun_direction) >= sun_cos {
+            WHITE * 2_000.0
+        } else if direction.y >= 0.0 {
+            let t = direction.y.sqrt();
+            lerp_color(Color::new(0.7, 0.8, 1.0), Color::new(0.15, 0.3, 0.8), t)
+        } else {
+            color_gray(0.05)
+        }
+    })
+}
+
+/// A smooth grayscale egg-crate pattern, `sin(u) * sin(v)` with the given number of periods along each uv axis,
+/// mapped to `[0, 1]`. Meant as the height of a [BumpMap].
+pub fn texture_sine_bumps(periods_u: u32, periods_v: u32) -> Texture {
+    let (width, height) = (64 * periods_u, 64 * periods_v);
+    let image = ImageTexture::from_fn(width, height, |x, y| {
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = (y as f32 + 0.5) / height as f32;
+        let value = (2.0 * PI * periods_u as f32 * u).sin() * (2.0 * PI * periods_v as f32 * v).sin();
+        color_gray(0.5 + 0.5 * value)
+    });
+    Texture::Image(Arc::new(image))
+}
+
+/// A tangent space normal map of a grid of round domes with `count` domes along each uv axis, see [NormalMap].
+pub fn texture_normal_domes(count: u32) -> Texture {
+    let size = 32 * count;
+    let image = ImageTexture::from_fn(size, size, |x, y| {
+        // the position within the cell of the dome, between -1 and 1, with y pointing towards increasing v
+        let cell = |i: u32| ((i as f32 + 0.5) / 32.0).fract() * 2.0 - 1.0;
+        let (dx, dy) = (cell(x), -cell(y));
+
+        let r2 = dx * dx + dy * dy;
+        let normal = if r2 < 0.8 {
+            // a sphere cap, its normal points away from the center of the sphere below the surface
+            Vec3::new(dx, dy, 1.0).normalized()
+        } else {
+            Vec3::z_axis()
+        };
+        Color::new(normal.x / 2.0 + 0.5, normal.y / 2.0 + 0.5, normal.z / 2.0 + 0.5)
+    });
+    Texture::Image(Arc::new(image))
+}
+
 /// A cuboid centered around the origin with edge lengths given by `size`.
 pub fn objects_cuboid(material: Material, size: Vec3, transform: Transform) -> Vec<Object> {
     let cx = size.x / 2.0;
@@ -122,56 +343,78 @@ pub fn objects_cuboid(material: Material, size: Vec3, transform: Transform) -> V
     ];
 
     triangles.into_iter().map(|(a, b, c)| {
-        Object {
-            shape: Shape::Triangle,
-            material,
-            transform: transform * triangle_as_transform(points[a], points[b], points[c]),
-        }
+        // make all triangles face outwards so the cuboid works as a closed transparent object
+        let normal = (points[b] - points[a]).cross(points[c] - points[a]);
+        let (b, c) = if normal.dot(points[a].coords()) < 0.0 { (c, b) } else { (b, c) };
+
+        Object::new(Shape::Triangle, material.clone(), transform * triangle_as_transform(points[a], points[b], points[c]))
+    }).collect()
+}
+
+/// A prism along the z-axis with length 1 and an equilateral triangle with sides 1 as cross-section,
+/// centered around the origin with one side parallel to the xz-plane at the bottom.
+pub fn objects_prism(material: Material, transform: Transform) -> Vec<Object> {
+    let h = 3f32.sqrt() / 2.0;
+    // shift the centroid of the triangle to the origin
+    let corners = [(-0.5, -h / 3.0), (0.5, -h / 3.0), (0.0, 2.0 * h / 3.0)];
+    let point = |i: usize, z: f32| Point3::new(corners[i].0, corners[i].1, z);
+
+    let mut faces = vec![
+        (Shape::Triangle, point(0, -0.5), point(1, -0.5), point(2, -0.5)),
+        (Shape::Triangle, point(0, 0.5), point(1, 0.5), point(2, 0.5)),
+    ];
+    for i in 0..3 {
+        let j = (i + 1) % 3;
+        faces.push((Shape::Square, point(i, -0.5), point(j, -0.5), point(i, 0.5)));
+    }
+
+    faces.into_iter().map(|(shape, a, b, c)| {
+        // make all faces face outwards so the prism works as a closed transparent object
+        let normal = (b - a).cross(c - a);
+        let (b, c) = if normal.dot(a.coords()) < 0.0 { (c, b) } else { (b, c) };
+
+        Object::new(shape, material.clone(), transform * triangle_as_transform(a, b, c))
     }).collect()
 }
 
 pub fn objects_axes(brightness: f32, radius_axis: f32, radius_dot: Option<f32>, cube_dots: bool) -> Vec<Object> {
     let scale_axis = Transform::scale(radius_axis);
-    let material_x = material_fixed(Color::new(brightness, 0.0, 0.0), true);
-    let material_y = material_fixed(Color::new(0.0, brightness, 0.0), true);
-    let material_z = material_fixed(Color::new(0.0, 0.0, brightness), true);
-    let material_cube = material_fixed(BLACK, true);
+    let material_x = material_fixed(Color::new(brightness, 0.0, 0.0));
+    let material_y = material_fixed(Color::new(0.0, brightness, 0.0));
+    let material_z = material_fixed(Color::new(0.0, 0.0, brightness));
+    let material_cube = material_fixed(BLACK);
 
     let mut result = vec![];
 
-    result.push(Object {
-        shape: Shape::Cylinder,
-        material: material_x,
-        transform: Transform::rotate(Vec3::z_axis(), Angle::degrees(90.0)) * scale_axis,
-    });
-    result.push(Object {
-        shape: Shape::Cylinder,
-        material: material_y,
-        transform: scale_axis,
-    });
-    result.push(Object {
-        shape: Shape::Cylinder,
-        material: material_z,
-        transform: Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0)) * scale_axis,
-    });
+    result.push(Object::new(
+        Shape::Cylinder,
+        material_x.clone(),
+        Transform::rotate(Vec3::z_axis(), Angle::degrees(90.0)) * scale_axis,
+    ).with_visibility(Visibility::CAMERA_ONLY));
+    result.push(Object::new(Shape::Cylinder, material_y.clone(), scale_axis).with_visibility(Visibility::CAMERA_ONLY));
+    result.push(Object::new(
+        Shape::Cylinder,
+        material_z.clone(),
+        Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0)) * scale_axis,
+    ).with_visibility(Visibility::CAMERA_ONLY));
 
     if let Some(radius_dot) = radius_dot {
         let scale_dot = Transform::scale(radius_dot);
-        result.push(Object {
-            shape: Shape::Sphere,
-            material: material_x,
-            transform: Transform::translate(Vec3::new(1.0, 0.0, 0.0)) * scale_dot,
-        });
-        result.push(Object {
-            shape: Shape::Sphere,
-            material: material_y,
-            transform: Transform::translate(Vec3::new(0.0, 1.0, 0.0)) * scale_dot,
-        });
-        result.push(Object {
-            shape: Shape::Sphere,
-            material: material_z,
-            transform: Transform::translate(Vec3::new(0.0, 0.0, 1.0)) * scale_dot,
-        });
+        result.push(Object::new(
+            Shape::Sphere,
+            material_x,
+            Transform::translate(Vec3::new(1.0, 0.0, 0.0)) * scale_dot,
+        ).with_visibility(Visibility::CAMERA_ONLY));
+        result.push(Object::new(
+            Shape::Sphere,
+            material_y,
+            Transform::translate(Vec3::new(0.0, 1.0, 0.0)) * scale_dot,
+        ).with_visibility(Visibility::CAMERA_ONLY));
+        result.push(Object::new(
+            Shape::Sphere,
+            material_z,
+            Transform::translate(Vec3::new(0.0, 0.0, 1.0)) * scale_dot,
+        ).with_visibility(Visibility::CAMERA_ONLY));
 
         if cube_dots {
             let coords = [
@@ -181,11 +424,11 @@ pub fn objects_axes(brightness: f32, radius_axis: f32, radius_dot: Option<f32>,
                 Vec3::new(1.0, 1.0, 0.0),
             ];
             for coord in coords {
-                result.push(Object {
-                    shape: Shape::Sphere,
-                    material: material_cube,
-                    transform: Transform::translate(coord) * scale_dot,
-                });
+                result.push(Object::new(
+                    Shape::Sphere,
+                    material_cube.clone(),
+                    Transform::translate(coord) * scale_dot,
+                ).with_visibility(Visibility::CAMERA_ONLY));
             }
         }
     }
@@ -196,23 +439,11 @@ pub fn objects_axes(brightness: f32, radius_axis: f32, radius_dot: Option<f32>,
 pub fn scene_single_red_sphere() -> Scene {
     Scene {
         objects: vec![
-            Object {
-                shape: Shape::Plane,
-                material: material_diffuse(color_by_name("grey")),
-                transform: Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0)),
-            },
-            Object {
-                shape: Shape::Sphere,
-                material: material_glass(Color::new(1.0, 0.1, 0.1)),
-                transform: Transform::translate(Vec3::new(0.0, 1.0, 0.0)),
-            },
-            Object {
-                shape: Shape::Sphere,
-                material: material_light(Color::new(1.0, 1.0, 1.0) * 1_000.0),
-                transform: Transform::translate(Vec3::new(10.0, 10.0, -5.0)),
-            },
+            ground_plane(material_diffuse(color_by_name("grey"))),
+            Object::new(Shape::Sphere, material_glass(Color::new(1.0, 0.1, 0.1)), Transform::translate(Vec3::new(0.0, 1.0, 0.0))),
+            Object::new(Shape::Sphere, material_light(Color::new(1.0, 1.0, 1.0), 1_000.0), Transform::translate(Vec3::new(10.0, 10.0, -5.0))),
         ],
-        sky_emission: color_by_name("gray"),
+        sky: Sky::Uniform(color_by_name("gray")),
         camera: Camera {
             fov_horizontal: Angle::degrees(90.0),
             transform: Transform::look_at(
@@ -229,35 +460,19 @@ pub fn scene_colored_spheres() -> Scene {
     Scene {
         objects: vec![
             //light
-            Object {
-                shape: Shape::Sphere,
-                material: material_light(Color::new(1.0, 1.0, 1.0) * 500.0),
-                transform: Transform::scale(3.0) * Transform::translate(Vec3::new(10.0, 20.0, -10.0)),
-            },
+            Object::new(
+                Shape::Sphere,
+                material_light(Color::new(1.0, 1.0, 1.0), 500.0),
+                Transform::scale(3.0) * Transform::translate(Vec3::new(10.0, 20.0, -10.0)),
+            ),
             //floor
-            Object {
-                shape: Shape::Plane,
-                material: material_diffuse(Color::new(0.9, 0.9, 0.9)),
-                transform: Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0)),
-            },
+            ground_plane(material_diffuse(Color::new(0.9, 0.9, 0.9))),
             //spheres
-            Object {
-                shape: Shape::Sphere,
-                material: material_mixed(Color::new(1.0, 0.05, 0.05), 0.5),
-                transform: Transform::translate(Vec3::new(-3.0, 1.0, -5.0)),
-            },
-            Object {
-                shape: Shape::Sphere,
-                material: material_glass(Color::new(0.4, 0.4, 1.0)),
-                transform: Transform::translate(Vec3::new(0.0, 1.0, -5.0)),
-            },
-            Object {
-                shape: Shape::Sphere,
-                material: material_mixed(Color::new(0.05, 1.0, 0.05), 0.5),
-                transform: Transform::translate(Vec3::new(3.0, 1.0, -5.0)),
-            },
+            Object::new(Shape::Sphere, material_mixed(Color::new(1.0, 0.05, 0.05), 0.5), Transform::translate(Vec3::new(-3.0, 1.0, -5.0))),
+            Object::new(Shape::Sphere, material_glass(Color::new(0.4, 0.4, 1.0)), Transform::translate(Vec3::new(0.0, 1.0, -5.0))),
+            Object::new(Shape::Sphere, material_mixed(Color::new(0.05, 1.0, 0.05), 0.5), Transform::translate(Vec3::new(3.0, 1.0, -5.0))),
         ],
-        sky_emission: color_gray(0.1),
+        sky: Sky::Uniform(color_gray(0.1)),
         camera: Camera {
             fov_horizontal: Angle::degrees(90.0),
             transform: Transform::look_at(
@@ -270,20 +485,143 @@ pub fn scene_colored_spheres() -> Scene {
     }
 }
 
-pub fn scene_obj_file(path: impl AsRef<Path>, transform: Transform) -> Scene {
+/// A row of red plastic spheres with increasingly shiny highlights from left to right, see [material_phong].
+pub fn scene_phong_sweep() -> Scene {
+    let shininess = [1.0, 10.0, 100.0, 1000.0, 10000.0];
+
     let mut objects = vec![
-        // floor
-        Object {
-            shape: Shape::Plane,
-            material: material_diffuse(color_by_name("grey")),
-            transform: Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0)),
+        Object::new(Shape::Sphere, material_light(WHITE, 500.0), Transform::translate(Vec3::new(-4.0, 8.0, 6.0)) * Transform::scale(0.3)),
+        ground_plane(material_diffuse(color_gray(0.5))),
+    ];
+    for (i, &shininess) in shininess.iter().enumerate() {
+        objects.push(Object::new(
+            Shape::Sphere,
+            material_phong(Color::new(0.8, 0.1, 0.1), shininess),
+            Transform::translate(Vec3::new(2.5 * (i as f32 - 2.0), 1.0, 0.0)),
+        ));
+    }
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(color_gray(0.2)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 3.0, 12.0),
+                Point3::new(0.0, 1.0, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
         },
-        // light
-        Object {
-            shape: Shape::Sphere,
-            material: material_light(WHITE * 1000.0),
-            transform: Transform::scale(3.0) * Transform::translate(Vec3::new(10.0, 20.0, 10.0)),
+    }
+}
+
+/// A row of spheres made of the preset metals, gold, copper, silver, aluminum and iron from left to right,
+/// on a checkered floor under a gradient sky. See [material_conductor].
+pub fn scene_metals() -> Scene {
+    let metals = [METAL_GOLD, METAL_COPPER, METAL_SILVER, METAL_ALUMINUM, METAL_IRON];
+
+    let mut objects = vec![ground_plane(material_checker(color_gray(0.7), color_gray(0.2), 0.5))];
+    for (i, &reflectance) in metals.iter().enumerate() {
+        objects.push(Object::new(Shape::Sphere, material_conductor(reflectance), Transform::translate(Vec3::new(2.5 * (i as f32 - 2.0), 1.0, 0.0))));
+    }
+
+    Scene {
+        objects,
+        sky: Sky::Gradient {
+            horizon: Color::new(0.9, 0.8, 0.7),
+            zenith: Color::new(0.1, 0.3, 0.8),
+        },
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 3.0, 12.0),
+                Point3::new(0.0, 1.0, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A Lambertian sphere on the left and a rough Oren-Nayar sphere on the right, lit by a grazing light from behind the camera.
+/// The rough sphere stays brighter towards its edge, like the full moon.
+pub fn scene_oren_nayar() -> Scene {
+    let objects = vec![
+        Object::new(Shape::Sphere, material_light(WHITE, 2000.0), Transform::translate(Vec3::new(-10.0, 2.0, 10.0)) * Transform::scale(0.3)),
+        ground_plane(material_diffuse(color_gray(0.5))),
+        Object::new(Shape::Sphere, material_diffuse(color_gray(0.8)), Transform::translate(Vec3::new(-1.2, 1.0, 0.0))),
+        Object::new(Shape::Sphere, material_oren_nayar(color_gray(0.8), 1.0), Transform::translate(Vec3::new(1.2, 1.0, 0.0))),
+    ];
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(color_gray(0.02)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(40.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 1.5, 8.0),
+                Point3::new(0.0, 1.0, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A room lit by a `count` by `count` grid of small square lights on the ceiling, with a few spheres on the floor.
+/// The lights are the first `count * count` objects, sample them as a single light with
+/// [LightSelection::Grouped](crate::cpu::LightSelection::Grouped) to keep the cost independent of the number of lights.
+pub fn scene_light_grid(count: usize) -> Scene {
+    let spacing = 8.0 / count as f32;
+    let mut objects = vec![];
+
+    for i in 0..count {
+        for j in 0..count {
+            let x = (i as f32 - (count - 1) as f32 / 2.0) * spacing;
+            let z = (j as f32 - (count - 1) as f32 / 2.0) * spacing;
+            objects.push(Object::new(
+                Shape::Square,
+                material_light(color_by_name("wheat"), 2000.0 / (count * count) as f32),
+                Transform::translate(Vec3::new(x - 0.05, 4.0, z - 0.05))
+                    * Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0))
+                    * Transform::scale(0.1),
+            ));
+        }
+    }
+
+    objects.push(ground_plane(material_diffuse(color_gray(0.5))));
+    for (i, color) in ["red", "green", "blue"].into_iter().enumerate() {
+        objects.push(Object::new(
+            Shape::Sphere,
+            material_diffuse(color_by_name(color)),
+            Transform::translate(Vec3::new(2.5 * (i as f32 - 1.0), 1.0, 0.0)),
+        ));
+    }
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(BLACK),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(70.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.5, 8.0),
+                Point3::new(0.0, 1.5, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
         },
+    }
+}
+
+/// The first object in the given obj file on a floor, `convention` is the coordinate convention of the file,
+/// see [CoordinateConvention]. Also returns the number of degenerate triangles that were skipped, see [obj_to_triangles].
+pub fn scene_obj_file(path: impl AsRef<Path>, convention: CoordinateConvention, transform: Transform) -> (Scene, usize) {
+    let mut objects = vec![
+        // floor
+        ground_plane(material_diffuse(color_by_name("grey"))),
+        // light
+        Object::new(Shape::Sphere, material_light(WHITE, 1000.0), Transform::scale(3.0) * Transform::translate(Vec3::new(10.0, 20.0, 10.0))),
     ];
 
     let obj_string = read_to_string(path)
@@ -294,11 +632,12 @@ pub fn scene_obj_file(path: impl AsRef<Path>, transform: Transform) -> Scene {
         .expect("No object found");
 
     let material_cube = material_diffuse(color_by_name("grey"));
-    objects.extend(obj_to_triangles(cube, material_cube, transform));
+    let (triangles, degenerate) = obj_to_triangles(cube, material_cube, convention, transform);
+    objects.extend(triangles);
 
-    Scene {
+    let scene = Scene {
         objects,
-        sky_emission: color_by_name("gray"),
+        sky: Sky::Uniform(color_by_name("gray")),
         camera: Camera {
             fov_horizontal: Angle::degrees(90.0),
             transform: Transform::look_at(
@@ -308,20 +647,20 @@ pub fn scene_obj_file(path: impl AsRef<Path>, transform: Transform) -> Scene {
             ),
             medium: VACUUM,
         },
-    }
+    };
+    (scene, degenerate)
 }
 
 pub fn scene_random_tiles() -> Scene {
-    let mut objects = vec![];
-    let rng = &mut SmallRng::seed_from_u64(0);
+    scene_random_tiles_with(100_000, 0, true)
+}
 
-    objects.push(Object {
-        shape: Shape::Sphere,
-        material: material_light(WHITE * 10000.0),
-        transform: Transform::translate(Vec3::new(0.0, 0.0, 100.0)),
-    });
+/// Randomly placed and rotated squares lit by a single light, useful for benchmarking the acceleration structures.
+/// Each tile uses its own rng derived from `seed` and its index, so the scene is the same whether or not it's generated in `parallel`.
+pub fn scene_random_tiles_with(tile_count: usize, seed: u64, parallel: bool) -> Scene {
+    let random_tile = |i: usize| {
+        let rng = &mut SmallRng::seed_from_u64(seed ^ (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
 
-    for _ in 0..100_000 {
         let trans = Vec3::new(
             rng.gen_range(-100.0..100.0),
             rng.gen_range(-100.0..100.0),
@@ -333,18 +672,22 @@ pub fn scene_random_tiles() -> Scene {
 
         let scale = rng.gen_range(0.5..2.0);
 
-        let transform = Transform::translate(trans) * Transform::rotate(rot_axis, rot_angle) * Transform::scale(scale);
+        let transform = Transform::trs(trans, (rot_axis, rot_angle), Vec3::new(scale, scale, scale));
 
-        objects.push(Object {
-            shape: Shape::Square,
-            material: material_diffuse(WHITE),
-            transform,
-        });
+        Object::new(Shape::Square, material_diffuse(WHITE), transform)
+    };
+
+    let mut objects = vec![Object::new(Shape::Sphere, material_light(WHITE, 10000.0), Transform::translate(Vec3::new(0.0, 0.0, 100.0)))];
+
+    if parallel {
+        objects.par_extend((0..tile_count).into_par_iter().map(random_tile));
+    } else {
+        objects.extend((0..tile_count).map(random_tile));
     }
 
     Scene {
         objects,
-        sky_emission: color_gray(0.01),
+        sky: Sky::Uniform(color_gray(0.01)),
         camera: Camera {
             fov_horizontal: Angle::degrees(90.0),
             transform: Transform::look_at(
@@ -374,16 +717,16 @@ pub fn scene_cornell_box() -> Scene {
         let y = (w * w - 4.0 * h * h) / (8.0 * h);
 
         println!("Sphere light with r={r}, y={y}");
-        objects.push(Object {
-            shape: Shape::Sphere,
-            material: material_light(light_color * 100.0),
-            transform: Transform::translate(Vec3::new(wall_size.x / 2.0, wall_size.y + y, wall_size.z / 2.0)) * Transform::scale(r),
-        });
+        objects.push(Object::new(
+            Shape::Sphere,
+            material_light(light_color, 100.0),
+            Transform::translate(Vec3::new(wall_size.x / 2.0, wall_size.y + y, wall_size.z / 2.0)) * Transform::scale(r),
+        ));
     }
 
     let mut push_triangle = |a: Point3, b: Point3, c: Point3, material: Material| {
         let transform = triangle_as_transform(a, b, c);
-        let object = Object { shape: Shape::Triangle, material, transform };
+        let object = Object::new(Shape::Triangle, material, transform);
         objects.push(object);
     };
 
@@ -396,7 +739,7 @@ pub fn scene_cornell_box() -> Scene {
         // let dx = Vec3::new(size / 2.0, 0.0, 0.0);
         // let dz = Vec3::new(0.0, 0.0, size / 2.0);
 
-        // let material = material_light(light_color * 10.0);
+        // let material = material_light(light_color, 10.0);
 
         // TODO support triangle lights
         // push_triangle(center - dx - dz, center - dx + dz, center + dx + dz, material);
@@ -416,8 +759,8 @@ pub fn scene_cornell_box() -> Scene {
             Point3::new(0.0, wall_size.y, wall_size.z),
         ];
 
-        let mut push_int_triangle = |a: usize, b: usize, c: usize, material: Material| {
-            push_triangle(corners[a], corners[b], corners[c], material);
+        let mut push_int_triangle = |a: usize, b: usize, c: usize, material: &Material| {
+            push_triangle(corners[a], corners[b], corners[c], material.clone());
         };
 
         let wall_gray = material_diffuse(color_gray(0.4));
@@ -425,26 +768,26 @@ pub fn scene_cornell_box() -> Scene {
         let wall_red = material_diffuse(Color::new(0.0, 0.5, 0.0));
 
         //  top
-        push_int_triangle(3, 2, 6, wall_gray);
-        push_int_triangle(3, 6, 7, wall_gray);
+        push_int_triangle(3, 2, 6, &wall_gray);
+        push_int_triangle(3, 6, 7, &wall_gray);
         // bottom
-        push_int_triangle(0, 1, 5, wall_gray);
-        push_int_triangle(0, 5, 4, wall_gray);
+        push_int_triangle(0, 1, 5, &wall_gray);
+        push_int_triangle(0, 5, 4, &wall_gray);
         // back
-        push_int_triangle(0, 1, 2, wall_gray);
-        push_int_triangle(0, 2, 3, wall_gray);
+        push_int_triangle(0, 1, 2, &wall_gray);
+        push_int_triangle(0, 2, 3, &wall_gray);
         // left
-        push_int_triangle(0, 3, 7, wall_green);
-        push_int_triangle(0, 7, 4, wall_green);
+        push_int_triangle(0, 3, 7, &wall_green);
+        push_int_triangle(0, 7, 4, &wall_green);
         // right
-        push_int_triangle(1, 2, 6, wall_red);
-        push_int_triangle(1, 6, 5, wall_red);
+        push_int_triangle(1, 2, 6, &wall_red);
+        push_int_triangle(1, 6, 5, &wall_red);
     }
 
     // boxes
     let material_box = material_diffuse(color_gray(0.5));
     objects.extend(objects_cuboid(
-        material_box,
+        material_box.clone(),
         Vec3::new(0.165, 0.165, 0.165),
         Transform::translate(Vec3::new(0.37035, 0.165 / 2.0, 0.38669)) * Transform::rotate(Vec3::y_axis(), Angle::degrees(-106.0)),
     ));
@@ -456,11 +799,831 @@ pub fn scene_cornell_box() -> Scene {
 
     Scene {
         objects,
-        sky_emission: BLACK,
+        sky: Sky::Uniform(BLACK),
         camera: Camera {
             fov_horizontal: Angle::degrees(36.0),
             transform: Transform::look_in_dir(Point3::new(wall_size.x / 2.0, wall_size.y / 2.0, 1.35), -Vec3::z_axis(), Vec3::y_axis()),
             medium: VACUUM,
         },
     }
-}
\ No newline at end of file
+}
+
+/// Square foliage cards with an alpha cutout texture, casting dappled shadows on the floor.
+pub fn scene_foliage() -> Scene {
+    let mut objects = vec![
+        // floor
+        ground_plane(material_diffuse(color_gray(0.8))),
+        // sun
+        Object::new(Shape::Sphere, material_light(WHITE, 2000.0), Transform::translate(Vec3::new(-10.0, 30.0, 10.0))),
+    ];
+
+    let rng = &mut SmallRng::seed_from_u64(0);
+    for i in 0..12 {
+        let material = Material {
+            alpha: Some(texture_leaves(i, 12)),
+            ..material_diffuse(Color::new(0.1, 0.5, 0.05))
+        };
+
+        let trans = Vec3::new(rng.gen_range(-2.0..2.0), rng.gen_range(2.0..3.5), rng.gen_range(-3.0..1.0));
+        let rot_axis = Unit::new_unchecked(Vec3::from_slice(&UnitSphere.sample(rng)));
+        let rot_angle = Angle::degrees(rng.gen_range(0.0..360.0));
+
+        objects.push(Object::new(Shape::Square, material, Transform::trs(trans, (rot_axis, rot_angle), Vec3::new(2.0, 2.0, 2.0))));
+    }
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(color_gray(0.2)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(90.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 1.5, 5.0),
+                Point3::new(0.0, 1.5, -1.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// Some spheres on a floor lit by an environment map with a bright sun, which needs importance sampling to converge.
+pub fn scene_sun_sky() -> Scene {
+    let sun_direction = Vec3::new(-1.0, 1.0, 0.5).normalized();
+    let sky = texture_sun_sky(sun_direction, Angle::degrees(1.0));
+
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.6))),
+            Object::new(Shape::Sphere, material_diffuse(Color::new(0.8, 0.2, 0.2)), Transform::translate(Vec3::new(-2.5, 1.0, 0.0))),
+            Object::new(Shape::Sphere, material_mixed(WHITE, 0.2), Transform::translate(Vec3::new(0.0, 1.0, 0.0))),
+            Object::new(Shape::Sphere, material_glass(WHITE), Transform::translate(Vec3::new(2.5, 1.0, 0.0))),
+        ],
+        sky: Sky::Environment(Arc::new(sky)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(70.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.0, 7.0),
+                Point3::new(0.0, 1.0, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A pillar and some spheres on a floor under an analytic daytime sky, see [Sky::Analytic].
+/// The low sun casts long hard shadows, which the blue sky fills in softly.
+pub fn scene_daylight() -> Scene {
+    let sphere = |x: f32, z: f32, radius: f32, material: Material| Object::new(
+        Shape::Sphere,
+        material,
+        Transform::translate(Vec3::new(x, radius, z)) * Transform::scale(radius),
+    );
+
+    let mut objects = vec![
+        ground_plane(material_diffuse(color_gray(0.6))),
+        sphere(-2.0, 0.5, 0.8, material_diffuse(Color::new(0.8, 0.3, 0.2))),
+        sphere(0.3, 1.2, 0.6, material_mixed(WHITE, 0.1)),
+        sphere(2.2, 0.0, 0.7, material_diffuse(color_gray(0.8))),
+    ];
+    objects.extend(objects_cuboid(
+        material_diffuse(color_gray(0.7)),
+        Vec3::new(0.6, 3.0, 0.6),
+        Transform::translate(Vec3::new(0.5, 1.5, -1.5)),
+    ));
+
+    Scene {
+        objects,
+        sky: Sky::Analytic { sun_direction: Vec3::new(-1.0, 0.6, 0.3).normalized(), turbidity: 3.0 },
+        camera: Camera {
+            fov_horizontal: Angle::degrees(70.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.5, 7.0),
+                Point3::new(0.0, 0.8, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A mirror sphere and a diffuse sphere in front of a flat gray backdrop, lit by the environment map of [scene_sun_sky].
+/// The mirror shows the environment while the camera only sees the backdrop behind the spheres, see [Sky::Backdrop].
+pub fn scene_backdrop() -> Scene {
+    let sun_direction = Vec3::new(-1.0, 1.0, 0.5).normalized();
+    let environment = texture_sun_sky(sun_direction, Angle::degrees(1.0));
+
+    Scene {
+        objects: vec![
+            Object::new(
+                Shape::Sphere,
+                Material { material_type: MaterialType::Mirror, ..material_diffuse(WHITE) },
+                Transform::translate(Vec3::new(-1.2, 0.0, 0.0)),
+            ),
+            Object::new(Shape::Sphere, material_diffuse(Color::new(0.8, 0.2, 0.2)), Transform::translate(Vec3::new(1.2, 0.0, 0.0))),
+        ],
+        sky: Sky::Backdrop {
+            background: color_gray(0.5),
+            lighting: Box::new(Sky::Environment(Arc::new(environment))),
+        },
+        camera: Camera {
+            fov_horizontal: Angle::degrees(50.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 1.0, 6.0),
+                Point3::new(0.0, 0.0, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// Glass cubes with different tints and sizes, showing how the color saturates with thickness.
+pub fn scene_tinted_glass() -> Scene {
+    let mut objects = vec![
+        ground_plane(material_diffuse(color_gray(0.8))),
+        Object::new(Shape::Sphere, material_light(WHITE, 1000.0), Transform::translate(Vec3::new(5.0, 20.0, 10.0))),
+    ];
+
+    let cubes = [
+        (Color::new(0.9, 0.3, 0.3), 0.5, -3.0),
+        (Color::new(0.3, 0.9, 0.3), 1.0, -1.0),
+        (Color::new(0.3, 0.3, 0.9), 1.5, 1.2),
+        (volumetric_color_at_depth(Color::new(0.9, 0.8, 0.2), 2.0), 2.0, 3.8),
+    ];
+    for (color, size, x) in cubes {
+        objects.extend(objects_cuboid(
+            material_glass_tinted(color, GLASS_IOR),
+            Vec3::new(size, size, size),
+            Transform::translate(Vec3::new(x, size / 2.0 + 0.01, 0.0)) * Transform::rotate(Vec3::y_axis(), Angle::degrees(30.0)),
+        ));
+    }
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(color_gray(0.3)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(70.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 3.0, 8.0),
+                Point3::new(0.5, 0.5, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A ground plane with some spheres placed far away from the origin.
+/// The floating point error on hit points this far out is larger than any small fixed offset,
+/// which causes shadow acne unless new rays are offset relative to the scale of the hit.
+pub fn scene_far_ground() -> Scene {
+    let origin = Vec3::new(20_000.0, 5_000.0, -30_000.0);
+    let at = |x: f32, y: f32, z: f32| Transform::translate(origin + Vec3::new(x, y, z));
+
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.8))),
+            Object::new(Shape::Sphere, material_diffuse(Color::new(0.8, 0.2, 0.2)), at(-2.5, 1.0, 0.0)),
+            Object::new(Shape::Sphere, material_diffuse(Color::new(0.2, 0.8, 0.2)), at(0.0, 1.0, -3.0)),
+            Object::new(Shape::Sphere, material_diffuse(Color::new(0.2, 0.2, 0.8)), at(2.5, 1.0, 0.0)),
+            Object::new(Shape::Sphere, material_light(WHITE, 2000.0), at(-10.0, 30.0, 10.0)),
+        ],
+        sky: Sky::Uniform(color_gray(0.2)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(70.0),
+            transform: Transform::look_at(
+                Point3::from_coords(origin + Vec3::new(0.0, 3.0, 10.0)),
+                Point3::from_coords(origin + Vec3::new(0.0, 1.0, 0.0)),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A glass sphere on a floor lit by a small light, casting a bright caustic.
+/// Best rendered with [Strategy::LightTracing](crate::cpu::Strategy::LightTracing), the other strategies converge very slowly.
+pub fn scene_caustic() -> Scene {
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.8))),
+            Object::new(Shape::Sphere, material_glass(WHITE), Transform::translate(Vec3::new(0.0, 1.5, 0.0))),
+            Object::new(Shape::Sphere, material_light(WHITE, 5000.0), Transform::translate(Vec3::new(1.5, 8.0, 0.0)) * Transform::scale(0.1)),
+        ],
+        sky: Sky::Uniform(color_gray(0.05)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 4.0, 7.0),
+                Point3::new(0.0, 0.5, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A glass prism with exaggerated dispersion splitting the light of a small white light into a rainbow on the floor.
+/// Needs [CpuRenderSettings::spectral](crate::cpu::CpuRenderSettings::spectral) to show the dispersion,
+/// and is best rendered with [Strategy::LightTracing](crate::cpu::Strategy::LightTracing).
+pub fn scene_prism() -> Scene {
+    let glass = Material {
+        inside: Medium {
+            dispersion: 0.05,
+            ..medium_glass(WHITE)
+        },
+        ..material_glass(WHITE)
+    };
+
+    let mut objects = vec![
+        ground_plane(material_diffuse(color_gray(0.8))),
+        Object::new(Shape::Sphere, material_light(WHITE, 20000.0), Transform::translate(Vec3::new(-6.0, 0.2, 0.0)) * Transform::scale(0.05)),
+    ];
+    objects.extend(objects_prism(glass, Transform::translate(Vec3::new(0.0, 1.0, 0.0)) * Transform::scale(1.5)));
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(color_gray(0.02)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(1.0, 5.0, 6.0),
+                Point3::new(2.0, 0.0, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A few tori with different tube radii and materials on a floor.
+pub fn scene_tori() -> Scene {
+    let torus = |tube_radius: f32, material: Material, transform: Transform| Object::new(Shape::Torus { tube_radius }, material, transform);
+
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.8))),
+            torus(
+                0.3,
+                material_diffuse(Color::new(0.8, 0.2, 0.1)),
+                Transform::translate(Vec3::new(-2.5, 0.3, 0.0)),
+            ),
+            torus(
+                0.1,
+                material_mixed(color_gray(0.9), 0.2),
+                Transform::translate(Vec3::new(0.0, 1.0, 0.0)) * Transform::rotate(Vec3::x_axis(), Angle::degrees(70.0)),
+            ),
+            torus(
+                0.4,
+                material_glass(WHITE),
+                Transform::trs(Vec3::new(2.5, 1.0, 0.0), (Vec3::z_axis(), Angle::degrees(90.0)), Vec3::new(0.8, 0.8, 0.8)),
+            ),
+            Object::new(Shape::Sphere, material_light(WHITE, 200.0), Transform::translate(Vec3::new(-1.0, 6.0, 3.0)) * Transform::scale(0.3)),
+        ],
+        sky: Sky::Uniform(color_gray(0.1)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 3.0, 7.0),
+                Point3::new(0.0, 0.7, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// The Mandelbulb fractal with the usual `power` of 8, using the distance estimator derived from the running derivative.
+/// More `iterations` give finer detail, but also more noise in the normals of the smallest details.
+pub fn sdf_mandelbulb(power: f32, iterations: u32) -> Sdf {
+    Sdf::new(1.2, move |point| {
+        let c = point.coords();
+        let mut z = c;
+        let mut derivative = 1.0;
+        let mut r = z.norm();
+
+        for _ in 0..iterations {
+            if r > 2.0 || r == 0.0 {
+                break;
+            }
+
+            // raise z to the power in spherical coordinates
+            let theta = (z.z / r).acos() * power;
+            let phi = z.y.atan2(z.x) * power;
+            derivative = power * r.powf(power - 1.0) * derivative + 1.0;
+
+            z = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) * r.powf(power) + c;
+            r = z.norm();
+        }
+
+        0.5 * r.ln() * r / derivative
+    })
+}
+
+/// A golden Mandelbulb floating above a gray floor, lit by a small bright light. See [sdf_mandelbulb].
+pub fn scene_mandelbulb() -> Scene {
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.5))),
+            Object::new(
+                Shape::Sdf(sdf_mandelbulb(8.0, 10)),
+                material_diffuse(Color::new(0.9, 0.6, 0.2)),
+                Transform::translate(Vec3::new(0.0, 1.3, 0.0)) * Transform::rotate(Vec3::x_axis(), Angle::degrees(-90.0)),
+            ),
+            Object::new(Shape::Sphere, material_light(WHITE, 2000.0), Transform::translate(Vec3::new(-6.0, 10.0, 8.0)) * Transform::scale(0.5)),
+        ],
+        sky: Sky::Uniform(Color::new(0.1, 0.15, 0.25)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(40.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.0, 8.0),
+                Point3::new(0.0, 1.2, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A dark room lit only by a "monitor", an emissive square showing a texture.
+pub fn scene_monitor() -> Scene {
+    let screen = Material {
+        emission_texture: Some(texture_color_bars()),
+        ..material_light(WHITE, 4.0)
+    };
+
+    let mut objects = vec![
+        ground_plane(material_diffuse(color_gray(0.8))),
+        Object::new(
+            Shape::Square,
+            screen,
+            triangle_as_transform(
+                Point3::new(-0.8, 0.3, -1.2),
+                Point3::new(0.8, 0.3, -1.2),
+                Point3::new(-0.8, 1.2, -1.2),
+            ),
+        ),
+        Object::new(Shape::Sphere, material_diffuse(color_gray(0.9)), Transform::translate(Vec3::new(-0.5, 0.3, 0.2)) * Transform::scale(0.3)),
+        Object::new(Shape::Sphere, material_mixed(color_gray(0.9), 0.3), Transform::translate(Vec3::new(0.5, 0.3, 0.2)) * Transform::scale(0.3)),
+    ];
+    objects.extend(objects_cuboid(
+        material_diffuse(color_gray(0.05)),
+        Vec3::new(1.7, 1.0, 0.05),
+        Transform::translate(Vec3::new(0.0, 0.75, -1.23)),
+    ));
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(BLACK),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 1.2, 3.0),
+                Point3::new(0.0, 0.5, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// Two intersecting glass spheres with different indices of refraction and colors.
+pub fn scene_overlapping_glass() -> Scene {
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.8))),
+            Object::new(Shape::Sphere, material_glass(WHITE), Transform::translate(Vec3::new(-0.5, 1.0, 0.0))),
+            Object::new(Shape::Sphere, material_glass_tinted(Color::new(0.3, 0.6, 0.9), 1.3), Transform::translate(Vec3::new(0.5, 1.0, 0.0))),
+            Object::new(Shape::Sphere, material_light(WHITE, 300.0), Transform::translate(Vec3::new(2.0, 6.0, 2.0)) * Transform::scale(0.3)),
+        ],
+        sky: Sky::Uniform(color_gray(0.2)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.0, 5.0),
+                Point3::new(0.0, 0.8, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A square area light on the ceiling above a diffuse floor, the cube casts a soft shadow.
+pub fn scene_area_light() -> Scene {
+    let mut objects = vec![
+        ground_plane(material_diffuse(color_gray(0.8))),
+        Object::new(
+            Shape::Square,
+            material_light(WHITE, 20.0),
+            Transform::translate(Vec3::new(-0.5, 2.0, -0.5)) * Transform::rotate(Vec3::x_axis(), Angle::degrees(90.0)),
+        ),
+        Object::new(
+            Shape::Sphere,
+            material_diffuse(Color::new(0.8, 0.3, 0.3)),
+            Transform::translate(Vec3::new(0.8, 0.3, 0.4)) * Transform::scale(0.3),
+        ),
+    ];
+    objects.extend(objects_cuboid(
+        material_diffuse(color_gray(0.9)),
+        Vec3::new(0.5, 0.5, 0.5),
+        Transform::translate(Vec3::new(-0.4, 0.25, 0.2)),
+    ));
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(BLACK),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 1.8, 3.5),
+                Point3::new(0.0, 0.4, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A dark floor lit by a grid of small colored lights with very different brightnesses, a good test for
+/// [LightSampling::Reservoir](crate::cpu::LightSampling::Reservoir).
+pub fn scene_many_lights(seed: u64) -> Scene {
+    let rng = &mut SmallRng::seed_from_u64(seed);
+
+    let mut objects = vec![ground_plane(material_diffuse(color_gray(0.8)))];
+    for x in -3..=3 {
+        for z in -3..=3 {
+            let color = Color::new(rng.gen_range(0.2..1.0), rng.gen_range(0.2..1.0), rng.gen_range(0.2..1.0));
+            let brightness = 10f32.powf(rng.gen_range(1.0..3.0));
+            let position = Vec3::new(x as f32, rng.gen_range(0.2..1.0), z as f32);
+
+            objects.push(Object::new(Shape::Sphere, material_light(color, brightness), Transform::translate(position) * Transform::scale(0.03)));
+        }
+    }
+    objects.extend(objects_cuboid(
+        material_diffuse(color_gray(0.9)),
+        Vec3::new(0.6, 0.6, 0.6),
+        Transform::translate(Vec3::new(0.5, 0.3, 0.5)),
+    ));
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(BLACK),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 3.0, 6.0),
+                Point3::new(0.0, 0.0, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A sphere of glowing fog lighting up the floor around it, next to a glass sphere.
+pub fn scene_glowing_fog() -> Scene {
+    let fog = Material {
+        inside: Medium {
+            index_of_refraction: 1.0,
+            volumetric_color: volumetric_color_at_depth(Color::new(0.6, 0.4, 0.3), 2.0),
+            dispersion: 0.0,
+            emission: Color::new(1.0, 0.5, 0.2) * 2.0,
+        },
+        ..material_glass(WHITE)
+    };
+
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.8))),
+            Object::new(Shape::Sphere, fog, Transform::translate(Vec3::new(-0.5, 1.0, 0.0))),
+            Object::new(Shape::Sphere, material_glass(WHITE), Transform::translate(Vec3::new(1.2, 0.5, 0.5)) * Transform::scale(0.5)),
+        ],
+        sky: Sky::Uniform(color_gray(0.02)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.0, 5.0),
+                Point3::new(0.0, 0.8, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// Spheres on the bottom of a pool of blue-green water, seen by a camera that's submerged in it.
+/// The camera medium is left at vacuum, the renderer finds out that the camera is inside of the water itself.
+pub fn scene_underwater() -> Scene {
+    let water = Material {
+        inside: Medium {
+            index_of_refraction: 1.33,
+            volumetric_color: volumetric_color_at_depth(Color::new(0.2, 0.6, 0.7), 4.0),
+            dispersion: 0.0,
+            emission: BLACK,
+        },
+        ..material_glass(WHITE)
+    };
+
+    let mut objects = vec![ground_plane(material_diffuse(Color::new(0.8, 0.7, 0.5)))];
+
+    let spheres = [
+        (Color::new(0.9, 0.3, 0.2), -1.5, -1.0),
+        (Color::new(0.9, 0.9, 0.9), 0.5, -3.0),
+        (Color::new(0.9, 0.8, 0.2), 2.5, -6.0),
+    ];
+    for (color, x, z) in spheres {
+        objects.push(Object::new(Shape::Sphere, material_diffuse(color), Transform::translate(Vec3::new(x, 0.6, z)) * Transform::scale(0.6)));
+    }
+
+    // the bottom of the water is below the ground, so only the surface and the far sides are ever crossed
+    // shadow rays pass through the water, otherwise the light could only reach the bottom as noisy caustics
+    let surface = objects_cuboid(water, Vec3::new(40.0, 4.5, 40.0), Transform::translate(Vec3::new(0.0, 1.75, 0.0)));
+    objects.extend(surface.into_iter().map(|object| object.with_visibility(Visibility { shadow: false, ..Visibility::ALL })));
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(Color::new(1.0, 1.2, 1.5)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(70.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.0, 4.0),
+                Point3::new(0.5, 0.8, -2.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// Cones standing on the floor: a diffuse one, an upside down open mirror one and a capped glass one.
+pub fn scene_cones() -> Scene {
+    // flip the cone so its apex points up and its base rests on the floor
+    let upright = Transform::translate(Vec3::new(0.0, 1.0, 0.0)) * Transform::rotate(Vec3::x_axis(), Angle::degrees(180.0));
+
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.8))),
+            Object::new(
+                Shape::Cone { capped: true },
+                material_diffuse(Color::new(0.8, 0.4, 0.2)),
+                Transform::translate(Vec3::new(-1.6, 0.0, 0.0)) * Transform::scale(0.6) * upright,
+            ),
+            Object::new(
+                Shape::Cone { capped: false },
+                material_mixed(color_gray(0.9), 0.2),
+                Transform::translate(Vec3::new(0.0, 0.0, -0.5)) * Transform::scale(0.8),
+            ),
+            Object::new(
+                Shape::Cone { capped: true },
+                material_glass(WHITE),
+                Transform::translate(Vec3::new(1.6, 0.0, 0.0)) * Transform::scale(0.6) * upright,
+            ),
+            Object::new(Shape::Sphere, material_light(WHITE, 300.0), Transform::translate(Vec3::new(2.0, 6.0, 4.0)) * Transform::scale(0.3)),
+        ],
+        sky: Sky::Uniform(color_gray(0.2)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.0, 5.0),
+                Point3::new(0.0, 0.5, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A mirror sphere reflecting a gradient sky above a diffuse floor.
+pub fn scene_gradient_sky() -> Scene {
+    Scene {
+        objects: vec![
+            ground_plane(material_checker(color_gray(0.7), color_gray(0.2), 1.0)),
+            Object::new(
+                Shape::Sphere,
+                Material { material_type: MaterialType::Mirror, ..material_diffuse(WHITE) },
+                Transform::translate(Vec3::new(0.0, 1.0, 0.0)),
+            ),
+            Object::new(
+                Shape::Sphere,
+                material_diffuse(Color::new(0.8, 0.3, 0.2)),
+                Transform::translate(Vec3::new(1.8, 0.5, 0.5)) * Transform::scale(0.5),
+            ),
+        ],
+        sky: Sky::Gradient {
+            horizon: Color::new(0.9, 0.8, 0.7),
+            zenith: Color::new(0.1, 0.3, 0.8),
+        },
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 1.5, 5.0),
+                Point3::new(0.0, 0.8, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A few spheres on a floor inside a huge emissive sphere, which lights them softly from every direction like an overcast sky.
+/// The dome is sampled as a light like the sky, see [Object::sample_enclosing](crate::common::scene::Object::sample_enclosing).
+pub fn scene_dome() -> Scene {
+    let sphere = |x: f32, radius: f32, material: Material| Object::new(
+        Shape::Sphere,
+        material,
+        Transform::translate(Vec3::new(x, radius, 0.0)) * Transform::scale(radius),
+    );
+
+    Scene {
+        objects: vec![
+            Object::new(Shape::Sphere, material_light(Color::new(0.8, 0.9, 1.0), 1.0), Transform::scale(100.0)),
+            ground_plane(material_diffuse(color_gray(0.6))),
+            sphere(-1.8, 0.6, material_diffuse(Color::new(0.8, 0.3, 0.2))),
+            sphere(0.0, 1.0, material_diffuse(color_gray(0.8))),
+            sphere(1.8, 0.6, material_glass(WHITE)),
+        ],
+        sky: Sky::Uniform(BLACK),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.0, 6.0),
+                Point3::new(0.0, 0.7, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A flat square with a [NormalMap] of domes lit from a low angle, the domes look raised and their shading follows
+/// the light as `light_angle` moves it around the square.
+pub fn scene_normal_map(light_angle: Angle) -> Scene {
+    let material = Material {
+        normal_map: Some(NormalMap { texture: texture_normal_domes(8), strength: 1.0 }),
+        ..material_diffuse(Color::new(0.8, 0.7, 0.6))
+    };
+    let light_position = Vec3::new(light_angle.radians.cos() * 4.0, 1.0, light_angle.radians.sin() * 4.0);
+
+    Scene {
+        objects: vec![
+            Object::new(
+                Shape::Square,
+                material,
+                Transform::rotate(Vec3::x_axis(), Angle::degrees(-90.0))
+                    * Transform::scale(4.0) * Transform::translate(Vec3::new(-0.5, -0.5, 0.0)),
+            ),
+            Object::new(Shape::Sphere, material_light(WHITE, 100.0), Transform::translate(light_position) * Transform::scale(0.2)),
+        ],
+        sky: Sky::Uniform(color_gray(0.02)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(50.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 4.0, 3.5),
+                Point3::new(0.0, 0.0, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A diffuse and a mirror sphere with the same sinusoidal [BumpMap], next to a smooth sphere for comparison.
+pub fn scene_bump_map() -> Scene {
+    let bump = BumpMap { height: texture_sine_bumps(16, 8), scale: 0.02 };
+    let sphere = |material: Material, x: f32| Object::new(Shape::Sphere, material, Transform::translate(Vec3::new(x, 1.0, 0.0)));
+
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.6))),
+            sphere(material_diffuse(Color::new(0.8, 0.3, 0.2)), -2.2),
+            sphere(Material { bump: Some(bump.clone()), ..material_diffuse(Color::new(0.8, 0.3, 0.2)) }, 0.0),
+            sphere(Material { material_type: MaterialType::Mirror, bump: Some(bump), ..material_diffuse(WHITE) }, 2.2),
+            Object::new(Shape::Sphere, material_light(WHITE, 200.0), Transform::translate(Vec3::new(-3.0, 6.0, 4.0)) * Transform::scale(0.3)),
+        ],
+        sky: Sky::Gradient {
+            horizon: Color::new(0.9, 0.8, 0.7),
+            zenith: Color::new(0.1, 0.3, 0.8),
+        },
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 2.0, 6.5),
+                Point3::new(0.0, 0.9, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// A few objects lit by a sun and a bright sky, standing on a shadow catcher ground that shows their shadows
+/// without bouncing any light back onto them, see [Visibility::CAMERA_ONLY].
+/// A hidden square above the scene casts an extra shadow on the ground without showing up itself.
+pub fn scene_shadow_catcher() -> Scene {
+
+    Scene {
+        objects: vec![
+            ground_plane(material_diffuse(color_gray(0.8))).with_visibility(Visibility::CAMERA_ONLY),
+            Object::new(Shape::Sphere, material_diffuse(Color::new(0.8, 0.3, 0.2)), Transform::translate(Vec3::new(-1.5, 1.0, 0.0))),
+            Object::new(Shape::Sphere, material_mixed(WHITE, 0.0), Transform::translate(Vec3::new(1.5, 1.0, 0.0))),
+            Object::new(Shape::Cone { capped: true }, material_diffuse(Color::new(0.2, 0.5, 0.8)), Transform::trs(Vec3::new(0.0, 1.2, 1.5), (Vec3::x_axis(), Angle::degrees(180.0)), Vec3::new(0.8, 1.2, 0.8))),
+            Object::new(
+                Shape::Square,
+                material_diffuse(BLACK),
+                Transform::trs(Vec3::new(-4.0, 6.0, 4.1), (Vec3::x_axis(), Angle::degrees(90.0)), Vec3::new(0.5, 0.5, 1.0)),
+            ).with_visibility(Visibility::HIDDEN),
+            Object::new(Shape::Sphere, material_light(WHITE, 400.0), Transform::translate(Vec3::new(-4.0, 8.0, 5.0)) * Transform::scale(0.3)),
+        ],
+        sky: Sky::Gradient {
+            horizon: Color::new(0.9, 0.8, 0.7),
+            zenith: Color::new(0.1, 0.3, 0.8),
+        },
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(
+                Point3::new(0.0, 3.0, 8.0),
+                Point3::new(0.0, 0.8, 0.0),
+                Vec3::y_axis(),
+            ),
+            medium: VACUUM,
+        },
+    }
+}
+
+/// The objects of [scene_shadow_catcher] with a [material_shadow_catcher] ground instead,
+/// render with [transparent_sky](crate::cpu::CpuRenderSettings::transparent_sky) to composite them over a photograph.
+pub fn scene_shadow_matte() -> Scene {
+    let mut scene = scene_shadow_catcher();
+    scene.objects[0] = ground_plane(material_shadow_catcher());
+    scene
+}
+
+/// The points of a point cloud drawn as small spheres in the given `style` and `material`, see [point_cloud_to_spheres],
+/// lit by the sky and a light above them. The camera looks at the center of the cloud from far enough away to see all of it.
+pub fn scene_point_cloud(points: &[CloudPoint], style: &PointCloudStyle, material: fn(Color) -> Material) -> Scene {
+    assert!(!points.is_empty(), "Point cloud is empty");
+
+    let bounds = points.iter()
+        .map(|p| AxisBox::new(p.position, p.position))
+        .reduce(AxisBox::combine)
+        .unwrap();
+    let center = Point3::origin() + (bounds.low.coords() + bounds.high.coords()) / 2.0;
+    let size = (bounds.high - bounds.low).norm().max(style.radius);
+
+    let mut objects = vec![Object::new(
+        Shape::Sphere,
+        material_light(WHITE, 50.0),
+        Transform::translate(center.coords() + Vec3::new(0.5, 2.0, 1.0) * size) * Transform::scale(0.2 * size),
+    )];
+    objects.extend(point_cloud_to_spheres(points, style, material));
+
+    Scene {
+        objects,
+        sky: Sky::Uniform(color_gray(0.2)),
+        camera: Camera {
+            fov_horizontal: Angle::degrees(60.0),
+            transform: Transform::look_at(center + Vec3::new(0.0, 0.5, 1.0) * size, center, Vec3::y_axis()),
+            medium: VACUUM,
+        },
+    }
+}
+
+pub type SceneFn = fn() -> Scene;
+
+/// The demo scenes by name, eg. for picking one on the command line.
+/// Scenes that take arguments are included with typical values, scenes that need external data are left out.
+pub const SCENES: &[(&str, SceneFn)] = &[
+    ("single-red-sphere", scene_single_red_sphere),
+    ("colored-spheres", scene_colored_spheres),
+    ("phong-sweep", scene_phong_sweep),
+    ("oren-nayar", scene_oren_nayar),
+    ("metals", scene_metals),
+    ("light-grid", || scene_light_grid(10)),
+    ("random-tiles", scene_random_tiles),
+    ("cornell", scene_cornell_box),
+    ("foliage", scene_foliage),
+    ("sun-sky", scene_sun_sky),
+    ("daylight", scene_daylight),
+    ("backdrop", scene_backdrop),
+    ("tinted-glass", scene_tinted_glass),
+    ("far-ground", scene_far_ground),
+    ("caustic", scene_caustic),
+    ("prism", scene_prism),
+    ("tori", scene_tori),
+    ("mandelbulb", scene_mandelbulb),
+    ("monitor", scene_monitor),
+    ("overlapping-glass", scene_overlapping_glass),
+    ("area-light", scene_area_light),
+    ("many-lights", || scene_many_lights(0)),
+    ("glowing-fog", scene_glowing_fog),
+    ("underwater", scene_underwater),
+    ("cones", scene_cones),
+    ("gradient-sky", scene_gradient_sky),
+    ("dome", scene_dome),
+    ("normal-map", || scene_normal_map(Angle::degrees(0.0))),
+    ("bump-map", scene_bump_map),
+    ("shadow-catcher", scene_shadow_catcher),
+    ("shadow-matte", scene_shadow_matte),
+];
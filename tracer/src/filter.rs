@@ -0,0 +1,168 @@
+use imgref::{ImgRef, ImgVec};
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use rayon::slice::ParallelSliceMut;
+
+use crate::common::math::Vec3;
+use crate::common::scene::Color;
+use crate::common::texture::luminance;
+
+/// The settings for [filter_bilateral].
+#[derive(Debug, Copy, Clone)]
+pub struct BilateralParams {
+    /// The radius of the square window of neighbours around each pixel, in pixels.
+    pub radius: u32,
+    /// The standard deviation of the gaussian falloff with the distance to the neighbour, in pixels.
+    pub sigma_spatial: f32,
+    /// How many standard deviations of the noise the luminance of a neighbour can differ before it's mostly ignored.
+    /// Higher values blur more, but also start to smear out shadows and texture details.
+    pub sigma_color: f32,
+    /// The exponent on the cosine between the normals, higher values keep creases between surfaces sharper.
+    pub normal_power: f32,
+    /// The relative depth difference at which neighbours get a weight of `1/e`.
+    pub sigma_depth: f32,
+}
+
+impl Default for BilateralParams {
+    fn default() -> Self {
+        BilateralParams {
+            radius: 5,
+            sigma_spatial: 3.0,
+            sigma_color: 2.0,
+            normal_power: 32.0,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+/// Reduce the noise of a render with an edge-aware blur, a cheap alternative to a proper denoiser.
+///
+/// Each pixel becomes a weighted average of its neighbours. Neighbours only count if they see a similar surface,
+/// judged by the `normal` and `depth` guides from [guide_buffers](crate::cpu::CpuPreparedScene::guide_buffers),
+/// and if their luminance is within the noise of both pixels. Pixels with a low `variance` are mostly left alone,
+/// noisy pixels are averaged with their neighbours.
+///
+/// `variance` is the variance of the noise on `color` itself, for a render that's
+/// [PixelResult::variance](crate::common::progress::PixelResult::variance) divided by the number of samples.
+pub fn filter_bilateral(color: ImgRef<Color>, variance: ImgRef<Color>, normal: ImgRef<Vec3>, depth: ImgRef<f32>, params: BilateralParams) -> ImgVec<Color> {
+    let (width, height) = (color.width(), color.height());
+    for size in [(variance.width(), variance.height()), (normal.width(), normal.height()), (depth.width(), depth.height())] {
+        assert_eq!(size, (width, height), "All buffers must have the same size");
+    }
+
+    // estimates of the variance from a few samples are noisy themselves, so average them over a small window first
+    let noise = box_blur(ImgVec::new(variance.pixels().map(luminance).collect(), width, height).as_ref());
+
+    let radius = params.radius as usize;
+    let mut result = vec![Color::new(0.0, 0.0, 0.0); width * height];
+
+    // filter the scanlines in parallel
+    result.par_chunks_mut(width).enumerate().for_each(|(y, result_row)| {
+        for (x, result) in result_row.iter_mut().enumerate() {
+            let center_luminance = luminance(color[(x, y)]);
+
+            let mut sum = Color::new(0.0, 0.0, 0.0);
+            let mut total_weight = 0.0;
+
+            for qy in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                for qx in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                    let (dx, dy) = (qx as f32 - x as f32, qy as f32 - y as f32);
+                    let spatial = -(dx * dx + dy * dy) / (2.0 * params.sigma_spatial * params.sigma_spatial);
+
+                    let difference = center_luminance - luminance(color[(qx, qy)]);
+                    let allowed = 2.0 * params.sigma_color * params.sigma_color * (noise[(x, y)] + noise[(qx, qy)]);
+                    let similarity = -(difference * difference) / allowed.max(f32::MIN_POSITIVE);
+
+                    let weight = (spatial + similarity).exp() * geometry_weight(normal, depth, (x, y), (qx, qy), params);
+                    sum += color[(qx, qy)] * weight;
+                    total_weight += weight;
+                }
+            }
+
+            // the pixel itself always has weight one
+            *result = sum / total_weight;
+        }
+    });
+
+    ImgVec::new(result, width, height)
+}
+
+/// How similar the surfaces seen through the pixels `p` and `q` are, between zero and one.
+/// The sky only matches the sky.
+fn geometry_weight(normal: ImgRef<Vec3>, depth: ImgRef<f32>, p: (usize, usize), q: (usize, usize), params: BilateralParams) -> f32 {
+    let (depth_p, depth_q) = (depth[p], depth[q]);
+    if !depth_p.is_finite() || !depth_q.is_finite() {
+        return if depth_p.is_finite() == depth_q.is_finite() { 1.0 } else { 0.0 };
+    }
+
+    let normal_weight = normal[p].dot(normal[q]).max(0.0).powf(params.normal_power);
+    let depth_weight = (-(depth_p - depth_q).abs() / (params.sigma_depth * depth_p).max(f32::MIN_POSITIVE)).exp();
+    normal_weight * depth_weight
+}
+
+/// Average each value with its neighbours in a 3x3 window.
+fn box_blur(image: ImgRef<f32>) -> ImgVec<f32> {
+    let (width, height) = (image.width(), image.height());
+    let mut result = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sum, mut count) = (0.0, 0);
+            for qy in y.saturating_sub(1)..(y + 2).min(height) {
+                for qx in x.saturating_sub(1)..(x + 2).min(width) {
+                    sum += image[(qx, qy)];
+                    count += 1;
+                }
+            }
+            result.push(sum / count as f32);
+        }
+    }
+    ImgVec::new(result, width, height)
+}
+
+#[cfg(test)]
+mod test {
+    use imgref::ImgVec;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::SmallRng;
+
+    use crate::common::math::Vec3;
+    use crate::common::scene::Color;
+    use crate::filter::{BilateralParams, filter_bilateral};
+
+    #[test]
+    fn smooths_noise_but_keeps_edges() {
+        let (width, height) = (32, 16);
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        // two walls at a right angle meeting in the middle, the left one dark and the right one bright, both noisy
+        let noise = 0.1;
+        let is_left = |i: usize| i % width < width / 2;
+        let expected = |i: usize| if is_left(i) { 0.2 } else { 0.8 };
+        let color = (0..width * height)
+            .map(|i| {
+                let v = expected(i) + rng.gen_range(-1.0..1.0) * noise * 3f32.sqrt();
+                Color::new(v, v, v)
+            })
+            .collect();
+        let variance = vec![Color::new(noise * noise, noise * noise, noise * noise); width * height];
+        let normal = (0..width * height).map(|i| if is_left(i) { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 0.0, 1.0) }).collect();
+
+        let color = ImgVec::new(color, width, height);
+        let variance = ImgVec::new(variance, width, height);
+        let normal = ImgVec::new(normal, width, height);
+        let depth = ImgVec::new(vec![1.0; width * height], width, height);
+
+        let filtered = filter_bilateral(color.as_ref(), variance.as_ref(), normal.as_ref(), depth.as_ref(), BilateralParams::default());
+
+        let error = |image: &ImgVec<Color>| {
+            image.pixels().enumerate().map(|(i, c)| (c.red - expected(i)).powi(2)).sum::<f32>() / (width * height) as f32
+        };
+        let (before, after) = (error(&color), error(&filtered));
+        assert!(after < before / 10.0, "error before {}, after {}", before, after);
+
+        // the pixels next to the edge don't bleed into each other
+        for y in 0..height {
+            assert!((filtered[(width / 2 - 1, y)].red - 0.2).abs() < 0.05);
+            assert!((filtered[(width / 2, y)].red - 0.8).abs() < 0.05);
+        }
+    }
+}
@@ -3,63 +3,178 @@ use exr::image::write::channels::GetPixel;
 use exr::math::Vec2;
 use exr::meta::attribute::{ChannelDescription, SampleType};
 use imgref::ImgRef;
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use rayon::slice::ParallelSliceMut;
 
+use crate::common::math::{Point3, Vec3};
 use crate::common::progress::PixelResult;
-use crate::common::scene::Color;
+use crate::common::scene::{Camera, Color};
+use crate::common::tonemap::{is_clipped, to_srgb16, to_srgb8, viridis};
+use crate::cpu::{ImageOrigin, RayCamera};
 
-type DiscreteImage = image::ImageBuffer<image::Rgb<u8>, Vec<u8>>;
+pub type DiscreteImage = image::ImageBuffer<image::Rgb<u8>, Vec<u8>>;
+pub type DiscreteImage16 = image::ImageBuffer<image::Rgb<u16>, Vec<u16>>;
+pub type DiscreteImageAlpha = image::ImageBuffer<image::Rgba<u8>, Vec<u8>>;
 
 /// Convert the given image to a format suitable for saving to a png file.
 /// The first return Image is the image itself, the second Image shows where values had to be clipped
 /// to fit into the image format .
-pub fn to_discrete_image(image: ImgRef<PixelResult>) -> (DiscreteImage, DiscreteImage) {
-    let mut result = DiscreteImage::new(image.width() as u32, image.height() as u32);
-    let mut clipped = DiscreteImage::new(image.width() as u32, image.height() as u32);
+///
+/// For images rendered with [transparent_sky](crate::cpu::CpuRenderSettings::transparent_sky) [PixelResult::alpha]
+/// becomes the alpha channel, and since png files don't use premultiplied alpha the color is divided by it first.
+/// Otherwise the image is fully opaque.
+///
+/// The rows are stored in the same order as in `image`, so the [ImageOrigin] it was rendered with is kept.
+pub fn to_discrete_image(image: ImgRef<PixelResult>, transparent_sky: bool) -> (DiscreteImageAlpha, DiscreteImage) {
+    let (width, height) = (image.width(), image.height());
+    let mut result = vec![0; width * height * 4];
+    let mut clipped = vec![0; width * height * 3];
 
-    let max = palette::Srgb::new(1.0, 1.0, 1.0).into_linear();
+    // convert the scanlines in parallel
+    result.par_chunks_mut(width * 4).zip(clipped.par_chunks_mut(width * 3)).enumerate()
+        .for_each(|(y, (result_row, clipped_row))| {
+            for x in 0..width {
+                let pixel = image[(x, y)];
+                let alpha = if transparent_sky { pixel.alpha } else { 1.0 };
+                let linear: Color = if alpha > 0.0 { pixel.color / alpha } else { pixel.color };
 
-    for (x, y, p) in result.enumerate_pixels_mut() {
-        let linear: Color = image[(x, y)].color;
+                let [red, green, blue] = to_srgb8(linear);
+                let alpha = (alpha.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+                result_row[4 * x..4 * x + 4].copy_from_slice(&[red, green, blue, alpha]);
+                clipped_row[3 * x..3 * x + 3].copy_from_slice(&is_clipped(linear).map(|c| if c { 255 } else { 0 }));
+            }
+        });
+
+    let result = DiscreteImageAlpha::from_raw(width as u32, height as u32, result).unwrap();
+    let clipped = DiscreteImage::from_raw(width as u32, height as u32, clipped).unwrap();
+    (result, clipped)
+}
+
+/// Convert the given image to sRGB with 16 bits per channel, which avoids the banding of [to_discrete_image] on smooth gradients.
+/// Values are clipped the same way, saving the result as png keeps the full precision.
+pub fn to_discrete_image16(image: ImgRef<PixelResult>) -> DiscreteImage16 {
+    let width = image.width();
+    let mut result = vec![0; width * image.height() * 3];
+
+    // convert the scanlines in parallel
+    result.par_chunks_mut(width * 3).enumerate()
+        .for_each(|(y, result_row)| {
+            for x in 0..width {
+                result_row[3 * x..3 * x + 3].copy_from_slice(&to_srgb16(image[(x, y)].color));
+            }
+        });
 
-        let srgb = palette::Srgb::from_linear(linear);
-        let data = srgb.into_format();
+    DiscreteImage16::from_raw(width as u32, image.height() as u32, result).unwrap()
+}
+
+/// Visualize the number of samples taken for each pixel, mapped to the viridis color ramp.
+/// Black is no samples and bright yellow is the maximum sample count in the image.
+pub fn samples_heatmap(image: ImgRef<PixelResult>) -> DiscreteImage {
+    let max_samples = image.pixels().map(|p| p.samples).max().unwrap_or(0).max(1);
 
-        *p = image::Rgb([data.red, data.green, data.blue]);
-        clipped[(x, y)] = image::Rgb([
-            if linear.red > max.red { 255 } else { 0 },
-            if linear.green > max.green { 255 } else { 0 },
-            if linear.blue > max.blue { 255 } else { 0 },
-        ]);
+    let mut result = DiscreteImage::new(image.width() as u32, image.height() as u32);
+    for (x, y, p) in result.enumerate_pixels_mut() {
+        let samples = image[(x, y)].samples;
+        *p = if samples == 0 {
+            image::Rgb([0, 0, 0])
+        } else {
+            image::Rgb(viridis(samples as f32 / max_samples as f32))
+        };
     }
 
-    (result, clipped)
+    result
+}
+
+/// Draw the world space axes on top of a rendered image, as seen by `camera`.
+/// Each axis is drawn as a line of `length` starting at the origin, X in red, Y in green and Z in blue.
+///
+/// This is an alternative to adding [objects_axes](crate::demos::objects_axes) to the scene,
+/// which keeps the axes out of the ray-traced geometry. The axes are not occluded by the scene.
+/// `origin` is the [ImageOrigin] the image was rendered with.
+pub fn draw_axes(image: &mut DiscreteImageAlpha, camera: &Camera, origin: ImageOrigin, length: f32) {
+    let ray_camera = RayCamera::new(camera, None, origin, image.width(), image.height());
+    let axes = [
+        (Vec3::x_axis(), image::Rgba([255, 0, 0, 255])),
+        (Vec3::y_axis(), image::Rgba([0, 255, 0, 255])),
+        (Vec3::z_axis(), image::Rgba([0, 0, 255, 255])),
+    ];
+
+    for (axis, color) in axes {
+        if let Some((start, end)) = ray_camera.project_segment(Point3::origin(), Point3::origin() + *axis * length) {
+            draw_line(image, start, end, color);
+        }
+    }
 }
 
-pub struct ImageWrapper<'a>(ImgRef<'a, PixelResult>);
+/// Draw a line between two points in continuous pixel coordinates, the parts outside of the image are skipped.
+fn draw_line(image: &mut DiscreteImageAlpha, start: (f32, f32), end: (f32, f32), color: image::Rgba<u8>) {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+
+    // clip the line to the image first, the endpoints can be very far outside of it
+    let mut t_min: f32 = 0.0;
+    let mut t_max: f32 = 1.0;
+    for (delta, low, high) in [(dx, -start.0, width - start.0), (dy, -start.1, height - start.1)] {
+        if delta == 0.0 {
+            if low > 0.0 || high < 0.0 {
+                return;
+            }
+        } else {
+            let (t0, t1) = (low / delta, high / delta);
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+    }
+    if t_min > t_max {
+        return;
+    }
 
-pub type ChannelTuple = (ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription);
+    let steps = ((t_max - t_min) * dx.abs().max(dy.abs())).ceil() as u32;
+    for i in 0..=steps {
+        let t = t_min + (t_max - t_min) * i as f32 / steps.max(1) as f32;
+        let x = start.0 + t * dx;
+        let y = start.1 + t * dy;
 
-/// Convert the given image to the exr file format.
-pub fn to_exr_image(image: ImgRef<PixelResult>) -> Image<Layer<SpecificChannels<ImageWrapper, ChannelTuple>>> {
-    impl GetPixel for ImageWrapper<'_> {
-        type Pixel = (f32, f32, f32, f32, f32, f32, f32, f32, f32, f32);
-
-        fn get_pixel(&self, Vec2(x, y): Vec2<usize>) -> Self::Pixel {
-            let pixel = self.0[(x, y)];
-            (
-                pixel.color.red, pixel.color.green, pixel.color.blue,
-                pixel.variance.red, pixel.variance.green, pixel.variance.blue,
-                pixel.rel_variance.red, pixel.rel_variance.green, pixel.rel_variance.blue,
-                pixel.samples as f32,
-            )
+        if (0.0..width).contains(&x) && (0.0..height).contains(&y) {
+            image[(x as u32, y as u32)] = color;
         }
     }
+}
+
+pub struct ImageWrapper<'a> {
+    image: ImgRef<'a, PixelResult>,
+    transparent_sky: bool,
+}
+
+pub type ChannelTuple = (ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription, ChannelDescription);
+
+impl GetPixel for ImageWrapper<'_> {
+    type Pixel = (f32, f32, f32, f32, f32, f32, f32, f32, f32, f32, f32);
+
+    fn get_pixel(&self, Vec2(x, y): Vec2<usize>) -> Self::Pixel {
+        let pixel = self.image[(x, y)];
+        let alpha = if self.transparent_sky { pixel.alpha } else { 1.0 };
+        (
+            pixel.color.red, pixel.color.green, pixel.color.blue, alpha,
+            pixel.variance.red, pixel.variance.green, pixel.variance.blue,
+            pixel.rel_variance.red, pixel.rel_variance.green, pixel.rel_variance.blue,
+            pixel.samples as f32,
+        )
+    }
+}
 
+/// Convert the given image to the exr file format.
+/// The `A` channel is [PixelResult::alpha] for images rendered with [transparent_sky](crate::cpu::CpuRenderSettings::transparent_sky),
+/// where the color is premultiplied by it as exr expects. Otherwise the image is fully opaque.
+/// Like [to_discrete_image] the rows are stored in the same order as in `image`, see [ImageOrigin].
+pub fn to_exr_image(image: ImgRef<PixelResult>, transparent_sky: bool) -> Image<Layer<SpecificChannels<ImageWrapper, ChannelTuple>>> {
     let channels = SpecificChannels {
         channels: (
             ChannelDescription::named("R", SampleType::F32),
             ChannelDescription::named("G", SampleType::F32),
             ChannelDescription::named("B", SampleType::F32),
+            ChannelDescription::named("A", SampleType::F32),
             ChannelDescription::named("var0-R", SampleType::F32),
             ChannelDescription::named("var1-G", SampleType::F32),
             ChannelDescription::named("var2-B", SampleType::F32),
@@ -68,7 +183,7 @@ pub fn to_exr_image(image: ImgRef<PixelResult>) -> Image<Layer<SpecificChannels<
             ChannelDescription::named("rel2-B", SampleType::F32),
             ChannelDescription::named("samples", SampleType::F32),
         ),
-        pixels: ImageWrapper(image),
+        pixels: ImageWrapper { image, transparent_sky },
     };
 
     exr::image::Image::from_channels((image.width(), image.height()), channels)
@@ -6,3 +6,8 @@ pub mod cpu;
 pub mod demos;
 pub mod tev;
 pub mod images;
+pub mod filter;
+#[cfg(feature = "indicatif")]
+pub mod progress_bar;
+#[cfg(feature = "web-preview")]
+pub mod web_preview;
@@ -1,5 +1,6 @@
 use std::{fs, io};
 use std::cmp::max;
+use std::collections::HashMap;
 use std::net::TcpStream;
 use std::path::PathBuf;
 use std::time::Instant;
@@ -8,52 +9,144 @@ use exr::prelude::WritableImage;
 use tev_client::TevClient;
 
 use tracer::common::progress::{CombinedProgress, PrintProgress};
-use tracer::common::scene::Object;
+use tracer::common::math::Point3;
+use tracer::common::scene::{Object, Scene};
 use tracer::common::util::lower_process_priority;
-use tracer::cpu::{CpuRenderer, CpuRenderSettings, StopCondition, Strategy};
+use tracer::cpu::{CpuRenderer, CpuRenderSettings, DEFAULT_MAX_PIXELS, LightSelection, orbit_camera_path, render_animation, StopCondition, Strategy, Subsampling};
 use tracer::cpu::accel::bvh::{BVH, BVHSplitStrategy};
-use tracer::demos;
-use tracer::images::{to_discrete_image, to_exr_image};
+use tracer::demos::{self, SceneFn};
+use tracer::images::{samples_heatmap, to_discrete_image, to_discrete_image16, to_exr_image};
 use tracer::tev::TevProgress;
 
+/// Also save a 16-bit png next to the regular 8-bit one, for smooth gradients without the size of the exr.
+const SAVE_PNG16: bool = false;
+
+/// Also save a coverage mask png for each named group of objects, for compositing.
+const SAVE_MASKS: bool = false;
+
+/// Render this many frames orbiting around the scene instead of a single image.
+const ANIMATION_FRAMES: Option<u32> = None;
+
+const USAGE: &str = "usage: tracer [--scene NAME] [--width PIXELS] [--height PIXELS] [--samples COUNT] [--strategy STRATEGY]";
+
+/// The command line arguments, anything that's not given keeps its default.
+#[derive(Debug)]
+struct Args {
+    /// The constructor of one of the [demos::SCENES].
+    scene: SceneFn,
+    width: u32,
+    height: u32,
+    samples: u32,
+    strategy: Strategy,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args { scene: demos::scene_random_tiles, width: 1920, height: 1080, samples: 10, strategy: Strategy::SampleLights }
+    }
+}
+
+impl Args {
+    /// Parse `args` without the program name, each flag is followed by its value.
+    fn parse(mut args: impl Iterator<Item=String>) -> Result<Args, String> {
+        let mut result = Args::default();
+
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Err(USAGE.to_owned());
+            }
+
+            let value = args.next().ok_or_else(|| format!("missing value for {}\n{}", flag, USAGE))?;
+            let number = || value.parse::<u32>().map_err(|_| format!("invalid value {:?} for {}, expected a number", value, flag));
+
+            match flag.as_str() {
+                "--scene" => result.scene = parse_scene(&value)?,
+                "--width" => result.width = number()?,
+                "--height" => result.height = number()?,
+                "--samples" => result.samples = number()?,
+                "--strategy" => result.strategy = parse_strategy(&value)?,
+                _ => return Err(format!("unknown argument {:?}\n{}", flag, USAGE)),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+fn parse_scene(name: &str) -> Result<SceneFn, String> {
+    demos::SCENES.iter().find(|&&(n, _)| n == name).map(|&(_, scene)| scene).ok_or_else(|| {
+        let names: Vec<&str> = demos::SCENES.iter().map(|&(name, _)| name).collect();
+        format!("unknown scene {:?}, expected one of {}", name, names.join(", "))
+    })
+}
+
+fn parse_strategy(name: &str) -> Result<Strategy, String> {
+    match name {
+        "simple" => Ok(Strategy::Simple),
+        "sample-lights" => Ok(Strategy::SampleLights),
+        "light-tracing" => Ok(Strategy::LightTracing),
+        "direct-only" => Ok(Strategy::DirectOnly),
+        "object-ids" => Ok(Strategy::ObjectIds),
+        "ambient-occlusion" => Ok(Strategy::AmbientOcclusion { rays: 16, radius: 1.0 }),
+        "emission-only" => Ok(Strategy::EmissionOnly),
+        "traversal-cost" => Ok(Strategy::TraversalCost { max_cost: 64 }),
+        _ => Err(format!("unknown strategy {:?}, expected one of simple, sample-lights, light-tracing, direct-only, object-ids, ambient-occlusion, emission-only, traversal-cost", name)),
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     lower_process_priority();
-    // rayon::ThreadPoolBuilder::new().num_threads(1).build_global().unwrap();
 
-    let scene = demos::scene_random_tiles();
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        std::process::exit(2);
+    });
+    let mut scene = (args.scene)();
 
     let client = TevClient::wrap(TcpStream::connect("127.0.0.1:14158")?);
 
+    let settings = CpuRenderSettings {
+        stop_condition: StopCondition::SampleCount(args.samples),
+        strategy: args.strategy,
+        ..Default::default()
+    };
     let renderer = CpuRenderer {
-        settings: CpuRenderSettings {
-            stop_condition: StopCondition::SampleCount(10),
-            max_bounces: 8,
-            anti_alias: true,
-            strategy: Strategy::SampleLights,
-        },
+        settings,
+        lights: LightSelection::Auto,
         progress_handler: CombinedProgress::new(
             PrintProgress,
-            TevProgress::new("test", client),
+            TevProgress::new("test", client).with_alpha(settings.transparent_sky),
         ),
+        threads: None,
+        max_pixels: DEFAULT_MAX_PIXELS,
+        subsampling: Subsampling::Full,
     };
 
-    let div = 1;
-    let (width, height) = (1920 / div, 1080 / div);
+    let (width, height) = (args.width, args.height);
 
-    let accel = |o: &[Object]| BVH::new(o, BVHSplitStrategy::default());
-    // let accel = |o: &[Object]| Octree::new(o, 16);
-    // let accel = |_: &[Object]| NoAccel;
+    let accel = |o: &[Object], progress: &mut dyn FnMut(f32)| BVH::new_with_progress(o, BVHSplitStrategy::default(), progress);
+    // let accel = |o: &[Object], _: &mut dyn FnMut(f32)| Octree::new(o, 16);
+    // let accel = |_: &[Object], _: &mut dyn FnMut(f32)| NoAccel;
+
+    if let Some(frame_count) = ANIMATION_FRAMES {
+        return save_animation(&mut scene, settings, width, height, frame_count);
+    }
 
-    let settings = renderer.settings.clone();
     let start = Instant::now();
-    let image = renderer.render(&scene, width, height, accel);
+    let (image, masks) = if SAVE_MASKS {
+        renderer.render_with_masks(&scene, width, height, None, accel)?
+    } else {
+        (renderer.render(&scene, width, height, None, accel)?, HashMap::new())
+    };
     let elapsed = Instant::now() - start;
     println!("Render took {}s", elapsed.as_secs_f32());
 
     let info = format!("{:#?}\n\n{:#?}\n\nRender took {}s\n", settings, scene, elapsed.as_secs_f32());
 
-    let (image_discrete, _) = to_discrete_image(image.as_ref());
-    let image_exr = to_exr_image(image.as_ref());
+    let (image_discrete, _) = to_discrete_image(image.as_ref(), settings.transparent_sky);
+    let image_exr = to_exr_image(image.as_ref(), settings.transparent_sky);
+    let image_samples = samples_heatmap(image.as_ref());
+    let image_discrete16 = SAVE_PNG16.then(|| to_discrete_image16(image.as_ref()));
 
     let output_paths = [PathBuf::from("ignored/output"), pick_output_file_path()?];
     for output_path in output_paths.iter() {
@@ -62,11 +155,51 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         fs::write(output_path.with_extension("txt"), info.as_bytes())?;
         image_exr.write().to_file(output_path.with_extension("exr"))?;
         image_discrete.save(output_path.with_extension("png"))?;
+        image_samples.save(output_path.with_extension("samples.png"))?;
+        if let Some(image_discrete16) = &image_discrete16 {
+            image_discrete16.save(output_path.with_extension("16.png"))?;
+        }
+        for (name, mask) in &masks {
+            mask.save(output_path.with_extension(format!("mask.{}.png", mask_file_name(name))))?;
+        }
     }
 
     Ok(())
 }
 
+/// Render `frame_count` frames with the camera orbiting around the origin and save them as numbered pngs
+/// in a new output directory.
+fn save_animation(scene: &mut Scene, settings: CpuRenderSettings, width: u32, height: u32, frame_count: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let output_dir = pick_output_file_path()?;
+    fs::create_dir_all(&output_dir)?;
+    println!("Saving frames to {:?}", output_dir);
+
+    let start = Instant::now();
+    let accel = BVH::new(&scene.objects, BVHSplitStrategy::default());
+    println!("Building accel took {}s", start.elapsed().as_secs_f32());
+
+    let camera_path = orbit_camera_path(scene.camera.transform * Point3::origin(), Point3::origin());
+    let renderer = |_| CpuRenderer { settings, lights: LightSelection::Auto, progress_handler: PrintProgress, threads: None, max_pixels: DEFAULT_MAX_PIXELS, subsampling: Subsampling::Full };
+
+    render_animation(scene, &accel, width, height, frame_count, camera_path, true, renderer, |frame, image| {
+        println!("Finished frame {}/{}", frame + 1, frame_count);
+        let (image_discrete, _) = to_discrete_image(image, settings.transparent_sky);
+        image_discrete.save(output_dir.join(format!("{:04}.png", frame)))?;
+        Ok::<(), Box<dyn std::error::Error>>(())
+    })?;
+
+    println!("Animation took {}s", start.elapsed().as_secs_f32());
+    Ok(())
+}
+
+/// The object name as it's used in the file name of its mask. Everything but ascii letters, digits, `-` and `_`
+/// is replaced, so names like `../x` or `a/b` can't write outside of the output directory.
+fn mask_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
 fn pick_output_file_path() -> io::Result<PathBuf> {
     fs::create_dir_all("ignored/output")?;
 
@@ -82,4 +215,32 @@ fn pick_output_file_path() -> io::Result<PathBuf> {
     let next_int = max_int? + 1;
     let path = ["ignored", "output", &next_int.to_string()].iter().collect();
     Ok(path)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use tracer::cpu::Strategy;
+
+    use crate::Args;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    #[test]
+    fn parse_defaults() {
+        let args = parse(&[]).unwrap();
+        assert_eq!((args.width, args.height, args.samples), (1920, 1080, 10));
+        assert!(matches!(args.strategy, Strategy::SampleLights));
+
+        let args = parse(&["--width", "64", "--strategy", "traversal-cost"]).unwrap();
+        assert_eq!((args.width, args.height, args.samples), (64, 1080, 10));
+        assert!(matches!(args.strategy, Strategy::TraversalCost { .. }));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse(&["--frames", "10"]).unwrap_err().starts_with("unknown argument \"--frames\""));
+        assert!(parse(&["--samples"]).unwrap_err().starts_with("missing value for --samples"));
+        assert!(parse(&["--samples", "many"]).unwrap_err().starts_with("invalid value \"many\" for --samples"));
+    }
+}
@@ -0,0 +1,90 @@
+use std::time::Instant;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::common::progress::{Block, PassInfo, PixelResult, PrepareStage, ProgressHandler, RenderStats};
+
+/// Show the render progress as a terminal progress bar that updates in place,
+/// with the elapsed time, the estimated remaining time and the number of samples per second.
+pub struct IndicatifProgress {
+    bar: ProgressBar,
+}
+
+pub struct IndicatifProgressState {
+    bar: ProgressBar,
+    samples: u64,
+    start: Instant,
+}
+
+impl IndicatifProgress {
+    pub fn new() -> Self {
+        let bar = ProgressBar::new(100);
+        bar.set_style(style("{msg:<14} [{wide_bar}] {percent:>3}%"));
+        IndicatifProgress { bar }
+    }
+}
+
+impl Default for IndicatifProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressHandler for IndicatifProgress {
+    type State = IndicatifProgressState;
+
+    fn prepare(&mut self, stage: PrepareStage, progress: f32) {
+        let message = match stage {
+            PrepareStage::BuildAccel => "Building accel",
+            PrepareStage::PrepareScene => "Preparing",
+            PrepareStage::TraceLightPaths => "Light paths",
+        };
+        self.bar.set_message(message);
+        self.bar.set_position((progress * 100.0) as u64);
+    }
+
+    fn init(self, _: u32, _: u32, region: Block) -> Self::State {
+        let bar = self.bar;
+        bar.set_style(style("[{elapsed_precise}] [{wide_bar}] {percent:>3}% eta {eta} {msg}"));
+        bar.set_length(region.pixel_count() as u64);
+        bar.set_message("");
+        bar.reset();
+
+        IndicatifProgressState { bar, samples: 0, start: Instant::now() }
+    }
+
+    fn update(state: &mut Self::State, block: Block, pixels: &[PixelResult]) {
+        state.samples += pixels.iter().map(|p| p.samples as u64).sum::<u64>();
+        let samples_per_sec = state.samples as f64 / state.start.elapsed().as_secs_f64();
+        state.bar.set_message(format!("{:.2}M samples/s", samples_per_sec / 1e6));
+
+        state.bar.inc(block.pixel_count() as u64);
+        if Some(state.bar.position()) == state.bar.length() {
+            state.bar.finish();
+        }
+    }
+
+    fn pass_finished(state: &mut Self::State, pass: PassInfo) {
+        match pass.rms_change {
+            Some(rms_change) => state.bar.println(format!("Finished pass with {} samples, rms change {:.06}", pass.samples, rms_change)),
+            None => state.bar.println(format!("Finished pass with {} samples", pass.samples)),
+        }
+
+        // the next pass starts over, the samples per second are only counted for the current pass
+        state.bar.reset();
+        state.samples = 0;
+        state.start = Instant::now();
+    }
+
+    fn finished(state: &mut Self::State, stats: RenderStats) {
+        if stats.rejected_hits > 0 {
+            state.bar.println(format!("Skipped {} invalid hits, the scene probably contains malformed geometry", stats.rejected_hits));
+        }
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("Invalid progress bar template")
+        .progress_chars("=> ")
+}
@@ -5,11 +5,22 @@ use crate::common::progress::{Block, PixelResult, ProgressHandler};
 pub struct TevProgress {
     name: String,
     client: Option<TevClient>,
+    alpha: bool,
 }
 
 impl TevProgress {
     pub fn new(name: &str, client: TevClient) -> Self {
-        TevProgress { name: name.into(), client: Some(client) }
+        TevProgress { name: name.into(), client: Some(client), alpha: false }
+    }
+
+    /// Also send [PixelResult::alpha] as the `A` channel,
+    /// for renders with [transparent_sky](crate::cpu::CpuRenderSettings::transparent_sky).
+    pub fn with_alpha(self, alpha: bool) -> Self {
+        TevProgress { alpha, ..self }
+    }
+
+    fn channel_names(&self) -> &'static [&'static str] {
+        if self.alpha { &["R", "G", "B", "A"] } else { &["R", "G", "B"] }
     }
 
     pub fn try_send<'s, P: TevPacket + 's>(&'s mut self, packet: impl FnOnce(&'s str) -> P) {
@@ -26,39 +37,41 @@ impl TevProgress {
 impl ProgressHandler for TevProgress {
     type State = Self;
 
-    fn init(mut self, width: u32, height: u32) -> Self::State {
+    fn init(mut self, width: u32, height: u32, _: Block) -> Self::State {
         self.try_send(|image_name| PacketCloseImage {
             image_name
         });
+        let channel_names = self.channel_names();
         self.try_send(|image_name| PacketCreateImage {
             image_name,
             grab_focus: false,
             width,
             height,
             // TODO send variance, samples, ... as well
-            channel_names: &["R", "G", "B"],
+            channel_names,
         });
 
         self
     }
 
-    fn update(state: &mut Self::State, block: Block, pixels: &Vec<PixelResult>) {
+    fn update(state: &mut Self::State, block: Block, pixels: &[PixelResult]) {
         //transform data into format expected by tev
-        let mut data = Vec::with_capacity(3 * pixels.len());
-        for dy in 0..block.height {
-            for dx in 0..block.width {
-                let p = pixels[(dx + dy * block.width) as usize];
-                data.extend_from_slice(&[p.color.red, p.color.green, p.color.blue])
-            }
+        debug_assert_eq!(pixels.len(), block.pixel_count() as usize);
+        let channel_names = state.channel_names();
+        let channels = channel_names.len();
+        let channel_strides = [channels as u64; 4];
+        let mut data = Vec::with_capacity(channels * pixels.len());
+        for p in pixels {
+            data.extend_from_slice(&[p.color.red, p.color.green, p.color.blue, p.alpha][..channels])
         }
 
         state.try_send(|image_name| {
             PacketUpdateImage {
                 image_name,
                 grab_focus: false,
-                channel_names: &["R", "G", "B"],
-                channel_offsets: &[0, 1, 2],
-                channel_strides: &[3, 3, 3],
+                channel_names,
+                channel_offsets: &[0, 1, 2, 3][..channels],
+                channel_strides: &channel_strides[..channels],
                 x: block.x,
                 y: block.y,
                 width: block.width,
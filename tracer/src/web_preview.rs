@@ -0,0 +1,303 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use std::{fmt, io};
+
+use crate::common::progress::{Block, PixelResult, ProgressHandler};
+use crate::common::tonemap::to_srgb8;
+
+/// Serve the image being rendered to a browser, like [TevProgress](crate::tev::TevProgress) does for tev.
+///
+/// Opening the address in a browser shows a page that draws the blocks as they finish. The pixels are streamed as
+/// [server-sent events](https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events) from `/events`,
+/// which other web frontends can also subscribe to:
+/// * `init` with data `width height` when a new image starts,
+/// * `block` with data `x y width height rgba`, where `rgba` is the base64 encoded 8-bit sRGB data of the block, row by row.
+///
+/// Clients that connect later first get the blocks that are already done. The server keeps running in the background
+/// after the render finishes, so a clone of the handler can be used for the next image.
+#[derive(Clone)]
+pub struct WebProgress {
+    address: SocketAddr,
+    sender: Sender<Message>,
+}
+
+pub struct WebProgressState {
+    sender: Sender<Message>,
+}
+
+/// Sent to the thread that owns the image and the clients, so the render threads never wait on a client.
+enum Message {
+    Init { width: u32, height: u32 },
+    Block { block: Block, rgba: Vec<u8> },
+    /// A client that requested `/events` and already got the response header.
+    Client(TcpStream),
+}
+
+#[derive(Default)]
+struct Server {
+    width: u32,
+    height: u32,
+    /// The 8-bit sRGB pixels of the current image, black and transparent where no block is done yet.
+    rgba: Vec<u8>,
+    clients: Vec<TcpStream>,
+}
+
+/// How long sending an event to a client may take before that client is dropped, so a stuck browser can't stall the render.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a client may take to send its request before the connection is closed.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl WebProgress {
+    /// Start serving on `address`, eg. `"127.0.0.1:8080"`. Use port 0 to pick a free port, see [WebProgress::address].
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let (sender, receiver) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("web-preview-sender".into())
+            .spawn(move || {
+                let mut server = Server::default();
+                for message in receiver {
+                    server.handle(message);
+                }
+            })?;
+
+        let listener_sender = sender.clone();
+        thread::Builder::new()
+            .name("web-preview".into())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    // each connection gets its own thread, so a slow or silent client only affects itself
+                    let sender = listener_sender.clone();
+                    let _ = thread::Builder::new()
+                        .name("web-preview-connection".into())
+                        .spawn(move || handle_connection(stream, &sender));
+                }
+            })?;
+
+        Ok(WebProgress { address, sender })
+    }
+
+    /// The address the server is listening on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl ProgressHandler for WebProgress {
+    type State = WebProgressState;
+
+    fn init(self, width: u32, height: u32, _: Block) -> Self::State {
+        // the server threads never stop, so sending can't fail
+        let _ = self.sender.send(Message::Init { width, height });
+        WebProgressState { sender: self.sender }
+    }
+
+    fn update(state: &mut Self::State, block: Block, pixels: &[PixelResult]) {
+        debug_assert_eq!(pixels.len(), block.pixel_count() as usize);
+        let rgba: Vec<u8> = pixels.iter().flat_map(to_rgba).collect();
+        let _ = state.sender.send(Message::Block { block, rgba });
+    }
+}
+
+impl Server {
+    fn handle(&mut self, message: Message) {
+        match message {
+            Message::Init { width, height } => {
+                self.width = width;
+                self.height = height;
+                self.rgba = vec![0; width as usize * height as usize * 4];
+                self.broadcast(&init_event(width, height));
+            }
+            Message::Block { block, rgba } => {
+                let row_len = block.width as usize * 4;
+                for (row, y) in block.y_range().enumerate() {
+                    let start = (y as usize * self.width as usize + block.x as usize) * 4;
+                    self.rgba[start..start + row_len].copy_from_slice(&rgba[row * row_len..(row + 1) * row_len]);
+                }
+                self.broadcast(&block_event(block, &rgba));
+            }
+            Message::Client(mut client) => {
+                // all messages are handled in order, so the new client doesn't miss or duplicate any block
+                if client.write_all(self.catch_up_events().as_bytes()).is_ok() {
+                    self.clients.push(client);
+                }
+            }
+        }
+    }
+
+    /// Send `event` to all clients, dropping the ones that disconnected.
+    fn broadcast(&mut self, event: &str) {
+        self.clients.retain_mut(|client| client.write_all(event.as_bytes()).is_ok());
+    }
+
+    /// The events that bring a new client up to date with the current image.
+    fn catch_up_events(&self) -> String {
+        if self.width == 0 || self.height == 0 {
+            return String::new();
+        }
+        let full = Block { x: 0, y: 0, width: self.width, height: self.height };
+        init_event(self.width, self.height) + &block_event(full, &self.rgba)
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, sender: &Sender<Message>) -> io::Result<()> {
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    // only the request line matters, eg. `GET /events HTTP/1.1`
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+    match path {
+        "/" => {
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                PAGE.len()
+            );
+            stream.write_all(header.as_bytes())?;
+            stream.write_all(PAGE.as_bytes())
+        }
+        "/events" => {
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nAccess-Control-Allow-Origin: *\r\n\r\n")?;
+            let _ = sender.send(Message::Client(stream));
+            Ok(())
+        }
+        _ => stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    }
+}
+
+fn to_rgba(pixel: &PixelResult) -> [u8; 4] {
+    let [red, green, blue] = to_srgb8(pixel.color);
+    [red, green, blue, 255]
+}
+
+fn init_event(width: u32, height: u32) -> String {
+    format!("event: init\ndata: {} {}\n\n", width, height)
+}
+
+fn block_event(block: Block, rgba: &[u8]) -> String {
+    format!("event: block\ndata: {} {} {} {} {}\n\n", block.x, block.y, block.width, block.height, Base64(rgba))
+}
+
+/// Formats the wrapped bytes as standard base64 with padding.
+struct Base64<'a>(&'a [u8]);
+
+impl fmt::Display for Base64<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+        for chunk in self.0.chunks(3) {
+            let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+            let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    let index = (bits >> (18 - 6 * i)) & 0x3f;
+                    write!(f, "{}", ALPHABET[index as usize] as char)?;
+                } else {
+                    f.write_str("=")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>tracer</title>
+<style>
+  body { margin: 0; background: #222; display: flex; align-items: center; justify-content: center; height: 100vh; }
+  canvas { max-width: 100vw; max-height: 100vh; image-rendering: pixelated; }
+</style>
+</head>
+<body>
+<canvas id="image" width="0" height="0"></canvas>
+<script>
+  const canvas = document.getElementById("image");
+  const context = canvas.getContext("2d");
+  const events = new EventSource("/events");
+
+  events.addEventListener("init", (event) => {
+    const [width, height] = event.data.split(" ").map(Number);
+    canvas.width = width;
+    canvas.height = height;
+  });
+
+  events.addEventListener("block", (event) => {
+    const [x, y, width, height, data] = event.data.split(" ");
+    const bytes = Uint8ClampedArray.from(atob(data), (c) => c.charCodeAt(0));
+    context.putImageData(new ImageData(bytes, Number(width), Number(height)), Number(x), Number(y));
+  });
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    use crate::common::progress::{Block, PixelResult, ProgressHandler};
+    use crate::common::scene::Color;
+    use crate::web_preview::{Base64, WebProgress};
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(Base64(b"").to_string(), "");
+        assert_eq!(Base64(b"f").to_string(), "Zg==");
+        assert_eq!(Base64(b"fo").to_string(), "Zm8=");
+        assert_eq!(Base64(b"foo").to_string(), "Zm9v");
+        assert_eq!(Base64(b"foobar").to_string(), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn late_client_catches_up() {
+        let progress = WebProgress::bind("127.0.0.1:0").unwrap();
+        let address = progress.address();
+
+        let mut state = progress.init(2, 1, Block { x: 0, y: 0, width: 2, height: 1 });
+        let white = PixelResult { color: Color::new(1.0, 1.0, 1.0), ..PixelResult::default() };
+        WebProgress::update(&mut state, Block { x: 1, y: 0, width: 1, height: 1 }, &[white]);
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let lines: Vec<String> = BufReader::new(stream).lines()
+            .map(|line| line.unwrap())
+            .skip_while(|line| !line.is_empty())
+            .take(6)
+            .collect();
+
+        // the full image so far, with the left pixel still black
+        assert_eq!(lines[1..], ["event: init", "data: 2 1", "", "event: block", "data: 0 0 2 1 AAAAAP////8="]);
+    }
+
+    #[test]
+    fn silent_client_does_not_block() {
+        let progress = WebProgress::bind("127.0.0.1:0").unwrap();
+        let address = progress.address();
+        let mut state = progress.init(1, 1, Block { x: 0, y: 0, width: 1, height: 1 });
+
+        // connects but never sends a request
+        let _silent = TcpStream::connect(address).unwrap();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut lines = BufReader::new(stream).lines().map(|line| line.unwrap());
+        assert_eq!(lines.next().unwrap(), "HTTP/1.1 200 OK");
+
+        WebProgress::update(&mut state, Block { x: 0, y: 0, width: 1, height: 1 }, &[PixelResult::default()]);
+        assert!(lines.any(|line| line == "event: block"));
+    }
+}
@@ -0,0 +1,75 @@
+//! Furnace tests: objects that don't absorb any light, lit by a uniform sky, reflect exactly the sky radiance in every
+//! direction. They should disappear from the render, any pixel that differs from the sky points to a material or
+//! sampling bug that loses or creates energy.
+//!
+//! Every path ends up at the sky with the same throughput, so the pixels have almost no noise and the tolerance can be tight.
+
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+use tracer::common::math::{Angle, Transform, Vec3};
+use tracer::common::scene::{Camera, Color, Material, Object, Scene, Shape, Sky};
+use tracer::cpu::{CpuPreparedScene, CpuRenderSettings, LightSelection, StopCondition, Strategy};
+use tracer::cpu::accel::NoAccel;
+use tracer::demos::{material_diffuse, material_glass, material_mixed, VACUUM, WHITE};
+
+const SIZE: u32 = 7;
+const SAMPLES: u32 = 100;
+
+fn sky() -> Color {
+    Color::new(0.2, 0.5, 1.0)
+}
+
+/// A sphere that fills the center of the image, most pixels see it and the corners only see the sky.
+fn furnace_scene(material: Material) -> Scene {
+    let object = Object::new(Shape::Sphere, material, Transform::translate(Vec3::new(0.0, 0.0, -3.0)));
+    let camera = Camera { fov_horizontal: Angle::degrees(60.0), transform: Transform::default(), medium: VACUUM };
+    Scene { objects: vec![object], sky: Sky::Uniform(sky()), camera }
+}
+
+fn assert_invisible(name: &str, material: Material) {
+    let scene = furnace_scene(material);
+
+    for strategy in [Strategy::Simple, Strategy::SampleLights] {
+        let settings = CpuRenderSettings {
+            stop_condition: StopCondition::SampleCount(SAMPLES),
+            max_bounces: 64,
+            strategy,
+            ..Default::default()
+        };
+        let prepared = CpuPreparedScene::new(&scene, settings, &LightSelection::Auto, NoAccel, SIZE, SIZE);
+        let rng = &mut SmallRng::seed_from_u64(0);
+
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let color = prepared.calculate_pixel(rng, x, y).color;
+                for (actual, expected) in [(color.red, sky().red), (color.green, sky().green), (color.blue, sky().blue)] {
+                    assert!(
+                        (actual - expected).abs() < 1e-3 * expected,
+                        "{name} with {strategy:?}: pixel ({x}, {y}) is {color:?} instead of the sky",
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn furnace_diffuse() {
+    assert_invisible("diffuse", material_diffuse(WHITE));
+}
+
+#[test]
+fn furnace_mirror() {
+    assert_invisible("mirror", material_mixed(WHITE, 0.0));
+}
+
+#[test]
+fn furnace_diffuse_mirror() {
+    assert_invisible("diffuse mirror", material_mixed(WHITE, 0.5));
+}
+
+#[test]
+fn furnace_glass() {
+    assert_invisible("glass", material_glass(WHITE));
+}